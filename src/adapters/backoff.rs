@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+// Exponential backoff between reconnect attempts
+#[derive(Default)]
+pub(super) struct Backoff {
+    pub(super) attempt: u32,
+}
+
+impl Backoff {
+    pub(super) fn next_delay(&mut self) -> Duration {
+        let delay = reconnect_delay(self.attempt, rand::random::<f64>());
+        self.attempt = self.attempt.saturating_add(1);
+
+        delay
+    }
+}
+
+// Delay before the reconnect attempt following the given number of failed ones.
+// The delay doubles with each attempt up to a limit and half of it is scaled by the
+// jitter, between 0 and 1, so many clients do not reconnect at the same moment
+fn reconnect_delay(attempt: u32, jitter: f64) -> Duration {
+    let ceiling = INITIAL_RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RECONNECT_DELAY);
+
+    ceiling / 2 + (ceiling / 2).mul_f64(jitter.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay_grows_up_to_limit() {
+        assert_eq!(reconnect_delay(0, 1.0), INITIAL_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(3, 1.0), INITIAL_RECONNECT_DELAY * 8);
+        assert_eq!(reconnect_delay(30, 1.0), MAX_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX, 1.0), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_reconnect_delay_jitter_keeps_half_of_delay() {
+        assert_eq!(reconnect_delay(3, 0.0), INITIAL_RECONNECT_DELAY * 4);
+        assert_eq!(reconnect_delay(3, 0.5), INITIAL_RECONNECT_DELAY * 6);
+    }
+}
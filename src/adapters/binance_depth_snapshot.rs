@@ -0,0 +1,114 @@
+use crate::{
+    ports::DepthSnapshotSource,
+    typespec::{DepthSnapshot, PriceLevel, Symbol},
+};
+use anyhow::{anyhow, Result};
+use binance_spot_connector_rust::{market, ureq::BinanceHttpClient};
+use serde::Deserialize;
+
+// binance only allows limits up to 5000 and weighs requests above 1000 far higher
+const SNAPSHOT_DEPTH_LIMIT: u32 = 1000;
+
+const BINANCE_REST_BASE_URL: &str = "https://api.binance.com";
+
+// A infrastructure struct that implements a driven port to fetch
+// order book snapshots from the binance REST api
+pub struct BinanceDepthSnapshot {
+    base_url: String,
+}
+
+impl BinanceDepthSnapshot {
+    pub fn new() -> Self {
+        Self::with_base_url(BINANCE_REST_BASE_URL)
+    }
+
+    pub fn with_base_url(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+        }
+    }
+}
+
+impl Default for BinanceDepthSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DepthSnapshotSource for BinanceDepthSnapshot {
+    async fn fetch_snapshot(&self, symbol: &Symbol) -> Result<DepthSnapshot> {
+        let symbol = symbol.0.clone();
+        let base_url = self.base_url.clone();
+
+        // http client of the connector crate is blocking so it is moved
+        // off the async runtime threads
+        let body = tokio::task::spawn_blocking(move || {
+            let request = market::depth(symbol.as_str()).limit(SNAPSHOT_DEPTH_LIMIT);
+
+            BinanceHttpClient::with_url(base_url.as_str())
+                .send(request)
+                .and_then(|response| response.into_body_str())
+                .map_err(|e| anyhow!("depth snapshot request failed: {:?}", e))
+        })
+        .await??;
+
+        depth_snapshot_from_json(body.as_str())
+    }
+}
+
+// DTO of the /api/v3/depth response
+#[derive(Deserialize)]
+struct DepthSnapshotResponse {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+fn depth_snapshot_from_json(json: &str) -> Result<DepthSnapshot> {
+    let response: DepthSnapshotResponse = serde_json::from_str(json)?;
+
+    let to_levels = |levels: Vec<[String; 2]>| -> Result<Vec<PriceLevel>> {
+        levels
+            .iter()
+            .map(|[price, qty]| Ok((price.parse()?, qty.parse()?)))
+            .collect()
+    };
+
+    Ok(DepthSnapshot {
+        last_update_id: response.last_update_id,
+        bids: to_levels(response.bids)?,
+        asks: to_levels(response.asks)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_parsed_from_rest_response() {
+        let json = r#"{
+            "lastUpdateId": 1027024,
+            "bids": [["4.00000000", "431.00000000"]],
+            "asks": [["4.00000200", "12.00000000"], ["4.00000300", "0.50000000"]]
+        }"#;
+
+        let snapshot = depth_snapshot_from_json(json).unwrap();
+        let level = |price: &str, qty: &str| -> PriceLevel {
+            (price.parse().unwrap(), qty.parse().unwrap())
+        };
+
+        assert_eq!(
+            snapshot,
+            DepthSnapshot {
+                last_update_id: 1027024,
+                bids: vec![level("4.00000000", "431.00000000")],
+                asks: vec![
+                    level("4.00000200", "12.00000000"),
+                    level("4.00000300", "0.50000000")
+                ],
+            }
+        );
+    }
+}
@@ -1,88 +1,594 @@
-use std::sync::Arc;
+use std::time::Duration;
 
+use super::venue_market_stream::{self, VenueProtocol};
 use crate::{
-    ports::{MarketStream, MarketStreamMessageBroadcastReceiver},
-    typespec::Symbol,
+    ports::{
+        MarketEvent, MarketStream, MarketStreamConnection, NoMetrics, SharedMetrics,
+        DEFAULT_EVENT_CAPACITY,
+    },
+    typespec::{BookTicker, DepthUpdate, PriceLevel, Symbol, Trade},
 };
-use anyhow::{anyhow, Result};
-use binance_spot_connector_rust::{
-    market_stream::diff_depth::DiffDepthStream, tokio_tungstenite::BinanceWebSocketClient,
-};
-use futures_util::StreamExt;
-use tokio::sync::broadcast;
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+
+const BINANCE_WSS_BASE_URL: &str = "wss://stream.binance.com:9443";
+
+// Interval binance pushes the diff depth updates of a symbol in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthUpdateSpeed {
+    Millis100,
+    // 100ms creates pure noise due to the small amount of asks and bids in each frame
+    #[default]
+    Millis1000,
+}
 
 // A infrastructure struct that implements a driven port to be used in
 // the application layer
-pub struct BinanceDiffDepthStream;
+pub struct BinanceDiffDepthStream {
+    base_url: String,
+    update_speed: DepthUpdateSpeed,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+}
 
 impl BinanceDiffDepthStream {
     pub fn new() -> Self {
-        Self
+        Self::with_base_url(BINANCE_WSS_BASE_URL)
+    }
+
+    // connects to another server speaking the binance protocol, like a local mock in tests
+    pub fn with_base_url(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            update_speed: DepthUpdateSpeed::default(),
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    pub fn with_update_speed(self, update_speed: DepthUpdateSpeed) -> Self {
+        Self {
+            update_speed,
+            ..self
+        }
+    }
+
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+}
+
+impl Default for BinanceDiffDepthStream {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl MarketStream for BinanceDiffDepthStream {
-    async fn subscribe(
-        &self,
-        symbols: Vec<Symbol>,
-    ) -> Result<MarketStreamMessageBroadcastReceiver> {
-        // guard against too many Symbols according to binance api 1024 streams,
-        if symbols.len() == 1024 {
-            return Err(anyhow!("Too many streams. Binance max limit 1024"));
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        let kind = match self.update_speed {
+            DepthUpdateSpeed::Millis100 => StreamKind::Depth100,
+            DepthUpdateSpeed::Millis1000 => StreamKind::Depth1000,
+        };
+
+        stream_symbols(
+            self.base_url.clone(),
+            symbols,
+            kind,
+            self.event_capacity,
+            self.metrics.clone(),
+        )
+        .await
+    }
+}
+
+/*
+Trades of the subscribed symbols, either every single trade or the trades aggregated by
+binance into one per taker order and price. Both are sent as trade events, the id of
+aggregated trades is the aggregate trade id.
+*/
+pub struct BinanceTradeStream {
+    base_url: String,
+    aggregated: bool,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+}
+
+impl BinanceTradeStream {
+    // every single trade, <symbol>@trade
+    pub fn new() -> Self {
+        Self::with_base_url(BINANCE_WSS_BASE_URL)
+    }
+
+    // trades aggregated per taker order and price, <symbol>@aggTrade
+    pub fn aggregated() -> Self {
+        Self::aggregated_with_base_url(BINANCE_WSS_BASE_URL)
+    }
+
+    pub fn aggregated_with_base_url(base_url: impl Into<String>) -> Self {
+        Self {
+            aggregated: true,
+            ..Self::with_base_url(base_url)
+        }
+    }
+
+    pub fn with_base_url(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            aggregated: false,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            metrics: NoMetrics::shared(),
         }
+    }
 
-        let (mut ws_conn, _resp) = BinanceWebSocketClient::connect_async_default()
-            .await
-            .expect("Failed to connect");
-
-        // Need to find way to coerce types so subscriptions can be dynamic
-        // may need to create service specific implementation
-        ws_conn
-            // calls 1000ms since 100ms creates pure noise due
-            // to the small amount of asks and bids in each frame
-            .subscribe(vec![&DiffDepthStream::from_1000ms(
-                symbols.first().unwrap().0.as_str(),
-            )
-            .into()])
-            .await;
-
-        // keep strings within an arc to minimize memory used among
-        // copying messages by the receiver
-        let (sender, receiver) = broadcast::channel::<Arc<String>>(16);
-
-        tokio::spawn(async move {
-            loop {
-                match ws_conn.as_mut().next().await {
-                    Some(Ok(message)) => {
-                        // TODO match msg based on type to either keep connection alive
-                        // or send message into the sender stream
-                        let msg = message.into_text().expect("message to convert to String");
-                        // TODO match if ping frame send pong
-
-                        //let market_stream_broadcast = arc_sender.clone();
-                        let _ = sender.send(Arc::new(msg));
-                    }
-                    Some(Err(_)) => break,
-                    None => break,
-                }
-            }
-        });
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+}
+
+impl Default for BinanceTradeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let recv = Arc::new(receiver);
+impl MarketStream for BinanceTradeStream {
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        let kind = match self.aggregated {
+            true => StreamKind::AggTrade,
+            false => StreamKind::Trade,
+        };
 
-        Ok(recv)
+        stream_symbols(
+            self.base_url.clone(),
+            symbols,
+            kind,
+            self.event_capacity,
+            self.metrics.clone(),
+        )
+        .await
+    }
+}
 
-        // update sender with new value
+// Stream subscribed for each symbol on a connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StreamKind {
+    Depth1000,
+    Depth100,
+    Trade,
+    AggTrade,
+}
+
+impl StreamKind {
+    // name the metrics and logs of the connection are labelled with
+    fn name(self) -> &'static str {
+        match self {
+            StreamKind::Depth1000 | StreamKind::Depth100 => "depth",
+            StreamKind::Trade => "trade",
+            StreamKind::AggTrade => "agg_trade",
+        }
     }
+
+    // name of the stream of the symbol on the combined stream api, like btcusdc@depth
+    fn stream_of(self, symbol: &Symbol) -> String {
+        let symbol = symbol.0.to_lowercase();
+
+        match self {
+            StreamKind::Depth1000 => format!("{}@depth", symbol),
+            StreamKind::Depth100 => format!("{}@depth@100ms", symbol),
+            StreamKind::Trade => format!("{}@trade", symbol),
+            StreamKind::AggTrade => format!("{}@aggTrade", symbol),
+        }
+    }
+}
+
+// Connects to the combined stream api and streams the events of the streams of the
+// symbols into the connection handed back
+async fn stream_symbols(
+    base_url: String,
+    symbols: Vec<Symbol>,
+    kind: StreamKind,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+) -> Result<MarketStreamConnection> {
+    // combined stream endpoint is {base url}/stream
+    let url = format!("{}/stream", base_url.trim_end_matches('/'));
+
+    venue_market_stream::subscribe(
+        url,
+        BinanceProtocol::new(kind),
+        symbols,
+        event_capacity,
+        metrics,
+    )
+    .await
+}
+
+/*
+Binance sends every subscribed stream of a connection as a frame of the combined stream
+api and acknowledges requests with their id. Binance pings the connection itself and
+closes it after 24 hours.
+*/
+#[derive(Clone)]
+struct BinanceProtocol {
+    kind: StreamKind,
+    last_request_id: u64,
+}
+
+impl BinanceProtocol {
+    fn new(kind: StreamKind) -> Self {
+        Self {
+            kind,
+            last_request_id: 0,
+        }
+    }
+
+    fn request(&mut self, method: &str, symbols: &[Symbol]) -> Vec<String> {
+        self.last_request_id += 1;
+        let streams: Vec<String> = symbols
+            .iter()
+            .map(|symbol| self.kind.stream_of(symbol))
+            .collect();
+
+        vec![json!({
+            "method": method,
+            "params": streams,
+            "id": self.last_request_id,
+        })
+        .to_string()]
+    }
+}
+
+impl VenueProtocol for BinanceProtocol {
+    const VENUE: &'static str = "binance";
+    // binance limits for a single websocket connection
+    const MAX_SYMBOLS_PER_CONNECTION: usize = 1024;
+    // binance allows 5 incoming messages per second on a connection
+    const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(200);
+    // binance pings every 20 seconds and answers the pongs tungstenite queues itself
+    const PING: Option<(&'static str, Duration)> = None;
+    // binance closes connections after 24 hours, reconnect shortly before that
+    const MAX_CONNECTION_AGE: Option<Duration> = Some(Duration::from_secs(23 * 60 * 60 + 55 * 60));
+
+    fn stream(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    fn subscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        self.request("SUBSCRIBE", symbols)
+    }
+
+    fn unsubscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        self.request("UNSUBSCRIBE", symbols)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Vec<MarketEvent>> {
+        Ok(vec![market_event_from_json(text)?])
+    }
+}
+
+/*
+DTOs replicating the messages of the binance combined stream api. Messages are either
+a frame of a subscribed stream or the response to a request.
+*/
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum BinanceMessage {
+    Frame(Frame),
+    ErrorResponse(ErrorResponse),
+    RequestResponse(RequestResponse),
+}
+
+#[derive(Deserialize, Debug)]
+struct Frame {
+    #[serde(rename = "stream")]
+    _stream: String,
+    #[serde(rename = "data")]
+    data: FrameData,
+}
+
+// book ticker frames are the only data without an event type
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum FrameData {
+    Event(EventData),
+    BookTicker(BookTickerData),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "e")]
+enum EventData {
+    #[serde(rename = "depthUpdate")]
+    DiffDepth(DiffDepthData),
+    #[serde(rename = "trade")]
+    Trade(TradeData),
+    #[serde(rename = "aggTrade")]
+    AggTrade(AggTradeData),
+}
+
+#[derive(Deserialize, Debug)]
+struct DiffDepthData {
+    #[serde(rename = "E")]
+    event_time: u64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "U")]
+    first_update_id_in_event: u64,
+    #[serde(rename = "u")]
+    final_update_id_in_event: u64,
+    #[serde(rename = "b")]
+    bids: Vec<[String; 2]>,
+    #[serde(rename = "a")]
+    asks: Vec<[String; 2]>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TradeData {
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "t")]
+    trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    quantity: String,
+    #[serde(rename = "T")]
+    trade_time: u64,
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AggTradeData {
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "a")]
+    agg_trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    quantity: String,
+    #[serde(rename = "T")]
+    trade_time: u64,
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookTickerData {
+    #[serde(rename = "u")]
+    update_id: u64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "b")]
+    best_bid_price: String,
+    #[serde(rename = "B")]
+    best_bid_qty: String,
+    #[serde(rename = "a")]
+    best_ask_price: String,
+    #[serde(rename = "A")]
+    best_ask_qty: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    error: ErrorData,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorData {
+    code: i64,
+    msg: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RequestResponse {
+    id: u64,
+}
+
+fn market_event_from_json(json: &str) -> Result<MarketEvent> {
+    let message: BinanceMessage = serde_json::from_str(json)?;
+
+    let level =
+        |[price, qty]: &[String; 2]| -> Result<PriceLevel> { Ok((price.parse()?, qty.parse()?)) };
+    let levels = |levels: &Vec<[String; 2]>| -> Result<Vec<PriceLevel>> {
+        levels.iter().map(level).collect()
+    };
+
+    let event = match message {
+        BinanceMessage::Frame(Frame { data, .. }) => match data {
+            FrameData::Event(EventData::DiffDepth(depth)) => {
+                MarketEvent::DepthUpdate(DepthUpdate {
+                    symbol: Symbol(depth.symbol),
+                    event_time: depth.event_time,
+                    first_update_id: depth.first_update_id_in_event,
+                    final_update_id: depth.final_update_id_in_event,
+                    bids: levels(&depth.bids)?,
+                    asks: levels(&depth.asks)?,
+                })
+            }
+            FrameData::Event(EventData::Trade(trade)) => MarketEvent::Trade(Trade {
+                symbol: Symbol(trade.symbol),
+                trade_id: trade.trade_id,
+                price: trade.price.parse()?,
+                quantity: trade.quantity.parse()?,
+                trade_time: trade.trade_time,
+                buyer_is_maker: trade.buyer_is_maker,
+            }),
+            FrameData::Event(EventData::AggTrade(trade)) => MarketEvent::Trade(Trade {
+                symbol: Symbol(trade.symbol),
+                trade_id: trade.agg_trade_id,
+                price: trade.price.parse()?,
+                quantity: trade.quantity.parse()?,
+                trade_time: trade.trade_time,
+                buyer_is_maker: trade.buyer_is_maker,
+            }),
+            FrameData::BookTicker(ticker) => MarketEvent::BookTicker(BookTicker {
+                symbol: Symbol(ticker.symbol),
+                update_id: ticker.update_id,
+                best_bid: level(&[ticker.best_bid_price, ticker.best_bid_qty])?,
+                best_ask: level(&[ticker.best_ask_price, ticker.best_ask_qty])?,
+            }),
+        },
+        BinanceMessage::ErrorResponse(ErrorResponse { error }) => MarketEvent::Error {
+            code: error.code,
+            msg: error.msg,
+        },
+        BinanceMessage::RequestResponse(RequestResponse { id }) => {
+            MarketEvent::SubscriptionAck { id }
+        }
+    };
+
+    Ok(event)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        adapters::mock_binance_server::{MockBinanceServer, MockRequest, MockStep},
+        ports::ConnectionState,
+    };
+    use tokio::sync::broadcast;
+
+    fn level(price: &str, qty: &str) -> PriceLevel {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn test_diff_depth_frame_parsed_into_depth_update() {
+        let json = r#"{"stream":"btcusdc@depth","data":{"e":"depthUpdate","E":1672515782136,"s":"BTCUSDC","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"],["0.0027","0"]]}}"#;
+
+        let event = market_event_from_json(json).unwrap();
+
+        assert_eq!(
+            event,
+            MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSDC".into()),
+                event_time: 1672515782136,
+                first_update_id: 157,
+                final_update_id: 160,
+                bids: vec![level("0.0024", "10")],
+                asks: vec![level("0.0026", "100"), level("0.0027", "0")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_trade_frame_parsed_into_trade() {
+        let json = r#"{"stream":"btcusdc@trade","data":{"e":"trade","E":1672515782136,"s":"BTCUSDC","t":12345,"p":"0.001","q":"100","T":1672515782136,"m":true,"M":true}}"#;
+
+        let event = market_event_from_json(json).unwrap();
+
+        assert_eq!(
+            event,
+            MarketEvent::Trade(Trade {
+                symbol: Symbol("BTCUSDC".into()),
+                trade_id: 12345,
+                price: "0.001".parse().unwrap(),
+                quantity: "100".parse().unwrap(),
+                trade_time: 1672515782136,
+                buyer_is_maker: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_agg_trade_frame_parsed_into_trade() {
+        let json = r#"{"stream":"btcusdc@aggTrade","data":{"e":"aggTrade","E":1672515782136,"s":"BTCUSDC","a":12345,"p":"0.001","q":"100","f":100,"l":105,"T":1672515782136,"m":false,"M":true}}"#;
+
+        let event = market_event_from_json(json).unwrap();
+
+        assert_eq!(
+            event,
+            MarketEvent::Trade(Trade {
+                symbol: Symbol("BTCUSDC".into()),
+                trade_id: 12345,
+                price: "0.001".parse().unwrap(),
+                quantity: "100".parse().unwrap(),
+                trade_time: 1672515782136,
+                buyer_is_maker: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_book_ticker_frame_parsed_into_book_ticker() {
+        let json = r#"{"stream":"btcusdc@bookTicker","data":{"u":400900217,"s":"BTCUSDC","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}}"#;
+
+        let event = market_event_from_json(json).unwrap();
+
+        assert_eq!(
+            event,
+            MarketEvent::BookTicker(BookTicker {
+                symbol: Symbol("BTCUSDC".into()),
+                update_id: 400900217,
+                best_bid: level("25.35190000", "31.21000000"),
+                best_ask: level("25.36520000", "40.66000000"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_responses_parsed_into_ack_and_error() {
+        let ack = market_event_from_json(r#"{"result":null,"id":1}"#).unwrap();
+        let error =
+            market_event_from_json(r#"{"error":{"code":2,"msg":"Invalid request"},"id":2}"#)
+                .unwrap();
+
+        assert_eq!(ack, MarketEvent::SubscriptionAck { id: 1 });
+        assert_eq!(
+            error,
+            MarketEvent::Error {
+                code: 2,
+                msg: "Invalid request".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_frame_is_a_parse_error() {
+        let json = r#"{"stream":"btcusdc@kline_1m","data":{"e":"kline","E":1}}"#;
+
+        assert!(market_event_from_json(json).is_err());
+    }
+
+    #[test]
+    fn test_requests_name_the_streams_of_the_kind() {
+        let mut protocol = BinanceProtocol::new(StreamKind::Depth100);
+
+        let subscribe = protocol.subscribe_requests(&[Symbol("BTCUSDC".into())]);
+        let unsubscribe = protocol.unsubscribe_requests(&[Symbol("BTCUSDC".into())]);
+
+        assert_eq!(
+            subscribe,
+            vec![r#"{"id":1,"method":"SUBSCRIBE","params":["btcusdc@depth@100ms"]}"#]
+        );
+        assert_eq!(
+            unsubscribe,
+            vec![r#"{"id":2,"method":"UNSUBSCRIBE","params":["btcusdc@depth@100ms"]}"#]
+        );
+    }
 
     #[tokio::test]
     async fn test_reciever_returned_by_stream_subscription() {
-        let setup = BinanceDiffDepthStream::new();
+        let server = MockBinanceServer::start(vec![vec![MockStep::AwaitRequest]]).await;
+        let setup = BinanceDiffDepthStream::with_base_url(server.base_url());
 
         let testfn = setup
             .subscribe(vec![Symbol("BTCUSDC".into())])
@@ -90,17 +596,187 @@ mod tests {
             .unwrap();
 
         let subscription_msg = testfn
-            .clone()
+            .receiver
             .resubscribe()
             .recv()
             .await
             .expect("message to be in receiver");
 
-        let test_val =
-            serde_json::to_value(subscription_msg.to_string()).expect("msg to be a json response");
-
         // ASSERTIONS
-        assert!(test_val["result"].is_null());
+        assert!(matches!(
+            subscription_msg.event,
+            MarketEvent::SubscriptionAck { .. }
+        ));
+        assert_eq!(
+            server.requests(),
+            vec![MockRequest {
+                connection: 0,
+                method: "SUBSCRIBE".into(),
+                params: vec!["btcusdc@depth".into()],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trade_stream_subscribes_trade_streams() {
+        let server = MockBinanceServer::start(vec![vec![MockStep::AwaitRequest]]).await;
+        let trades = BinanceTradeStream {
+            aggregated: true,
+            ..BinanceTradeStream::with_base_url(server.base_url())
+        };
+
+        let connection = trades
+            .subscribe(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+        let _ack = connection.receiver.resubscribe().recv().await;
+
+        assert_eq!(
+            server.requests(),
+            vec![MockRequest {
+                connection: 0,
+                method: "SUBSCRIBE".into(),
+                params: vec!["btcusdc@aggTrade".into()],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close_unsubscribes_symbols_and_ends_the_stream() {
+        let server = MockBinanceServer::start(vec![vec![MockStep::AwaitRequest]]).await;
+        let connection = BinanceDiffDepthStream::with_base_url(server.base_url())
+            .subscribe(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+        let _ack = receiver.recv().await;
+
+        connection.close().await.unwrap();
+
+        // receivers see the stream closed once the supervisor stopped
+        loop {
+            match receiver.recv().await {
+                Err(broadcast::error::RecvError::Closed) => break,
+                _ => continue,
+            }
+        }
+        assert!(connection.add_symbols(vec![]).await.is_err());
+
+        // the mock server reads the request in its own task
+        let unsubscribe = MockRequest {
+            connection: 0,
+            method: "UNSUBSCRIBE".into(),
+            params: vec!["btcusdc@depth".into()],
+        };
+        for _ in 0..100 {
+            if server.requests().contains(&unsubscribe) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.requests().last(), Some(&unsubscribe));
+    }
+
+    #[tokio::test]
+    async fn test_frames_of_mock_server_become_events() {
+        let server = MockBinanceServer::start(vec![vec![
+            MockStep::Ping,
+            MockStep::Text("{\"stream\": ".into()),
+            MockStep::DepthUpdate {
+                symbol: "BTCUSDC",
+                first_update_id: 1,
+                final_update_id: 2,
+                bids: vec![("99.00", "1")],
+                asks: vec![],
+            },
+        ]])
+        .await;
+
+        let connection = BinanceDiffDepthStream::with_base_url(server.base_url())
+            .subscribe(vec![])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        // the malformed frame is skipped
+        assert_eq!(receiver.recv().await.unwrap().event, MarketEvent::Heartbeat);
+        assert_eq!(
+            receiver.recv().await.unwrap().event,
+            MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSDC".into()),
+                event_time: 1,
+                first_update_id: 1,
+                final_update_id: 2,
+                bids: vec![level("99.00", "1")],
+                asks: vec![],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close_frame_of_mock_server_is_a_disconnect() {
+        let server = MockBinanceServer::start(vec![vec![MockStep::Close]]).await;
+
+        let connection = BinanceDiffDepthStream::with_base_url(server.base_url())
+            .subscribe(vec![])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        assert_eq!(
+            receiver.recv().await.unwrap().event,
+            MarketEvent::ConnectionState(ConnectionState::Disconnected {
+                reason: "connection closed by the market".into()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forced_disconnect_reconnects_and_subscribes_again() {
+        let server = MockBinanceServer::start(vec![
+            vec![MockStep::AwaitRequest, MockStep::Disconnect],
+            vec![MockStep::AwaitRequest],
+        ])
+        .await;
+
+        let connection = BinanceDiffDepthStream::with_base_url(server.base_url())
+            .subscribe(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        let mut events = vec![];
+        while events.len() < 5 {
+            events.push(receiver.recv().await.unwrap());
+        }
+
+        assert!(matches!(
+            events[0].event,
+            MarketEvent::SubscriptionAck { .. }
+        ));
+        assert!(matches!(
+            events[1].event,
+            MarketEvent::ConnectionState(ConnectionState::Disconnected { .. })
+        ));
+        assert!(matches!(
+            events[2].event,
+            MarketEvent::ConnectionState(ConnectionState::Reconnecting { attempt: 1, .. })
+        ));
+        assert_eq!(
+            events[3].event,
+            MarketEvent::ConnectionState(ConnectionState::Connected)
+        );
+        assert!(matches!(
+            events[4].event,
+            MarketEvent::SubscriptionAck { .. }
+        ));
+
+        let resubscribed: Vec<MockRequest> = server
+            .requests()
+            .into_iter()
+            .filter(|request| request.connection == 1)
+            .collect();
+        assert_eq!(resubscribed[0].params, vec!["btcusdc@depth".to_string()]);
     }
 }
 
@@ -138,29 +814,4 @@ struct SendRequest<'send_request> {
     pub params: Vec<&'send_request str>,
     pub id: usize,
 }
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Frame {
-    #[serde(rename = "stream")]
-    stream: String,
-    #[serde(rename = "data")]
-    data: DiffDepthData,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub struct DiffDepthData {
-    #[serde(rename = "e")]
-    event_type: String,
-    #[serde(rename = "E")]
-    event_time: usize,
-    #[serde(rename = "s")]
-    symbol: String,
-    #[serde(rename = "U")]
-    first_update_id_in_event: usize,
-    #[serde(rename = "u")]
-    final_update_id_in_event: usize,
-    #[serde(rename = "b")]
-    bids: Vec<[String; 2]>,
-    #[serde(rename = "a")]
-    asks: Vec<[String; 2]>,
-}*/
+*/
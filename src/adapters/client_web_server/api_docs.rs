@@ -0,0 +1,479 @@
+use super::{rest::rest_api, stream_socket::MIN_THROTTLE};
+use poem_openapi::{
+    registry::{MetaSchema, MetaSchemaRef, Registry},
+    types::{ParseError, ParseFromJSON, ParseResult, ToJSON, Type},
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{borrow::Cow, fmt};
+
+/*
+  OpenAPI document of the web server. The rest endpoints are regular operations derived
+  by poem-openapi from their handlers and the types of their responses. The websocket
+  routes and the event stream are beyond what it can describe, so they are written out
+  here and added to the derived document: the sockets as GET operations upgrading the
+  connection, with the messages each side sends given under `x-websocket`. Every message
+  shape is a JSON Schema in the components of the document, so clients of the sockets
+  can validate messages with the same schemas as clients of the rest endpoints, and the
+  schemas of the sockets refer to components of the rest endpoints like Decimal and Level.
+
+  The tests of the web server check every route against the document and the messages
+  of the sockets against their schemas.
+*/
+pub(super) fn open_api_document() -> Value {
+    let mut document: Value =
+        serde_json::from_str(&rest_api().spec()).expect("derived document is json");
+
+    // the written schemas use json schema keywords like const of open api 3.1
+    document["openapi"] = json!("3.1.0");
+    for (path, operation) in as_object(paths()) {
+        document["paths"][path] = operation;
+    }
+    for (name, schema) in as_object(schemas()) {
+        document["components"]["schemas"][name] = schema;
+    }
+
+    document
+}
+
+fn as_object(value: Value) -> serde_json::Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/*
+Decimal number of a response, sent as a string of the digits it was made from so it
+keeps its precision and trailing zeros. Schemas refer to it as the Decimal component.
+*/
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub(super) struct DecimalText(String);
+
+impl DecimalText {
+    pub(super) fn of(value: impl fmt::Display) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl Type for DecimalText {
+    const IS_REQUIRED: bool = true;
+
+    type RawValueType = Self;
+
+    type RawElementValueType = Self;
+
+    fn name() -> Cow<'static, str> {
+        "Decimal".into()
+    }
+
+    fn schema_ref() -> MetaSchemaRef {
+        MetaSchemaRef::Reference(Self::name().into_owned())
+    }
+
+    fn register(registry: &mut Registry) {
+        registry.create_schema::<Self, _>(Self::name().into_owned(), |_| MetaSchema {
+            description: Some("Decimal number as a string to keep its precision"),
+            pattern: Some("^-?[0-9]+(\\.[0-9]+)?$".into()),
+            example: Some(json!("100.25")),
+            ..MetaSchema::new("string")
+        });
+    }
+
+    fn as_raw_value(&self) -> Option<&Self::RawValueType> {
+        Some(self)
+    }
+
+    fn raw_element_iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = &'a Self::RawElementValueType> + 'a> {
+        Box::new(self.as_raw_value().into_iter())
+    }
+}
+
+impl ToJSON for DecimalText {
+    fn to_json(&self) -> Option<Value> {
+        Some(Value::String(self.0.clone()))
+    }
+}
+
+impl ParseFromJSON for DecimalText {
+    fn parse_from_json(value: Option<Value>) -> ParseResult<Self> {
+        match value.unwrap_or_default() {
+            Value::String(text) => {
+                text.parse::<Decimal>()?;
+                Ok(Self(text))
+            }
+            value => Err(ParseError::expected_type(value)),
+        }
+    }
+}
+
+/*
+Value of a response that is null while there is none. Unlike an optional field the field
+is always sent, the schema allows either the value or null.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Nullable<T>(pub(super) Option<T>);
+
+impl<T: Type> Type for Nullable<T> {
+    const IS_REQUIRED: bool = true;
+
+    type RawValueType = T::RawValueType;
+
+    type RawElementValueType = T::RawElementValueType;
+
+    fn name() -> Cow<'static, str> {
+        format!("nullable_{}", T::name()).into()
+    }
+
+    fn schema_ref() -> MetaSchemaRef {
+        MetaSchemaRef::Inline(Box::new(MetaSchema {
+            one_of: vec![
+                T::schema_ref(),
+                MetaSchemaRef::Inline(Box::new(MetaSchema::new("null"))),
+            ],
+            ..MetaSchema::ANY
+        }))
+    }
+
+    fn register(registry: &mut Registry) {
+        T::register(registry);
+    }
+
+    fn as_raw_value(&self) -> Option<&Self::RawValueType> {
+        self.0.as_ref().and_then(|value| value.as_raw_value())
+    }
+
+    fn raw_element_iter<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = &'a Self::RawElementValueType> + 'a> {
+        Box::new(self.0.iter().flat_map(|value| value.raw_element_iter()))
+    }
+}
+
+impl<T: ToJSON> ToJSON for Nullable<T> {
+    fn to_json(&self) -> Option<Value> {
+        match &self.0 {
+            Some(value) => value.to_json(),
+            None => Some(Value::Null),
+        }
+    }
+}
+
+impl<T: ParseFromJSON> ParseFromJSON for Nullable<T> {
+    fn parse_from_json(value: Option<Value>) -> ParseResult<Self> {
+        match value {
+            None | Some(Value::Null) => Ok(Self(None)),
+            value => T::parse_from_json(value)
+                .map(|value| Self(Some(value)))
+                .map_err(ParseError::propagate),
+        }
+    }
+}
+
+// Page rendering the document with redoc
+pub(super) const API_DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Order book api</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <redoc spec-url="/api/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+  </body>
+</html>
+"#;
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "oneOf": [schema, { "type": "null" }] })
+}
+
+fn array_of(schema: Value) -> Value {
+    json!({ "type": "array", "items": schema })
+}
+
+fn map_of(schema: Value) -> Value {
+    json!({ "type": "object", "additionalProperties": schema })
+}
+
+// Object with the given properties that does not allow any other property
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+// Websocket route where the client sends the first schema and the server the second
+fn web_socket_operation(summary: &str, client: Value, server: Value) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "tags": ["websocket"],
+            "responses": {
+                "101": { "description": "Switching protocols to a websocket" },
+            },
+            "x-websocket": { "client": client, "server": server },
+        }
+    })
+}
+
+fn paths() -> Value {
+    json!({
+        "/api/events": {
+            "get": {
+                "summary": "Server-sent events with the updates of the stream socket for the \
+                    channels of the symbols",
+                "tags": ["events"],
+                "parameters": [
+                    {
+                        "name": "symbols",
+                        "in": "query",
+                        "required": true,
+                        "description": "Comma separated symbols",
+                        "schema": { "type": "string", "example": "BTCUSDC,ETHUSDC" },
+                    },
+                    {
+                        "name": "channels",
+                        "in": "query",
+                        "description": "Comma separated channels",
+                        "schema": { "type": "string", "default": "average_price" },
+                    },
+                    {
+                        "name": "interval",
+                        "in": "query",
+                        "description": "Interval of the candles channel",
+                        "schema": { "type": "string", "example": "1m" },
+                    },
+                    {
+                        "name": "Last-Event-ID",
+                        "in": "header",
+                        "description": "Id of the last event received, the kept events after \
+                            it are sent first",
+                        "schema": { "type": "integer", "minimum": 0 },
+                    },
+                ],
+                "responses": {
+                    "200": {
+                        "description": "Event stream, the data of each event is a message of \
+                            the stream socket",
+                        "content": {
+                            "text/event-stream": {
+                                "schema": {
+                                    "oneOf": [
+                                        schema_ref("StreamUpdate"),
+                                        schema_ref("StreamResponse"),
+                                    ]
+                                }
+                            }
+                        },
+                    },
+                    "400": {
+                        "description": "Invalid parameters",
+                        "content": json_content(schema_ref("Error")),
+                    },
+                    "503": {
+                        "description": "Symbol could not be subscribed",
+                        "content": json_content(schema_ref("Error")),
+                    },
+                },
+            }
+        },
+        "/api/average_order_book_price": web_socket_operation(
+            "Average price of a pair with optional metrics, each time the client asks for it",
+            schema_ref("PairQuery"),
+            schema_ref("PairValue"),
+        ),
+        "/api/stream": web_socket_operation(
+            "Throttled updates of the channels the client subscribed to",
+            schema_ref("StreamRequest"),
+            json!({ "oneOf": [schema_ref("StreamResponse"), schema_ref("StreamUpdate")] }),
+        ),
+        "/api/arbitrage": web_socket_operation(
+            "Arbitrage opportunities of a pair between venues, each time they change",
+            schema_ref("PairQuery"),
+            schema_ref("Arbitrage"),
+        ),
+        "/api/triangular_arbitrage": web_socket_operation(
+            "Profitable round trips of the configured triangles, each time they change",
+            json!({}),
+            schema_ref("TriangularArbitrage"),
+        ),
+    })
+}
+
+fn schemas() -> Value {
+    let decimal = || schema_ref("Decimal");
+    let string = || json!({ "type": "string" });
+    let integer = || json!({ "type": "integer", "minimum": 0 });
+
+    json!({
+        "Side": { "type": "string", "enum": ["buy", "sell"] },
+        "MetricsQuery": object(&[], json!({
+            "levels": integer(),
+            "notional": decimal(),
+            "bps": decimal(),
+            "depths": array_of(integer()),
+        })),
+        "OrderQuery": object(&["side"], json!({
+            "side": schema_ref("Side"),
+            "qty": decimal(),
+            "notional": decimal(),
+        })),
+        "PairQuery": object(&["p"], json!({
+            "p": { "type": "string", "description": "Pair of the market, case insensitive" },
+            "m": schema_ref("MetricsQuery"),
+            "o": schema_ref("OrderQuery"),
+            "c": integer(),
+        })),
+        "Sides": object(&[], json!({ "b": decimal(), "a": decimal() })),
+        "MetricsValue": object(&[], json!({
+            "mid": decimal(),
+            "micro": decimal(),
+            "qwap": decimal(),
+            "vwap": schema_ref("Sides"),
+            "fill": schema_ref("Sides"),
+            "bid": schema_ref("Level"),
+            "ask": schema_ref("Level"),
+            "spread": object(&["abs", "bps"], json!({ "abs": decimal(), "bps": decimal() })),
+            "depth": schema_ref("Sides"),
+            "imbalance": map_of(decimal()),
+        })),
+        "OrderEstimate": object(
+            &["levels", "filled_qty", "filled_notional", "sufficient"],
+            json!({
+                "avg": decimal(),
+                "worst": decimal(),
+                "slip_bps": decimal(),
+                "levels": integer(),
+                "filled_qty": decimal(),
+                "filled_notional": decimal(),
+                "sufficient": { "type": "boolean" },
+            }),
+        ),
+        "ConsolidatedLevel": object(&["p", "q", "v"], json!({
+            "p": decimal(),
+            "q": decimal(),
+            "v": map_of(decimal()),
+        })),
+        "ConsolidatedBook": object(&["venues", "b", "a"], json!({
+            "venues": array_of(string()),
+            "b": array_of(schema_ref("ConsolidatedLevel")),
+            "a": array_of(schema_ref("ConsolidatedLevel")),
+        })),
+        "PairValue": object(&["p", "v"], json!({
+            "p": string(),
+            "v": {
+                "description": "Average price or None while the book is empty",
+                "type": "string",
+            },
+            "m": schema_ref("MetricsValue"),
+            "o": schema_ref("OrderEstimate"),
+            "c": schema_ref("ConsolidatedBook"),
+        })),
+        "Opportunity": object(
+            &["buy", "sell", "buy_p", "sell_p", "edge_bps", "qty", "profit"],
+            json!({
+                "buy": string(),
+                "sell": string(),
+                "buy_p": decimal(),
+                "sell_p": decimal(),
+                "edge_bps": decimal(),
+                "qty": decimal(),
+                "profit": decimal(),
+            }),
+        ),
+        "Arbitrage": object(&["p", "t", "o"], json!({
+            "p": string(),
+            "t": integer(),
+            "o": array_of(schema_ref("Opportunity")),
+        })),
+        "TriangularArbitrage": object(&["t", "r"], json!({
+            "t": integer(),
+            "r": array_of(object(&["start", "legs", "rate", "profit_bps", "max"], json!({
+                "start": string(),
+                "legs": array_of(object(&["s", "side", "p"], json!({
+                    "s": string(),
+                    "side": schema_ref("Side"),
+                    "p": decimal(),
+                }))),
+                "rate": decimal(),
+                "profit_bps": decimal(),
+                "max": decimal(),
+            }))),
+        })),
+        "Channel": {
+            "type": "string",
+            "enum": ["average_price", "metrics", "candles", "arbitrage"],
+        },
+        "StreamRequest": {
+            "oneOf": [
+                object(&["op", "channel", "symbol"], json!({
+                    "op": { "const": "subscribe" },
+                    "id": integer(),
+                    "channel": schema_ref("Channel"),
+                    "symbol": string(),
+                    "interval": { "type": "string", "example": "1m" },
+                    "throttle_ms": { "type": "integer", "minimum": MIN_THROTTLE.as_millis() },
+                    "metrics": schema_ref("MetricsQuery"),
+                })),
+                object(&["op", "channel", "symbol"], json!({
+                    "op": { "const": "unsubscribe" },
+                    "id": integer(),
+                    "channel": schema_ref("Channel"),
+                    "symbol": string(),
+                    "interval": string(),
+                })),
+            ]
+        },
+        "StreamResponse": object(&["status"], json!({
+            "id": integer(),
+            "status": { "type": "string", "enum": ["subscribed", "unsubscribed", "error"] },
+            "channel": schema_ref("Channel"),
+            "symbol": string(),
+            "interval": string(),
+            "error": string(),
+        })),
+        "StreamUpdate": object(&["channel", "symbol"], json!({
+            "channel": schema_ref("Channel"),
+            "symbol": string(),
+            "interval": string(),
+            // the data of each channel
+            "data": {
+                "anyOf": [
+                    object(&["v"], json!({ "v": nullable(decimal()) })),
+                    schema_ref("MetricsValue"),
+                    schema_ref("Candle"),
+                    schema_ref("Arbitrage"),
+                ]
+            },
+            "status": { "const": "out_of_sync" },
+        })),
+        "Candle": object(&["t", "o", "h", "l", "c", "v", "qv", "vwap", "n"], json!({
+            "t": integer(),
+            "o": decimal(),
+            "h": decimal(),
+            "l": decimal(),
+            "c": decimal(),
+            "v": decimal(),
+            "qv": decimal(),
+            "vwap": decimal(),
+            "n": integer(),
+        })),
+    })
+}
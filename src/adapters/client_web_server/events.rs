@@ -0,0 +1,444 @@
+use super::{
+    stream_socket::{
+        stream_subscription, Channel, MetricsQuery, SubscriptionQuery, DEFAULT_THROTTLE,
+        MAX_PENDING_UPDATES, MAX_SUBSCRIPTIONS_PER_SOCKET,
+    },
+    ConnectedClient,
+};
+use crate::{
+    application::ApplicationQuery,
+    lifecycle::ShutdownSignal,
+    ports::SharedMetrics,
+    typespec::{ApplicationLayer, CandleInterval, Symbol},
+};
+use anyhow::{Error, Result};
+use poem::{
+    handler,
+    http::{HeaderMap, StatusCode},
+    web::{Data, Json, Query},
+    Body, IntoResponse, Response,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+// events kept for clients resuming their event stream with the Last-Event-ID header
+const EVENT_HISTORY: usize = 512;
+// comment sent on an event stream without events, so proxies keep the connection open
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+// feeds without listeners keep running this long, so clients reconnecting can resume
+const FEED_LINGER: Duration = Duration::from_secs(30);
+// milliseconds clients wait before reconnecting an event stream
+const RECONNECT_RETRY_MS: u64 = 3000;
+
+// Update of a subscription numbered in the order the event hub published it
+#[derive(Debug)]
+struct ServerEvent {
+    id: u64,
+    subscription: SubscriptionQuery,
+    // stream update or the response ending the subscription, as sent on the socket
+    data: String,
+}
+
+impl ServerEvent {
+    fn to_event_stream(&self) -> String {
+        format!("id: {}\ndata: {}\n\n", self.id, self.data)
+    }
+}
+
+// A subscription streamed for every event stream listening to it
+struct Feed {
+    task: JoinHandle<()>,
+    listeners: usize,
+}
+
+struct EventHubState {
+    next_id: u64,
+    history: VecDeque<Arc<ServerEvent>>,
+    feeds: BTreeMap<SubscriptionQuery, Feed>,
+}
+
+/*
+Streams each subscription of the event streams once, however many clients listen to it,
+and numbers the updates of all subscriptions with one sequence. The latest events are
+kept so a client reconnecting with the id of the last event it received gets the events
+it missed, as long as they are still kept and its feeds did not stop in the meantime.
+*/
+#[derive(Clone)]
+pub(super) struct EventHub {
+    app_layer: ApplicationLayer,
+    metrics: SharedMetrics,
+    state: Arc<std::sync::Mutex<EventHubState>>,
+    events: broadcast::Sender<Arc<ServerEvent>>,
+}
+
+impl EventHub {
+    pub(super) fn new(app_layer: ApplicationLayer, metrics: SharedMetrics) -> Self {
+        Self {
+            app_layer,
+            metrics,
+            state: Arc::new(std::sync::Mutex::new(EventHubState {
+                next_id: 1,
+                history: VecDeque::new(),
+                feeds: BTreeMap::new(),
+            })),
+            events: broadcast::channel(EVENT_HISTORY).0,
+        }
+    }
+
+    // Starts streaming the subscription unless it already is and adds a listener to it
+    async fn acquire(
+        &self,
+        subscription: &SubscriptionQuery,
+        interval: Option<CandleInterval>,
+    ) -> Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(feed) = state.feeds.get_mut(subscription) {
+                if !feed.task.is_finished() {
+                    feed.listeners += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        if subscription.channel.needs_order_book() {
+            let query = ApplicationQuery::SubscribeToSymbol(Symbol(subscription.symbol.clone()));
+            self.app_layer.handle_query(query).await?;
+        }
+
+        let (updates, mut published) = mpsc::channel(MAX_PENDING_UPDATES);
+        let producer = stream_subscription(
+            self.app_layer.clone(),
+            self.metrics.clone(),
+            subscription.clone(),
+            interval,
+            MetricsQuery::default(),
+            DEFAULT_THROTTLE,
+            updates,
+        );
+        let hub = self.clone();
+        let published_subscription = subscription.clone();
+        let task = tokio::spawn(async move {
+            let publisher = async {
+                while let Some(data) = published.recv().await {
+                    hub.publish(&published_subscription, data);
+                }
+            };
+            tokio::join!(producer, publisher);
+        });
+
+        let mut state = self.state.lock().unwrap();
+        let unsubscribe = match state.feeds.get_mut(subscription) {
+            // another listener started the feed in the meantime
+            Some(feed) if !feed.task.is_finished() => {
+                feed.listeners += 1;
+                task.abort();
+                true
+            }
+            // feeds end when their channel can not be streamed for the symbol, its listeners
+            // stay with the new one
+            Some(feed) => {
+                feed.listeners += 1;
+                feed.task = task;
+                true
+            }
+            None => {
+                state
+                    .feeds
+                    .insert(subscription.clone(), Feed { task, listeners: 1 });
+                false
+            }
+        };
+        drop(state);
+
+        // the symbol was subscribed once more than there are feeds for it
+        if unsubscribe && subscription.channel.needs_order_book() {
+            self.unsubscribe_symbol(subscription);
+        }
+
+        Ok(())
+    }
+
+    // Removes a listener, the feed stops once it had no listener for the linger time
+    fn release(&self, subscription: &SubscriptionQuery) {
+        let mut state = self.state.lock().unwrap();
+        let feed = match state.feeds.get_mut(subscription) {
+            Some(feed) => feed,
+            None => return,
+        };
+        feed.listeners = feed.listeners.saturating_sub(1);
+        if feed.listeners > 0 {
+            return;
+        }
+
+        let hub = self.clone();
+        let subscription = subscription.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(FEED_LINGER).await;
+
+            let mut state = hub.state.lock().unwrap();
+            if state
+                .feeds
+                .get(&subscription)
+                .is_some_and(|feed| feed.listeners == 0)
+            {
+                if let Some(feed) = state.feeds.remove(&subscription) {
+                    feed.task.abort();
+                }
+                drop(state);
+                if subscription.channel.needs_order_book() {
+                    hub.unsubscribe_symbol(&subscription);
+                }
+            }
+        });
+    }
+
+    fn unsubscribe_symbol(&self, subscription: &SubscriptionQuery) {
+        let app_layer = self.app_layer.clone();
+        let query = ApplicationQuery::UnsubscribeFromSymbol(Symbol(subscription.symbol.clone()));
+        tokio::spawn(async move { app_layer.handle_query(query).await });
+    }
+
+    fn publish(&self, subscription: &SubscriptionQuery, data: String) {
+        let mut state = self.state.lock().unwrap();
+        let event = Arc::new(ServerEvent {
+            id: state.next_id,
+            subscription: subscription.clone(),
+            data,
+        });
+        state.next_id += 1;
+        state.history.push_back(event.clone());
+        if state.history.len() > EVENT_HISTORY {
+            state.history.pop_front();
+        }
+
+        // events are sent while the state is locked so listeners starting at the same
+        // time see each event either in the history or from the channel
+        let _ = self.events.send(event);
+    }
+
+    // Kept events of the subscriptions after the given id with a receiver of the next ones
+    fn listen(
+        &self,
+        subscriptions: &BTreeSet<SubscriptionQuery>,
+        last_event_id: Option<u64>,
+    ) -> (
+        VecDeque<Arc<ServerEvent>>,
+        broadcast::Receiver<Arc<ServerEvent>>,
+    ) {
+        let state = self.state.lock().unwrap();
+        let missed = match last_event_id {
+            Some(last_event_id) => state
+                .history
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .filter(|event| subscriptions.contains(&event.subscription))
+                .cloned()
+                .collect(),
+            None => VecDeque::new(),
+        };
+
+        (missed, self.events.subscribe())
+    }
+}
+
+// Listeners of an event stream, released once the client is gone
+struct EventListener {
+    hub: EventHub,
+    subscriptions: BTreeSet<SubscriptionQuery>,
+}
+
+impl Drop for EventListener {
+    fn drop(&mut self) {
+        for subscription in self.subscriptions.iter() {
+            self.hub.release(subscription);
+        }
+    }
+}
+
+struct EventStream {
+    listener: EventListener,
+    missed: VecDeque<Arc<ServerEvent>>,
+    events: broadcast::Receiver<Arc<ServerEvent>>,
+    started: bool,
+    shutdown: ShutdownSignal,
+    // counted as connected until the response body is dropped
+    _client: ConnectedClient,
+}
+
+impl EventStream {
+    // Next chunk of the event stream, None once the hub is gone or the server shuts down
+    async fn next_chunk(&mut self) -> Option<String> {
+        if !self.started {
+            self.started = true;
+            return Some(format!("retry: {}\n\n", RECONNECT_RETRY_MS));
+        }
+        if let Some(event) = self.missed.pop_front() {
+            return Some(event.to_event_stream());
+        }
+
+        loop {
+            tokio::select! {
+                event = self.events.recv() => match event {
+                    Ok(event) if self.listener.subscriptions.contains(&event.subscription) => {
+                        return Some(event.to_event_stream());
+                    }
+                    Ok(_) => continue,
+                    // ending the stream makes the client reconnect and resume from the history
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "event stream lagged behind the subscriptions");
+                        self._client.metrics.messages_lagged("event_stream", skipped);
+                        return None;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = tokio::time::sleep(KEEP_ALIVE) => return Some(":keep-alive\n\n".into()),
+                // clients reconnect to another instance and resume from its history
+                _ = self.shutdown.wait() => return None,
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct EventsQuery {
+    // comma separated symbols
+    symbols: String,
+    // comma separated channels, the average price when not given
+    #[serde(default)]
+    channels: Option<String>,
+    #[serde(default)]
+    interval: Option<String>,
+}
+
+impl EventsQuery {
+    fn subscriptions(&self) -> Result<Vec<(SubscriptionQuery, Option<CandleInterval>)>> {
+        let channels = self.channels.as_deref().unwrap_or("average_price");
+        let mut subscriptions = Vec::new();
+
+        for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+            let channel = serde_json::from_value::<Channel>(json!(channel))
+                .map_err(|_| Error::msg(format!("unknown channel {}", channel)))?;
+
+            for symbol in self.symbols.split(',').filter(|symbol| !symbol.is_empty()) {
+                let subscription = SubscriptionQuery {
+                    channel,
+                    symbol: symbol.into(),
+                    interval: self.interval.clone(),
+                };
+                subscriptions.push(subscription.normalised()?);
+            }
+        }
+
+        if subscriptions.is_empty() {
+            return Err(Error::msg("no symbols"));
+        }
+        if subscriptions.len() > MAX_SUBSCRIPTIONS_PER_SOCKET {
+            return Err(Error::msg("too many subscriptions"));
+        }
+
+        Ok(subscriptions)
+    }
+}
+
+/*
+Server-sent events controller for clients that can not keep a websocket open. Streams
+the updates of the channels of the symbols in the query, the same as the subscriptions of
+the stream socket with the default throttle. Each event carries an id, and a client
+reconnecting with the Last-Event-ID header first gets the kept events it missed.
+*/
+#[handler]
+pub(super) async fn events_sse(
+    Query(query): Query<EventsQuery>,
+    headers: &HeaderMap,
+    Data(hub): Data<&EventHub>,
+    Data(shutdown): Data<&ShutdownSignal>,
+) -> Response {
+    let subscriptions = match query.subscriptions() {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => return rest_response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() })),
+    };
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse::<u64>().ok());
+
+    let mut listener = EventListener {
+        hub: hub.clone(),
+        subscriptions: BTreeSet::new(),
+    };
+    for (subscription, interval) in subscriptions {
+        if listener.subscriptions.contains(&subscription) {
+            continue;
+        }
+        if let Err(e) = hub.acquire(&subscription, interval).await {
+            warn!(symbol = %subscription.symbol, error = %e, "symbol could not be subscribed");
+            return rest_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "error": "symbol could not be subscribed", "symbol": subscription.symbol }),
+            );
+        }
+        listener.subscriptions.insert(subscription);
+    }
+
+    let (missed, events) = hub.listen(&listener.subscriptions, last_event_id);
+    let stream = EventStream {
+        listener,
+        missed,
+        events,
+        started: false,
+        shutdown: shutdown.clone(),
+        _client: ConnectedClient::new(&hub.metrics, "/api/events"),
+    };
+    let chunks = futures_util::stream::unfold(stream, |mut stream| async move {
+        let chunk = stream.next_chunk().await?;
+        Some((Ok::<String, std::io::Error>(chunk), stream))
+    });
+
+    Response::builder()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        // proxies buffering the response would hold events back
+        .header("X-Accel-Buffering", "no")
+        .body(Body::from_bytes_stream(chunks))
+}
+
+// Json body of a rest response with its status code
+fn rest_response(status: StatusCode, body: serde_json::Value) -> Response {
+    Json(body).with_status(status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::client_web_server::tests::{http_get, read_events, serve_mock_market};
+
+    #[tokio::test]
+    async fn test_event_stream_resumed_from_last_event_id() {
+        let port = serve_mock_market().await;
+        let path = "/api/events?symbols=btcusdc&channels=average_price";
+
+        let first = read_events(port, path, None, 2).await;
+        let (first_id, _) = first[0];
+        let resumed = read_events(port, path, Some(first_id), 1).await;
+        let invalid = http_get(port, "/api/events?symbols=btcusdc&channels=trades").await;
+
+        assert_eq!(
+            first[0].1,
+            json!({ "channel": "average_price", "symbol": "BTCUSDC", "data": { "v": "100.00" } })
+        );
+        // the event after the last one received is sent again from the history
+        assert_eq!(resumed[0], first[1]);
+        assert_eq!(invalid, (400, json!({ "error": "unknown channel trades" })));
+    }
+}
@@ -0,0 +1,591 @@
+use crate::{
+    core::BASIS_POINTS,
+    lifecycle::ShutdownSignal,
+    ports::{NoMetrics, SharedMetrics, WebServer, WebServerSettings},
+    typespec::{ApplicationLayer, Notional, OrderSize, Quantity},
+};
+use anyhow::{Error, Result};
+use poem::{
+    endpoint::{BoxEndpoint, StaticFilesEndpoint},
+    get,
+    listener::{Listener, TcpListener},
+    EndpointExt, Route, Server,
+};
+use poem_openapi::Object;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+mod api_docs;
+mod events;
+mod rest;
+mod stream_socket;
+
+use api_docs::DecimalText;
+use events::{events_sse, EventHub};
+use rest::rest_routes;
+use stream_socket::{
+    arbitrage_web_socket, average_price_web_socket, stream_web_socket,
+    triangular_arbitrage_web_socket,
+};
+
+pub struct ClientWebServer {
+    settings: WebServerSettings,
+    app_layer: ApplicationLayer,
+    shutdown: ShutdownSignal,
+    metrics: SharedMetrics,
+}
+
+impl ClientWebServer {
+    /*
+    On the signal the server stops accepting connections, sends a close frame to every
+    client websocket and ends the event streams, then returns once the open requests are
+    answered. Clients are expected to reconnect to another instance.
+    */
+    pub fn with_shutdown(mut self, shutdown: ShutdownSignal) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    // metrics the server reports its clients to and serves on /metrics
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+impl WebServer for ClientWebServer {
+    fn new(settings: WebServerSettings, app_layer: ApplicationLayer) -> Self {
+        Self {
+            settings,
+            app_layer,
+            shutdown: ShutdownSignal::never(),
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    async fn run_server(&self) -> Result<()> {
+        let static_files_location = Route::new().nest(
+            "/",
+            StaticFilesEndpoint::new(&self.settings.static_dir).index_file("index.html"),
+        );
+
+        let web_app = written_routes()
+            .into_iter()
+            .fold(rest_routes(), |routes, (path, endpoint)| {
+                routes.at(path, endpoint)
+            })
+            .nest("/", static_files_location)
+            .data(EventHub::new(self.app_layer.clone(), self.metrics.clone()))
+            .data(self.app_layer.clone())
+            .data(self.shutdown.clone())
+            .data(self.metrics.clone())
+            // boxed as the compiler fails to prove the nested endpoint types are Send
+            .boxed();
+
+        let acceptor = TcpListener::bind(format!(
+            "{}:{}",
+            self.settings.bind_address, self.settings.port
+        ))
+        .into_acceptor()
+        .await?;
+
+        let mut shutdown = self.shutdown.clone();
+        Server::new_with_acceptor(acceptor)
+            .run_with_graceful_shutdown(web_app, async move { shutdown.wait().await }, None)
+            .await
+            .map_err(Error::msg)
+    }
+}
+
+// level of a book the same way the sockets and the rest endpoints send it
+#[derive(Deserialize, Serialize, Object, Debug, Clone)]
+#[oai(rename = "Level")]
+struct LevelValue {
+    p: DecimalText,
+    q: DecimalText,
+}
+
+// a distance of 10000 bps from the mid price already holds every level of the bids
+const MAX_DEPTH_BPS: Decimal = BASIS_POINTS;
+
+// Basis points given by a client, None unless between 0 and MAX_DEPTH_BPS
+fn parse_bps(text: &str) -> Option<Decimal> {
+    text.parse::<Decimal>()
+        .ok()
+        .filter(|bps| bps.is_sign_positive() && *bps <= MAX_DEPTH_BPS)
+}
+
+// Size of a market order given by a client in either qty or notional, an order of
+// nothing would be reported as filled without taking any level
+fn parse_order_size(qty: Option<&str>, notional: Option<&str>) -> Result<OrderSize> {
+    match (qty, notional) {
+        (Some(qty), None) => match qty.parse::<Quantity>() {
+            Ok(qty) if qty.0 > Decimal::ZERO => Ok(OrderSize::Quantity(qty)),
+            Ok(_) => Err(Error::msg("order qty needs to be above 0")),
+            Err(_) => Err(Error::msg("invalid order qty")),
+        },
+        (None, Some(notional)) => match notional.parse::<Notional>() {
+            Ok(notional) if notional.0 > Decimal::ZERO => Ok(OrderSize::Notional(notional)),
+            Ok(_) => Err(Error::msg("order notional needs to be above 0")),
+            Err(_) => Err(Error::msg("invalid order notional")),
+        },
+        _ => Err(Error::msg("order needs either qty or notional")),
+    }
+}
+
+// Routes of the websockets and the event stream, their part of the open api document is
+// written out in api_docs
+fn written_routes() -> Vec<(&'static str, BoxEndpoint<'static>)> {
+    vec![
+        (
+            "/api/average_order_book_price",
+            get(average_price_web_socket).boxed(),
+        ),
+        ("/api/stream", get(stream_web_socket).boxed()),
+        ("/api/arbitrage", get(arbitrage_web_socket).boxed()),
+        (
+            "/api/triangular_arbitrage",
+            get(triangular_arbitrage_web_socket).boxed(),
+        ),
+        ("/api/events", get(events_sse).boxed()),
+    ]
+}
+
+// Counts a client as connected to an endpoint for as long as it is held
+struct ConnectedClient {
+    metrics: SharedMetrics,
+    endpoint: &'static str,
+}
+
+impl ConnectedClient {
+    fn new(metrics: &SharedMetrics, endpoint: &'static str) -> Self {
+        metrics.client_connected(endpoint);
+
+        Self {
+            metrics: metrics.clone(),
+            endpoint,
+        }
+    }
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.metrics.client_disconnected(self.endpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{api_docs::open_api_document, rest::rest_api, *};
+    use crate::{
+        adapters::{
+            mock_binance_server::{MockBinanceServer, MockStep},
+            BinanceDiffDepthStream, PrometheusMetrics,
+        },
+        application::Application,
+        lifecycle::Lifecycle,
+        ports::{DepthSnapshotSource, MarketStream},
+        typespec::{DepthSnapshot, Symbol},
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use std::{sync::Arc, time::Duration};
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    // snapshot the depth updates of the mock server follow on
+    struct FixedDepthSnapshot;
+
+    impl DepthSnapshotSource for FixedDepthSnapshot {
+        async fn fetch_snapshot(&self, _symbol: &Symbol) -> Result<DepthSnapshot> {
+            Ok(DepthSnapshot {
+                last_update_id: 100,
+                bids: vec![("99.25".parse()?, "1".parse()?)],
+                asks: vec![("100.75".parse()?, "1".parse()?)],
+            })
+        }
+    }
+
+    pub(super) async fn serve_mock_market() -> u16 {
+        serve_mock_market_until(ShutdownSignal::never()).await
+    }
+
+    // Serves a web server on a free port for a market that sends a depth update every 20ms
+    // once the symbol is subscribed
+    async fn serve_mock_market_until(shutdown: ShutdownSignal) -> u16 {
+        serve_mock_market_with(shutdown, NoMetrics::shared()).await
+    }
+
+    async fn serve_mock_market_with(shutdown: ShutdownSignal, metrics: SharedMetrics) -> u16 {
+        let app_layer = mock_market_app(metrics.clone()).await;
+
+        // port of the os is free again once the listener is dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let web_server = ClientWebServer::new(
+            WebServerSettings {
+                port,
+                ..Default::default()
+            },
+            app_layer,
+        )
+        .with_shutdown(shutdown)
+        .with_metrics(metrics);
+        tokio::spawn(async move { web_server.run_server().await });
+
+        // requests are only answered once the server listens
+        while tokio::net::TcpStream::connect(("localhost", port))
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        port
+    }
+
+    // Application kept in sync with a market that sends a depth update every 20ms once the
+    // symbol is subscribed
+    pub(super) async fn mock_market_app(metrics: SharedMetrics) -> ApplicationLayer {
+        let mut script = vec![
+            MockStep::AwaitRequest,
+            MockStep::Wait(Duration::from_millis(100)),
+        ];
+        for update_id in 101..=300 {
+            script.push(MockStep::DepthUpdate {
+                symbol: "BTCUSDC",
+                first_update_id: update_id,
+                final_update_id: update_id,
+                bids: vec![("99.25", "2")],
+                asks: vec![],
+            });
+            script.push(MockStep::Wait(Duration::from_millis(20)));
+        }
+        let market = MockBinanceServer::start(vec![script]).await;
+
+        let symbols = vec![Symbol("BTCUSDC".into())];
+        let connection = BinanceDiffDepthStream::with_base_url(market.base_url())
+            .with_metrics(metrics.clone())
+            .subscribe(symbols.clone())
+            .await
+            .unwrap();
+        let app_layer = Application::new(connection).with_metrics(metrics);
+
+        let order_book_sync = app_layer.clone();
+        tokio::spawn(async move {
+            // the mock server lives as long as the order book sync
+            let _market = market;
+            order_book_sync
+                .maintain_order_books(FixedDepthSnapshot, symbols)
+                .await
+        });
+
+        app_layer
+    }
+
+    type ClientSocket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    pub(super) async fn connect_web_socket(port: u16, path: &str) -> ClientSocket {
+        let url = format!("ws://localhost:{}{}", port, path);
+
+        loop {
+            match connect_async(url.as_str()).await {
+                Ok((socket, _)) => break socket,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    // Status code and json body of a get request on the web server
+    pub(super) async fn http_get(port: u16, path: &str) -> (u16, serde_json::Value) {
+        let (status, body) = http_get_text(port, path).await;
+
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    async fn http_get_text(port: u16, path: &str) -> (u16, String) {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+
+        let response = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Write};
+
+            let mut stream = std::net::TcpStream::connect(("localhost", port)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+        (status, body.to_string())
+    }
+
+    /*
+    Whether the value is valid against a schema of the open api document. Only covers
+    the keywords the document uses, and the only pattern it uses is the one of decimals.
+    */
+    pub(super) fn matches_schema(schema: &serde_json::Value, value: &serde_json::Value) -> bool {
+        use serde_json::Value;
+
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return matches_schema(&open_api_document()["components"]["schemas"][name], value);
+        }
+        if let Some(schemas) = schema["oneOf"].as_array() {
+            let matching = schemas.iter().filter(|s| matches_schema(s, value)).count();
+            return matching == 1;
+        }
+        if let Some(schemas) = schema["anyOf"].as_array() {
+            return schemas.iter().any(|s| matches_schema(s, value));
+        }
+        if let Some(schemas) = schema["allOf"].as_array() {
+            return schemas.iter().all(|s| matches_schema(s, value));
+        }
+        if let Some(constant) = schema.get("const") {
+            return constant == value;
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            return options.contains(value);
+        }
+
+        match (schema["type"].as_str(), value) {
+            (None, _) => true,
+            (Some("null"), Value::Null) | (Some("boolean"), Value::Bool(_)) => true,
+            (Some("integer"), Value::Number(number)) => number.is_u64(),
+            (Some("string"), Value::String(string)) => {
+                schema.get("pattern").is_none()
+                    || (string.parse::<Decimal>().is_ok() && !string.contains(['e', 'E']))
+            }
+            (Some("array"), Value::Array(items)) => items
+                .iter()
+                .all(|item| matches_schema(&schema["items"], item)),
+            (Some("object"), Value::Object(fields)) => {
+                let required = schema["required"].as_array().cloned().unwrap_or_default();
+                required
+                    .iter()
+                    .all(|name| fields.contains_key(name.as_str().unwrap()))
+                    && fields
+                        .iter()
+                        .all(|(name, field)| match schema["properties"].get(name) {
+                            Some(property) => matches_schema(property, field),
+                            None => match &schema["additionalProperties"] {
+                                Value::Bool(allowed) => *allowed,
+                                additional => matches_schema(additional, field),
+                            },
+                        })
+            }
+            _ => false,
+        }
+    }
+
+    // Schema of the json body of a response of the document, whatever its media type
+    fn body_schema(response: &serde_json::Value) -> &serde_json::Value {
+        let content = response["content"].as_object().unwrap();
+
+        content
+            .iter()
+            .find(|(media_type, _)| media_type.starts_with("application/json"))
+            .map(|(_, media)| &media["schema"])
+            .unwrap()
+    }
+
+    // Ids and data of the given number of events of an event stream
+    pub(super) async fn read_events(
+        port: u16,
+        path: &str,
+        last_event_id: Option<u64>,
+        count: usize,
+    ) -> Vec<(u64, serde_json::Value)> {
+        let last_event_id = match last_event_id {
+            Some(id) => format!("Last-Event-ID: {}\r\n", id),
+            None => String::new(),
+        };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            path, last_event_id
+        );
+
+        tokio::task::spawn_blocking(move || {
+            use std::io::{BufRead, BufReader, Write};
+
+            let mut stream = std::net::TcpStream::connect(("localhost", port)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut events = Vec::new();
+            let mut id = None;
+            // events are whole chunks of the body, so lines of chunk sizes sit between them
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                if let Some(value) = line.strip_prefix("id: ") {
+                    id = value.parse::<u64>().ok();
+                } else if let (Some(data), Some(event_id)) = (line.strip_prefix("data: "), id) {
+                    events.push((event_id, serde_json::from_str(data).unwrap()));
+                    if events.len() == count {
+                        break;
+                    }
+                }
+            }
+            events
+        })
+        .await
+        .unwrap()
+    }
+
+    pub(super) async fn next_json(socket: &mut ClientSocket) -> serde_json::Value {
+        let reply = socket.next().await.unwrap().unwrap().into_text().unwrap();
+
+        serde_json::from_str(reply.as_str()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_client_sockets_and_stops_accepting_connections() {
+        let lifecycle = Lifecycle::new(Duration::from_secs(5));
+        let port = serve_mock_market_until(lifecycle.signal()).await;
+        let mut socket = connect_web_socket(port, "/api/stream").await;
+
+        lifecycle.shutdown();
+
+        match socket.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(
+                    frame.code,
+                    tungstenite::protocol::frame::coding::CloseCode::Away
+                );
+                assert_eq!(frame.reason, "server shutting down");
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+
+        let stopped = tokio::time::timeout(Duration::from_secs(5), async {
+            while tokio::net::TcpStream::connect(("localhost", port))
+                .await
+                .is_ok()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(stopped.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_open_api_document_in_sync_with_routes() {
+        let port = serve_mock_market().await;
+        let mut socket = connect_web_socket(port, "/api/average_order_book_price").await;
+        socket
+            .send(tungstenite::Message::text(r#"{"p": "btcusdc"}"#))
+            .await
+            .unwrap();
+        next_json(&mut socket).await;
+        let document = open_api_document();
+
+        assert_eq!(
+            http_get(port, "/api/openapi.json").await,
+            (200, document.clone())
+        );
+        for (path, operation) in document["paths"].as_object().unwrap() {
+            let operation = &operation["get"];
+            if operation.get("x-websocket").is_some() {
+                // routes of the sockets upgrade the connection
+                drop(connect_web_socket(port, path).await);
+                continue;
+            }
+            let events = &operation["responses"]["200"]["content"]["text/event-stream"];
+            if !events.is_null() {
+                // event streams do not end, so only their first event is validated
+                let path = format!("{}?symbols=btcusdc", path);
+                let (_, data) = read_events(port, &path, None, 1).await.remove(0);
+                assert!(
+                    matches_schema(&events["schema"], &data),
+                    "{} {}",
+                    path,
+                    data
+                );
+                continue;
+            }
+
+            // the order estimate needs an order to estimate
+            let query = match path.ends_with("/order_estimate") {
+                true => "?side=buy&qty=1",
+                false => "",
+            };
+            let path = format!("{}{}", path.replace("{symbol}", "btcusdc"), query);
+            let (status, body) = http_get(port, &path).await;
+            let schema = body_schema(&operation["responses"][status.to_string()]);
+            assert!(
+                matches_schema(schema, &body),
+                "{} {} {}",
+                path,
+                status,
+                body
+            );
+        }
+        let (status, body) = http_get(port, "/api/symbols/ethusdc/book").await;
+        let responses = &document["paths"]["/api/symbols/{symbol}/book"]["get"]["responses"];
+        assert_eq!(status, 404);
+        assert!(matches_schema(body_schema(&responses["404"]), &body));
+
+        // every route is documented, the ones written out by hand as well as the derived ones
+        let rest: serde_json::Value = serde_json::from_str(&rest_api().spec()).unwrap();
+        let routed = written_routes()
+            .into_iter()
+            .map(|(path, _)| path.to_string())
+            .chain(rest["paths"].as_object().unwrap().keys().cloned());
+        for path in routed {
+            assert!(
+                document["paths"].get(&path).is_some(),
+                "{} undocumented",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics_served_in_text_exposition_format() {
+        let metrics: SharedMetrics = Arc::new(PrometheusMetrics::new().unwrap());
+        let port = serve_mock_market_with(ShutdownSignal::never(), metrics).await;
+        let mut socket = connect_web_socket(port, "/api/average_order_book_price").await;
+        socket
+            .send(tungstenite::Message::text(r#"{"p": "btcusdc"}"#))
+            .await
+            .unwrap();
+        next_json(&mut socket).await;
+
+        let (status, text) = http_get_text(port, "/metrics").await;
+
+        assert_eq!(status, 200);
+        for line in [
+            "orderbook_clients_connected{endpoint=\"/api/average_order_book_price\"} 1",
+            "orderbook_market_messages_received_total{stream=\"depth\",venue=\"binance\"}",
+            "orderbook_query_duration_seconds_count{query=\"GetAverageValueOfSymbol\"}",
+            "orderbook_update_delivery_seconds_count{channel=\"average_price\"}",
+        ] {
+            assert!(text.contains(line), "{} missing in {}", line, text);
+        }
+
+        // the client counts as disconnected once its socket is closed
+        socket.close(None).await.unwrap();
+        let disconnected =
+            "orderbook_clients_connected{endpoint=\"/api/average_order_book_price\"} 0";
+        for _ in 0..100 {
+            if http_get_text(port, "/metrics")
+                .await
+                .1
+                .contains(disconnected)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("client still counted as connected");
+    }
+}
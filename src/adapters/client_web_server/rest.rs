@@ -0,0 +1,622 @@
+use super::{
+    api_docs::{open_api_document, DecimalText, Nullable, API_DOCS_PAGE},
+    parse_bps, parse_order_size, LevelValue,
+};
+use crate::{
+    application::{ApplicationQuery, ApplicationResponse, OrderBookSyncState},
+    ports::{ConnectionState, SharedMetrics},
+    typespec::{ApplicationLayer, PriceLevel, Side, Symbol},
+};
+use anyhow::Result;
+use poem::{
+    get, handler,
+    http::StatusCode,
+    web::{Data, Html, Json},
+    IntoResponse, Response, Route,
+};
+use poem_openapi::{
+    param, payload, types::ToJSON, ApiResponse, Enum, Object, OpenApi, OpenApiService, Tags, Union,
+};
+use tracing::error;
+
+// levels of each side returned by the book endpoint unless the client asks otherwise
+const DEFAULT_BOOK_DEPTH: usize = 10;
+// the most levels binance returns for a depth snapshot
+const MAX_BOOK_DEPTH: usize = 5000;
+// basis points around the mid price the depth endpoint sums unless the client asks otherwise
+const DEFAULT_DEPTH_BPS: &str = "10";
+
+// Routes of the rest endpoints for queries on the local order books, the other routes of
+// the web server are added to them
+pub(super) fn rest_routes() -> Route {
+    Route::new()
+        .at("/metrics", get(metrics_rest))
+        .at("/api/openapi.json", get(open_api_rest))
+        .at("/api/docs", get(api_docs_rest))
+        .nest_no_strip("/api", rest_api())
+}
+
+// Service of the rest endpoints, the open api document of the web server is derived from it
+pub(super) fn rest_api() -> OpenApiService<RestApi, ()> {
+    OpenApiService::new(RestApi, "Order book api", env!("CARGO_PKG_VERSION")).description(
+        "Local order books of the market stream, their metrics and the arbitrage found \
+        between them",
+    )
+}
+
+#[handler]
+async fn metrics_rest(Data(metrics): Data<&SharedMetrics>) -> Response {
+    match metrics.render() {
+        Ok(text) => Response::builder()
+            .content_type("text/plain; version=0.0.4")
+            .body(text),
+        Err(e) => {
+            error!(error = %e, "metrics could not be rendered");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[handler]
+async fn open_api_rest() -> Json<serde_json::Value> {
+    Json(open_api_document())
+}
+
+#[handler]
+async fn api_docs_rest() -> Html<&'static str> {
+    Html(API_DOCS_PAGE)
+}
+
+#[derive(Tags)]
+#[oai(rename_all = "lowercase")]
+enum RestTag {
+    Rest,
+}
+
+/*
+Answers of the rest endpoints, the value asked for or an error. Every endpoint documents
+every error as the queries of the application can answer any of them.
+*/
+#[derive(ApiResponse)]
+enum RestResponse<T: ToJSON + Send + Sync> {
+    /// OK
+    #[oai(status = 200)]
+    Ok(payload::Json<T>),
+    /// Invalid parameters
+    #[oai(status = 400)]
+    BadRequest(payload::Json<ErrorValue>),
+    /// Unknown symbol
+    #[oai(status = 404)]
+    UnknownSymbol(payload::Json<ErrorValue>),
+    /// Order book not synced yet
+    #[oai(status = 503)]
+    OutOfSync(payload::Json<ErrorValue>),
+    /// Internal error
+    #[oai(status = 500)]
+    Internal(payload::Json<ErrorValue>),
+}
+
+impl<T: ToJSON + Send + Sync> RestResponse<T> {
+    fn ok(value: T) -> Self {
+        RestResponse::Ok(payload::Json(value))
+    }
+
+    fn bad_request(error: impl Into<String>) -> Self {
+        RestResponse::BadRequest(payload::Json(ErrorValue {
+            error: error.into(),
+            symbol: None,
+        }))
+    }
+
+    // Maps the answers of the application that are not the one asked for to an error
+    fn error(res: Result<ApplicationResponse>) -> Self {
+        let error = |error: &str, symbol: Option<Symbol>| {
+            payload::Json(ErrorValue {
+                error: error.into(),
+                symbol: symbol.map(|symbol| symbol.0),
+            })
+        };
+
+        match res {
+            Ok(ApplicationResponse::UnknownSymbol { symbol }) => {
+                RestResponse::UnknownSymbol(error("unknown symbol", Some(symbol)))
+            }
+            Ok(ApplicationResponse::OrderBookOutOfSync { symbol }) => {
+                RestResponse::OutOfSync(error("order book not synced", Some(symbol)))
+            }
+            Ok(_) => RestResponse::Internal(error("unexpected response", None)),
+            Err(e) => {
+                error!(error = %e, "query failed");
+                RestResponse::Internal(error("internal error", None))
+            }
+        }
+    }
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Error")]
+struct ErrorValue {
+    error: String,
+    #[oai(skip_serializing_if_is_none)]
+    symbol: Option<String>,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "ConnectionStatus")]
+struct ConnectionStatusValue {
+    market: ConnectionStateValue,
+}
+
+#[derive(Union, Debug)]
+#[oai(rename = "ConnectionState", discriminator_name = "state", one_of)]
+enum ConnectionStateValue {
+    #[oai(mapping = "connected")]
+    Connected(ConnectedValue),
+    #[oai(mapping = "disconnected")]
+    Disconnected(DisconnectedValue),
+    #[oai(mapping = "reconnecting")]
+    Reconnecting(ReconnectingValue),
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Connected")]
+struct ConnectedValue {}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Disconnected")]
+struct DisconnectedValue {
+    reason: String,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Reconnecting")]
+struct ReconnectingValue {
+    attempt: u32,
+    delay: DelayValue,
+}
+
+// a duration the way serde sends it on the sockets
+#[derive(Object, Debug)]
+#[oai(rename = "Delay")]
+struct DelayValue {
+    secs: u64,
+    nanos: u32,
+}
+
+impl From<ConnectionState> for ConnectionStateValue {
+    fn from(state: ConnectionState) -> Self {
+        match state {
+            ConnectionState::Connected => ConnectionStateValue::Connected(ConnectedValue {}),
+            ConnectionState::Disconnected { reason } => {
+                ConnectionStateValue::Disconnected(DisconnectedValue { reason })
+            }
+            ConnectionState::Reconnecting { attempt, delay } => {
+                ConnectionStateValue::Reconnecting(ReconnectingValue {
+                    attempt,
+                    delay: DelayValue {
+                        secs: delay.as_secs(),
+                        nanos: delay.subsec_nanos(),
+                    },
+                })
+            }
+        }
+    }
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "ActiveSymbols")]
+struct ActiveSymbolsValue {
+    symbols: Vec<SymbolStateValue>,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "SymbolState")]
+struct SymbolStateValue {
+    symbol: String,
+    state: SyncStateValue,
+}
+
+#[derive(Enum, Debug)]
+#[oai(rename = "SyncState", rename_all = "snake_case")]
+enum SyncStateValue {
+    Synced,
+    OutOfSync,
+}
+
+impl From<OrderBookSyncState> for SyncStateValue {
+    fn from(state: OrderBookSyncState) -> Self {
+        match state {
+            OrderBookSyncState::Synced => SyncStateValue::Synced,
+            OrderBookSyncState::OutOfSync => SyncStateValue::OutOfSync,
+        }
+    }
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "OrderBook")]
+struct OrderBookValue {
+    symbol: String,
+    last_update_id: u64,
+    bids: Vec<LevelValue>,
+    asks: Vec<LevelValue>,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "AveragePrice")]
+struct AveragePriceValue {
+    symbol: String,
+    price: Nullable<DecimalText>,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Spread")]
+struct SpreadRestValue {
+    symbol: String,
+    abs: Nullable<DecimalText>,
+    bps: Nullable<DecimalText>,
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "Depth")]
+struct DepthValue {
+    symbol: String,
+    bps: DecimalText,
+    b: Nullable<DecimalText>,
+    a: Nullable<DecimalText>,
+}
+
+// the same as the side of the orders the sockets estimate
+#[derive(Enum, Debug, Clone, Copy)]
+#[oai(rename = "Side", rename_all = "lowercase")]
+enum SideParam {
+    Buy,
+    Sell,
+}
+
+impl From<SideParam> for Side {
+    fn from(side: SideParam) -> Self {
+        match side {
+            SideParam::Buy => Side::Buy,
+            SideParam::Sell => Side::Sell,
+        }
+    }
+}
+
+#[derive(Object, Debug)]
+#[oai(rename = "MarketOrderEstimate")]
+struct MarketOrderEstimateValue {
+    symbol: String,
+    avg: Nullable<DecimalText>,
+    worst: Nullable<DecimalText>,
+    slip_bps: Nullable<DecimalText>,
+    levels: usize,
+    filled_qty: DecimalText,
+    filled_notional: DecimalText,
+    // false when the book is not deep enough to fill the whole order
+    sufficient: bool,
+}
+
+fn default_book_depth() -> usize {
+    DEFAULT_BOOK_DEPTH
+}
+
+fn default_depth_bps() -> String {
+    DEFAULT_DEPTH_BPS.into()
+}
+
+pub(super) struct RestApi;
+
+#[OpenApi(prefix_path = "/api", tag = "RestTag::Rest")]
+impl RestApi {
+    /// Connection state of the market stream
+    #[oai(path = "/status", method = "get")]
+    async fn connection_status(
+        &self,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<ConnectionStatusValue> {
+        match app_layer
+            .handle_query(ApplicationQuery::GetMarketConnectionState)
+            .await
+        {
+            Ok(ApplicationResponse::MarketConnectionState { state }) => {
+                RestResponse::ok(ConnectionStatusValue {
+                    market: state.into(),
+                })
+            }
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Symbols with a local order book and the sync state of each book
+    #[oai(path = "/symbols", method = "get")]
+    async fn active_symbols(
+        &self,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<ActiveSymbolsValue> {
+        match app_layer
+            .handle_query(ApplicationQuery::GetActiveSymbols)
+            .await
+        {
+            Ok(ApplicationResponse::ActiveSymbols { symbols }) => {
+                let symbols = symbols
+                    .into_iter()
+                    .map(|(symbol, state)| SymbolStateValue {
+                        symbol: symbol.0,
+                        state: state.into(),
+                    })
+                    .collect();
+                RestResponse::ok(ActiveSymbolsValue { symbols })
+            }
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Best levels of each side of the book
+    #[oai(path = "/symbols/:symbol/book", method = "get")]
+    async fn order_book(
+        &self,
+        /// Symbol of the market, case insensitive
+        symbol: param::Path<String>,
+        /// Number of levels of each side, 1 to 5000
+        #[oai(default = "default_book_depth")]
+        depth: param::Query<usize>,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<OrderBookValue> {
+        if depth.0 == 0 || depth.0 > MAX_BOOK_DEPTH {
+            return RestResponse::bad_request(format!(
+                "depth must be between 1 and {}",
+                MAX_BOOK_DEPTH
+            ));
+        }
+
+        let query = ApplicationQuery::GetOrderBookLevels {
+            symbol: Symbol(symbol.0.to_uppercase()),
+            depth: depth.0,
+        };
+        match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::OrderBookLevels {
+                symbol,
+                last_update_id,
+                bids,
+                asks,
+            }) => {
+                let levels = |levels: Vec<PriceLevel>| -> Vec<LevelValue> {
+                    levels
+                        .into_iter()
+                        .map(|(price, qty)| LevelValue {
+                            p: DecimalText::of(price),
+                            q: DecimalText::of(qty),
+                        })
+                        .collect()
+                };
+                RestResponse::ok(OrderBookValue {
+                    symbol: symbol.0,
+                    last_update_id,
+                    bids: levels(bids),
+                    asks: levels(asks),
+                })
+            }
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Average price of the book as it is now
+    #[oai(path = "/symbols/:symbol/average_price", method = "get")]
+    async fn average_price(
+        &self,
+        /// Symbol of the market, case insensitive
+        symbol: param::Path<String>,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<AveragePriceValue> {
+        let query = ApplicationQuery::GetCurrentAveragePrice(Symbol(symbol.0.to_uppercase()));
+
+        match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::CurrentAveragePriceForSymbol { symbol, price }) => {
+                RestResponse::ok(AveragePriceValue {
+                    symbol: symbol.0,
+                    price: Nullable(price.map(DecimalText::of)),
+                })
+            }
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Spread between the best bid and ask
+    #[oai(path = "/symbols/:symbol/spread", method = "get")]
+    async fn spread(
+        &self,
+        /// Symbol of the market, case insensitive
+        symbol: param::Path<String>,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<SpreadRestValue> {
+        let query = ApplicationQuery::GetSpread(Symbol(symbol.0.to_uppercase()));
+
+        match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::Spread {
+                symbol,
+                absolute,
+                relative_bps,
+            }) => RestResponse::ok(SpreadRestValue {
+                symbol: symbol.0,
+                abs: Nullable(absolute.map(DecimalText::of)),
+                bps: Nullable(relative_bps.map(DecimalText::of)),
+            }),
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Quantity of each side within basis points around the mid price
+    #[oai(path = "/symbols/:symbol/depth", method = "get")]
+    async fn depth(
+        &self,
+        /// Symbol of the market, case insensitive
+        symbol: param::Path<String>,
+        /// Basis points around the mid price, 0 to 10000
+        #[oai(default = "default_depth_bps")]
+        bps: param::Query<String>,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<DepthValue> {
+        let bps = match parse_bps(&bps.0) {
+            Some(bps) => bps,
+            None => return RestResponse::bad_request("invalid bps"),
+        };
+
+        let query = ApplicationQuery::GetDepthWithinBps {
+            symbol: Symbol(symbol.0.to_uppercase()),
+            bps,
+        };
+        match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::DepthWithinBps {
+                symbol,
+                bps,
+                bids,
+                asks,
+            }) => RestResponse::ok(DepthValue {
+                symbol: symbol.0,
+                bps: DecimalText::of(bps),
+                b: Nullable(bids.map(DecimalText::of)),
+                a: Nullable(asks.map(DecimalText::of)),
+            }),
+            res => RestResponse::error(res),
+        }
+    }
+
+    /// Fill of a market order of either qty or notional walking the book as it is now
+    #[oai(path = "/symbols/:symbol/order_estimate", method = "get")]
+    async fn order_estimate(
+        &self,
+        /// Symbol of the market, case insensitive
+        symbol: param::Path<String>,
+        /// Side of the order
+        side: param::Query<SideParam>,
+        /// Quantity of the base asset, above 0
+        qty: param::Query<Option<String>>,
+        /// Notional of the quote asset, above 0
+        notional: param::Query<Option<String>>,
+        app_layer: Data<&ApplicationLayer>,
+    ) -> RestResponse<MarketOrderEstimateValue> {
+        let size = match parse_order_size(qty.0.as_deref(), notional.0.as_deref()) {
+            Ok(size) => size,
+            Err(e) => return RestResponse::bad_request(e.to_string()),
+        };
+
+        let query = ApplicationQuery::EstimateMarketOrder {
+            symbol: Symbol(symbol.0.to_uppercase()),
+            side: side.0.into(),
+            size,
+        };
+        match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::MarketOrderEstimate {
+                symbol, estimate, ..
+            }) => RestResponse::ok(MarketOrderEstimateValue {
+                symbol: symbol.0,
+                avg: Nullable(estimate.average_price.map(DecimalText::of)),
+                worst: Nullable(estimate.worst_price.map(DecimalText::of)),
+                slip_bps: Nullable(estimate.slippage_bps.map(DecimalText::of)),
+                levels: estimate.levels_consumed,
+                filled_qty: DecimalText::of(estimate.filled_quantity),
+                filled_notional: DecimalText::of(estimate.filled_notional),
+                sufficient: estimate.sufficient_depth,
+            }),
+            res => RestResponse::error(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adapters::client_web_server::tests::{
+        connect_web_socket, http_get, next_json, serve_mock_market,
+    };
+    use futures_util::SinkExt;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    #[tokio::test]
+    async fn test_rest_endpoints_answer_with_status_of_book() {
+        let port = serve_mock_market().await;
+        // the mock market only sends updates once a client asked for the symbol
+        let mut socket = connect_web_socket(port, "/api/average_order_book_price").await;
+        socket
+            .send(tungstenite::Message::text(r#"{"p": "btcusdc"}"#))
+            .await
+            .unwrap();
+        next_json(&mut socket).await;
+
+        let book = loop {
+            match http_get(port, "/api/symbols/btcusdc/book?depth=1").await {
+                (200, book) => break book,
+                (status, _) => assert_eq!(status, 503),
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let unknown = http_get(port, "/api/symbols/ethusdc/spread").await;
+        let symbols = http_get(port, "/api/symbols").await;
+        // far past any level of the book, would overflow the distance from the mid price
+        let huge_bps = http_get(
+            port,
+            "/api/symbols/btcusdc/depth?bps=79228162514264337593543950",
+        )
+        .await;
+
+        assert_eq!(book["symbol"], "BTCUSDC");
+        assert_eq!(book["bids"], json!([{ "p": "99.25", "q": "2" }]));
+        assert_eq!(book["asks"], json!([{ "p": "100.75", "q": "1" }]));
+        assert_eq!(
+            unknown,
+            (
+                404,
+                json!({ "error": "unknown symbol", "symbol": "ETHUSDC" })
+            )
+        );
+        assert_eq!(
+            symbols,
+            (
+                200,
+                json!({ "symbols": [{ "symbol": "BTCUSDC", "state": "synced" }] })
+            )
+        );
+        assert_eq!(huge_bps, (400, json!({ "error": "invalid bps" })));
+    }
+
+    #[tokio::test]
+    async fn test_order_estimate_rejects_orders_of_nothing() {
+        let port = serve_mock_market().await;
+        let mut socket = connect_web_socket(port, "/api/average_order_book_price").await;
+        socket
+            .send(tungstenite::Message::text(r#"{"p": "btcusdc"}"#))
+            .await
+            .unwrap();
+        next_json(&mut socket).await;
+
+        let estimate = loop {
+            match http_get(port, "/api/symbols/btcusdc/order_estimate?side=buy&qty=1").await {
+                (200, estimate) => break estimate,
+                (status, _) => assert_eq!(status, 503),
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let zero = http_get(port, "/api/symbols/btcusdc/order_estimate?side=buy&qty=0").await;
+        let negative = http_get(
+            port,
+            "/api/symbols/btcusdc/order_estimate?side=sell&notional=-5",
+        )
+        .await;
+
+        assert_eq!(estimate["filled_qty"], "1");
+        assert_eq!(estimate["worst"], "100.75");
+        assert_eq!(estimate["sufficient"], true);
+        assert_eq!(
+            zero,
+            (400, json!({ "error": "order qty needs to be above 0" }))
+        );
+        assert_eq!(
+            negative,
+            (
+                400,
+                json!({ "error": "order notional needs to be above 0" })
+            )
+        );
+    }
+}
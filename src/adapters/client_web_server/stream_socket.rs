@@ -0,0 +1,1717 @@
+use super::{api_docs::DecimalText, parse_bps, parse_order_size, ConnectedClient, LevelValue};
+use crate::{
+    application::{ApplicationQuery, ApplicationResponse},
+    core::{ArbitrageOpportunity, Candle, ConsolidatedLevel},
+    lifecycle::ShutdownSignal,
+    ports::{ConnectionState, SharedMetrics, TraceContext},
+    typespec::{unix_millis, ApplicationLayer, CandleInterval, Notional, PriceLevel, Side, Symbol},
+};
+use anyhow::{Error, Result};
+use futures_util::{SinkExt, TryStreamExt};
+use opentelemetry::trace::SpanContext;
+use poem::{
+    handler,
+    web::{
+        websocket::{CloseCode, Message, WebSocket},
+        Data,
+    },
+    IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+use tracing::{debug, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// updates of a subscription are sent at most once per throttle unless the client asks otherwise
+pub(super) const DEFAULT_THROTTLE: Duration = Duration::from_millis(250);
+// the shortest throttle a client can ask for, every update costs a serialization and a frame
+pub(super) const MIN_THROTTLE: Duration = Duration::from_millis(50);
+// subscriptions a single socket can hold at once
+pub(super) const MAX_SUBSCRIPTIONS_PER_SOCKET: usize = 64;
+// updates waiting to be written to a socket, while it is full each subscription only keeps
+// its latest update so a client reading slowly gets fewer updates instead of the server
+// holding every update for it
+pub(super) const MAX_PENDING_UPDATES: usize = MAX_SUBSCRIPTIONS_PER_SOCKET;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct PairQuery {
+    #[serde(rename(serialize = "p", deserialize = "p"))]
+    pair: String,
+    // price metrics to send along with the average price
+    #[serde(
+        rename(serialize = "m", deserialize = "m"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    metrics: Option<MetricsQuery>,
+    // hypothetical market order to estimate along with the average price
+    #[serde(
+        rename(serialize = "o", deserialize = "o"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    order: Option<OrderQuery>,
+    // number of best levels of the book consolidated from every venue to send along
+    #[serde(
+        rename(serialize = "c", deserialize = "c"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    consolidated: Option<usize>,
+}
+
+// an order is sized by either qty in the base asset or notional in the quote asset
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OrderQuery {
+    side: SideValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    qty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notional: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum SideValue {
+    Buy,
+    Sell,
+}
+
+impl From<SideValue> for Side {
+    fn from(side: SideValue) -> Self {
+        match side {
+            SideValue::Buy => Side::Buy,
+            SideValue::Sell => Side::Sell,
+        }
+    }
+}
+
+impl From<Side> for SideValue {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => SideValue::Buy,
+            Side::Sell => SideValue::Sell,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(super) struct MetricsQuery {
+    // number of best levels of each side used for the vwap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    levels: Option<usize>,
+    // notional in the quote asset to fill against each side
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notional: Option<String>,
+    // basis points around the mid price the depth of each side is summed within
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bps: Option<String>,
+    // numbers of best levels the imbalance is calculated at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depths: Option<Vec<usize>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct PairValue<'v> {
+    #[serde(rename(serialize = "p", deserialize = "p"))]
+    pair: String,
+    #[serde(rename(serialize = "v", deserialize = "v"))]
+    value: &'v str,
+    #[serde(
+        rename(serialize = "m", deserialize = "m"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    metrics: Option<MetricsValue>,
+    #[serde(
+        rename(serialize = "o", deserialize = "o"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    order: Option<OrderEstimateValue>,
+    #[serde(
+        rename(serialize = "c", deserialize = "c"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    consolidated: Option<ConsolidatedValue>,
+}
+
+// consolidated book with the venues it was merged from, like binance:BTCUSDT
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ConsolidatedValue {
+    venues: Vec<String>,
+    b: Vec<ConsolidatedLevelValue>,
+    a: Vec<ConsolidatedLevelValue>,
+}
+
+// level with the quantity of each venue quoting its price
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ConsolidatedLevelValue {
+    p: String,
+    q: String,
+    v: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OrderEstimateValue {
+    // average fill price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    worst: Option<String>,
+    // slippage against the mid price in basis points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slip_bps: Option<String>,
+    levels: usize,
+    filled_qty: String,
+    filled_notional: String,
+    // false when the book is not deep enough to fill the whole order
+    sufficient: bool,
+}
+
+// values of metrics that could not be calculated, like a mid price of an empty book, are left out
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct MetricsValue {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    micro: Option<String>,
+    // quantity weighted average price of the whole book
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    qwap: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vwap: Option<SidesValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fill: Option<SidesValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bid: Option<LevelValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ask: Option<LevelValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spread: Option<SpreadValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depth: Option<SidesValue>,
+    // imbalance keyed by the number of best levels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imbalance: Option<BTreeMap<usize, String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct SpreadValue {
+    abs: String,
+    bps: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct SidesValue {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    b: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    a: Option<String>,
+}
+
+// Queries the price metrics asked for along with the average price of a pair
+async fn price_metrics(
+    app_layer: &ApplicationLayer,
+    symbol: &Symbol,
+    query: &MetricsQuery,
+) -> Result<MetricsValue> {
+    let notional = match &query.notional {
+        Some(notional) => Some(
+            notional
+                .parse::<Notional>()
+                .map_err(|_| Error::msg("invalid notional"))?,
+        ),
+        None => None,
+    };
+    let bps = match &query.bps {
+        Some(bps) => Some(parse_bps(bps).ok_or_else(|| Error::msg("invalid bps"))?),
+        None => None,
+    };
+
+    let price = |res: Result<ApplicationResponse>| match res {
+        Ok(ApplicationResponse::MidPrice { price, .. })
+        | Ok(ApplicationResponse::Microprice { price, .. })
+        | Ok(ApplicationResponse::QuantityWeightedAveragePrice { price, .. }) => {
+            price.map(|price| price.to_string())
+        }
+        _ => None,
+    };
+    let sides = |res: Result<ApplicationResponse>| match res {
+        Ok(ApplicationResponse::VwapOfTopLevels { bids, asks, .. })
+        | Ok(ApplicationResponse::VwapToFillNotional { bids, asks, .. }) => Some(SidesValue {
+            b: bids.map(|price| price.to_string()),
+            a: asks.map(|price| price.to_string()),
+        }),
+        _ => None,
+    };
+
+    let mut metrics = MetricsValue {
+        mid: price(
+            app_layer
+                .handle_query(ApplicationQuery::GetMidPrice(symbol.clone()))
+                .await,
+        ),
+        micro: price(
+            app_layer
+                .handle_query(ApplicationQuery::GetMicroprice(symbol.clone()))
+                .await,
+        ),
+        qwap: price(
+            app_layer
+                .handle_query(ApplicationQuery::GetQuantityWeightedAveragePrice(
+                    symbol.clone(),
+                ))
+                .await,
+        ),
+        ..Default::default()
+    };
+
+    if let Some(depth) = query.levels {
+        let query = ApplicationQuery::GetVwapOfTopLevels {
+            symbol: symbol.clone(),
+            depth,
+        };
+        metrics.vwap = sides(app_layer.handle_query(query).await);
+    }
+
+    if let Some(notional) = notional {
+        let query = ApplicationQuery::GetVwapToFillNotional {
+            symbol: symbol.clone(),
+            notional,
+        };
+        metrics.fill = sides(app_layer.handle_query(query).await);
+    }
+
+    if let Ok(ApplicationResponse::BestBidAsk { bid, ask, .. }) = app_layer
+        .handle_query(ApplicationQuery::GetBestBidAsk(symbol.clone()))
+        .await
+    {
+        let level = |(price, qty): PriceLevel| LevelValue {
+            p: DecimalText::of(price),
+            q: DecimalText::of(qty),
+        };
+        metrics.bid = bid.map(level);
+        metrics.ask = ask.map(level);
+    }
+
+    if let Ok(ApplicationResponse::Spread {
+        absolute: Some(absolute),
+        relative_bps: Some(relative_bps),
+        ..
+    }) = app_layer
+        .handle_query(ApplicationQuery::GetSpread(symbol.clone()))
+        .await
+    {
+        metrics.spread = Some(SpreadValue {
+            abs: absolute.to_string(),
+            bps: relative_bps.to_string(),
+        });
+    }
+
+    if let Some(bps) = bps {
+        let query = ApplicationQuery::GetDepthWithinBps {
+            symbol: symbol.clone(),
+            bps,
+        };
+        if let Ok(ApplicationResponse::DepthWithinBps { bids, asks, .. }) =
+            app_layer.handle_query(query).await
+        {
+            metrics.depth = Some(SidesValue {
+                b: bids.map(|qty| qty.to_string()),
+                a: asks.map(|qty| qty.to_string()),
+            });
+        }
+    }
+
+    if let Some(depths) = &query.depths {
+        let query = ApplicationQuery::GetImbalance {
+            symbol: symbol.clone(),
+            depths: depths.clone(),
+        };
+        if let Ok(ApplicationResponse::Imbalance { imbalances, .. }) =
+            app_layer.handle_query(query).await
+        {
+            metrics.imbalance = Some(
+                imbalances
+                    .into_iter()
+                    .filter_map(|(depth, imbalance)| {
+                        imbalance.map(|imbalance| (depth, imbalance.to_string()))
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    Ok(metrics)
+}
+
+// Estimates the fill of a market order asked for along with the average price of a pair
+async fn order_estimate(
+    app_layer: &ApplicationLayer,
+    symbol: &Symbol,
+    query: &OrderQuery,
+) -> Result<Option<OrderEstimateValue>> {
+    let size = parse_order_size(query.qty.as_deref(), query.notional.as_deref())?;
+
+    let query = ApplicationQuery::EstimateMarketOrder {
+        symbol: symbol.clone(),
+        side: query.side.into(),
+        size,
+    };
+
+    let estimate = match app_layer.handle_query(query).await? {
+        ApplicationResponse::MarketOrderEstimate { estimate, .. } => estimate,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(OrderEstimateValue {
+        avg: estimate.average_price.map(|price| price.to_string()),
+        worst: estimate.worst_price.map(|price| price.to_string()),
+        slip_bps: estimate.slippage_bps.map(|bps| bps.to_string()),
+        levels: estimate.levels_consumed,
+        filled_qty: estimate.filled_quantity.to_string(),
+        filled_notional: estimate.filled_notional.to_string(),
+        sufficient: estimate.sufficient_depth,
+    }))
+}
+
+// opportunities of a pair between venues and the time they were detected at
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ArbitrageValue {
+    p: String,
+    // milliseconds since the unix epoch
+    t: u64,
+    o: Vec<OpportunityValue>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OpportunityValue {
+    buy: String,
+    sell: String,
+    // best ask of the buy venue and best bid of the sell venue
+    buy_p: String,
+    sell_p: String,
+    edge_bps: String,
+    qty: String,
+    profit: String,
+}
+
+impl From<ArbitrageOpportunity> for OpportunityValue {
+    fn from(opportunity: ArbitrageOpportunity) -> Self {
+        Self {
+            buy: opportunity.buy_venue.0,
+            sell: opportunity.sell_venue.0,
+            buy_p: opportunity.buy_price.to_string(),
+            sell_p: opportunity.sell_price.to_string(),
+            edge_bps: opportunity.edge_bps.to_string(),
+            qty: opportunity.quantity.to_string(),
+            profit: opportunity.profit.to_string(),
+        }
+    }
+}
+
+// round trips around the configured triangles and the time they were detected at
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct TriangularArbitrageValue {
+    // milliseconds since the unix epoch
+    t: u64,
+    r: Vec<RoundTripValue>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct RoundTripValue {
+    start: String,
+    legs: Vec<LegValue>,
+    rate: String,
+    profit_bps: String,
+    // largest amount of the start asset the best levels can take
+    max: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct LegValue {
+    s: String,
+    side: SideValue,
+    p: String,
+}
+
+/*
+DTOs of the streaming protocol. A client subscribes to a channel of a symbol and is pushed
+an update each time the data of the channel changes, at most once per throttle. Requests
+carry an id the answer echoes so clients can match them.
+
+{"op": "subscribe", "id": 1, "channel": "metrics", "symbol": "BTCUSDC", "throttle_ms": 500, "metrics": {...}}
+{"op": "unsubscribe", "id": 2, "channel": "metrics", "symbol": "BTCUSDC"}
+*/
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum StreamRequest {
+    Subscribe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        #[serde(flatten)]
+        subscription: SubscriptionQuery,
+        // minimum milliseconds between two updates, at least MIN_THROTTLE
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        throttle_ms: Option<u64>,
+        // price metrics sent by the metrics channel
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metrics: Option<MetricsQuery>,
+    },
+    Unsubscribe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        #[serde(flatten)]
+        subscription: SubscriptionQuery,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Channel {
+    AveragePrice,
+    Metrics,
+    Candles,
+    Arbitrage,
+}
+
+impl Channel {
+    // channels calculated from the order book of the symbol on the market stream
+    pub(super) fn needs_order_book(&self) -> bool {
+        matches!(self, Channel::AveragePrice | Channel::Metrics)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::AveragePrice => "average_price",
+            Channel::Metrics => "metrics",
+            Channel::Candles => "candles",
+            Channel::Arbitrage => "arbitrage",
+        }
+    }
+}
+
+// A socket holds at most one subscription of each channel, symbol and interval
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct SubscriptionQuery {
+    pub(super) channel: Channel,
+    pub(super) symbol: String,
+    // length of the candles of the candles channel, like 1m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) interval: Option<String>,
+}
+
+impl SubscriptionQuery {
+    // symbols are upper case and intervals written the same way however the client sent them
+    pub(super) fn normalised(self) -> Result<(Self, Option<CandleInterval>)> {
+        let interval = match (self.channel, &self.interval) {
+            (Channel::Candles, Some(interval)) => Some(interval.parse::<CandleInterval>()?),
+            (Channel::Candles, None) => return Err(Error::msg("candles need an interval")),
+            _ => None,
+        };
+
+        Ok((
+            Self {
+                symbol: self.symbol.to_uppercase(),
+                interval: interval.map(|interval| interval.to_string()),
+                ..self
+            },
+            interval,
+        ))
+    }
+}
+
+// answer to a request, errors of a subscription are also sent this way when it ends
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct StreamResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    // subscribed, unsubscribed or error
+    status: String,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    subscription: Option<SubscriptionQuery>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl StreamResponse {
+    fn status(id: Option<u64>, status: &str, subscription: SubscriptionQuery) -> Self {
+        Self {
+            id,
+            status: status.into(),
+            subscription: Some(subscription),
+            error: None,
+        }
+    }
+
+    fn error(id: Option<u64>, subscription: Option<SubscriptionQuery>, error: String) -> Self {
+        Self {
+            id,
+            status: "error".into(),
+            subscription,
+            error: Some(error),
+        }
+    }
+}
+
+// update pushed for a subscription, without data while the book of the symbol is out of sync
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct StreamUpdate {
+    #[serde(flatten)]
+    subscription: SubscriptionQuery,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+}
+
+// candle of the trades within an interval starting at t, milliseconds since the unix epoch
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct CandleValue {
+    t: u64,
+    o: String,
+    h: String,
+    l: String,
+    c: String,
+    v: String,
+    // volume in the quote asset
+    qv: String,
+    vwap: String,
+    // number of trades
+    n: u64,
+}
+
+impl From<Candle> for CandleValue {
+    fn from(candle: Candle) -> Self {
+        Self {
+            t: candle.open_time,
+            o: candle.open.to_string(),
+            h: candle.high.to_string(),
+            l: candle.low.to_string(),
+            c: candle.close.to_string(),
+            v: candle.volume.to_string(),
+            qv: candle.quote_volume.to_string(),
+            vwap: candle.vwap.to_string(),
+            n: candle.trades,
+        }
+    }
+}
+
+// Queries the consolidated book asked for along with the average price of a pair.
+// None when no venue has a synced book of the pair
+async fn consolidated_book(
+    app_layer: &ApplicationLayer,
+    symbol: &Symbol,
+    depth: usize,
+) -> Option<ConsolidatedValue> {
+    let query = ApplicationQuery::GetConsolidatedBook {
+        symbol: symbol.clone(),
+        depth,
+    };
+
+    let (venues, book) = match app_layer.handle_query(query).await {
+        Ok(ApplicationResponse::ConsolidatedBook { venues, book, .. }) => (venues, book),
+        _ => return None,
+    };
+
+    let levels = |levels: Vec<ConsolidatedLevel>| -> Vec<ConsolidatedLevelValue> {
+        levels
+            .into_iter()
+            .map(|level| ConsolidatedLevelValue {
+                p: level.price.to_string(),
+                q: level.quantity.to_string(),
+                v: level
+                    .venues
+                    .into_iter()
+                    .map(|(venue, qty)| (venue.0, qty.to_string()))
+                    .collect(),
+            })
+            .collect()
+    };
+
+    Some(ConsolidatedValue {
+        venues: venues
+            .into_iter()
+            .map(|(venue, symbol)| format!("{}:{}", venue.0, symbol.0))
+            .collect(),
+        b: levels(book.bids),
+        a: levels(book.asks),
+    })
+}
+
+// Controllers
+
+// Websocket controller to display main information
+#[handler]
+pub(super) async fn average_price_web_socket(
+    ws: WebSocket,
+    Data(app_layer): Data<&ApplicationLayer>,
+    Data(shutdown): Data<&ShutdownSignal>,
+    Data(service_metrics): Data<&SharedMetrics>,
+) -> impl IntoResponse {
+    // clones pointer within function to avoid compile time errors
+    let app_layer = app_layer.clone();
+    let mut shutdown = shutdown.clone();
+    let service_metrics = service_metrics.clone();
+
+    ws.on_upgrade(|mut socket| async move {
+        let _client = ConnectedClient::new(&service_metrics, "/api/average_order_book_price");
+        // symbols this socket subscribed to, released when the socket closes
+        let mut subscribed_symbols: BTreeSet<Symbol> = BTreeSet::new();
+
+        // loop is needed to loop through all frames for the socket
+        loop {
+            let frame = tokio::select! {
+                frame = socket.try_next() => frame,
+                _ = shutdown.wait() => {
+                    let _ = socket.send(shutdown_close_message()).await;
+                    break;
+                }
+            };
+
+            match frame {
+                Ok(Some(Message::Text(msg))) => {
+                    let pair = serde_json::from_str::<PairQuery>(msg.clone().as_str());
+
+                    if let Ok(dto) = pair {
+                        // market symbols are upper case
+                        let symbol = Symbol(dto.pair.to_uppercase());
+
+                        // first query of a pair subscribes it on the market stream
+                        if !subscribed_symbols.contains(&symbol) {
+                            let query = ApplicationQuery::SubscribeToSymbol(symbol.clone());
+
+                            if let Err(e) = app_layer.handle_query(query).await {
+                                warn!(symbol = %symbol.0, error = %e, "pair could not be subscribed");
+                                let close_message = Message::close_with(
+                                    CloseCode::Error,
+                                    "pair could not be subscribed",
+                                );
+                                let _ = socket.send(close_message).await;
+                                break;
+                            }
+                            subscribed_symbols.insert(symbol.clone());
+                        }
+
+                        let app_layer_res = {
+                            let query = ApplicationQuery::GetAverageValueOfSymbol(symbol);
+
+                            app_layer.handle_query(query).await
+                        };
+
+                        match app_layer_res {
+                            Ok(ApplicationResponse::CurrentAveragePriceForSymbol {
+                                symbol,
+                                price,
+                            }) => {
+                                //serialize value and return message to client
+                                let price = match price {
+                                    Some(price) => price.to_string(),
+                                    None => "None".into(),
+                                };
+                                let metrics = match &dto.metrics {
+                                    Some(query) => {
+                                        match price_metrics(&app_layer, &symbol, query).await {
+                                            Ok(metrics) => Some(metrics),
+                                            Err(e) => {
+                                                let close_message = Message::close_with(
+                                                    CloseCode::Invalid,
+                                                    e.to_string(),
+                                                );
+                                                let _ = socket.send(close_message).await;
+                                                break;
+                                            }
+                                        }
+                                    }
+                                    None => None,
+                                };
+                                let order = match &dto.order {
+                                    Some(query) => {
+                                        match order_estimate(&app_layer, &symbol, query).await {
+                                            Ok(order) => order,
+                                            Err(e) => {
+                                                let close_message = Message::close_with(
+                                                    CloseCode::Invalid,
+                                                    e.to_string(),
+                                                );
+                                                let _ = socket.send(close_message).await;
+                                                break;
+                                            }
+                                        }
+                                    }
+                                    None => None,
+                                };
+                                let consolidated = match dto.consolidated {
+                                    Some(depth) => {
+                                        consolidated_book(&app_layer, &symbol, depth).await
+                                    }
+                                    None => None,
+                                };
+                                let pv = PairValue {
+                                    pair: symbol.0.to_string(),
+                                    value: price.as_str(),
+                                    metrics,
+                                    order,
+                                    consolidated,
+                                };
+                                let json_res = serde_json::to_string(&pv).unwrap();
+                                let res = Message::text(json_res);
+                                let delivery =
+                                    Delivery::start(&app_layer, Channel::AveragePrice, &symbol)
+                                        .await;
+                                let sent = socket.send(res).instrument(delivery.span.clone()).await;
+                                if sent.is_ok() {
+                                    delivery.sent(&service_metrics);
+                                }
+                            }
+                            Ok(ApplicationResponse::InfrastructureConnected) => {
+                                let res = Message::text("{\"msg\": \"Market connected\"}");
+                                let _ = socket.send(res).await;
+                            }
+                            Ok(ApplicationResponse::OrderBookOutOfSync { .. }) => {
+                                // books are out of sync while the market is disconnected
+                                let state = app_layer
+                                    .handle_query(ApplicationQuery::GetMarketConnectionState)
+                                    .await;
+
+                                let res = match state {
+                                    Ok(ApplicationResponse::MarketConnectionState {
+                                        state: ConnectionState::Disconnected { reason },
+                                    }) => Message::text(
+                                        json!({ "msg": "Market disconnected", "reason": reason })
+                                            .to_string(),
+                                    ),
+                                    Ok(ApplicationResponse::MarketConnectionState {
+                                        state: ConnectionState::Reconnecting { attempt, delay },
+                                    }) => Message::text(
+                                        json!({
+                                            "msg": "Market reconnecting",
+                                            "attempt": attempt,
+                                            "delay_ms": delay.as_millis() as u64,
+                                        })
+                                        .to_string(),
+                                    ),
+                                    _ => Message::text("{\"msg\": \"Order book out of sync\"}"),
+                                };
+                                let _ = socket.send(res).await;
+                            }
+                            _ => {
+                                let close_message =
+                                    Message::close_with(CloseCode::Error, "Internal server error");
+                                let _ = socket.send(close_message).await;
+                                break;
+                            }
+                        }
+                    } else {
+                        let close_message =
+                            Message::close_with(CloseCode::Error, "Internal server error");
+                        let _ = socket.send(close_message).await;
+                        break;
+                    }
+                }
+                Ok(Some(_)) => {
+                    let close_message =
+                        Message::close_with(CloseCode::Unsupported, "unsupported message type");
+                    let _ = socket.send(close_message).await;
+                    break;
+                }
+                Ok(None) => {
+                    let close_message = Message::close_with(CloseCode::Normal, "connection killed");
+                    let _ = socket.send(close_message).await;
+                    break;
+                }
+                Err(e) => {
+                    warn!(error = %e, "average price websocket failed");
+                    let close_message = Message::close_with(CloseCode::Error, "error with socket");
+                    let _ = socket.send(close_message).await;
+                    break;
+                }
+            }
+        }
+
+        for symbol in subscribed_symbols {
+            let _ = app_layer
+                .handle_query(ApplicationQuery::UnsubscribeFromSymbol(symbol))
+                .await;
+        }
+    })
+}
+
+/*
+Websocket controller pushing the arbitrage opportunities of a pair between the venues.
+The client names the pair once, {"p": "BTCUSD"}, and is sent the opportunities each time
+they change while books of the pair change on any venue.
+*/
+#[handler]
+pub(super) async fn arbitrage_web_socket(
+    ws: WebSocket,
+    Data(app_layer): Data<&ApplicationLayer>,
+    Data(shutdown): Data<&ShutdownSignal>,
+    Data(service_metrics): Data<&SharedMetrics>,
+) -> impl IntoResponse {
+    let app_layer = app_layer.clone();
+    let mut shutdown = shutdown.clone();
+    let service_metrics = service_metrics.clone();
+
+    ws.on_upgrade(|mut socket| async move {
+        let _client = ConnectedClient::new(&service_metrics, "/api/arbitrage");
+        let symbol = match socket.try_next().await {
+            Ok(Some(Message::Text(msg))) => match serde_json::from_str::<PairQuery>(&msg) {
+                Ok(dto) => Symbol(dto.pair.to_uppercase()),
+                Err(_) => {
+                    let close_message = Message::close_with(CloseCode::Invalid, "invalid pair");
+                    let _ = socket.send(close_message).await;
+                    return;
+                }
+            },
+            _ => return,
+        };
+        let mut last_sent: Option<Vec<OpportunityValue>> = None;
+
+        loop {
+            let query = ApplicationQuery::WaitForArbitrageOpportunities(symbol.clone());
+
+            tokio::select! {
+                msg = socket.try_next() => match msg {
+                    Ok(Some(Message::Close(_))) | Ok(None) | Err(_) => break,
+                    _ => continue,
+                },
+                _ = shutdown.wait() => {
+                    let _ = socket.send(shutdown_close_message()).await;
+                    break;
+                }
+                res = app_layer.handle_query(query) => match res {
+                    Ok(ApplicationResponse::ArbitrageOpportunities {
+                        symbol,
+                        detected_at,
+                        opportunities,
+                    }) => {
+                        let opportunities: Vec<OpportunityValue> =
+                            opportunities.into_iter().map(OpportunityValue::from).collect();
+
+                        // books change far more often than the opportunities between them
+                        let unchanged = last_sent.as_ref().is_some_and(|last| {
+                            serde_json::to_value(last).ok()
+                                == serde_json::to_value(&opportunities).ok()
+                        });
+                        if unchanged || (last_sent.is_none() && opportunities.is_empty()) {
+                            continue;
+                        }
+
+                        let value = ArbitrageValue {
+                            p: symbol.0,
+                            t: detected_at,
+                            o: opportunities.clone(),
+                        };
+                        let _ = socket
+                            .send(Message::text(serde_json::to_string(&value).unwrap()))
+                            .await;
+                        last_sent = Some(opportunities);
+                    }
+                    Ok(ApplicationResponse::OrderBookOutOfSync { .. }) => continue,
+                    Ok(ApplicationResponse::UnknownSymbol { .. }) => {
+                        let close_message = Message::close_with(CloseCode::Invalid, "unknown pair");
+                        let _ = socket.send(close_message).await;
+                        break;
+                    }
+                    _ => {
+                        let close_message =
+                            Message::close_with(CloseCode::Error, "Internal server error");
+                        let _ = socket.send(close_message).await;
+                        break;
+                    }
+                },
+            }
+        }
+    })
+}
+
+/*
+Websocket controller pushing the round trips around the configured triangles that clear
+the arbitrage threshold. Nothing has to be sent by the client, round trips are sent each
+time they change while books of the symbols of the triangles change.
+*/
+#[handler]
+pub(super) async fn triangular_arbitrage_web_socket(
+    ws: WebSocket,
+    Data(app_layer): Data<&ApplicationLayer>,
+    Data(shutdown): Data<&ShutdownSignal>,
+    Data(service_metrics): Data<&SharedMetrics>,
+) -> impl IntoResponse {
+    let app_layer = app_layer.clone();
+    let mut shutdown = shutdown.clone();
+    let service_metrics = service_metrics.clone();
+
+    ws.on_upgrade(|mut socket| async move {
+        let _client = ConnectedClient::new(&service_metrics, "/api/triangular_arbitrage");
+        let mut last_sent: Option<Vec<RoundTripValue>> = None;
+
+        loop {
+            tokio::select! {
+                msg = socket.try_next() => match msg {
+                    Ok(Some(Message::Close(_))) | Ok(None) | Err(_) => break,
+                    _ => continue,
+                },
+                _ = shutdown.wait() => {
+                    let _ = socket.send(shutdown_close_message()).await;
+                    break;
+                }
+                res = app_layer.handle_query(ApplicationQuery::WaitForTriangularArbitrage) => match res {
+                    Ok(ApplicationResponse::TriangularArbitrage {
+                        detected_at,
+                        round_trips,
+                    }) => {
+                        let round_trips: Vec<RoundTripValue> = round_trips
+                            .into_iter()
+                            .map(|round_trip| RoundTripValue {
+                                start: round_trip.start_asset,
+                                legs: round_trip
+                                    .legs
+                                    .into_iter()
+                                    .map(|leg| LegValue {
+                                        s: leg.symbol.0,
+                                        side: leg.side.into(),
+                                        p: leg.price.to_string(),
+                                    })
+                                    .collect(),
+                                rate: round_trip.rate.to_string(),
+                                profit_bps: round_trip.profit_bps.to_string(),
+                                max: round_trip.max_start_amount.to_string(),
+                            })
+                            .collect();
+
+                        let unchanged = last_sent.as_ref().is_some_and(|last| {
+                            serde_json::to_value(last).ok()
+                                == serde_json::to_value(&round_trips).ok()
+                        });
+                        if unchanged || (last_sent.is_none() && round_trips.is_empty()) {
+                            continue;
+                        }
+
+                        let value = TriangularArbitrageValue {
+                            t: detected_at,
+                            r: round_trips.clone(),
+                        };
+                        let _ = socket
+                            .send(Message::text(serde_json::to_string(&value).unwrap()))
+                            .await;
+                        last_sent = Some(round_trips);
+                    }
+                    _ => {
+                        let close_message =
+                            Message::close_with(CloseCode::Error, "Internal server error");
+                        let _ = socket.send(close_message).await;
+                        break;
+                    }
+                },
+            }
+        }
+    })
+}
+
+/*
+Websocket controller of the streaming protocol. Each subscription runs in its own task
+that waits for the data of its channel to change and pushes updates to the socket through
+a channel, so one socket receives the updates of many symbols and channels at once.
+Symbols of order book channels are subscribed on the market stream while any subscription
+of the socket needs them.
+*/
+#[handler]
+pub(super) async fn stream_web_socket(
+    ws: WebSocket,
+    Data(app_layer): Data<&ApplicationLayer>,
+    Data(shutdown): Data<&ShutdownSignal>,
+    Data(service_metrics): Data<&SharedMetrics>,
+) -> impl IntoResponse {
+    let app_layer = app_layer.clone();
+    let mut shutdown = shutdown.clone();
+    let service_metrics = service_metrics.clone();
+
+    ws.on_upgrade(|mut socket| async move {
+        let _client = ConnectedClient::new(&service_metrics, "/api/stream");
+        let (updates, mut outgoing) = mpsc::channel::<String>(MAX_PENDING_UPDATES);
+        let mut session = StreamSession {
+            app_layer,
+            service_metrics,
+            updates,
+            subscriptions: BTreeMap::new(),
+            book_symbols: BTreeMap::new(),
+        };
+
+        loop {
+            tokio::select! {
+                Some(update) = outgoing.recv() => {
+                    if socket.send(Message::text(update)).await.is_err() {
+                        break;
+                    }
+                }
+                _ = shutdown.wait() => {
+                    let _ = socket.send(shutdown_close_message()).await;
+                    break;
+                }
+                msg = socket.try_next() => {
+                    let msg = match msg {
+                        Ok(Some(Message::Text(msg))) => msg,
+                        Ok(Some(Message::Close(_))) | Ok(None) | Err(_) => break,
+                        Ok(Some(_)) => continue,
+                    };
+
+                    let response = match serde_json::from_str::<StreamRequest>(&msg) {
+                        Ok(request) => session.handle_request(request).await,
+                        Err(e) => StreamResponse::error(None, None, format!("invalid request: {}", e)),
+                    };
+                    let _ = socket
+                        .send(Message::text(serde_json::to_string(&response).unwrap()))
+                        .await;
+                }
+            }
+        }
+
+        session.close().await;
+    })
+}
+
+// Subscriptions of a socket of the streaming protocol
+struct StreamSession {
+    app_layer: ApplicationLayer,
+    service_metrics: SharedMetrics,
+    // serialized updates to send on the socket
+    updates: mpsc::Sender<String>,
+    subscriptions: BTreeMap<SubscriptionQuery, JoinHandle<()>>,
+    // symbols subscribed on the market stream with the number of subscriptions needing them
+    book_symbols: BTreeMap<Symbol, usize>,
+}
+
+impl StreamSession {
+    async fn handle_request(&mut self, request: StreamRequest) -> StreamResponse {
+        match request {
+            StreamRequest::Subscribe {
+                id,
+                subscription,
+                throttle_ms,
+                metrics,
+            } => {
+                let (subscription, interval) = match subscription.normalised() {
+                    Ok(normalised) => normalised,
+                    Err(e) => return StreamResponse::error(id, None, e.to_string()),
+                };
+                let throttle = throttle_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_THROTTLE);
+                if throttle < MIN_THROTTLE {
+                    return StreamResponse::error(
+                        id,
+                        Some(subscription),
+                        format!(
+                            "throttle_ms needs to be at least {}",
+                            MIN_THROTTLE.as_millis()
+                        ),
+                    );
+                }
+
+                // subscriptions end by themselves when their symbol can not be streamed
+                let ended: Vec<SubscriptionQuery> = self
+                    .subscriptions
+                    .iter()
+                    .filter(|(_, task)| task.is_finished())
+                    .map(|(subscription, _)| subscription.clone())
+                    .collect();
+                for subscription in ended.iter() {
+                    self.release(subscription).await;
+                }
+
+                if self.subscriptions.contains_key(&subscription) {
+                    return StreamResponse::error(
+                        id,
+                        Some(subscription),
+                        "already subscribed".into(),
+                    );
+                }
+                if self.subscriptions.len() >= MAX_SUBSCRIPTIONS_PER_SOCKET {
+                    return StreamResponse::error(
+                        id,
+                        Some(subscription),
+                        "too many subscriptions".into(),
+                    );
+                }
+
+                let symbol = Symbol(subscription.symbol.clone());
+                if subscription.channel.needs_order_book() {
+                    if !self.book_symbols.contains_key(&symbol) {
+                        let query = ApplicationQuery::SubscribeToSymbol(symbol.clone());
+
+                        if let Err(e) = self.app_layer.handle_query(query).await {
+                            warn!(symbol = %symbol.0, error = %e, "symbol could not be subscribed");
+                            return StreamResponse::error(
+                                id,
+                                Some(subscription),
+                                "symbol could not be subscribed".into(),
+                            );
+                        }
+                    }
+                    *self.book_symbols.entry(symbol).or_insert(0) += 1;
+                }
+
+                let task = tokio::spawn(stream_subscription(
+                    self.app_layer.clone(),
+                    self.service_metrics.clone(),
+                    subscription.clone(),
+                    interval,
+                    metrics.unwrap_or_default(),
+                    throttle,
+                    self.updates.clone(),
+                ));
+                self.subscriptions.insert(subscription.clone(), task);
+
+                StreamResponse::status(id, "subscribed", subscription)
+            }
+            StreamRequest::Unsubscribe { id, subscription } => {
+                let subscription = match subscription.normalised() {
+                    Ok((subscription, _)) => subscription,
+                    Err(e) => return StreamResponse::error(id, None, e.to_string()),
+                };
+
+                if self.release(&subscription).await {
+                    StreamResponse::status(id, "unsubscribed", subscription)
+                } else {
+                    StreamResponse::error(id, Some(subscription), "not subscribed".into())
+                }
+            }
+        }
+    }
+
+    // Stops a subscription and removes its symbol from the market stream once no other
+    // subscription of the socket needs it. Returns false when there was no subscription
+    async fn release(&mut self, subscription: &SubscriptionQuery) -> bool {
+        let task = match self.subscriptions.remove(subscription) {
+            Some(task) => task,
+            None => return false,
+        };
+        task.abort();
+
+        let symbol = Symbol(subscription.symbol.clone());
+        if subscription.channel.needs_order_book() {
+            match self.book_symbols.get(&symbol).copied() {
+                Some(1) => {
+                    self.book_symbols.remove(&symbol);
+                    let query = ApplicationQuery::UnsubscribeFromSymbol(symbol);
+                    let _ = self.app_layer.handle_query(query).await;
+                }
+                Some(count) => {
+                    self.book_symbols.insert(symbol, count - 1);
+                }
+                None => {}
+            }
+        }
+
+        true
+    }
+
+    async fn close(mut self) {
+        let subscriptions: Vec<SubscriptionQuery> = self.subscriptions.keys().cloned().collect();
+
+        for subscription in subscriptions.iter() {
+            self.release(subscription).await;
+        }
+    }
+}
+
+/*
+Pushes the updates of a subscription until the socket is gone or the channel can not be
+streamed for the symbol. Changes within the throttle keep only the latest update, which
+is sent once the throttle since the previous update has passed and there is room for it
+in the updates of the socket. Changes until then replace it.
+*/
+pub(super) async fn stream_subscription(
+    app_layer: ApplicationLayer,
+    service_metrics: SharedMetrics,
+    subscription: SubscriptionQuery,
+    interval: Option<CandleInterval>,
+    metrics: MetricsQuery,
+    throttle: Duration,
+    updates: mpsc::Sender<String>,
+) {
+    let mut latest: Option<StreamUpdate> = None;
+    let mut send_after = Instant::now();
+
+    loop {
+        let mut pending = None;
+
+        let room = async {
+            tokio::time::sleep_until(send_after).await;
+            updates.reserve().await
+        };
+        tokio::select! {
+            update = next_stream_update(&app_layer, &subscription, interval, &metrics) => {
+                match update {
+                    Ok(update) => latest = Some(update),
+                    Err(e) => {
+                        let response = StreamResponse::error(None, Some(subscription), e.to_string());
+                        let _ = updates.send(serde_json::to_string(&response).unwrap()).await;
+                        return;
+                    }
+                }
+            }
+            room = room, if latest.is_some() => {
+                match room {
+                    Ok(permit) => pending = latest.take().map(|update| (update, permit)),
+                    // the socket is gone
+                    Err(_) => return,
+                }
+                send_after = Instant::now() + throttle;
+            }
+        }
+
+        if let Some((update, permit)) = pending {
+            // only updates of a book follow a market message
+            let delivery = match update.data.is_some() && subscription.channel.needs_order_book() {
+                true => {
+                    let symbol = Symbol(subscription.symbol.clone());
+                    Some(Delivery::start(&app_layer, subscription.channel, &symbol).await)
+                }
+                false => None,
+            };
+
+            permit.send(serde_json::to_string(&update).unwrap());
+            if let Some(delivery) = delivery {
+                delivery.sent(&service_metrics);
+            }
+        }
+    }
+}
+
+// Waits for the data of the channel of a subscription to change
+async fn next_stream_update(
+    app_layer: &ApplicationLayer,
+    subscription: &SubscriptionQuery,
+    interval: Option<CandleInterval>,
+    metrics: &MetricsQuery,
+) -> Result<StreamUpdate> {
+    let symbol = Symbol(subscription.symbol.clone());
+    let update = |data: serde_json::Value| StreamUpdate {
+        subscription: subscription.clone(),
+        data: Some(data),
+        status: None,
+    };
+    let out_of_sync = StreamUpdate {
+        subscription: subscription.clone(),
+        data: None,
+        status: Some("out_of_sync".into()),
+    };
+
+    match subscription.channel {
+        Channel::AveragePrice | Channel::Metrics => {
+            let query = ApplicationQuery::GetAverageValueOfSymbol(symbol.clone());
+
+            match app_layer.handle_query(query).await? {
+                ApplicationResponse::CurrentAveragePriceForSymbol { price, .. } => {
+                    if subscription.channel == Channel::AveragePrice {
+                        Ok(update(json!({ "v": price.map(|price| price.to_string()) })))
+                    } else {
+                        let metrics = price_metrics(app_layer, &symbol, metrics).await?;
+                        Ok(update(serde_json::to_value(metrics)?))
+                    }
+                }
+                ApplicationResponse::OrderBookOutOfSync { .. } => Ok(out_of_sync),
+                _ => Err(Error::msg("internal server error")),
+            }
+        }
+        Channel::Candles => {
+            let interval = interval.ok_or_else(|| Error::msg("candles need an interval"))?;
+            let query = ApplicationQuery::WaitForCandle { symbol, interval };
+
+            match app_layer.handle_query(query).await? {
+                ApplicationResponse::CandleUpdate { candle, .. } => {
+                    Ok(update(serde_json::to_value(CandleValue::from(candle))?))
+                }
+                ApplicationResponse::UnknownSymbol { .. } => {
+                    Err(Error::msg("no candles of the symbol and interval"))
+                }
+                _ => Err(Error::msg("internal server error")),
+            }
+        }
+        Channel::Arbitrage => {
+            let query = ApplicationQuery::WaitForArbitrageOpportunities(symbol);
+
+            match app_layer.handle_query(query).await? {
+                ApplicationResponse::ArbitrageOpportunities {
+                    symbol,
+                    detected_at,
+                    opportunities,
+                } => Ok(update(serde_json::to_value(ArbitrageValue {
+                    p: symbol.0,
+                    t: detected_at,
+                    o: opportunities
+                        .into_iter()
+                        .map(OpportunityValue::from)
+                        .collect(),
+                })?)),
+                ApplicationResponse::OrderBookOutOfSync { .. } => Ok(out_of_sync),
+                ApplicationResponse::UnknownSymbol { .. } => Err(Error::msg("unknown symbol")),
+                _ => Err(Error::msg("internal server error")),
+            }
+        }
+    }
+}
+
+/*
+Sending of an update of the book of a symbol to a client. It is traced in a span of its own
+linked to the one the market message that last changed the book was applied in, so the
+message can be followed until it reaches the clients without keeping its spans open. The
+time from the event time of the message until the update was sent is reported to the metrics.
+*/
+struct Delivery {
+    channel: Channel,
+    event_time: Option<u64>,
+    span: Span,
+}
+
+impl Delivery {
+    async fn start(app_layer: &ApplicationLayer, channel: Channel, symbol: &Symbol) -> Self {
+        let query = ApplicationQuery::GetLastBookUpdate(symbol.clone());
+        let (event_time, update_context) = match app_layer.handle_query(query).await {
+            Ok(ApplicationResponse::LastBookUpdate {
+                event_time,
+                trace_context,
+                ..
+            }) => (event_time, trace_context),
+            _ => (None, TraceContext::default()),
+        };
+        let span = info_span!(
+            "deliver_update",
+            channel = channel.name(),
+            symbol = %symbol.0,
+            event_time,
+        );
+        span.add_link(SpanContext::from(update_context));
+
+        Self {
+            channel,
+            event_time,
+            span,
+        }
+    }
+
+    fn sent(self, metrics: &SharedMetrics) {
+        let event_time = match self.event_time {
+            Some(event_time) => event_time,
+            None => return,
+        };
+        // clocks of the market and the service can be apart by more than the latency
+        let latency = Duration::from_millis(unix_millis().saturating_sub(event_time));
+
+        metrics.update_delivered(self.channel.name(), latency);
+        self.span.in_scope(|| {
+            debug!(latency_ms = latency.as_millis() as u64, "update delivered");
+        });
+    }
+}
+
+// Close frame sent to the client websockets when the server shuts down, going away tells
+// clients to reconnect rather than report an error
+fn shutdown_close_message() -> Message {
+    Message::close_with(CloseCode::Away, "server shutting down")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapters::client_web_server::tests::{
+            connect_web_socket, matches_schema, mock_market_app, next_json, serve_mock_market,
+        },
+        ports::NoMetrics,
+    };
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite;
+
+    #[tokio::test]
+    async fn test_average_price_served_from_mock_market() {
+        let port = serve_mock_market().await;
+        let mut socket = connect_web_socket(port, "/api/average_order_book_price").await;
+
+        socket
+            .send(tungstenite::Message::text(r#"{"p": "btcusdc"}"#))
+            .await
+            .unwrap();
+        let reply = socket.next().await.unwrap().unwrap().into_text().unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(reply.as_str()).unwrap(),
+            json!({ "p": "BTCUSDC", "v": "100.00" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttle_below_minimum_rejected() {
+        let port = serve_mock_market().await;
+        let mut socket = connect_web_socket(port, "/api/stream").await;
+        let subscribe = json!({
+            "op": "subscribe",
+            "id": 1,
+            "channel": "average_price",
+            "symbol": "BTCUSDC",
+            "throttle_ms": 0,
+        });
+        socket
+            .send(tungstenite::Message::text(subscribe.to_string()))
+            .await
+            .unwrap();
+
+        let reply = next_json(&mut socket).await;
+
+        assert_eq!(reply["status"], "error");
+        assert_eq!(reply["error"], "throttle_ms needs to be at least 50");
+    }
+
+    #[tokio::test]
+    async fn test_socket_reading_slowly_gets_only_latest_update() {
+        let app_layer = mock_market_app(NoMetrics::shared()).await;
+        let (updates, mut outgoing) = mpsc::channel(1);
+        updates.send("unread".to_string()).await.unwrap();
+        let subscription = SubscriptionQuery {
+            channel: Channel::AveragePrice,
+            symbol: "BTCUSDC".into(),
+            interval: None,
+        };
+        tokio::spawn(stream_subscription(
+            app_layer,
+            NoMetrics::shared(),
+            subscription,
+            None,
+            MetricsQuery::default(),
+            MIN_THROTTLE,
+            updates,
+        ));
+
+        // the book changes every 20ms while the socket reads nothing
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(outgoing.recv().await.unwrap(), "unread");
+        let latest = outgoing.recv().await.unwrap();
+
+        // the changes in between replaced each other instead of queueing up
+        assert!(latest.contains("\"v\":\"100.00\""));
+        assert!(outgoing.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_streams_throttled_updates_until_unsubscribed() {
+        let port = serve_mock_market().await;
+        let mut socket = connect_web_socket(port, "/api/stream").await;
+        let subscribe = json!({
+            "op": "subscribe",
+            "id": 1,
+            "channel": "average_price",
+            "symbol": "btcusdc",
+            "throttle_ms": 200,
+        });
+        socket
+            .send(tungstenite::Message::text(subscribe.to_string()))
+            .await
+            .unwrap();
+        let ack = next_json(&mut socket).await;
+        let first = next_json(&mut socket).await;
+        let first_at = Instant::now();
+        let second = next_json(&mut socket).await;
+        let throttled_for = first_at.elapsed();
+        let unsubscribe = json!({
+            "op": "unsubscribe",
+            "id": 2,
+            "channel": "average_price",
+            "symbol": "BTCUSDC",
+        });
+        socket
+            .send(tungstenite::Message::text(unsubscribe.to_string()))
+            .await
+            .unwrap();
+        // updates sent before the unsubscribe arrived come ahead of its answer
+        let unsubscribed = loop {
+            let reply = next_json(&mut socket).await;
+            if reply.get("id").is_some() {
+                break reply;
+            }
+        };
+
+        assert_eq!(
+            ack,
+            json!({ "id": 1, "status": "subscribed", "channel": "average_price", "symbol": "BTCUSDC" })
+        );
+        assert_eq!(
+            first,
+            json!({ "channel": "average_price", "symbol": "BTCUSDC", "data": { "v": "100.00" } })
+        );
+        assert_eq!(second, first);
+        // books change every 20ms but updates are sent at most every 200ms
+        assert!(throttled_for >= Duration::from_millis(150));
+        assert_eq!(unsubscribed["status"], "unsubscribed");
+    }
+
+    #[test]
+    fn test_web_socket_messages_match_their_schemas() {
+        let schema = |name: &str| json!({ "$ref": format!("#/components/schemas/{}", name) });
+        fn value<T: Serialize>(message: &T) -> serde_json::Value {
+            serde_json::to_value(message).unwrap()
+        }
+        let level = || LevelValue {
+            p: DecimalText::of("100.25"),
+            q: DecimalText::of("1.5"),
+        };
+        let opportunity = OpportunityValue {
+            buy: "binance".into(),
+            sell: "kraken".into(),
+            buy_p: "100.00".into(),
+            sell_p: "100.50".into(),
+            edge_bps: "-2.5".into(),
+            qty: "1".into(),
+            profit: "0.25".into(),
+        };
+        let subscription = SubscriptionQuery {
+            channel: Channel::Candles,
+            symbol: "BTCUSDC".into(),
+            interval: Some("1m".into()),
+        };
+        let metrics = MetricsValue {
+            mid: Some("100.5".into()),
+            vwap: Some(SidesValue {
+                b: Some("99".into()),
+                a: None,
+            }),
+            bid: Some(level()),
+            spread: Some(SpreadValue {
+                abs: "1".into(),
+                bps: "9.95".into(),
+            }),
+            imbalance: Some(BTreeMap::from([(1, "0.5".into())])),
+            ..Default::default()
+        };
+
+        let messages = [
+            (
+                "PairQuery",
+                json!({ "p": "btcusdc", "m": { "levels": 2, "depths": [1, 5] }, "o": { "side": "buy", "qty": "1" }, "c": 3 }),
+            ),
+            (
+                "PairValue",
+                value(&PairValue {
+                    pair: "BTCUSDC".into(),
+                    value: "100.50",
+                    metrics: Some(metrics.clone()),
+                    order: Some(OrderEstimateValue {
+                        avg: Some("100.5".into()),
+                        worst: None,
+                        slip_bps: Some("0".into()),
+                        levels: 2,
+                        filled_qty: "1".into(),
+                        filled_notional: "100.5".into(),
+                        sufficient: true,
+                    }),
+                    consolidated: Some(ConsolidatedValue {
+                        venues: vec!["binance".into()],
+                        b: vec![ConsolidatedLevelValue {
+                            p: "100".into(),
+                            q: "2".into(),
+                            v: BTreeMap::from([("binance".into(), "2".into())]),
+                        }],
+                        a: vec![],
+                    }),
+                }),
+            ),
+            (
+                "Arbitrage",
+                value(&ArbitrageValue {
+                    p: "BTCUSDC".into(),
+                    t: 1,
+                    o: vec![opportunity.clone()],
+                }),
+            ),
+            (
+                "TriangularArbitrage",
+                value(&TriangularArbitrageValue {
+                    t: 1,
+                    r: vec![RoundTripValue {
+                        start: "USDC".into(),
+                        legs: vec![LegValue {
+                            s: "BTCUSDC".into(),
+                            side: SideValue::Buy,
+                            p: "100".into(),
+                        }],
+                        rate: "1.001".into(),
+                        profit_bps: "10".into(),
+                        max: "500".into(),
+                    }],
+                }),
+            ),
+            (
+                "StreamRequest",
+                value(&StreamRequest::Subscribe {
+                    id: Some(1),
+                    subscription: subscription.clone(),
+                    throttle_ms: Some(500),
+                    metrics: Some(MetricsQuery::default()),
+                }),
+            ),
+            (
+                "StreamRequest",
+                value(&StreamRequest::Unsubscribe {
+                    id: None,
+                    subscription: subscription.clone(),
+                }),
+            ),
+            (
+                "StreamResponse",
+                value(&StreamResponse::status(
+                    Some(1),
+                    "subscribed",
+                    subscription.clone(),
+                )),
+            ),
+            (
+                "StreamUpdate",
+                value(&StreamUpdate {
+                    subscription: subscription.clone(),
+                    data: Some(value(&CandleValue {
+                        t: 60_000,
+                        o: "100".into(),
+                        h: "101".into(),
+                        l: "99".into(),
+                        c: "100.5".into(),
+                        v: "3".into(),
+                        qv: "301.5".into(),
+                        vwap: "100.5".into(),
+                        n: 3,
+                    })),
+                    status: None,
+                }),
+            ),
+            (
+                "StreamUpdate",
+                value(&StreamUpdate {
+                    subscription,
+                    data: Some(value(&metrics)),
+                    status: None,
+                }),
+            ),
+        ];
+
+        for (name, message) in messages {
+            assert!(
+                matches_schema(&schema(name), &message),
+                "{} {}",
+                name,
+                message
+            );
+        }
+        // fields the schemas do not know about are rejected
+        assert!(!matches_schema(
+            &schema("Arbitrage"),
+            &json!({ "p": "BTCUSDC", "t": 1, "o": [], "x": 1 })
+        ));
+    }
+}
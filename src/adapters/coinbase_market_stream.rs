@@ -0,0 +1,351 @@
+use super::venue_market_stream::{self, rfc_3339_millis, UpdateIds, VenueProtocol};
+use crate::{
+    ports::{
+        MarketEvent, MarketStream, MarketStreamConnection, NoMetrics, SharedMetrics,
+        DEFAULT_EVENT_CAPACITY,
+    },
+    typespec::{PriceLevel, Symbol},
+};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+const COINBASE_WSS_URL: &str = "wss://advanced-trade-ws.coinbase.com";
+
+// A infrastructure struct that implements a driven port for the level2
+// channel of the coinbase advanced trade websocket api
+pub struct CoinbaseMarketStream {
+    url: String,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+}
+
+impl CoinbaseMarketStream {
+    pub fn new() -> Self {
+        Self::with_url(COINBASE_WSS_URL)
+    }
+
+    pub fn with_url(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+}
+
+impl Default for CoinbaseMarketStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarketStream for CoinbaseMarketStream {
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        venue_market_stream::subscribe(
+            self.url.clone(),
+            CoinbaseProtocol::default(),
+            symbols,
+            self.event_capacity,
+            self.metrics.clone(),
+        )
+        .await
+    }
+}
+
+/*
+Coinbase sends the snapshot of a product as the first level2 event after subscribing and
+numbers every message of a connection with a single sequence. The sequence is shared by
+all products and channels so update ids are counted per product, and a skipped sequence
+number skips an id of every product.
+*/
+#[derive(Clone, Default)]
+struct CoinbaseProtocol {
+    update_ids: UpdateIds,
+    last_sequence_num: Option<u64>,
+    // coinbase answers each request with the current subscriptions but without its id
+    subscriptions_received: u64,
+}
+
+impl VenueProtocol for CoinbaseProtocol {
+    const VENUE: &'static str = "coinbase";
+    // snapshots of many products on one connection can take longer than coinbase waits
+    const MAX_SYMBOLS_PER_CONNECTION: usize = 100;
+    // coinbase allows 8 requests per second
+    const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(125);
+    // the heartbeats channel keeps connections of quiet products open
+    const PING: Option<(&'static str, Duration)> = None;
+
+    fn subscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        let product_ids = product_ids(symbols);
+
+        vec![
+            json!({ "type": "subscribe", "product_ids": product_ids, "channel": "level2" })
+                .to_string(),
+            json!({ "type": "subscribe", "product_ids": product_ids, "channel": "heartbeats" })
+                .to_string(),
+        ]
+    }
+
+    fn unsubscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        let product_ids = product_ids(symbols);
+
+        vec![
+            json!({ "type": "unsubscribe", "product_ids": product_ids, "channel": "level2" })
+                .to_string(),
+        ]
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Vec<MarketEvent>> {
+        let message: CoinbaseMessage = serde_json::from_str(text)?;
+
+        let message = match message {
+            CoinbaseMessage::Channel(message) => message,
+            CoinbaseMessage::Error(error) => {
+                return Ok(vec![MarketEvent::Error {
+                    code: 0,
+                    msg: error.message,
+                }])
+            }
+        };
+
+        let sequence_num = match &message {
+            ChannelMessage::Level2 { sequence_num, .. }
+            | ChannelMessage::Subscriptions { sequence_num }
+            | ChannelMessage::Heartbeats { sequence_num } => *sequence_num,
+        };
+        if self
+            .last_sequence_num
+            .is_some_and(|last| sequence_num > last + 1)
+        {
+            self.update_ids.skip_all();
+        }
+        self.last_sequence_num = Some(sequence_num);
+
+        let events = match message {
+            ChannelMessage::Level2 {
+                timestamp, events, ..
+            } => {
+                let event_time = rfc_3339_millis(timestamp.as_str())?;
+
+                events
+                    .into_iter()
+                    .map(|event| self.level2_event(event, event_time))
+                    .collect::<Result<Vec<MarketEvent>>>()?
+            }
+            ChannelMessage::Subscriptions { .. } => {
+                self.subscriptions_received += 1;
+                vec![MarketEvent::SubscriptionAck {
+                    id: self.subscriptions_received,
+                }]
+            }
+            ChannelMessage::Heartbeats { .. } => vec![MarketEvent::Heartbeat],
+        };
+
+        Ok(events)
+    }
+}
+
+impl CoinbaseProtocol {
+    fn level2_event(&mut self, event: Level2Event, event_time: u64) -> Result<MarketEvent> {
+        let symbol = Symbol::from_pair(event.product_id.as_str());
+        let mut bids = vec![];
+        let mut asks = vec![];
+
+        for update in event.updates {
+            let level: PriceLevel = (update.price_level.parse()?, update.new_quantity.parse()?);
+
+            match update.side {
+                Level2Side::Bid => bids.push(level),
+                Level2Side::Offer => asks.push(level),
+            }
+        }
+
+        let event = match event.kind {
+            Level2EventKind::Snapshot => self.update_ids.snapshot(symbol, bids, asks),
+            Level2EventKind::Update => self.update_ids.update(symbol, event_time, bids, asks),
+        };
+
+        Ok(event)
+    }
+}
+
+// coinbase names products with a dash between the assets, BTC-USD
+fn product_ids(symbols: &[Symbol]) -> Vec<String> {
+    symbols
+        .iter()
+        .map(|symbol| symbol.to_pair('-').unwrap_or_else(|| symbol.0.clone()))
+        .collect()
+}
+
+/*
+DTOs replicating the messages of the coinbase advanced trade websocket api
+*/
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum CoinbaseMessage {
+    Channel(ChannelMessage),
+    Error(ErrorMessage),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "channel")]
+enum ChannelMessage {
+    #[serde(rename = "l2_data")]
+    Level2 {
+        timestamp: String,
+        sequence_num: u64,
+        events: Vec<Level2Event>,
+    },
+    #[serde(rename = "subscriptions")]
+    Subscriptions { sequence_num: u64 },
+    #[serde(rename = "heartbeats")]
+    Heartbeats { sequence_num: u64 },
+}
+
+#[derive(Deserialize, Debug)]
+struct Level2Event {
+    #[serde(rename = "type")]
+    kind: Level2EventKind,
+    product_id: String,
+    updates: Vec<Level2Update>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Level2EventKind {
+    Snapshot,
+    Update,
+}
+
+#[derive(Deserialize, Debug)]
+struct Level2Update {
+    side: Level2Side,
+    price_level: String,
+    new_quantity: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Level2Side {
+    Bid,
+    Offer,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorMessage {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::{DepthSnapshot, DepthUpdate};
+
+    // frames recorded from the level2 and heartbeats channels
+    const SNAPSHOT_FRAME: &str = r#"{"channel":"l2_data","client_id":"","timestamp":"2023-02-09T20:32:50.714964855Z","sequence_num":0,"events":[{"type":"snapshot","product_id":"BTC-USD","updates":[{"side":"bid","event_time":"1970-01-01T00:00:00Z","price_level":"21921.73","new_quantity":"0.06317902"},{"side":"offer","event_time":"1970-01-01T00:00:00Z","price_level":"21921.74","new_quantity":"0.02"}]}]}"#;
+    const UPDATE_FRAME: &str = r#"{"channel":"l2_data","client_id":"","timestamp":"2023-02-09T20:32:51.000000000Z","sequence_num":1,"events":[{"type":"update","product_id":"BTC-USD","updates":[{"side":"bid","event_time":"2023-02-09T20:32:50.978Z","price_level":"21921.73","new_quantity":"0"}]}]}"#;
+    const SUBSCRIPTIONS_FRAME: &str = r#"{"channel":"subscriptions","client_id":"","timestamp":"2023-02-09T20:32:50.714964855Z","sequence_num":2,"events":[{"subscriptions":{"level2":["BTC-USD"]}}]}"#;
+    const HEARTBEAT_FRAME: &str = r#"{"channel":"heartbeats","client_id":"","timestamp":"2023-06-23T20:31:26.122969572Z","sequence_num":3,"events":[{"current_time":"2023-06-23 20:31:56.121961769 +0000 UTC m=+91717.525857105","heartbeat_counter":"3049"}]}"#;
+
+    fn level(price: &str, qty: &str) -> PriceLevel {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn test_level2_frames_normalised_into_snapshot_and_update() {
+        let mut protocol = CoinbaseProtocol::default();
+
+        let snapshot = protocol.parse(SNAPSHOT_FRAME).unwrap();
+        let update = protocol.parse(UPDATE_FRAME).unwrap();
+
+        assert_eq!(
+            snapshot,
+            vec![MarketEvent::BookSnapshot {
+                symbol: Symbol("BTCUSD".into()),
+                snapshot: DepthSnapshot {
+                    last_update_id: 1,
+                    bids: vec![level("21921.73", "0.06317902")],
+                    asks: vec![level("21921.74", "0.02")],
+                },
+            }]
+        );
+        assert_eq!(
+            update,
+            vec![MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSD".into()),
+                event_time: 1675974771000,
+                first_update_id: 2,
+                final_update_id: 2,
+                bids: vec![level("21921.73", "0")],
+                asks: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_skipped_sequence_number_leaves_gap_in_update_ids() {
+        let mut protocol = CoinbaseProtocol::default();
+
+        protocol.parse(SNAPSHOT_FRAME).unwrap();
+        // sequence number 1 is lost
+        protocol.parse(SUBSCRIPTIONS_FRAME).unwrap();
+        let update = protocol
+            .parse(&UPDATE_FRAME.replace("\"sequence_num\":1", "\"sequence_num\":3"))
+            .unwrap();
+
+        assert!(matches!(
+            update[0],
+            MarketEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_subscriptions_heartbeats_and_errors() {
+        let mut protocol = CoinbaseProtocol::default();
+
+        assert_eq!(
+            protocol.parse(SUBSCRIPTIONS_FRAME).unwrap(),
+            vec![MarketEvent::SubscriptionAck { id: 1 }]
+        );
+        assert_eq!(
+            protocol.parse(HEARTBEAT_FRAME).unwrap(),
+            vec![MarketEvent::Heartbeat]
+        );
+        assert_eq!(
+            protocol
+                .parse(r#"{"type":"error","message":"failure to subscribe"}"#)
+                .unwrap(),
+            vec![MarketEvent::Error {
+                code: 0,
+                msg: "failure to subscribe".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_subscribe_requests_use_product_ids() {
+        let requests = CoinbaseProtocol::default().subscribe_requests(&[Symbol("ETHUSDC".into())]);
+
+        let level2: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+        assert_eq!(
+            level2,
+            json!({ "type": "subscribe", "product_ids": ["ETH-USDC"], "channel": "level2" })
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use super::venue_market_stream::{self, rfc_3339_millis, UpdateIds, VenueProtocol};
+use crate::{
+    ports::{
+        MarketEvent, MarketStream, MarketStreamConnection, NoMetrics, SharedMetrics,
+        DEFAULT_EVENT_CAPACITY,
+    },
+    typespec::{Price, PriceLevel, Symbol},
+};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, value::RawValue};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+const KRAKEN_WSS_URL: &str = "wss://ws.kraken.com/v2";
+// deepest book kraken streams
+const BOOK_DEPTH: u32 = 1000;
+
+// A infrastructure struct that implements a driven port for the book
+// channel of the kraken v2 websocket api
+pub struct KrakenMarketStream {
+    url: String,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+}
+
+impl KrakenMarketStream {
+    pub fn new() -> Self {
+        Self::with_url(KRAKEN_WSS_URL)
+    }
+
+    pub fn with_url(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+}
+
+impl Default for KrakenMarketStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarketStream for KrakenMarketStream {
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        venue_market_stream::subscribe(
+            self.url.clone(),
+            KrakenProtocol::default(),
+            symbols,
+            self.event_capacity,
+            self.metrics.clone(),
+        )
+        .await
+    }
+}
+
+/*
+Kraken sends the snapshot of a symbol as the first book message after subscribing. Book
+messages carry a checksum of the top of the book but no sequence number, so update ids
+are counted per symbol and lost messages are only found through a reconnect.
+
+Kraken keeps the book at the subscribed depth without telling when a level falls out of
+it, so the levels in scope are tracked per symbol and the ones pushed out by an update are
+removed with it.
+*/
+#[derive(Clone, Default)]
+struct KrakenProtocol {
+    update_ids: UpdateIds,
+    scopes: BTreeMap<Symbol, BookScope>,
+    last_req_id: u64,
+}
+
+impl VenueProtocol for KrakenProtocol {
+    const VENUE: &'static str = "kraken";
+    const MAX_SYMBOLS_PER_CONNECTION: usize = 100;
+    const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(100);
+    // kraken only sends heartbeats while something is subscribed
+    const PING: Option<(&'static str, Duration)> =
+        Some((r#"{"method":"ping"}"#, Duration::from_secs(30)));
+
+    fn subscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        self.last_req_id += 1;
+
+        vec![json!({
+            "method": "subscribe",
+            "params": {
+                "channel": "book",
+                "symbol": kraken_symbols(symbols),
+                "depth": BOOK_DEPTH,
+                "snapshot": true,
+            },
+            "req_id": self.last_req_id,
+        })
+        .to_string()]
+    }
+
+    fn unsubscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        self.last_req_id += 1;
+
+        vec![json!({
+            "method": "unsubscribe",
+            "params": {
+                "channel": "book",
+                "symbol": kraken_symbols(symbols),
+                "depth": BOOK_DEPTH,
+            },
+            "req_id": self.last_req_id,
+        })
+        .to_string()]
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Vec<MarketEvent>> {
+        let message: KrakenMessage = serde_json::from_str(text)?;
+
+        let events = match message {
+            KrakenMessage::Channel(ChannelMessage::Book { .. }) => {
+                let BookMessage { kind, data } = serde_json::from_str(text)?;
+                data.into_iter()
+                    .map(|book| self.book_event(&kind, book))
+                    .collect::<Result<Vec<MarketEvent>>>()?
+            }
+            KrakenMessage::Channel(ChannelMessage::Heartbeat {}) => vec![MarketEvent::Heartbeat],
+            KrakenMessage::Channel(ChannelMessage::Status {}) => vec![],
+            KrakenMessage::Response(response) => match response {
+                MethodResponse { method, .. } if method == "pong" => vec![MarketEvent::Heartbeat],
+                MethodResponse {
+                    success: true,
+                    req_id,
+                    ..
+                } => vec![MarketEvent::SubscriptionAck {
+                    id: req_id.unwrap_or_default(),
+                }],
+                MethodResponse { error, .. } => vec![MarketEvent::Error {
+                    code: 0,
+                    msg: error.unwrap_or_default(),
+                }],
+            },
+        };
+
+        Ok(events)
+    }
+}
+
+impl KrakenProtocol {
+    fn book_event(&mut self, kind: &BookKind, book: BookData) -> Result<MarketEvent> {
+        let symbol = Symbol::from_pair(book.symbol.as_str());
+        let mut bids = levels(book.bids)?;
+        let mut asks = levels(book.asks)?;
+
+        let event = match kind {
+            BookKind::Snapshot => {
+                let scope = self.scopes.entry(symbol.clone()).or_default();
+                *scope = BookScope::default();
+                scope.apply(&mut bids, &mut asks);
+                self.update_ids.snapshot(symbol, bids, asks)
+            }
+            BookKind::Update => {
+                let event_time = match book.timestamp {
+                    Some(timestamp) => rfc_3339_millis(timestamp.as_str())?,
+                    None => 0,
+                };
+                self.scopes
+                    .entry(symbol.clone())
+                    .or_default()
+                    .apply(&mut bids, &mut asks);
+                self.update_ids.update(symbol, event_time, bids, asks)
+            }
+        };
+
+        Ok(event)
+    }
+}
+
+// Prices of the levels of a symbol within the depth kraken keeps its book at
+#[derive(Clone, Default)]
+struct BookScope {
+    bids: BTreeSet<Price>,
+    asks: BTreeSet<Price>,
+}
+
+impl BookScope {
+    // Applies the levels of a message and adds the removal of the levels it pushed out of
+    // the depth, the lowest bids and the highest asks
+    fn apply(&mut self, bids: &mut Vec<PriceLevel>, asks: &mut Vec<PriceLevel>) {
+        apply_levels(&mut self.bids, bids);
+        while self.bids.len() > BOOK_DEPTH as usize {
+            let price = self.bids.pop_first().unwrap();
+            bids.push((price, Default::default()));
+        }
+
+        apply_levels(&mut self.asks, asks);
+        while self.asks.len() > BOOK_DEPTH as usize {
+            let price = self.asks.pop_last().unwrap();
+            asks.push((price, Default::default()));
+        }
+    }
+}
+
+fn apply_levels(prices: &mut BTreeSet<Price>, levels: &[PriceLevel]) {
+    for (price, qty) in levels {
+        if qty.0.is_zero() {
+            prices.remove(price);
+        } else {
+            prices.insert(*price);
+        }
+    }
+}
+
+// kraken names symbols with a slash between the assets, BTC/USD
+fn kraken_symbols(symbols: &[Symbol]) -> Vec<String> {
+    symbols
+        .iter()
+        .map(|symbol| symbol.to_pair('/').unwrap_or_else(|| symbol.0.clone()))
+        .collect()
+}
+
+// Kraken sends prices and quantities as json numbers. They are read from the digits kraken
+// sent, as a float can not hold every digit of them
+fn levels(levels: Vec<BookLevel>) -> Result<Vec<PriceLevel>> {
+    levels
+        .into_iter()
+        .map(|level| Ok((level.price.get().parse()?, level.qty.get().parse()?)))
+        .collect()
+}
+
+/*
+DTOs replicating the messages of the kraken v2 websocket api
+*/
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum KrakenMessage {
+    Channel(ChannelMessage),
+    Response(MethodResponse),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "channel")]
+enum ChannelMessage {
+    // read again as a BookMessage, the untagged enum buffers numbers as floats
+    #[serde(rename = "book")]
+    Book {},
+    #[serde(rename = "heartbeat")]
+    Heartbeat {},
+    #[serde(rename = "status")]
+    Status {},
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum BookKind {
+    Snapshot,
+    Update,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookMessage {
+    #[serde(rename = "type")]
+    kind: BookKind,
+    data: Vec<BookData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookData {
+    symbol: String,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    timestamp: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookLevel {
+    price: Box<RawValue>,
+    qty: Box<RawValue>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MethodResponse {
+    method: String,
+    #[serde(default)]
+    success: bool,
+    error: Option<String>,
+    req_id: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapters::mock_binance_server::{MockBinanceServer, MockStep},
+        typespec::{DepthSnapshot, DepthUpdate},
+    };
+
+    // frames recorded from the book channel
+    const SNAPSHOT_FRAME: &str = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":45283.5,"qty":0.10000000},{"price":45283.4,"qty":1.54582015}],"asks":[{"price":45285.2,"qty":0.00100000}],"checksum":3310070434}]}"#;
+    const UPDATE_FRAME: &str = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":45283.5,"qty":0.0}],"asks":[{"price":45285.3,"qty":0.00500000}],"checksum":281817320,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+
+    fn level(price: &str, qty: &str) -> PriceLevel {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn test_book_frames_normalised_into_snapshot_and_update() {
+        let mut protocol = KrakenProtocol::default();
+
+        let snapshot = protocol.parse(SNAPSHOT_FRAME).unwrap();
+        let update = protocol.parse(UPDATE_FRAME).unwrap();
+
+        assert_eq!(
+            snapshot,
+            vec![MarketEvent::BookSnapshot {
+                symbol: Symbol("BTCUSD".into()),
+                snapshot: DepthSnapshot {
+                    last_update_id: 1,
+                    bids: vec![level("45283.5", "0.1"), level("45283.4", "1.54582015")],
+                    asks: vec![level("45285.2", "0.001")],
+                },
+            }]
+        );
+        assert_eq!(
+            update,
+            vec![MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSD".into()),
+                event_time: 1696613755440,
+                first_update_id: 2,
+                final_update_id: 2,
+                bids: vec![level("45283.5", "0")],
+                asks: vec![level("45285.3", "0.005")],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_numbers_read_with_every_digit_sent() {
+        let mut protocol = KrakenProtocol::default();
+        // 17 and 18 significant digits, more than a float holds
+        let frame = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":45283.123456789012,"qty":0.123456789012345678}],"asks":[],"checksum":0}]}"#;
+
+        let snapshot = protocol.parse(frame).unwrap();
+
+        assert!(matches!(
+            &snapshot[..],
+            [MarketEvent::BookSnapshot { snapshot, .. }]
+                if snapshot.bids == vec![level("45283.123456789012", "0.123456789012345678")]
+        ));
+    }
+
+    #[test]
+    fn test_levels_pushed_out_of_depth_removed_by_update() {
+        let mut protocol = KrakenProtocol::default();
+        let bids: Vec<serde_json::Value> = (1..=BOOK_DEPTH)
+            .map(|price| json!({"price": price, "qty": 1}))
+            .collect();
+        let snapshot = json!({"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":bids,"asks":[],"checksum":0}]});
+        protocol.parse(&snapshot.to_string()).unwrap();
+
+        // a bid above the book pushes the lowest one out of the depth
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":1001,"qty":1}],"asks":[],"checksum":0}]}"#;
+        let pushed_out = protocol.parse(update).unwrap();
+        // a removal within the depth leaves room for a level below the book
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":500,"qty":0},{"price":0.5,"qty":1}],"asks":[],"checksum":0}]}"#;
+        let in_depth = protocol.parse(update).unwrap();
+
+        assert!(matches!(
+            &pushed_out[..],
+            [MarketEvent::DepthUpdate(DepthUpdate { bids, .. })]
+                if bids == &vec![level("1001", "1"), level("1", "0")]
+        ));
+        assert!(matches!(
+            &in_depth[..],
+            [MarketEvent::DepthUpdate(DepthUpdate { bids, .. })]
+                if bids == &vec![level("500", "0"), level("0.5", "1")]
+        ));
+    }
+
+    #[test]
+    fn test_method_responses_and_heartbeats() {
+        let mut protocol = KrakenProtocol::default();
+        let ack = r#"{"method":"subscribe","result":{"channel":"book","depth":1000,"snapshot":true,"symbol":"BTC/USD"},"success":true,"time_in":"2023-10-06T17:35:55.000000Z","time_out":"2023-10-06T17:35:55.000100Z","req_id":7}"#;
+        let error = r#"{"error":"Currency pair not supported ABC/USD","method":"subscribe","success":false,"symbol":"ABC/USD","time_in":"2023-10-06T17:35:55.000000Z","time_out":"2023-10-06T17:35:55.000100Z","req_id":8}"#;
+        let status = r#"{"channel":"status","type":"update","data":[{"api_version":"v2","connection_id":12393906104898154338,"system":"online","version":"2.0.0"}]}"#;
+
+        assert_eq!(
+            protocol.parse(ack).unwrap(),
+            vec![MarketEvent::SubscriptionAck { id: 7 }]
+        );
+        assert_eq!(
+            protocol.parse(error).unwrap(),
+            vec![MarketEvent::Error {
+                code: 0,
+                msg: "Currency pair not supported ABC/USD".into()
+            }]
+        );
+        assert_eq!(
+            protocol.parse(r#"{"channel":"heartbeat"}"#).unwrap(),
+            vec![MarketEvent::Heartbeat]
+        );
+        assert_eq!(protocol.parse(status).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_subscribe_request_uses_kraken_symbols() {
+        let requests = KrakenProtocol::default().subscribe_requests(&[Symbol("BTCUSD".into())]);
+
+        let request: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+        assert_eq!(request["params"]["symbol"], json!(["BTC/USD"]));
+        assert_eq!(request["req_id"], json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_frames_of_connection_become_events() {
+        // the mock sends any text frame so it can stand in for kraken as well
+        let server = MockBinanceServer::start(vec![vec![
+            MockStep::Text(SNAPSHOT_FRAME.into()),
+            MockStep::Text(UPDATE_FRAME.into()),
+        ]])
+        .await;
+
+        let connection = KrakenMarketStream::with_url(server.base_url())
+            .subscribe(vec![])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        assert!(matches!(
+            receiver.recv().await.unwrap().event,
+            MarketEvent::BookSnapshot { .. }
+        ));
+        assert!(matches!(
+            receiver.recv().await.unwrap().event,
+            MarketEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 2,
+                ..
+            })
+        ));
+    }
+}
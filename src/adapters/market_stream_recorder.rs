@@ -0,0 +1,163 @@
+use crate::{
+    ports::{MarketEvent, MarketMessage, MarketStreamMessageBroadcastReceiver},
+    typespec::unix_millis,
+};
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::broadcast::error::{RecvError, TryRecvError},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+// A line of a recording. Events are kept with the time they were received so they
+// can be replayed with the same spacing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    // milliseconds since the unix epoch
+    pub received_at: u64,
+    pub event: MarketEvent,
+}
+
+pub struct RecorderSettings {
+    pub directory: PathBuf,
+    // gzip each file of the recording
+    pub compress: bool,
+    // start a new file once the current one holds this many bytes
+    pub max_file_bytes: Option<u64>,
+    // start a new file once the current one is this old
+    pub max_file_age: Option<Duration>,
+}
+
+/*
+Tees the events of a market stream into NDJSON files, one recorded event per line.
+Files are named after the time they were created and their index in the recording,
+market-<unix millis>-<index>.ndjson with a .gz extension when compressed, so sorting
+the names gives the order of the recording.
+*/
+pub struct MarketStreamRecorder {
+    settings: RecorderSettings,
+}
+
+impl MarketStreamRecorder {
+    pub fn new(settings: RecorderSettings) -> Self {
+        Self { settings }
+    }
+
+    // Records until the market stream is closed. Files are written on a blocking
+    // thread so slow disks do not hold up the async runtime
+    pub fn record(
+        self,
+        market_stream: MarketStreamMessageBroadcastReceiver,
+    ) -> JoinHandle<Result<()>> {
+        let mut receiver = market_stream.resubscribe();
+
+        tokio::task::spawn_blocking(move || {
+            fs::create_dir_all(&self.settings.directory)?;
+            let mut file_index = 0;
+            let mut file = RecordingFile::create(&self.settings, file_index)?;
+
+            loop {
+                // flush whenever the recorder caught up so a crash loses as little as possible
+                let event = match receiver.try_recv() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => {
+                        file.flush()?;
+
+                        match receiver.blocking_recv() {
+                            Ok(event) => event,
+                            Err(RecvError::Lagged(skipped)) => {
+                                warn!(skipped, "market stream recorder lagged behind");
+                                continue;
+                            }
+                            Err(RecvError::Closed) => break,
+                        }
+                    }
+                    Err(TryRecvError::Lagged(skipped)) => {
+                        warn!(skipped, "market stream recorder lagged behind");
+                        continue;
+                    }
+                    Err(TryRecvError::Closed) => break,
+                };
+
+                if file.is_full(&self.settings) {
+                    file_index += 1;
+                    file = RecordingFile::create(&self.settings, file_index)?;
+                }
+                file.write_event(event)?;
+            }
+
+            file.flush()
+        })
+    }
+}
+
+struct RecordingFile {
+    writer: Box<dyn Write + Send>,
+    bytes_written: u64,
+    created_at: Instant,
+}
+
+impl RecordingFile {
+    fn create(settings: &RecorderSettings, index: u32) -> Result<Self> {
+        let path =
+            recording_file_path(&settings.directory, unix_millis(), index, settings.compress);
+        let file = BufWriter::new(File::create(path)?);
+
+        let writer: Box<dyn Write + Send> = if settings.compress {
+            Box::new(GzEncoder::new(file, Compression::default()))
+        } else {
+            Box::new(file)
+        };
+
+        Ok(Self {
+            writer,
+            bytes_written: 0,
+            created_at: Instant::now(),
+        })
+    }
+
+    fn write_event(&mut self, message: Arc<MarketMessage>) -> Result<()> {
+        let recorded = RecordedEvent {
+            received_at: unix_millis(),
+            event: message.event.clone(),
+        };
+
+        let mut line = serde_json::to_vec(&recorded)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.bytes_written += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn is_full(&self, settings: &RecorderSettings) -> bool {
+        let too_large = settings
+            .max_file_bytes
+            .is_some_and(|max_bytes| self.bytes_written >= max_bytes);
+        let too_old = settings
+            .max_file_age
+            .is_some_and(|max_age| self.created_at.elapsed() >= max_age);
+
+        too_large || too_old
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+// the index keeps files created within the same millisecond apart and in order
+fn recording_file_path(directory: &Path, created_at: u64, index: u32, compress: bool) -> PathBuf {
+    let extension = if compress { "ndjson.gz" } else { "ndjson" };
+
+    directory.join(format!("market-{}-{:06}.{}", created_at, index, extension))
+}
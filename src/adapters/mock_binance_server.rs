@@ -0,0 +1,180 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+
+// A step of the script a mock connection plays to the client
+pub(super) enum MockStep {
+    // waits for the next request of the client and acknowledges it
+    AwaitRequest,
+    DepthUpdate {
+        symbol: &'static str,
+        first_update_id: u64,
+        final_update_id: u64,
+        bids: Vec<(&'static str, &'static str)>,
+        asks: Vec<(&'static str, &'static str)>,
+    },
+    // sent as is, used for malformed frames
+    Text(String),
+    Ping,
+    Wait(Duration),
+    // closes the connection with a close frame
+    Close,
+    // drops the connection without a close frame
+    Disconnect,
+}
+
+// A request the client sent on one of its connections, counted from 0
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct MockRequest {
+    pub(super) connection: usize,
+    pub(super) method: String,
+    pub(super) params: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RequestMessage {
+    method: String,
+    #[serde(default)]
+    params: Vec<String>,
+    id: u64,
+}
+
+/*
+In-process stand-in for the binance combined stream api so adapters can be tested
+without network. Each accepted connection plays the next script of the server and keeps
+acknowledging requests once its script is done, connections beyond the last script only
+acknowledge requests. Requests of every connection are kept for assertions.
+*/
+pub(super) struct MockBinanceServer {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockBinanceServer {
+    pub(super) async fn start(scripts: Vec<Vec<MockStep>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("mock server to bind a local port");
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let connection_requests = requests.clone();
+        tokio::spawn(async move {
+            let mut scripts = scripts.into_iter();
+            let mut connection = 0;
+
+            while let Ok((stream, _)) = listener.accept().await {
+                let script = scripts.next().unwrap_or_default();
+                tokio::spawn(serve_connection(
+                    stream,
+                    connection,
+                    script,
+                    connection_requests.clone(),
+                ));
+                connection += 1;
+            }
+        });
+
+        Self { address, requests }
+    }
+
+    pub(super) fn base_url(&self) -> String {
+        format!("ws://{}", self.address)
+    }
+
+    pub(super) fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    connection: usize,
+    script: Vec<MockStep>,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+) {
+    let mut socket = match accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+
+    for step in script {
+        let sent = match step {
+            MockStep::AwaitRequest => acknowledge_request(&mut socket, connection, &requests).await,
+            MockStep::DepthUpdate {
+                symbol,
+                first_update_id,
+                final_update_id,
+                bids,
+                asks,
+            } => {
+                let frame = json!({
+                    "stream": format!("{}@depth", symbol.to_lowercase()),
+                    "data": {
+                        "e": "depthUpdate",
+                        "E": 1,
+                        "s": symbol,
+                        "U": first_update_id,
+                        "u": final_update_id,
+                        "b": bids,
+                        "a": asks,
+                    }
+                });
+                socket.send(Message::text(frame.to_string())).await.is_ok()
+            }
+            MockStep::Text(text) => socket.send(Message::text(text)).await.is_ok(),
+            MockStep::Ping => socket.send(Message::Ping(vec![1])).await.is_ok(),
+            MockStep::Wait(delay) => {
+                tokio::time::sleep(delay).await;
+                true
+            }
+            MockStep::Close => {
+                let _ = socket.close(None).await;
+                return;
+            }
+            MockStep::Disconnect => return,
+        };
+
+        if !sent {
+            return;
+        }
+    }
+
+    while acknowledge_request(&mut socket, connection, &requests).await {}
+}
+
+// Reads frames until a request arrives and answers it the way binance does.
+// Returns false once the connection is closed
+async fn acknowledge_request(
+    socket: &mut WebSocketStream<TcpStream>,
+    connection: usize,
+    requests: &Mutex<Vec<MockRequest>>,
+) -> bool {
+    loop {
+        let text = match socket.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return false,
+            Some(Ok(_)) => continue,
+        };
+
+        let request: RequestMessage = match serde_json::from_str(text.as_str()) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        requests.lock().unwrap().push(MockRequest {
+            connection,
+            method: request.method,
+            params: request.params,
+        });
+
+        let ack = json!({ "result": null, "id": request.id });
+        return socket.send(Message::text(ack.to_string())).await.is_ok();
+    }
+}
@@ -1,5 +1,25 @@
+mod backoff;
+mod binance_depth_snapshot;
 mod binance_market_stream;
 mod client_web_server;
+mod coinbase_market_stream;
+mod kraken_market_stream;
+mod market_stream_recorder;
+#[cfg(test)]
+mod mock_binance_server;
+mod okx_market_stream;
+mod prometheus_metrics;
+mod replay_market_stream;
+mod stream_depth_snapshot;
+mod venue_market_stream;
 
-pub use binance_market_stream::BinanceDiffDepthStream;
+pub use binance_depth_snapshot::BinanceDepthSnapshot;
+pub use binance_market_stream::{BinanceDiffDepthStream, BinanceTradeStream, DepthUpdateSpeed};
 pub use client_web_server::ClientWebServer;
+pub use coinbase_market_stream::CoinbaseMarketStream;
+pub use kraken_market_stream::KrakenMarketStream;
+pub use market_stream_recorder::{MarketStreamRecorder, RecordedEvent, RecorderSettings};
+pub use okx_market_stream::OkxMarketStream;
+pub use prometheus_metrics::PrometheusMetrics;
+pub use replay_market_stream::{RecordedDepthSnapshot, ReplayMarketStream, ReplaySpeed};
+pub use stream_depth_snapshot::StreamDepthSnapshot;
@@ -0,0 +1,329 @@
+use super::venue_market_stream::{self, UpdateIds, VenueProtocol};
+use crate::{
+    ports::{
+        MarketEvent, MarketStream, MarketStreamConnection, NoMetrics, SharedMetrics,
+        DEFAULT_EVENT_CAPACITY,
+    },
+    typespec::{PriceLevel, Symbol},
+};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+
+const OKX_WSS_URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
+
+// A infrastructure struct that implements a driven port for the books
+// channel of the okx v5 public websocket api
+pub struct OkxMarketStream {
+    url: String,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+}
+
+impl OkxMarketStream {
+    pub fn new() -> Self {
+        Self::with_url(OKX_WSS_URL)
+    }
+
+    pub fn with_url(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+}
+
+impl Default for OkxMarketStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarketStream for OkxMarketStream {
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        venue_market_stream::subscribe(
+            self.url.clone(),
+            OkxProtocol::default(),
+            symbols,
+            self.event_capacity,
+            self.metrics.clone(),
+        )
+        .await
+    }
+}
+
+/*
+OKX sends the snapshot of an instrument as the first books message after subscribing.
+Each message holds the sequence id of the previous one of the instrument. The sequence ids
+do not follow on each other and reset during maintenance, so update ids are counted per
+instrument and an id is skipped when a message does not follow the last one.
+*/
+#[derive(Clone, Default)]
+struct OkxProtocol {
+    update_ids: UpdateIds,
+    last_seq_ids: BTreeMap<Symbol, i64>,
+    last_request_id: u64,
+}
+
+impl VenueProtocol for OkxProtocol {
+    const VENUE: &'static str = "okx";
+    const MAX_SYMBOLS_PER_CONNECTION: usize = 100;
+    // okx allows 3 requests per second on a connection
+    const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(350);
+    // okx closes connections without any message for 30 seconds
+    const PING: Option<(&'static str, Duration)> = Some(("ping", Duration::from_secs(20)));
+
+    fn subscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        vec![self.request("subscribe", symbols)]
+    }
+
+    fn unsubscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String> {
+        vec![self.request("unsubscribe", symbols)]
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Vec<MarketEvent>> {
+        if text == "pong" {
+            return Ok(vec![MarketEvent::Heartbeat]);
+        }
+
+        let message: OkxMessage = serde_json::from_str(text)?;
+
+        let events = match message {
+            OkxMessage::Books { arg, action, data } => {
+                let symbol = Symbol::from_pair(arg.inst_id.as_str());
+
+                data.into_iter()
+                    .filter_map(|book| self.book_event(symbol.clone(), &action, book).transpose())
+                    .collect::<Result<Vec<MarketEvent>>>()?
+            }
+            OkxMessage::Event {
+                event,
+                code,
+                msg,
+                id,
+            } => match event.as_str() {
+                "error" => vec![MarketEvent::Error {
+                    code: code.and_then(|code| code.parse().ok()).unwrap_or_default(),
+                    msg: msg.unwrap_or_default(),
+                }],
+                _ => vec![MarketEvent::SubscriptionAck {
+                    id: id.and_then(|id| id.parse().ok()).unwrap_or_default(),
+                }],
+            },
+        };
+
+        Ok(events)
+    }
+}
+
+impl OkxProtocol {
+    fn request(&mut self, op: &str, symbols: &[Symbol]) -> String {
+        self.last_request_id += 1;
+
+        // okx names instruments with a dash between the assets, BTC-USDT
+        let args: Vec<serde_json::Value> = symbols
+            .iter()
+            .map(|symbol| {
+                let inst_id = symbol.to_pair('-').unwrap_or_else(|| symbol.0.clone());
+                json!({ "channel": "books", "instId": inst_id })
+            })
+            .collect();
+
+        json!({ "id": self.last_request_id.to_string(), "op": op, "args": args }).to_string()
+    }
+
+    // None for updates that do not change the book
+    fn book_event(
+        &mut self,
+        symbol: Symbol,
+        action: &BookAction,
+        book: BookData,
+    ) -> Result<Option<MarketEvent>> {
+        let bids = levels(book.bids)?;
+        let asks = levels(book.asks)?;
+        let last_seq_id = self.last_seq_ids.insert(symbol.clone(), book.seq_id);
+
+        let event = match action {
+            BookAction::Snapshot => self.update_ids.snapshot(symbol, bids, asks),
+            // okx repeats the sequence id when nothing changed
+            BookAction::Update if book.prev_seq_id == book.seq_id => return Ok(None),
+            BookAction::Update => {
+                if last_seq_id != Some(book.prev_seq_id) {
+                    self.update_ids.skip(&symbol);
+                }
+                self.update_ids.update(symbol, book.ts.parse()?, bids, asks)
+            }
+        };
+
+        Ok(Some(event))
+    }
+}
+
+// levels are sent as [price, quantity, deprecated, number of orders]
+fn levels(levels: Vec<Vec<String>>) -> Result<Vec<PriceLevel>> {
+    levels
+        .iter()
+        .map(|level| match level.as_slice() {
+            [price, qty, ..] => Ok((price.parse()?, qty.parse()?)),
+            _ => Err(anyhow!("book level without price and quantity")),
+        })
+        .collect()
+}
+
+/*
+DTOs replicating the messages of the okx v5 public websocket api
+*/
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OkxMessage {
+    Books {
+        arg: BooksArg,
+        action: BookAction,
+        data: Vec<BookData>,
+    },
+    Event {
+        event: String,
+        code: Option<String>,
+        msg: Option<String>,
+        id: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct BooksArg {
+    #[serde(rename = "instId")]
+    inst_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum BookAction {
+    Snapshot,
+    Update,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookData {
+    asks: Vec<Vec<String>>,
+    bids: Vec<Vec<String>>,
+    // milliseconds since the unix epoch
+    ts: String,
+    #[serde(rename = "prevSeqId")]
+    prev_seq_id: i64,
+    #[serde(rename = "seqId")]
+    seq_id: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::{DepthSnapshot, DepthUpdate};
+
+    // frames recorded from the books channel
+    const SNAPSHOT_FRAME: &str = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[["8476.98","415","0","13"],["8477","7","0","2"]],"bids":[["8476.97","256","0","12"]],"ts":"1597026383085","checksum":-855196043,"prevSeqId":-1,"seqId":123456}]}"#;
+    const UPDATE_FRAME: &str = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["8476.98","0","0","0"]],"bids":[],"ts":"1597026383185","checksum":-1200119424,"prevSeqId":123456,"seqId":123460}]}"#;
+
+    fn level(price: &str, qty: &str) -> PriceLevel {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn test_books_frames_normalised_into_snapshot_and_update() {
+        let mut protocol = OkxProtocol::default();
+
+        let snapshot = protocol.parse(SNAPSHOT_FRAME).unwrap();
+        let update = protocol.parse(UPDATE_FRAME).unwrap();
+
+        assert_eq!(
+            snapshot,
+            vec![MarketEvent::BookSnapshot {
+                symbol: Symbol("BTCUSDT".into()),
+                snapshot: DepthSnapshot {
+                    last_update_id: 1,
+                    bids: vec![level("8476.97", "256")],
+                    asks: vec![level("8476.98", "415"), level("8477", "7")],
+                },
+            }]
+        );
+        assert_eq!(
+            update,
+            vec![MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSDT".into()),
+                event_time: 1597026383185,
+                first_update_id: 2,
+                final_update_id: 2,
+                bids: vec![],
+                asks: vec![level("8476.98", "0")],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_update_not_following_last_sequence_id_leaves_gap() {
+        let mut protocol = OkxProtocol::default();
+
+        protocol.parse(SNAPSHOT_FRAME).unwrap();
+        let update = protocol
+            .parse(&UPDATE_FRAME.replace("\"prevSeqId\":123456", "\"prevSeqId\":123458"))
+            .unwrap();
+        let unchanged = protocol
+            .parse(&UPDATE_FRAME.replace("\"prevSeqId\":123456", "\"prevSeqId\":123460"))
+            .unwrap();
+
+        assert!(matches!(
+            update[0],
+            MarketEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 3,
+                ..
+            })
+        ));
+        assert_eq!(unchanged, vec![]);
+    }
+
+    #[test]
+    fn test_events_and_pongs() {
+        let mut protocol = OkxProtocol::default();
+        let ack = r#"{"id":"4","event":"subscribe","arg":{"channel":"books","instId":"BTC-USDT"},"connId":"a4d3ae55"}"#;
+        let error = r#"{"event":"error","code":"60012","msg":"Invalid request: {\"op\": \"subscribe\"}","connId":"a4d3ae55"}"#;
+
+        assert_eq!(
+            protocol.parse(ack).unwrap(),
+            vec![MarketEvent::SubscriptionAck { id: 4 }]
+        );
+        assert_eq!(
+            protocol.parse(error).unwrap(),
+            vec![MarketEvent::Error {
+                code: 60012,
+                msg: "Invalid request: {\"op\": \"subscribe\"}".into()
+            }]
+        );
+        assert_eq!(
+            protocol.parse("pong").unwrap(),
+            vec![MarketEvent::Heartbeat]
+        );
+    }
+
+    #[test]
+    fn test_subscribe_request_uses_instrument_ids() {
+        let requests = OkxProtocol::default().subscribe_requests(&[Symbol("BTCUSDT".into())]);
+
+        let request: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+        assert_eq!(
+            request,
+            json!({ "id": "1", "op": "subscribe", "args": [{ "channel": "books", "instId": "BTC-USDT" }] })
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{ports::Metrics, typespec::Symbol};
+use anyhow::Result;
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Duration;
+
+// prefix of the name of every metric of the service
+const NAMESPACE: &str = "orderbook";
+
+/*
+Metrics of the service kept in a prometheus registry of their own and served in the text
+exposition format on /metrics. Every metric is labelled by the names the reports are made
+with, so the streams, consumers, symbols, endpoints, queries and channels are all bounded
+by the settings and the routes of the service.
+*/
+pub struct PrometheusMetrics {
+    registry: Registry,
+    messages_received: IntCounterVec,
+    parse_failures: IntCounterVec,
+    lagged_messages: IntCounterVec,
+    reconnects: IntCounterVec,
+    book_resyncs: IntCounterVec,
+    clients_connected: IntGaugeVec,
+    query_seconds: HistogramVec,
+    delivery_seconds: HistogramVec,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let counter = |name: &str, help: &str, labels: &[&str]| -> Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help).namespace(NAMESPACE), labels)?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let histogram = |name: &str, help: &str, label: &str, buckets: Vec<f64>| {
+            let opts = HistogramOpts::new(name, help)
+                .namespace(NAMESPACE)
+                .buckets(buckets);
+            let histogram = HistogramVec::new(opts, &[label])?;
+            registry.register(Box::new(histogram.clone()))?;
+            Ok::<_, anyhow::Error>(histogram)
+        };
+
+        let messages_received = counter(
+            "market_messages_received_total",
+            "Messages received from the market streams",
+            &["venue", "stream"],
+        )?;
+        let parse_failures = counter(
+            "market_message_parse_failures_total",
+            "Messages of the market streams that could not be parsed",
+            &["venue", "stream"],
+        )?;
+        let lagged_messages = counter(
+            "lagged_messages_total",
+            "Messages of the market streams lost by consumers falling behind",
+            &["consumer"],
+        )?;
+        let reconnects = counter(
+            "market_reconnects_total",
+            "Connections to the market apis made again after they were lost",
+            &["venue", "stream"],
+        )?;
+        let book_resyncs = counter(
+            "order_book_resyncs_total",
+            "Order books bootstrapped again from a new snapshot after missing updates",
+            &["symbol"],
+        )?;
+
+        let clients_connected = IntGaugeVec::new(
+            Opts::new(
+                "clients_connected",
+                "Clients connected to the websockets and event streams",
+            )
+            .namespace(NAMESPACE),
+            &["endpoint"],
+        )?;
+        registry.register(Box::new(clients_connected.clone()))?;
+
+        // queries waiting for the next change of a book take as long as the market is quiet
+        let query_seconds = histogram(
+            "query_duration_seconds",
+            "Time the application took to answer a query",
+            "query",
+            exponential_buckets(0.0001, 4.0, 10)?,
+        )?;
+        let delivery_seconds = histogram(
+            "update_delivery_seconds",
+            "Time from the event time of a market message until the update is sent to a client",
+            "channel",
+            exponential_buckets(0.001, 2.0, 15)?,
+        )?;
+
+        Ok(Self {
+            registry,
+            messages_received,
+            parse_failures,
+            lagged_messages,
+            reconnects,
+            book_resyncs,
+            clients_connected,
+            query_seconds,
+            delivery_seconds,
+        })
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn message_received(&self, venue: &str, stream: &str) {
+        self.messages_received
+            .with_label_values(&[venue, stream])
+            .inc();
+    }
+
+    fn message_parse_failed(&self, venue: &str, stream: &str) {
+        self.parse_failures
+            .with_label_values(&[venue, stream])
+            .inc();
+    }
+
+    fn messages_lagged(&self, consumer: &str, skipped: u64) {
+        self.lagged_messages
+            .with_label_values(&[consumer])
+            .inc_by(skipped);
+    }
+
+    fn reconnected(&self, venue: &str, stream: &str) {
+        self.reconnects.with_label_values(&[venue, stream]).inc();
+    }
+
+    fn book_resynced(&self, symbol: &Symbol) {
+        self.book_resyncs.with_label_values(&[&symbol.0]).inc();
+    }
+
+    fn client_connected(&self, endpoint: &str) {
+        self.clients_connected.with_label_values(&[endpoint]).inc();
+    }
+
+    fn client_disconnected(&self, endpoint: &str) {
+        self.clients_connected.with_label_values(&[endpoint]).dec();
+    }
+
+    fn query_answered(&self, query: &str, elapsed: Duration) {
+        self.query_seconds
+            .with_label_values(&[query])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    fn update_delivered(&self, channel: &str, latency: Duration) {
+        self.delivery_seconds
+            .with_label_values(&[channel])
+            .observe(latency.as_secs_f64());
+    }
+
+    fn render(&self) -> Result<String> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_rendered_in_text_exposition_format() {
+        let metrics = PrometheusMetrics::new().unwrap();
+
+        metrics.message_received("binance", "depth");
+        metrics.message_received("binance", "depth");
+        metrics.messages_lagged("order_book_sync", 12);
+        metrics.client_connected("/api/stream");
+        metrics.client_connected("/api/stream");
+        metrics.client_disconnected("/api/stream");
+        metrics.query_answered("GetSpread", Duration::from_micros(150));
+
+        let text = metrics.render().unwrap();
+
+        assert!(text.contains(
+            "orderbook_market_messages_received_total{stream=\"depth\",venue=\"binance\"} 2"
+        ));
+        assert!(text.contains("orderbook_lagged_messages_total{consumer=\"order_book_sync\"} 12"));
+        assert!(text.contains("orderbook_clients_connected{endpoint=\"/api/stream\"} 1"));
+        assert!(text.contains("orderbook_query_duration_seconds_count{query=\"GetSpread\"} 1"));
+        assert!(text.contains(
+            "orderbook_query_duration_seconds_bucket{query=\"GetSpread\",le=\"0.0004\"} 1"
+        ));
+    }
+}
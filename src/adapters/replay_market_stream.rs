@@ -0,0 +1,518 @@
+use super::market_stream_recorder::RecordedEvent;
+use crate::{
+    ports::{
+        DepthSnapshotSource, MarketMessage, MarketStream, MarketStreamConnection,
+        SubscriptionCommand,
+    },
+    typespec::{DepthSnapshot, Symbol},
+};
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
+
+// replays are not rate limited by a market so consumers get more room before they lag
+const REPLAY_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    // events are spaced as they were received
+    Original,
+    // spacing between events is divided by the factor
+    Accelerated(f64),
+    // events are sent one after the other without waiting
+    AsFastAsPossible,
+}
+
+// original, max or the factor the spacing is divided by, like 10
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "original" => Ok(ReplaySpeed::Original),
+            "max" => Ok(ReplaySpeed::AsFastAsPossible),
+            factor => match factor.parse::<f64>() {
+                Ok(1.0) => Ok(ReplaySpeed::Original),
+                Ok(factor) if factor > 0.0 && factor.is_finite() => {
+                    Ok(ReplaySpeed::Accelerated(factor))
+                }
+                _ => Err(anyhow!(
+                    "replay speed {} is not original, max or a positive factor",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/*
+Market stream adapter that replays NDJSON recordings of the MarketStreamRecorder instead
+of connecting to a market. Only the events of subscribed symbols are sent, events without
+a symbol like connection states are always sent.
+
+The replay starts once a consumer subscribed to the receiver of the connection so
+no event is sent before anybody listens. The connection stays open after the last event
+unless the replay is made to close it, which lets consumers tell that the replay ended.
+*/
+pub struct ReplayMarketStream {
+    files: Vec<PathBuf>,
+    speed: ReplaySpeed,
+    event_capacity: usize,
+    consumers: usize,
+    close_at_end: bool,
+}
+
+impl ReplayMarketStream {
+    pub fn new(files: Vec<PathBuf>, speed: ReplaySpeed) -> Self {
+        Self {
+            files,
+            speed,
+            event_capacity: REPLAY_CHANNEL_CAPACITY,
+            consumers: 1,
+            close_at_end: false,
+        }
+    }
+
+    // recording files are replayed as given, directories with all of their recording files
+    pub fn from_paths(paths: &[PathBuf], speed: ReplaySpeed) -> Result<Self> {
+        let mut files = vec![];
+        for path in paths {
+            if path.is_dir() {
+                files.extend(Self::from_directory(path, speed)?.files);
+            } else {
+                files.push(path.clone());
+            }
+        }
+
+        Ok(Self::new(files, speed))
+    }
+
+    // replays every recording file of a directory in the order they were written
+    pub fn from_directory(directory: impl AsRef<Path>, speed: ReplaySpeed) -> Result<Self> {
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_recording_file(path))
+            .collect();
+        files.sort();
+
+        Ok(Self::new(files, speed))
+    }
+
+    // a replay as fast as possible does not lag consumers holding every event of the recording
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    // the replay waits for this many consumers so none of them misses the first events
+    pub fn with_consumers(self, consumers: usize) -> Self {
+        Self { consumers, ..self }
+    }
+
+    pub fn closing_at_end(self) -> Self {
+        Self {
+            close_at_end: true,
+            ..self
+        }
+    }
+
+    // every event of the recording files, in the order they were received
+    pub async fn recorded_events(&self) -> Result<Vec<RecordedEvent>> {
+        let files = self.files.clone();
+
+        tokio::task::spawn_blocking(move || read_recordings(&files)).await?
+    }
+
+    // symbols with any event in the recording files, in the order they first appear
+    pub async fn recorded_symbols(&self) -> Result<Vec<Symbol>> {
+        let mut symbols: Vec<Symbol> = vec![];
+        for recorded in self.recorded_events().await? {
+            if let Some(symbol) = recorded.event.symbol() {
+                if !symbols.contains(symbol) {
+                    symbols.push(symbol.clone());
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+impl MarketStream for ReplayMarketStream {
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection> {
+        let events = self.recorded_events().await?;
+        let speed = self.speed;
+        let consumers = self.consumers;
+        let close_at_end = self.close_at_end;
+
+        let (sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(self.event_capacity);
+        let (commands, mut command_receiver) = mpsc::unbounded_channel::<SubscriptionCommand>();
+
+        tokio::spawn(async move {
+            let mut active_symbols: BTreeSet<Symbol> = symbols.into_iter().collect();
+
+            // the connection holds a receiver of its own, subscriptions can change before the start
+            while sender.receiver_count() < consumers + 1 {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                    Some(command) = command_receiver.recv() => {
+                        if !apply_command(&mut active_symbols, command) {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            let started_at = Instant::now();
+            let first_received_at = events.first().map(|recorded| recorded.received_at);
+
+            for recorded in events {
+                let offset = Duration::from_millis(
+                    recorded
+                        .received_at
+                        .saturating_sub(first_received_at.unwrap_or(recorded.received_at)),
+                );
+                let send_at = match speed {
+                    ReplaySpeed::Original => Some(started_at + offset),
+                    ReplaySpeed::Accelerated(factor) => Some(started_at + offset.div_f64(factor)),
+                    ReplaySpeed::AsFastAsPossible => None,
+                };
+
+                // subscriptions change while waiting for the next event
+                if let Some(send_at) = send_at {
+                    loop {
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(send_at) => break,
+                            Some(command) = command_receiver.recv() => {
+                                if !apply_command(&mut active_symbols, command) {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    while let Ok(command) = command_receiver.try_recv() {
+                        if !apply_command(&mut active_symbols, command) {
+                            return;
+                        }
+                    }
+                    tokio::task::yield_now().await;
+                }
+
+                let subscribed = match recorded.event.symbol() {
+                    Some(symbol) => active_symbols.contains(symbol),
+                    None => true,
+                };
+                if subscribed {
+                    // recorded events are traced like the messages of a live market
+                    let span = MarketMessage::received("replay", "recording");
+                    let message = MarketMessage::parsed(recorded.event, &span);
+                    let _ = sender.send(Arc::new(message));
+                }
+            }
+
+            // receivers are told the replay ended once the sender is dropped
+            let _sender = (!close_at_end).then_some(sender);
+
+            // keep answering commands so clients do not wait on a finished replay
+            while let Some(command) = command_receiver.recv().await {
+                if !apply_command(&mut active_symbols, command) {
+                    return;
+                }
+            }
+        });
+
+        Ok(MarketStreamConnection::new(Arc::new(receiver), commands))
+    }
+}
+
+/*
+Snapshot source of replayed order books. Recordings can not be asked for a new snapshot,
+so books are only bootstrapped by the snapshots recorded on the stream and stay out of
+sync after a gap until the next one.
+*/
+pub struct RecordedDepthSnapshot;
+
+impl DepthSnapshotSource for RecordedDepthSnapshot {
+    async fn fetch_snapshot(&self, symbol: &Symbol) -> Result<DepthSnapshot> {
+        Err(anyhow!(
+            "book of {} waits for a snapshot in the recording",
+            symbol.0
+        ))
+    }
+}
+
+// Updates the active symbols and answers the command. Returns false once the replay is
+// asked to close so the task ends and receivers see the stream closed
+fn apply_command(active_symbols: &mut BTreeSet<Symbol>, command: SubscriptionCommand) -> bool {
+    match command {
+        SubscriptionCommand::Subscribe(symbols, respond) => {
+            active_symbols.extend(symbols);
+            let _ = respond.send(Ok(()));
+        }
+        SubscriptionCommand::Unsubscribe(symbols, respond) => {
+            for symbol in symbols.iter() {
+                active_symbols.remove(symbol);
+            }
+            let _ = respond.send(Ok(()));
+        }
+        SubscriptionCommand::Close(respond) => {
+            let _ = respond.send(Ok(()));
+            return false;
+        }
+    }
+
+    true
+}
+
+fn is_recording_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    name.starts_with("market-") && (name.ends_with(".ndjson") || name.ends_with(".ndjson.gz"))
+}
+
+// Reads the recorded events of the files in order, gzipped files are decompressed
+fn read_recordings(files: &[PathBuf]) -> Result<Vec<RecordedEvent>> {
+    let mut events = vec![];
+
+    for path in files {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("failed to open recording {}: {}", path.display(), e))?;
+        let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "gz") {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let recorded: RecordedEvent = serde_json::from_str(&line).map_err(|e| {
+                anyhow!(
+                    "invalid recorded event in {} line {}: {}",
+                    path.display(),
+                    line_number + 1,
+                    e
+                )
+            })?;
+            events.push(recorded);
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapters::{MarketStreamRecorder, RecorderSettings},
+        ports::MarketEvent,
+        typespec::{DepthUpdate, Trade},
+    };
+
+    // empty directory of the system temp dir for a single test
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("orderbook-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn depth_update(symbol: &str, update_id: u64) -> MarketEvent {
+        MarketEvent::DepthUpdate(DepthUpdate {
+            symbol: Symbol(symbol.into()),
+            event_time: 1,
+            first_update_id: update_id,
+            final_update_id: update_id,
+            bids: vec![("99.00".parse().unwrap(), "1".parse().unwrap())],
+            asks: vec![],
+        })
+    }
+
+    fn trade(symbol: &str) -> MarketEvent {
+        MarketEvent::Trade(Trade {
+            symbol: Symbol(symbol.into()),
+            trade_id: 1,
+            price: "100.00".parse().unwrap(),
+            quantity: "0.5".parse().unwrap(),
+            trade_time: 1,
+            buyer_is_maker: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_recorded_events_are_replayed_in_order() {
+        let directory = test_directory("recording");
+        let events = [
+            depth_update("BTCUSDC", 1),
+            trade("ETHUSDC"),
+            MarketEvent::Heartbeat,
+            depth_update("BTCUSDC", 2),
+        ];
+
+        // every event goes into its own compressed file
+        let (sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let recording = MarketStreamRecorder::new(RecorderSettings {
+            directory: directory.clone(),
+            compress: true,
+            max_file_bytes: Some(1),
+            max_file_age: None,
+        })
+        .record(Arc::new(receiver));
+        for event in events.iter() {
+            sender.send(Arc::new(event.clone().into())).unwrap();
+        }
+        drop(sender);
+        recording.await.unwrap().unwrap();
+
+        let replay =
+            ReplayMarketStream::from_directory(&directory, ReplaySpeed::AsFastAsPossible).unwrap();
+        assert_eq!(replay.files.len(), events.len());
+
+        let connection = replay
+            .subscribe(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        let mut replayed = vec![];
+        for _ in 0..3 {
+            replayed.push(receiver.recv().await.unwrap().event.clone());
+        }
+
+        // trades of the unsubscribed symbol are left out
+        assert_eq!(
+            replayed,
+            vec![
+                depth_update("BTCUSDC", 1),
+                MarketEvent::Heartbeat,
+                depth_update("BTCUSDC", 2),
+            ]
+        );
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_accelerated_replay_shortens_spacing() {
+        let directory = test_directory("accelerated-replay");
+        let lines: Vec<String> = [
+            (1_000, depth_update("BTCUSDC", 1)),
+            (5_000, depth_update("BTCUSDC", 2)),
+        ]
+        .into_iter()
+        .map(|(received_at, event)| {
+            serde_json::to_string(&RecordedEvent { received_at, event }).unwrap()
+        })
+        .collect();
+        let path = directory.join("market-1000-000000.ndjson");
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let connection = ReplayMarketStream::new(vec![path], ReplaySpeed::Accelerated(4.0))
+            .subscribe(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+        let mut receiver = connection.receiver.resubscribe();
+
+        receiver.recv().await.unwrap();
+        let first_sent_at = Instant::now();
+        receiver.recv().await.unwrap();
+
+        // 4 seconds apart in the recording
+        assert_eq!(first_sent_at.elapsed(), Duration::from_secs(1));
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test]
+    async fn test_replay_closing_at_end_waits_for_every_consumer() {
+        let directory = test_directory("closing-replay");
+        let path = directory.join("market-1000-000000.ndjson");
+        let line = serde_json::to_string(&RecordedEvent {
+            received_at: 1_000,
+            event: depth_update("BTCUSDC", 1),
+        })
+        .unwrap();
+        fs::write(&path, line).unwrap();
+
+        let connection = ReplayMarketStream::from_paths(
+            std::slice::from_ref(&directory),
+            "max".parse().unwrap(),
+        )
+        .unwrap()
+        .with_consumers(2)
+        .closing_at_end()
+        .subscribe(vec![Symbol("BTCUSDC".into())])
+        .await
+        .unwrap();
+        let mut first = connection.receiver.resubscribe();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut second = connection.receiver.resubscribe();
+
+        for receiver in [&mut first, &mut second] {
+            assert_eq!(
+                &receiver.recv().await.unwrap().event,
+                &depth_update("BTCUSDC", 1)
+            );
+            assert!(matches!(
+                receiver.recv().await,
+                Err(broadcast::error::RecvError::Closed)
+            ));
+        }
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_replay_speed_from_str() {
+        assert_eq!(
+            "original".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Original
+        );
+        assert_eq!("1".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Original);
+        assert_eq!(
+            "max".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::AsFastAsPossible
+        );
+        assert_eq!(
+            "2.5".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Accelerated(2.5)
+        );
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_recording_is_an_error() {
+        let directory = test_directory("invalid-recording");
+        let path = directory.join("market-1000-000000.ndjson");
+        fs::write(&path, "{\"received_at\": 1}").unwrap();
+
+        let res = ReplayMarketStream::new(vec![path], ReplaySpeed::Original)
+            .subscribe(vec![])
+            .await;
+
+        assert!(res.is_err());
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
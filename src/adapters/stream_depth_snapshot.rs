@@ -0,0 +1,112 @@
+use crate::{
+    ports::{DepthSnapshotSource, MarketEvent, MarketStreamConnection},
+    typespec::{DepthSnapshot, Symbol},
+};
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+// markets answer a subscription with a snapshot right away
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+Depth snapshot source of markets that send snapshots on their market stream instead of
+a REST api, like coinbase, kraken and okx. A new snapshot is requested by subscribing the
+symbol again and is taken from the stream once it arrives.
+*/
+pub struct StreamDepthSnapshot {
+    connection: MarketStreamConnection,
+}
+
+impl StreamDepthSnapshot {
+    pub fn new(connection: MarketStreamConnection) -> Self {
+        Self { connection }
+    }
+}
+
+impl DepthSnapshotSource for StreamDepthSnapshot {
+    async fn fetch_snapshot(&self, symbol: &Symbol) -> Result<DepthSnapshot> {
+        // subscribe before asking for the snapshot so it can not be missed
+        let mut receiver = self.connection.receiver.resubscribe();
+
+        self.connection.remove_symbols(vec![symbol.clone()]).await?;
+        self.connection.add_symbols(vec![symbol.clone()]).await?;
+
+        let snapshot = async {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => match &message.event {
+                        MarketEvent::BookSnapshot {
+                            symbol: snapshot_symbol,
+                            snapshot,
+                        } if snapshot_symbol == symbol => return Ok(snapshot.clone()),
+                        _ => continue,
+                    },
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(anyhow!("market stream closed")),
+                }
+            }
+        };
+
+        tokio::time::timeout(SNAPSHOT_TIMEOUT, snapshot)
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "no snapshot of {} received within {} seconds",
+                    symbol.0,
+                    SNAPSHOT_TIMEOUT.as_secs()
+                )
+            })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::SubscriptionCommand;
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+
+    #[tokio::test]
+    async fn test_snapshot_taken_from_stream_after_subscribing_again() {
+        let (sender, receiver) = broadcast::channel(16);
+        let (commands, mut command_receiver) = mpsc::unbounded_channel();
+        let source =
+            StreamDepthSnapshot::new(MarketStreamConnection::new(Arc::new(receiver), commands));
+
+        // stand-in for a market that answers a subscription with a snapshot
+        tokio::spawn(async move {
+            while let Some(command) = command_receiver.recv().await {
+                match command {
+                    SubscriptionCommand::Unsubscribe(_, respond)
+                    | SubscriptionCommand::Close(respond) => {
+                        let _ = respond.send(Ok(()));
+                    }
+                    SubscriptionCommand::Subscribe(symbols, respond) => {
+                        let _ = respond.send(Ok(()));
+                        for symbol in symbols {
+                            let _ = sender.send(Arc::new(
+                                MarketEvent::BookSnapshot {
+                                    symbol,
+                                    snapshot: DepthSnapshot {
+                                        last_update_id: 1,
+                                        bids: vec![],
+                                        asks: vec![],
+                                    },
+                                }
+                                .into(),
+                            ));
+                        }
+                    }
+                }
+            }
+        });
+
+        let snapshot = source
+            .fetch_snapshot(&Symbol("BTCUSD".into()))
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot.last_update_id, 1);
+    }
+}
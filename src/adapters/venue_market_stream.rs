@@ -0,0 +1,738 @@
+use super::backoff::Backoff;
+use crate::{
+    ports::{
+        ConnectionState, MarketEvent, MarketMessage, MarketStreamCommandReceiver,
+        MarketStreamConnection, MarketStreamMessageBroadcastSender, SharedMetrics,
+        SubscriptionCommand,
+    },
+    typespec::{DepthSnapshot, DepthUpdate, PriceLevel, Symbol},
+};
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, oneshot},
+    time::Instant,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+// every supported market sends a ping, a heartbeat or a book update at least every few
+// seconds, a connection without any message for longer is considered dead
+const STALE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+type VenueSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/*
+Protocol of a market api that streams json over a websocket. The supervisor of this module
+keeps the connection alive and subscribed while the protocol turns requests and frames of
+its market into the shared model. The protocol handed to the supervisor is cloned for each
+connection so state like sequence numbers starts over with it.
+*/
+pub(super) trait VenueProtocol: Clone + Send + Sync + 'static {
+    // name of the market used in logs and errors
+    const VENUE: &'static str;
+    const MAX_SYMBOLS_PER_CONNECTION: usize;
+    // time to leave between two requests to stay under the rate limit of the market
+    const MIN_REQUEST_INTERVAL: Duration;
+    // text frame sent when the connection was idle for the interval, for markets that
+    // close connections without application level pings
+    const PING: Option<(&'static str, Duration)>;
+    // age after which a connection is made again, for markets that close them on a schedule
+    const MAX_CONNECTION_AGE: Option<Duration> = None;
+
+    // kind of stream the metrics and logs of the connection are labelled with
+    fn stream(&self) -> &'static str {
+        "depth"
+    }
+
+    fn subscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String>;
+
+    fn unsubscribe_requests(&mut self, symbols: &[Symbol]) -> Vec<String>;
+
+    // Events of a text frame. Frames without any, like status messages, return none
+    fn parse(&mut self, text: &str) -> Result<Vec<MarketEvent>>;
+}
+
+// Connects to the market and spawns the supervisor streaming the events of the symbols
+// into the connection handed back
+pub(super) async fn subscribe<P: VenueProtocol>(
+    url: String,
+    protocol: P,
+    symbols: Vec<Symbol>,
+    event_capacity: usize,
+    metrics: SharedMetrics,
+) -> Result<MarketStreamConnection> {
+    let symbols = plan_subscription::<P>(&BTreeSet::new(), symbols)?;
+    let active_symbols: BTreeSet<Symbol> = symbols.into_iter().collect();
+    let stream = protocol.stream();
+    let initial_protocol = protocol;
+
+    // the first connection is made here so an unreachable api is reported to the caller
+    let (mut socket, mut protocol) = connect(&url, &initial_protocol, &active_symbols).await?;
+
+    // keep events within an arc to minimize memory used among
+    // copying messages by the receiver
+    let (sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(event_capacity);
+    let (commands, command_receiver) = mpsc::unbounded_channel::<SubscriptionCommand>();
+
+    /*
+    Supervisor of the connection. Whenever the connection is lost it is made again
+    after a jittered exponential backoff and the active symbols are subscribed again.
+    Every change is sent as a connection state event so consumers know the events
+    in between were lost.
+    */
+    tokio::spawn(async move {
+        let mut task = VenueTask {
+            sender,
+            commands: command_receiver,
+            active_symbols,
+            last_request: Instant::now(),
+            stream,
+            closing: None,
+            metrics,
+        };
+        let mut backoff = Backoff::default();
+
+        loop {
+            let reason = match task.stream_events(&mut socket, &mut protocol).await {
+                Some(reason) => reason,
+                // every handle of the connection is dropped so nobody listens anymore or
+                // the connection is asked to close once its symbols are unsubscribed
+                None => {
+                    let closed = socket
+                        .close(None)
+                        .await
+                        .map_err(|e| anyhow!("failed to close the {} connection: {}", P::VENUE, e));
+                    task.closed(closed);
+                    break;
+                }
+            };
+            // the connection broke while its symbols were unsubscribed on close
+            if task.closing.is_some() {
+                task.closed(Err(anyhow!(
+                    "{} connection lost on close: {}",
+                    P::VENUE,
+                    reason
+                )));
+                return;
+            }
+            warn!(venue = P::VENUE, stream, %reason, "market connection lost");
+            task.publish(ConnectionState::Disconnected {
+                reason: reason.to_string(),
+            });
+
+            (socket, protocol) = loop {
+                let attempt = backoff.attempt + 1;
+                let delay = backoff.next_delay();
+                task.publish(ConnectionState::Reconnecting { attempt, delay });
+
+                if !task.wait::<P>(delay).await {
+                    task.closed(Ok(()));
+                    return;
+                }
+
+                match connect(&url, &initial_protocol, &task.active_symbols).await {
+                    Ok(connection) => break connection,
+                    Err(e) => warn!(
+                        venue = P::VENUE,
+                        stream,
+                        attempt,
+                        error = %e,
+                        "market reconnect attempt failed"
+                    ),
+                }
+            };
+
+            backoff = Backoff::default();
+            task.last_request = Instant::now();
+            task.metrics.reconnected(P::VENUE, stream);
+            info!(venue = P::VENUE, stream, "market connection restored");
+            task.publish(ConnectionState::Connected);
+        }
+    });
+
+    Ok(MarketStreamConnection::new(Arc::new(receiver), commands))
+}
+
+// Connects to the market and subscribes the symbols with a fresh copy of the protocol
+async fn connect<P: VenueProtocol>(
+    url: &str,
+    initial_protocol: &P,
+    symbols: &BTreeSet<Symbol>,
+) -> Result<(VenueSocket, P)> {
+    let (mut socket, _resp) = connect_async(url)
+        .await
+        .map_err(|e| anyhow!("Failed to connect to {}: {}", P::VENUE, e))?;
+    let mut protocol = initial_protocol.clone();
+
+    if !symbols.is_empty() {
+        let symbols: Vec<Symbol> = symbols.iter().cloned().collect();
+
+        for request in protocol.subscribe_requests(&symbols) {
+            socket.send(Message::text(request)).await?;
+        }
+    }
+
+    Ok((socket, protocol))
+}
+
+// Reasons for the supervisor to drop a connection and make a new one
+#[derive(Debug, PartialEq)]
+enum Disconnect {
+    Closed,
+    Failed(String),
+    Stale,
+    Expired,
+}
+
+impl fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disconnect::Closed => write!(f, "connection closed by the market"),
+            Disconnect::Failed(e) => write!(f, "connection error: {}", e),
+            Disconnect::Stale => write!(
+                f,
+                "no message received for {} seconds",
+                STALE_CONNECTION_TIMEOUT.as_secs()
+            ),
+            Disconnect::Expired => write!(f, "connection reached the age limit of the market"),
+        }
+    }
+}
+
+// Requests sent to the market to bring the connection in line with the active symbols
+#[derive(Debug, PartialEq)]
+enum StreamRequest {
+    Subscribe(Vec<Symbol>),
+    Unsubscribe(Vec<Symbol>),
+}
+
+// answer of a command that is sent once its request is written to the market
+type PendingAnswer = Option<oneshot::Sender<Result<()>>>;
+
+// State of the spawned task that outlives each single connection
+struct VenueTask {
+    sender: MarketStreamMessageBroadcastSender,
+    commands: MarketStreamCommandReceiver,
+    active_symbols: BTreeSet<Symbol>,
+    last_request: Instant,
+    // kind of stream the metrics and logs of the connection are labelled with
+    stream: &'static str,
+    // answered once the connection is closed on request
+    closing: Option<oneshot::Sender<Result<()>>>,
+    metrics: SharedMetrics,
+}
+
+impl VenueTask {
+    fn publish(&self, state: ConnectionState) {
+        let _ = self
+            .sender
+            .send(Arc::new(MarketEvent::ConnectionState(state).into()));
+    }
+
+    fn closed(&mut self, result: Result<()>) {
+        if let Some(respond) = self.closing.take() {
+            let _ = respond.send(result);
+        }
+    }
+
+    // Passes the events of a connection into the broadcast channel until the connection
+    // is lost. None is returned when every handle of the connection is dropped or the
+    // connection is asked to close
+    async fn stream_events<P: VenueProtocol>(
+        &mut self,
+        socket: &mut VenueSocket,
+        protocol: &mut P,
+    ) -> Option<Disconnect> {
+        let connected_at = Instant::now();
+        let mut last_message = Instant::now();
+        let mut last_ping = Instant::now();
+
+        loop {
+            let ping_interval = P::PING
+                .map(|(_, interval)| interval)
+                .unwrap_or(STALE_CONNECTION_TIMEOUT);
+            let next_ping = last_message.max(last_ping) + ping_interval;
+
+            tokio::select! {
+                message = socket.next() => {
+                    last_message = Instant::now();
+
+                    let messages: Vec<MarketMessage> = match message {
+                        Some(Ok(Message::Text(text))) => {
+                            let span = MarketMessage::received(P::VENUE, self.stream);
+                            let _entered = span.enter();
+                            self.metrics.message_received(P::VENUE, self.stream);
+
+                            // events of one message share its span
+                            match protocol.parse(text.as_str()) {
+                                Ok(events) => events
+                                    .into_iter()
+                                    .map(|event| MarketMessage::parsed(event, &span))
+                                    .collect(),
+                                Err(e) => {
+                                    warn!(error = %e, "failed to parse market message");
+                                    self.metrics.message_parse_failed(P::VENUE, self.stream);
+                                    continue;
+                                }
+                            }
+                        }
+                        // pong replies are queued by tungstenite itself and
+                        // flushed with the next read of the connection
+                        Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
+                            vec![MarketEvent::Heartbeat.into()]
+                        }
+                        Some(Ok(Message::Close(_))) | None => return Some(Disconnect::Closed),
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return Some(Disconnect::Failed(e.to_string())),
+                    };
+
+                    for message in messages {
+                        let _ = self.sender.send(Arc::new(message));
+                    }
+                }
+                command = self.commands.recv() => {
+                    let request = match command {
+                        Some(command) => self.apply_command::<P>(command),
+                        None => return None,
+                    };
+
+                    if let Some((request, answer)) = request {
+                        // space out requests to stay under the message rate limit
+                        tokio::time::sleep_until(self.last_request + P::MIN_REQUEST_INTERVAL).await;
+
+                        let texts = match &request {
+                            StreamRequest::Subscribe(symbols) => protocol.subscribe_requests(symbols),
+                            StreamRequest::Unsubscribe(symbols) => {
+                                protocol.unsubscribe_requests(symbols)
+                            }
+                        };
+                        let mut sent = Ok(());
+                        for text in texts {
+                            if let Err(e) = socket.send(Message::text(text)).await {
+                                sent = Err(e.to_string());
+                                break;
+                            }
+                        }
+                        self.last_request = Instant::now();
+
+                        self.answer_request(&request, answer, &sent);
+                        if let Err(reason) = sent {
+                            return Some(Disconnect::Failed(reason));
+                        }
+                    }
+
+                    if self.closing.is_some() {
+                        return None;
+                    }
+                }
+                _ = tokio::time::sleep_until(next_ping), if P::PING.is_some() => {
+                    let ping = P::PING.map(|(ping, _)| ping).unwrap_or_default();
+
+                    if let Err(e) = socket.send(Message::text(ping)).await {
+                        return Some(Disconnect::Failed(e.to_string()));
+                    }
+                    last_ping = Instant::now();
+                }
+                _ = tokio::time::sleep_until(last_message + STALE_CONNECTION_TIMEOUT) => {
+                    return Some(Disconnect::Stale);
+                }
+                _ = tokio::time::sleep_until(connected_at + P::MAX_CONNECTION_AGE.unwrap_or_default()),
+                    if P::MAX_CONNECTION_AGE.is_some() => {
+                    return Some(Disconnect::Expired);
+                }
+            }
+        }
+    }
+
+    // Waits out a reconnect delay while still answering commands. Returns false when
+    // every handle of the connection is dropped or the connection is asked to close
+    async fn wait<P: VenueProtocol>(&mut self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => return true,
+                command = self.commands.recv() => match command {
+                    // symbols of commands sent in between are subscribed with the next connection
+                    Some(command) => {
+                        if let Some((_, Some(answer))) = self.apply_command::<P>(command) {
+                            let _ = answer.send(Ok(()));
+                        }
+                        if self.closing.is_some() {
+                            return false;
+                        }
+                    }
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    // Updates the active symbols. Returns the request the market needs to receive for a
+    // live connection to match the active symbols along with the answer of the command,
+    // which is left to be sent once the request is written. Commands without a request
+    // are answered right away
+    fn apply_command<P: VenueProtocol>(
+        &mut self,
+        command: SubscriptionCommand,
+    ) -> Option<(StreamRequest, PendingAnswer)> {
+        match command {
+            SubscriptionCommand::Subscribe(symbols, respond) => {
+                match plan_subscription::<P>(&self.active_symbols, symbols) {
+                    Ok(new_symbols) if new_symbols.is_empty() => {
+                        let _ = respond.send(Ok(()));
+                        None
+                    }
+                    Ok(new_symbols) => {
+                        self.active_symbols.extend(new_symbols.iter().cloned());
+                        Some((StreamRequest::Subscribe(new_symbols), Some(respond)))
+                    }
+                    Err(e) => {
+                        let _ = respond.send(Err(e));
+                        None
+                    }
+                }
+            }
+            SubscriptionCommand::Unsubscribe(symbols, respond) => {
+                let old_symbols: Vec<Symbol> = symbols
+                    .into_iter()
+                    .filter(|symbol| self.active_symbols.remove(symbol))
+                    .collect();
+
+                if old_symbols.is_empty() {
+                    let _ = respond.send(Ok(()));
+                    None
+                } else {
+                    Some((StreamRequest::Unsubscribe(old_symbols), Some(respond)))
+                }
+            }
+            // answered by the supervisor once the connection is closed
+            SubscriptionCommand::Close(respond) => {
+                self.closing = Some(respond);
+                let old_symbols: Vec<Symbol> = std::mem::take(&mut self.active_symbols)
+                    .into_iter()
+                    .collect();
+
+                if old_symbols.is_empty() {
+                    None
+                } else {
+                    Some((StreamRequest::Unsubscribe(old_symbols), None))
+                }
+            }
+        }
+    }
+
+    // Answers a command once its request is written to the market or the connection failed
+    // writing it. Symbols of a subscribe that failed are no longer active, so the next
+    // connection does not subscribe them behind the back of the caller told about the error.
+    // The symbols of a failed unsubscribe are already left out of the next connection
+    fn answer_request(
+        &mut self,
+        request: &StreamRequest,
+        answer: PendingAnswer,
+        sent: &std::result::Result<(), String>,
+    ) {
+        let result = match (request, sent) {
+            (StreamRequest::Subscribe(symbols), Err(reason)) => {
+                for symbol in symbols {
+                    self.active_symbols.remove(symbol);
+                }
+                Err(anyhow!("subscribe request was not sent: {}", reason))
+            }
+            _ => Ok(()),
+        };
+
+        if let Some(answer) = answer {
+            let _ = answer.send(result);
+        }
+    }
+}
+
+// Returns the requested symbols that are not subscribed yet or an error
+// when subscribing them would go over the symbol limit of a connection
+fn plan_subscription<P: VenueProtocol>(
+    active_symbols: &BTreeSet<Symbol>,
+    requested: Vec<Symbol>,
+) -> Result<Vec<Symbol>> {
+    let new_symbols: BTreeSet<Symbol> = requested
+        .into_iter()
+        .filter(|symbol| !active_symbols.contains(symbol))
+        .collect();
+
+    if active_symbols.len() + new_symbols.len() > P::MAX_SYMBOLS_PER_CONNECTION {
+        return Err(anyhow!(
+            "Too many symbols. {} max limit {}",
+            P::VENUE,
+            P::MAX_SYMBOLS_PER_CONNECTION
+        ));
+    }
+
+    Ok(new_symbols.into_iter().collect())
+}
+
+/*
+Update ids of markets whose own sequence numbers do not follow on each other per book.
+The snapshot and every update of a symbol take the next id of the symbol, and an id is
+skipped whenever the market shows that a message was lost so the local book finds the
+gap and is bootstrapped again.
+*/
+#[derive(Clone, Default)]
+pub(super) struct UpdateIds {
+    last: BTreeMap<Symbol, u64>,
+}
+
+impl UpdateIds {
+    fn next(&mut self, symbol: &Symbol) -> u64 {
+        let id = self.last.entry(symbol.clone()).or_insert(0);
+        *id += 1;
+
+        *id
+    }
+
+    pub(super) fn skip(&mut self, symbol: &Symbol) {
+        self.next(symbol);
+    }
+
+    // a lost message can hold levels of any symbol
+    pub(super) fn skip_all(&mut self) {
+        for id in self.last.values_mut() {
+            *id += 1;
+        }
+    }
+
+    pub(super) fn snapshot(
+        &mut self,
+        symbol: Symbol,
+        bids: Vec<PriceLevel>,
+        asks: Vec<PriceLevel>,
+    ) -> MarketEvent {
+        MarketEvent::BookSnapshot {
+            snapshot: DepthSnapshot {
+                last_update_id: self.next(&symbol),
+                bids,
+                asks,
+            },
+            symbol,
+        }
+    }
+
+    pub(super) fn update(
+        &mut self,
+        symbol: Symbol,
+        event_time: u64,
+        bids: Vec<PriceLevel>,
+        asks: Vec<PriceLevel>,
+    ) -> MarketEvent {
+        let update_id = self.next(&symbol);
+
+        MarketEvent::DepthUpdate(DepthUpdate {
+            symbol,
+            event_time,
+            first_update_id: update_id,
+            final_update_id: update_id,
+            bids,
+            asks,
+        })
+    }
+}
+
+// milliseconds since the unix epoch of an RFC 3339 timestamp like 2023-02-09T20:32:50.714964855Z
+pub(super) fn rfc_3339_millis(timestamp: &str) -> Result<u64> {
+    let time = chrono::DateTime::parse_from_rfc3339(timestamp)?;
+
+    Ok(time.timestamp_millis().max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::NoMetrics;
+
+    // protocol of a market that allows two symbols per connection
+    #[derive(Clone, Default)]
+    struct TwoSymbolProtocol;
+
+    impl VenueProtocol for TwoSymbolProtocol {
+        const VENUE: &'static str = "test";
+        const MAX_SYMBOLS_PER_CONNECTION: usize = 2;
+        const MIN_REQUEST_INTERVAL: Duration = Duration::ZERO;
+        const PING: Option<(&'static str, Duration)> = None;
+
+        fn subscribe_requests(&mut self, _symbols: &[Symbol]) -> Vec<String> {
+            vec![]
+        }
+
+        fn unsubscribe_requests(&mut self, _symbols: &[Symbol]) -> Vec<String> {
+            vec![]
+        }
+
+        fn parse(&mut self, _text: &str) -> Result<Vec<MarketEvent>> {
+            Ok(vec![])
+        }
+    }
+
+    fn level(price: &str, qty: &str) -> PriceLevel {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn test_subscription_plan_skips_active_symbols() {
+        let active: BTreeSet<Symbol> = [Symbol("BTCUSDC".into())].into_iter().collect();
+
+        let new_symbols = plan_subscription::<TwoSymbolProtocol>(
+            &active,
+            vec![Symbol("BTCUSDC".into()), Symbol("ETHUSDC".into())],
+        )
+        .unwrap();
+
+        assert_eq!(new_symbols, vec![Symbol("ETHUSDC".into())]);
+    }
+
+    #[test]
+    fn test_subscription_plan_respects_symbol_limit() {
+        let active: BTreeSet<Symbol> = (0..TwoSymbolProtocol::MAX_SYMBOLS_PER_CONNECTION)
+            .map(|i| Symbol(format!("PAIR{}", i)))
+            .collect();
+
+        let at_limit =
+            plan_subscription::<TwoSymbolProtocol>(&active, vec![Symbol("PAIR0".into())]);
+        let over_limit =
+            plan_subscription::<TwoSymbolProtocol>(&active, vec![Symbol("BTCUSDC".into())]);
+
+        assert!(at_limit.is_ok());
+        assert!(over_limit.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_commands_while_reconnecting_update_active_symbols() {
+        let (sender, _receiver) = broadcast::channel(16);
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let connection = MarketStreamConnection::new(Arc::new(sender.subscribe()), commands);
+        let mut task = VenueTask {
+            sender,
+            commands: command_receiver,
+            active_symbols: [Symbol("BTCUSDC".into())].into_iter().collect(),
+            last_request: Instant::now(),
+            stream: "depth",
+            closing: None,
+            metrics: NoMetrics::shared(),
+        };
+
+        let waiting = tokio::spawn(async move {
+            task.wait::<TwoSymbolProtocol>(Duration::from_millis(200))
+                .await;
+            task.active_symbols
+        });
+        connection
+            .add_symbols(vec![Symbol("ETHUSDC".into())])
+            .await
+            .unwrap();
+        connection
+            .remove_symbols(vec![Symbol("BTCUSDC".into())])
+            .await
+            .unwrap();
+
+        let active_symbols: Vec<Symbol> = waiting.await.unwrap().into_iter().collect();
+
+        assert_eq!(active_symbols, vec![Symbol("ETHUSDC".into())]);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_answered_once_its_request_is_sent() {
+        let (sender, _receiver) = broadcast::channel(16);
+        let (_commands, command_receiver) = mpsc::unbounded_channel();
+        let mut task = VenueTask {
+            sender,
+            commands: command_receiver,
+            active_symbols: [Symbol("BTCUSDC".into())].into_iter().collect(),
+            last_request: Instant::now(),
+            stream: "depth",
+            closing: None,
+            metrics: NoMetrics::shared(),
+        };
+        let subscribe = |symbol: &str| {
+            let (respond, response) = oneshot::channel();
+            let command = SubscriptionCommand::Subscribe(vec![Symbol(symbol.into())], respond);
+            (command, response)
+        };
+
+        let (command, mut sent_response) = subscribe("ETHUSDC");
+        let (request, answer) = task.apply_command::<TwoSymbolProtocol>(command).unwrap();
+        assert_eq!(
+            request,
+            StreamRequest::Subscribe(vec![Symbol("ETHUSDC".into())])
+        );
+        assert!(sent_response.try_recv().is_err());
+        task.answer_request(&request, answer, &Ok(()));
+        assert!(sent_response.await.unwrap().is_ok());
+
+        // leaves room for another symbol under the limit of two
+        task.active_symbols.remove(&Symbol("ETHUSDC".into()));
+        let (command, failed_response) = subscribe("SOLUSDC");
+        let (request, answer) = task.apply_command::<TwoSymbolProtocol>(command).unwrap();
+        task.answer_request(&request, answer, &Err("connection reset".into()));
+        assert!(failed_response.await.unwrap().is_err());
+        // the next connection only subscribes the symbols the callers were told about
+        assert_eq!(
+            task.active_symbols.into_iter().collect::<Vec<Symbol>>(),
+            vec![Symbol("BTCUSDC".into())]
+        );
+    }
+
+    #[test]
+    fn test_update_ids_follow_snapshot_and_skip_lost_messages() {
+        let mut update_ids = UpdateIds::default();
+        let btc = Symbol("BTCUSD".into());
+        let eth = Symbol("ETHUSD".into());
+
+        let snapshot = update_ids.snapshot(btc.clone(), vec![level("99", "1")], vec![]);
+        let btc_update = update_ids.update(btc.clone(), 1, vec![], vec![level("101", "1")]);
+        update_ids.snapshot(eth.clone(), vec![], vec![]);
+        update_ids.skip_all();
+        let eth_update = update_ids.update(eth.clone(), 1, vec![], vec![]);
+
+        assert!(matches!(
+            snapshot,
+            MarketEvent::BookSnapshot {
+                snapshot: DepthSnapshot {
+                    last_update_id: 1,
+                    ..
+                },
+                ..
+            }
+        ));
+        assert!(matches!(
+            btc_update,
+            MarketEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 2,
+                final_update_id: 2,
+                ..
+            })
+        ));
+        // id 2 of eth was skipped
+        assert!(matches!(
+            eth_update,
+            MarketEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rfc_3339_timestamp_in_unix_millis() {
+        assert_eq!(
+            rfc_3339_millis("2023-02-09T20:32:50.714964855Z").unwrap(),
+            1675974770714
+        );
+        assert!(rfc_3339_millis("yesterday").is_err());
+    }
+}
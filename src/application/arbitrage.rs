@@ -0,0 +1,222 @@
+use super::{Application, ApplicationResponse};
+use crate::{
+    core::{self, ArbitrageOpportunity, ArbitrageSettings},
+    typespec::{unix_millis, Symbol, Venue},
+};
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeSet, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+
+// decimal places of the edge of an opportunity in basis points
+const EDGE_DECIMAL_PLACES: u32 = 4;
+
+impl Application {
+    // Fees and threshold opportunities between the venues are detected with
+    pub fn with_arbitrage_settings(self, settings: ArbitrageSettings) -> Self {
+        Self {
+            arbitrage_settings: Arc::new(settings),
+            ..self
+        }
+    }
+
+    // Opportunities between the synced books of every venue trading the instrument
+    pub(super) async fn arbitrage_opportunities(&self, symbol: Symbol) -> ApplicationResponse {
+        let (instrument, synced) = match self.synced_venue_books(symbol).await {
+            Ok(books) => books,
+            Err(res) => return res,
+        };
+
+        let opportunities = core::detect_arbitrage(
+            synced.iter().map(|(venue, _, book)| (venue, book)),
+            &self.arbitrage_settings,
+        );
+
+        ApplicationResponse::ArbitrageOpportunities {
+            symbol: instrument,
+            detected_at: unix_millis(),
+            opportunities: opportunities
+                .into_iter()
+                .map(|opportunity| ArbitrageOpportunity {
+                    edge_bps: opportunity
+                        .edge_bps
+                        .round_dp(EDGE_DECIMAL_PLACES)
+                        .normalize(),
+                    ..opportunity
+                })
+                .collect(),
+        }
+    }
+
+    // Waits for a book of the instrument to change on any venue before detecting opportunities
+    pub(super) async fn next_arbitrage_opportunities(&self, symbol: Symbol) -> ApplicationResponse {
+        // subscribe before checking the books so no update is missed in between
+        let mut updates = self.venue_book_updates.subscribe();
+
+        let instrument = match self.synced_venue_books(symbol.clone()).await {
+            Ok((instrument, _)) => instrument,
+            Err(ApplicationResponse::UnknownSymbol { symbol }) => {
+                return ApplicationResponse::UnknownSymbol { symbol }
+            }
+            Err(_) => symbol.normalised(),
+        };
+
+        loop {
+            match updates.recv().await {
+                Ok(updated) if updated.normalised() == instrument => break,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => return ApplicationResponse::UnknownSymbol { symbol },
+            }
+        }
+
+        self.arbitrage_opportunities(symbol).await
+    }
+
+    /*
+    Logs the opportunities of an instrument as they open and close, with the time they
+    were detected at. Runs until the instrument is no longer tracked so it is meant to be
+    spawned in its own task.
+    */
+    pub async fn log_arbitrage_opportunities(&self, symbol: Symbol) -> Result<()> {
+        let mut open: BTreeSet<(Venue, Venue)> = BTreeSet::new();
+
+        loop {
+            let (detected_at, opportunities) =
+                match self.next_arbitrage_opportunities(symbol.clone()).await {
+                    ApplicationResponse::ArbitrageOpportunities {
+                        detected_at,
+                        opportunities,
+                        ..
+                    } => (detected_at, opportunities),
+                    // books out of sync have no opportunities
+                    ApplicationResponse::OrderBookOutOfSync { .. } => continue,
+                    _ => return Err(anyhow!("{} is not tracked on any venue", symbol.0)),
+                };
+
+            let mut still_open = BTreeSet::new();
+            for opportunity in opportunities {
+                let venues = (
+                    opportunity.buy_venue.clone(),
+                    opportunity.sell_venue.clone(),
+                );
+
+                if !open.contains(&venues) {
+                    info!(
+                        detected_at,
+                        instrument = %symbol.normalised().0,
+                        buy_venue = %opportunity.buy_venue.0,
+                        buy_price = %opportunity.buy_price,
+                        sell_venue = %opportunity.sell_venue.0,
+                        sell_price = %opportunity.sell_price,
+                        edge_bps = %opportunity.edge_bps,
+                        quantity = %opportunity.quantity,
+                        profit = %opportunity.profit,
+                        "arbitrage opened"
+                    );
+                }
+                still_open.insert(venues);
+            }
+
+            for (buy_venue, sell_venue) in open.difference(&still_open) {
+                info!(
+                    detected_at,
+                    instrument = %symbol.normalised().0,
+                    buy_venue = %buy_venue.0,
+                    sell_venue = %sell_venue.0,
+                    "arbitrage closed"
+                );
+            }
+
+            open = still_open;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::{order_books::LocalOrderBook, ApplicationQuery, OrderBookSyncState},
+        core::OrderBook,
+        ports::{MarketMessage, MarketStreamConnection},
+        typespec::{levels, DepthSnapshot},
+    };
+    use tokio::sync::{broadcast, mpsc};
+
+    fn connection() -> MarketStreamConnection {
+        let (_sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        MarketStreamConnection::new(Arc::new(receiver), commands)
+    }
+
+    async fn insert_book(app: &Application, bids: &[(&str, &str)], asks: &[(&str, &str)]) {
+        let symbol = Symbol("BTCUSDT".into());
+        let mut local = LocalOrderBook::new();
+        local.book = Some(OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(bids),
+            asks: levels(asks),
+        }));
+        local.state = OrderBookSyncState::Synced;
+        app.order_books.write().await.insert(symbol.clone(), local);
+        let _ = app.book_updates.send(symbol);
+    }
+
+    #[tokio::test]
+    async fn test_opportunity_detected_once_book_of_venue_changes() {
+        let binance = Application::new(connection()).with_arbitrage_settings(ArbitrageSettings {
+            threshold_bps: "5".parse().unwrap(),
+            ..Default::default()
+        });
+        binance.register_venue(Venue("binance".into())).await;
+        let okx = binance.add_venue(Venue("okx".into()), connection()).await;
+        insert_book(&binance, &[("99.00", "1")], &[("100.00", "1")]).await;
+        insert_book(&okx, &[("99.50", "1")], &[("100.50", "1")]).await;
+        // changes of the initial books are told before any query waits
+        tokio::task::yield_now().await;
+
+        let none = binance
+            .handle_query(ApplicationQuery::GetArbitrageOpportunities(Symbol(
+                "BTCUSD".into(),
+            )))
+            .await
+            .unwrap();
+        let waiting = {
+            let binance = binance.clone();
+            tokio::spawn(async move {
+                binance
+                    .handle_query(ApplicationQuery::WaitForArbitrageOpportunities(Symbol(
+                        "BTCUSD".into(),
+                    )))
+                    .await
+            })
+        };
+        // let the query subscribe before okx bids above the ask of binance
+        tokio::task::yield_now().await;
+        insert_book(&okx, &[("101.00", "2")], &[("101.50", "1")]).await;
+
+        match none {
+            ApplicationResponse::ArbitrageOpportunities { opportunities, .. } => {
+                assert_eq!(opportunities, vec![])
+            }
+            _ => panic!("expected an arbitrage opportunities response"),
+        }
+        match waiting.await.unwrap().unwrap() {
+            ApplicationResponse::ArbitrageOpportunities {
+                symbol,
+                opportunities,
+                detected_at,
+            } => {
+                assert_eq!(symbol, Symbol("BTCUSD".into()));
+                assert!(detected_at > 0);
+                assert_eq!(opportunities.len(), 1);
+                assert_eq!(opportunities[0].buy_venue, Venue("binance".into()));
+                assert_eq!(opportunities[0].sell_venue, Venue("okx".into()));
+                assert_eq!(opportunities[0].edge_bps, "79.8202".parse().unwrap());
+                assert_eq!(opportunities[0].quantity, "1".parse().unwrap());
+            }
+            _ => panic!("expected an arbitrage opportunities response"),
+        }
+    }
+}
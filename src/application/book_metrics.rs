@@ -0,0 +1,453 @@
+use super::{order_books::LocalOrderBook, Application, ApplicationResponse, OrderBookSyncState};
+use crate::{
+    core::{self, OrderBook},
+    typespec::{Notional, OrderSize, Price, PriceLevel, Side, Symbol, TickSize},
+};
+use rust_decimal::Decimal;
+
+// metrics like the mid price can fall between ticks so they keep a few more
+// decimal places than the prices the market quotes
+const METRIC_EXTRA_DECIMAL_PLACES: u32 = 2;
+
+// decimal places of ratios like the relative spread or the imbalance
+const RATIO_DECIMAL_PLACES: u32 = 4;
+
+fn round_metric(price: Option<Price>, tick_size: TickSize) -> Option<Price> {
+    price.map(|price| price.round_dp(tick_size.decimal_places() + METRIC_EXTRA_DECIMAL_PLACES))
+}
+
+fn round_ratio(ratio: Option<Decimal>) -> Option<Decimal> {
+    ratio.map(|ratio| ratio.round_dp(RATIO_DECIMAL_PLACES).normalize())
+}
+
+// Average price of all levels of a book, in the same precision the market quotes prices in
+pub(super) fn average_price(book: &OrderBook) -> Option<Price> {
+    let prices = |levels: Vec<PriceLevel>| -> Vec<Price> {
+        levels.into_iter().map(|(price, _)| price).collect()
+    };
+
+    // Run asks and bids through pure functions from the core
+    core::average_price_of_order_book(prices(book.asks()), prices(book.bids()))
+        .map(|price| price.round_to_tick(book.tick_size()))
+}
+
+impl Application {
+    // Runs a metric on the book of a symbol when it is in sync and answers why there is none otherwise
+    async fn with_synced_book(
+        &self,
+        symbol: Symbol,
+        metric: impl FnOnce(Symbol, &OrderBook) -> ApplicationResponse,
+    ) -> ApplicationResponse {
+        let books = self.order_books.read().await;
+
+        match books.get(&symbol) {
+            Some(LocalOrderBook {
+                book: Some(book),
+                state: OrderBookSyncState::Synced,
+                ..
+            }) => metric(symbol, book),
+            Some(_) => ApplicationResponse::OrderBookOutOfSync { symbol },
+            None => ApplicationResponse::UnknownSymbol { symbol },
+        }
+    }
+
+    // Average price of the book as it is now rather than after its next change
+    pub(super) async fn current_average_price(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            ApplicationResponse::CurrentAveragePriceForSymbol {
+                price: average_price(book),
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn order_book_levels(
+        &self,
+        symbol: Symbol,
+        depth: usize,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            ApplicationResponse::OrderBookLevels {
+                last_update_id: book.last_update_id(),
+                bids: book.bids().into_iter().take(depth).collect(),
+                asks: book.asks().into_iter().take(depth).collect(),
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn mid_price(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| ApplicationResponse::MidPrice {
+            price: round_metric(
+                core::mid_price(&book.bids(), &book.asks()),
+                book.tick_size(),
+            ),
+            symbol,
+        })
+        .await
+    }
+
+    pub(super) async fn microprice(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| ApplicationResponse::Microprice {
+            price: round_metric(
+                core::microprice(&book.bids(), &book.asks()),
+                book.tick_size(),
+            ),
+            symbol,
+        })
+        .await
+    }
+
+    pub(super) async fn vwap_of_top_levels(
+        &self,
+        symbol: Symbol,
+        depth: usize,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let tick_size = book.tick_size();
+
+            ApplicationResponse::VwapOfTopLevels {
+                bids: round_metric(core::vwap_of_top_levels(&book.bids(), depth), tick_size),
+                asks: round_metric(core::vwap_of_top_levels(&book.asks(), depth), tick_size),
+                depth,
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn vwap_to_fill_notional(
+        &self,
+        symbol: Symbol,
+        notional: Notional,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let tick_size = book.tick_size();
+
+            ApplicationResponse::VwapToFillNotional {
+                bids: round_metric(
+                    core::vwap_to_fill_notional(&book.bids(), notional),
+                    tick_size,
+                ),
+                asks: round_metric(
+                    core::vwap_to_fill_notional(&book.asks(), notional),
+                    tick_size,
+                ),
+                notional,
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn quantity_weighted_average_price(
+        &self,
+        symbol: Symbol,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            ApplicationResponse::QuantityWeightedAveragePrice {
+                price: round_metric(
+                    core::quantity_weighted_average_price(&book.bids(), &book.asks()),
+                    book.tick_size(),
+                ),
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn best_bid_ask(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let (bid, ask) = core::best_bid_ask(&book.bids(), &book.asks());
+
+            ApplicationResponse::BestBidAsk { symbol, bid, ask }
+        })
+        .await
+    }
+
+    pub(super) async fn spread(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let spread = core::spread(&book.bids(), &book.asks());
+
+            ApplicationResponse::Spread {
+                absolute: spread.map(|spread| spread.absolute),
+                relative_bps: round_ratio(spread.map(|spread| spread.relative_bps)),
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn depth_within_bps(
+        &self,
+        symbol: Symbol,
+        bps: Decimal,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let (bids, asks) = (book.bids(), book.asks());
+            let mid = core::mid_price(&bids, &asks);
+
+            ApplicationResponse::DepthWithinBps {
+                bids: mid.and_then(|mid| core::depth_within_bps(&bids, mid, bps)),
+                asks: mid.and_then(|mid| core::depth_within_bps(&asks, mid, bps)),
+                bps,
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn imbalance(
+        &self,
+        symbol: Symbol,
+        depths: Vec<usize>,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let (bids, asks) = (book.bids(), book.asks());
+
+            ApplicationResponse::Imbalance {
+                imbalances: depths
+                    .into_iter()
+                    .map(|depth| (depth, round_ratio(core::imbalance(&bids, &asks, depth))))
+                    .collect(),
+                symbol,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn estimate_market_order(
+        &self,
+        symbol: Symbol,
+        side: Side,
+        size: OrderSize,
+    ) -> ApplicationResponse {
+        self.with_synced_book(symbol, |symbol, book| {
+            let tick_size = book.tick_size();
+            let estimate = core::estimate_market_order(&book.bids(), &book.asks(), side, size);
+
+            ApplicationResponse::MarketOrderEstimate {
+                estimate: core::FillEstimate {
+                    average_price: round_metric(estimate.average_price, tick_size),
+                    slippage_bps: round_ratio(estimate.slippage_bps),
+                    ..estimate
+                },
+                symbol,
+                side,
+                size,
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::ApplicationQuery,
+        ports::{MarketMessage, MarketStreamConnection},
+        typespec::{levels, DepthSnapshot},
+    };
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+
+    // application with a synced book inserted directly instead of from a market stream
+    async fn setup() -> (Application, Symbol) {
+        let (_sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        let app = Application::new(MarketStreamConnection::new(Arc::new(receiver), commands));
+        let symbol = Symbol("BTCUSDC".into());
+
+        let mut local = LocalOrderBook::new();
+        local.book = Some(OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(&[("99.00", "3"), ("98.25", "1")]),
+            asks: levels(&[("100.00", "1"), ("101.00", "3")]),
+        }));
+        local.state = OrderBookSyncState::Synced;
+        app.order_books.write().await.insert(symbol.clone(), local);
+
+        (app, symbol)
+    }
+
+    #[tokio::test]
+    async fn test_price_metrics_of_synced_book() {
+        let (app, symbol) = setup().await;
+
+        let mid = app
+            .handle_query(ApplicationQuery::GetMidPrice(symbol.clone()))
+            .await
+            .unwrap();
+        let vwap = app
+            .handle_query(ApplicationQuery::GetVwapOfTopLevels {
+                symbol: symbol.clone(),
+                depth: 2,
+            })
+            .await
+            .unwrap();
+
+        // metrics keep two more decimal places than the 0.01 tick size
+        match mid {
+            ApplicationResponse::MidPrice { price, .. } => {
+                assert_eq!(price.map(|p| p.to_string()), Some("99.5000".into()))
+            }
+            _ => panic!("expected a mid price response"),
+        }
+        match vwap {
+            ApplicationResponse::VwapOfTopLevels { bids, asks, .. } => {
+                assert_eq!(bids.map(|p| p.to_string()), Some("98.8125".into()));
+                assert_eq!(asks.map(|p| p.to_string()), Some("100.7500".into()));
+            }
+            _ => panic!("expected a vwap response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_price_metrics_of_out_of_sync_and_unknown_books() {
+        let (app, symbol) = setup().await;
+        if let Some(local) = app.order_books.write().await.get_mut(&symbol) {
+            local.state = OrderBookSyncState::OutOfSync;
+        }
+
+        let out_of_sync = app
+            .handle_query(ApplicationQuery::GetMicroprice(symbol))
+            .await
+            .unwrap();
+        let unknown = app
+            .handle_query(ApplicationQuery::GetMicroprice(Symbol("ETHUSDC".into())))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            out_of_sync,
+            ApplicationResponse::OrderBookOutOfSync { .. }
+        ));
+        assert!(matches!(unknown, ApplicationResponse::UnknownSymbol { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_spread_and_imbalance_of_synced_book() {
+        let (app, symbol) = setup().await;
+
+        let spread = app
+            .handle_query(ApplicationQuery::GetSpread(symbol.clone()))
+            .await
+            .unwrap();
+        let imbalance = app
+            .handle_query(ApplicationQuery::GetImbalance {
+                symbol: symbol.clone(),
+                depths: vec![1, 2],
+            })
+            .await
+            .unwrap();
+
+        match spread {
+            ApplicationResponse::Spread {
+                absolute,
+                relative_bps,
+                ..
+            } => {
+                assert_eq!(absolute.map(|p| p.to_string()), Some("1.00".into()));
+                // 1 / 99.5 in basis points
+                assert_eq!(relative_bps.map(|r| r.to_string()), Some("100.5025".into()));
+            }
+            _ => panic!("expected a spread response"),
+        }
+        match imbalance {
+            ApplicationResponse::Imbalance { imbalances, .. } => {
+                let imbalances: Vec<(usize, Option<String>)> = imbalances
+                    .into_iter()
+                    .map(|(depth, imbalance)| (depth, imbalance.map(|i| i.to_string())))
+                    .collect();
+
+                assert_eq!(
+                    imbalances,
+                    vec![(1, Some("0.5".into())), (2, Some("0".into()))]
+                );
+            }
+            _ => panic!("expected an imbalance response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_market_order_estimate_of_synced_book() {
+        let (app, symbol) = setup().await;
+
+        let response = app
+            .handle_query(ApplicationQuery::EstimateMarketOrder {
+                symbol,
+                side: Side::Buy,
+                size: OrderSize::Quantity("2".parse().unwrap()),
+            })
+            .await
+            .unwrap();
+
+        match response {
+            ApplicationResponse::MarketOrderEstimate { estimate, .. } => {
+                // 1 at 100.00 and 1 at 101.00 against a mid price of 99.50
+                assert_eq!(
+                    estimate.average_price.map(|p| p.to_string()),
+                    Some("100.5000".into())
+                );
+                assert_eq!(
+                    estimate.slippage_bps.map(|s| s.to_string()),
+                    Some("100.5025".into())
+                );
+                assert_eq!(estimate.levels_consumed, 2);
+                assert!(estimate.sufficient_depth);
+            }
+            _ => panic!("expected a market order estimate response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_top_levels_and_average_price_of_synced_book() {
+        let (app, symbol) = setup().await;
+
+        let top_levels = app
+            .handle_query(ApplicationQuery::GetOrderBookLevels {
+                symbol: symbol.clone(),
+                depth: 1,
+            })
+            .await
+            .unwrap();
+        let average = app
+            .handle_query(ApplicationQuery::GetCurrentAveragePrice(symbol))
+            .await
+            .unwrap();
+        let active = app
+            .handle_query(ApplicationQuery::GetActiveSymbols)
+            .await
+            .unwrap();
+
+        match top_levels {
+            ApplicationResponse::OrderBookLevels {
+                last_update_id,
+                bids,
+                asks,
+                ..
+            } => {
+                assert_eq!(last_update_id, 1);
+                assert_eq!(bids, levels(&[("99.00", "3")]));
+                assert_eq!(asks, levels(&[("100.00", "1")]));
+            }
+            _ => panic!("expected an order book levels response"),
+        }
+        match average {
+            ApplicationResponse::CurrentAveragePriceForSymbol { price, .. } => {
+                assert_eq!(price.map(|p| p.to_string()), Some("99.56".into()))
+            }
+            _ => panic!("expected an average price response"),
+        }
+        match active {
+            ApplicationResponse::ActiveSymbols { symbols } => assert_eq!(
+                symbols,
+                vec![(Symbol("BTCUSDC".into()), OrderBookSyncState::Synced)]
+            ),
+            _ => panic!("expected an active symbols response"),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use super::{Application, ApplicationResponse};
+use crate::{
+    core::{Candle, CandleAggregator},
+    ports::{MarketEvent, MarketStreamMessageBroadcastReceiver},
+    typespec::{CandleInterval, Symbol},
+};
+use anyhow::{anyhow, Result};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+// candles kept for each symbol and interval, the most binance returns for a kline request
+const MAX_CANDLES: usize = 1000;
+
+// A candle of a symbol changed by a trade
+pub(super) type CandleUpdate = (Symbol, CandleInterval, Candle);
+
+impl Application {
+    /*
+    Aggregates the trades of a market stream into candles of each interval for every symbol
+    trading on it. Runs until the market stream is closed so it is meant to be spawned in
+    its own task.
+
+    The symbols given have candles from the start, even before their first trade, and
+    symbols trading later get theirs with their first trade. Every candle changed by a
+    trade is told to the clients waiting for candles of its symbol and interval.
+    Trades lost while the receiver lags behind can not be fetched again, so the candles
+    of those intervals miss them.
+    */
+    pub async fn aggregate_candles(
+        &self,
+        trades: MarketStreamMessageBroadcastReceiver,
+        symbols: Vec<Symbol>,
+        intervals: Vec<CandleInterval>,
+    ) -> Result<()> {
+        let mut receiver = trades.resubscribe();
+
+        {
+            let mut candles = self.candles.write().await;
+            for symbol in symbols.iter() {
+                for interval in intervals.iter() {
+                    candles
+                        .entry((symbol.clone(), *interval))
+                        .or_insert_with(|| CandleAggregator::new(*interval, MAX_CANDLES));
+                }
+            }
+        }
+
+        loop {
+            let trade = match receiver.recv().await {
+                Ok(message) => match &message.event {
+                    MarketEvent::Trade(trade) => trade.clone(),
+                    _ => continue,
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "candle aggregation lagged behind the trade stream");
+                    self.metrics.messages_lagged("candle_aggregation", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => return Err(anyhow!("trade stream closed")),
+            };
+
+            let mut candles = self.candles.write().await;
+            for interval in intervals.iter() {
+                let aggregator = candles
+                    .entry((trade.symbol.clone(), *interval))
+                    .or_insert_with(|| CandleAggregator::new(*interval, MAX_CANDLES));
+
+                if let Some(candle) = aggregator.add_trade(&trade) {
+                    let update = (trade.symbol.clone(), *interval, candle.clone());
+                    let _ = self.candle_updates.send(update);
+                }
+            }
+        }
+    }
+
+    // The given number of most recent candles ordered from the oldest
+    pub(super) async fn last_candles(
+        &self,
+        symbol: Symbol,
+        interval: CandleInterval,
+        limit: usize,
+    ) -> ApplicationResponse {
+        match self.candles.read().await.get(&(symbol.clone(), interval)) {
+            Some(aggregator) => ApplicationResponse::Candles {
+                symbol,
+                interval,
+                candles: aggregator.last_candles(limit),
+            },
+            None => ApplicationResponse::UnknownSymbol { symbol },
+        }
+    }
+
+    // Waits for a trade to change a candle of the symbol and interval
+    pub(super) async fn next_candle(
+        &self,
+        symbol: Symbol,
+        interval: CandleInterval,
+    ) -> ApplicationResponse {
+        // subscribe before checking the candles so no update is missed in between
+        let mut updates = self.candle_updates.subscribe();
+
+        if !self
+            .candles
+            .read()
+            .await
+            .contains_key(&(symbol.clone(), interval))
+        {
+            return ApplicationResponse::UnknownSymbol { symbol };
+        }
+
+        loop {
+            match updates.recv().await {
+                Ok((updated, updated_interval, candle))
+                    if updated == symbol && updated_interval == interval =>
+                {
+                    return ApplicationResponse::CandleUpdate {
+                        symbol,
+                        interval,
+                        candle,
+                    }
+                }
+                Ok(_) => continue,
+                // the latest candle is the one most likely changed by the skipped updates
+                Err(RecvError::Lagged(_)) => {
+                    return match self.last_candles(symbol, interval, 1).await {
+                        ApplicationResponse::Candles {
+                            symbol,
+                            interval,
+                            mut candles,
+                        } => match candles.pop() {
+                            Some(candle) => ApplicationResponse::CandleUpdate {
+                                symbol,
+                                interval,
+                                candle,
+                            },
+                            None => ApplicationResponse::InternalError,
+                        },
+                        res => res,
+                    }
+                }
+                Err(RecvError::Closed) => return ApplicationResponse::InternalError,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::ApplicationQuery,
+        ports::{MarketMessage, MarketStreamConnection},
+        typespec::Trade,
+    };
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+
+    fn trade(trade_id: u64, trade_time: u64, price: &str) -> Arc<MarketMessage> {
+        Arc::new(
+            MarketEvent::Trade(Trade {
+                symbol: Symbol("BTCUSDC".into()),
+                trade_id,
+                price: price.parse().unwrap(),
+                quantity: "1".parse().unwrap(),
+                trade_time,
+                buyer_is_maker: false,
+            })
+            .into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_trades_of_stream_served_as_candles() {
+        let (depth_sender, depth_receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        let app = Application::new(MarketStreamConnection::new(
+            Arc::new(depth_receiver),
+            commands,
+        ));
+        let (trade_sender, trade_receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let symbol = Symbol("BTCUSDC".into());
+        let interval: CandleInterval = "1s".parse().unwrap();
+
+        let aggregating = {
+            let app = app.clone();
+            let symbols = vec![symbol.clone()];
+            tokio::spawn(async move {
+                app.aggregate_candles(Arc::new(trade_receiver), symbols, vec![interval])
+                    .await
+            })
+        };
+        // let the aggregation subscribe to the trades and set up the candles
+        tokio::task::yield_now().await;
+        let waiting = {
+            let app = app.clone();
+            let symbol = symbol.clone();
+            tokio::spawn(async move {
+                app.handle_query(ApplicationQuery::WaitForCandle { symbol, interval })
+                    .await
+            })
+        };
+        tokio::task::yield_now().await;
+        trade_sender.send(trade(1, 1_000, "100")).unwrap();
+        trade_sender.send(trade(2, 2_500, "101")).unwrap();
+        tokio::task::yield_now().await;
+
+        match waiting.await.unwrap().unwrap() {
+            ApplicationResponse::CandleUpdate { candle, .. } => {
+                assert_eq!(candle.open_time, 1_000)
+            }
+            _ => panic!("expected a candle update"),
+        }
+        match app
+            .handle_query(ApplicationQuery::GetCandles {
+                symbol,
+                interval,
+                limit: 5,
+            })
+            .await
+            .unwrap()
+        {
+            ApplicationResponse::Candles { candles, .. } => {
+                assert_eq!(candles.len(), 2);
+                assert_eq!(candles[1].close, "101".parse().unwrap());
+            }
+            _ => panic!("expected candles"),
+        }
+
+        drop((trade_sender, depth_sender));
+        assert!(aggregating.await.unwrap().is_err());
+    }
+}
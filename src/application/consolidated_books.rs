@@ -0,0 +1,344 @@
+use super::{order_books::LocalOrderBook, Application, ApplicationResponse, OrderBookSyncState};
+use crate::{
+    core::{self, ConsolidatedBook, OrderBook},
+    ports::MarketStreamConnection,
+    typespec::{Symbol, Venue},
+};
+use rust_decimal::Decimal;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::{broadcast::error::RecvError, RwLock};
+
+// order books of every venue, shared by the applications of all venues
+pub(super) type VenueBooks =
+    Arc<RwLock<BTreeMap<Venue, Arc<RwLock<BTreeMap<Symbol, LocalOrderBook>>>>>>;
+
+impl Application {
+    /*
+    Adds the order books of this application to the consolidated books under the name
+    of its venue. Applications of other venues are made with add_venue so each venue
+    keeps its own market stream, books and resyncs while queries consolidate all of them.
+    */
+    pub async fn register_venue(&self, venue: Venue) {
+        self.venue_books
+            .write()
+            .await
+            .insert(venue, self.order_books.clone());
+
+        // changes of the books of every venue are told on one channel
+        let mut book_updates = self.book_updates.subscribe();
+        let venue_book_updates = self.venue_book_updates.clone();
+        tokio::spawn(async move {
+            loop {
+                match book_updates.recv().await {
+                    Ok(symbol) => {
+                        let _ = venue_book_updates.send(symbol);
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    // Application of another venue whose books are consolidated with the ones of this
+    // application. Its order books are to be maintained like the ones of this application
+    pub async fn add_venue(&self, venue: Venue, market_stream: MarketStreamConnection) -> Self {
+        let app = Self {
+            venue_books: self.venue_books.clone(),
+            venue_book_updates: self.venue_book_updates.clone(),
+            metrics: self.metrics.clone(),
+            ..Self::new(market_stream)
+        };
+        app.register_venue(venue).await;
+
+        app
+    }
+
+    /*
+    Synced books of every venue trading a symbol of the instrument the symbol is normalised
+    to, along with the instrument. Books out of sync are left out so a venue that lost
+    updates does not add stale levels. Answers why there are no books otherwise.
+    */
+    pub(super) async fn synced_venue_books(
+        &self,
+        symbol: Symbol,
+    ) -> Result<(Symbol, Vec<(Venue, Symbol, OrderBook)>), ApplicationResponse> {
+        let instrument = symbol.normalised();
+        let mut tracked = false;
+        let mut synced: Vec<(Venue, Symbol, OrderBook)> = vec![];
+
+        for (venue, books) in self.venue_books.read().await.iter() {
+            for (venue_symbol, local) in books.read().await.iter() {
+                if venue_symbol.normalised() != instrument {
+                    continue;
+                }
+                tracked = true;
+
+                if let LocalOrderBook {
+                    book: Some(book),
+                    state: OrderBookSyncState::Synced,
+                    ..
+                } = local
+                {
+                    synced.push((venue.clone(), venue_symbol.clone(), book.clone()));
+                }
+            }
+        }
+
+        if !tracked {
+            return Err(ApplicationResponse::UnknownSymbol { symbol });
+        }
+        if synced.is_empty() {
+            return Err(ApplicationResponse::OrderBookOutOfSync { symbol });
+        }
+
+        Ok((instrument, synced))
+    }
+
+    // Runs a metric on the book consolidated from the synced books of every venue
+    async fn with_consolidated_book(
+        &self,
+        symbol: Symbol,
+        metric: impl FnOnce(Symbol, Vec<(Venue, Symbol)>, ConsolidatedBook) -> ApplicationResponse,
+    ) -> ApplicationResponse {
+        let (instrument, synced) = match self.synced_venue_books(symbol).await {
+            Ok(books) => books,
+            Err(res) => return res,
+        };
+
+        let book =
+            ConsolidatedBook::from_books(synced.iter().map(|(venue, _, book)| (venue, book)));
+        let venues = synced
+            .into_iter()
+            .map(|(venue, venue_symbol, _)| (venue, venue_symbol))
+            .collect();
+
+        metric(instrument, venues, book)
+    }
+
+    pub(super) async fn consolidated_book(
+        &self,
+        symbol: Symbol,
+        depth: usize,
+    ) -> ApplicationResponse {
+        self.with_consolidated_book(symbol, |symbol, venues, book| {
+            ApplicationResponse::ConsolidatedBook {
+                book: book.top_levels(depth),
+                symbol,
+                venues,
+            }
+        })
+        .await
+    }
+
+    pub(super) async fn consolidated_best_bid_offer(&self, symbol: Symbol) -> ApplicationResponse {
+        self.with_consolidated_book(symbol, |symbol, venues, book| {
+            let (bid, offer) = book.best_bid_offer();
+
+            ApplicationResponse::ConsolidatedBestBidOffer {
+                bid: bid.cloned(),
+                offer: offer.cloned(),
+                symbol,
+                venues,
+            }
+        })
+        .await
+    }
+
+    // depth of each side of the consolidated book around its mid price
+    pub(super) async fn consolidated_depth_within_bps(
+        &self,
+        symbol: Symbol,
+        bps: Decimal,
+    ) -> ApplicationResponse {
+        self.with_consolidated_book(symbol, |symbol, venues, book| {
+            let (bids, asks) = (book.bid_levels(), book.ask_levels());
+            let mid = core::mid_price(&bids, &asks);
+
+            ApplicationResponse::ConsolidatedDepthWithinBps {
+                bids: mid.and_then(|mid| core::depth_within_bps(&bids, mid, bps)),
+                asks: mid.and_then(|mid| core::depth_within_bps(&asks, mid, bps)),
+                bps,
+                symbol,
+                venues,
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::ApplicationQuery,
+        ports::MarketMessage,
+        typespec::{levels, DepthSnapshot},
+    };
+    use tokio::sync::{broadcast, mpsc};
+
+    fn connection() -> MarketStreamConnection {
+        let (_sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        MarketStreamConnection::new(Arc::new(receiver), commands)
+    }
+
+    // inserts a book directly instead of from a market stream
+    async fn insert_book(
+        app: &Application,
+        symbol: &str,
+        bids: &[(&str, &str)],
+        asks: &[(&str, &str)],
+        state: OrderBookSyncState,
+    ) {
+        let mut local = LocalOrderBook::new();
+        local.book = Some(OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(bids),
+            asks: levels(asks),
+        }));
+        local.state = state;
+        app.order_books
+            .write()
+            .await
+            .insert(Symbol(symbol.into()), local);
+    }
+
+    // binance quotes BTCUSDT and coinbase BTCUSD, both consolidated as BTCUSD
+    async fn setup() -> (Application, Application) {
+        let binance = Application::new(connection());
+        binance.register_venue(Venue("binance".into())).await;
+        let coinbase = binance
+            .add_venue(Venue("coinbase".into()), connection())
+            .await;
+
+        insert_book(
+            &binance,
+            "BTCUSDT",
+            &[("99.00", "1"), ("98.00", "2")],
+            &[("101.00", "1")],
+            OrderBookSyncState::Synced,
+        )
+        .await;
+        insert_book(
+            &coinbase,
+            "BTCUSD",
+            &[("99.00", "2"), ("98.50", "1")],
+            &[("100.50", "3")],
+            OrderBookSyncState::Synced,
+        )
+        .await;
+
+        (binance, coinbase)
+    }
+
+    #[tokio::test]
+    async fn test_consolidated_book_attributes_levels_to_venues() {
+        let (binance, _coinbase) = setup().await;
+
+        let response = binance
+            .handle_query(ApplicationQuery::GetConsolidatedBook {
+                symbol: Symbol("BTCUSDC".into()),
+                depth: 2,
+            })
+            .await
+            .unwrap();
+
+        match response {
+            ApplicationResponse::ConsolidatedBook {
+                symbol,
+                venues,
+                book,
+            } => {
+                assert_eq!(symbol, Symbol("BTCUSD".into()));
+                assert_eq!(venues.len(), 2);
+                assert_eq!(book.bid_levels(), levels(&[("99", "3"), ("98.5", "1")]));
+                assert_eq!(
+                    book.bids[0].venues,
+                    vec![
+                        (Venue("coinbase".into()), "2".parse().unwrap()),
+                        (Venue("binance".into()), "1".parse().unwrap())
+                    ]
+                );
+            }
+            _ => panic!("expected a consolidated book response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consolidated_best_bid_offer_and_depth() {
+        let (binance, _coinbase) = setup().await;
+        let symbol = Symbol("BTCUSD".into());
+
+        let bbo = binance
+            .handle_query(ApplicationQuery::GetConsolidatedBestBidOffer(
+                symbol.clone(),
+            ))
+            .await
+            .unwrap();
+        let depth = binance
+            .handle_query(ApplicationQuery::GetConsolidatedDepthWithinBps {
+                symbol,
+                bps: "150".parse().unwrap(),
+            })
+            .await
+            .unwrap();
+
+        match bbo {
+            ApplicationResponse::ConsolidatedBestBidOffer { bid, offer, .. } => {
+                assert_eq!(bid.unwrap().price, "99".parse().unwrap());
+                let offer = offer.unwrap();
+                assert_eq!(offer.price, "100.5".parse().unwrap());
+                assert_eq!(offer.venues[0].0, Venue("coinbase".into()));
+            }
+            _ => panic!("expected a consolidated best bid offer response"),
+        }
+        match depth {
+            // within 150 bps of the 99.75 mid price
+            ApplicationResponse::ConsolidatedDepthWithinBps { bids, asks, .. } => {
+                assert_eq!(bids.map(|q| q.to_string()), Some("4".into()));
+                assert_eq!(asks.map(|q| q.to_string()), Some("4".into()));
+            }
+            _ => panic!("expected a consolidated depth response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_books_out_of_sync_left_out_of_consolidation() {
+        let (binance, coinbase) = setup().await;
+        insert_book(
+            &coinbase,
+            "BTCUSD",
+            &[("99.50", "5")],
+            &[("100.00", "5")],
+            OrderBookSyncState::OutOfSync,
+        )
+        .await;
+
+        let consolidated = binance
+            .handle_query(ApplicationQuery::GetConsolidatedBook {
+                symbol: Symbol("BTCUSD".into()),
+                depth: 10,
+            })
+            .await
+            .unwrap();
+        let unknown = binance
+            .handle_query(ApplicationQuery::GetConsolidatedBook {
+                symbol: Symbol("ETHUSD".into()),
+                depth: 10,
+            })
+            .await
+            .unwrap();
+
+        match consolidated {
+            ApplicationResponse::ConsolidatedBook { venues, .. } => {
+                assert_eq!(
+                    venues,
+                    vec![(Venue("binance".into()), Symbol("BTCUSDT".into()))]
+                );
+            }
+            _ => panic!("expected a consolidated book response"),
+        }
+        assert!(matches!(unknown, ApplicationResponse::UnknownSymbol { .. }));
+    }
+}
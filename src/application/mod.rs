@@ -1,7 +1,48 @@
-use crate::{core, ports::MarketStreamMessageBroadcastReceiver, typespec::Symbol};
+use crate::{
+    core::{
+        ArbitrageOpportunity, Candle, CandleAggregator, ConsolidatedBook, ConsolidatedLevel,
+        FillEstimate, TriangleRoundTrip,
+    },
+    ports::{
+        ConnectionState, MarketStreamConnection, MarketStreamMessageBroadcastReceiver, NoMetrics,
+        SharedMetrics, TraceContext,
+    },
+    typespec::{
+        CandleInterval, Notional, OrderSize, Price, PriceLevel, Quantity, Side, Symbol, Triangle,
+        Venue,
+    },
+};
 use anyhow::Result;
-use serde_json::Value;
-use std::sync::Arc;
+use rust_decimal::Decimal;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    RwLock,
+};
+use tracing::{debug_span, Instrument};
+
+mod arbitrage;
+mod book_metrics;
+mod candles;
+mod consolidated_books;
+mod order_books;
+mod subscriptions;
+mod triangular_arbitrage;
+
+use candles::CandleUpdate;
+use consolidated_books::VenueBooks;
+use order_books::LocalOrderBook;
+// settings of the core the application is made with
+pub use crate::core::ArbitrageSettings;
+pub use order_books::OrderBookSyncState;
+use subscriptions::SymbolSubscriptions;
 
 /*
 Application struct holds pointers to be used by different adapters
@@ -13,6 +54,29 @@ It is the API of all driving adapters that a client can access.
 #[derive(Clone)]
 pub struct Application {
     pub market_stream: MarketStreamMessageBroadcastReceiver,
+    // local order books kept in sync with the market stream
+    order_books: Arc<RwLock<BTreeMap<Symbol, LocalOrderBook>>>,
+    // notifies which symbol had its order book or its sync state changed
+    book_updates: broadcast::Sender<Symbol>,
+    // messages of the market stream lost because the order book sync lagged behind
+    lagged_messages: Arc<AtomicU64>,
+    // symbols subscribed on the market stream connection for clients
+    subscriptions: Arc<SymbolSubscriptions>,
+    // last connection state reported by the market stream
+    connection_state: Arc<RwLock<ConnectionState>>,
+    // order books of the venues consolidated with this application
+    venue_books: VenueBooks,
+    // notifies which symbol had its order book changed on any of the venues
+    venue_book_updates: broadcast::Sender<Symbol>,
+    // fees and threshold of arbitrage between the venues
+    arbitrage_settings: Arc<ArbitrageSettings>,
+    // triangles of symbols of this market scanned for round trips
+    triangles: Arc<Vec<Triangle>>,
+    // candles of the trades of each symbol and interval
+    candles: Arc<RwLock<BTreeMap<(Symbol, CandleInterval), CandleAggregator>>>,
+    // notifies every candle changed by a trade
+    candle_updates: broadcast::Sender<CandleUpdate>,
+    metrics: SharedMetrics,
 }
 
 /*
@@ -21,109 +85,482 @@ way instead of writing explicit functions that is called with different type par
 that can then be pattern matched into a workflow of functions
 */
 pub enum ApplicationQuery {
+    // average price once the book of the symbol changes
     GetAverageValueOfSymbol(Symbol),
+    // average price of the book as it is now
+    GetCurrentAveragePrice(Symbol),
+    GetOrderBookSyncStatus(Symbol),
+    GetMarketConnectionState,
+    // symbols with a local order book and the sync state of each book
+    GetActiveSymbols,
+    // best levels of each side of the book
+    GetOrderBookLevels {
+        symbol: Symbol,
+        depth: usize,
+    },
+    GetMidPrice(Symbol),
+    GetMicroprice(Symbol),
+    // volume weighted average price of the given number of best levels of each side
+    GetVwapOfTopLevels {
+        symbol: Symbol,
+        depth: usize,
+    },
+    // volume weighted average price of filling the notional against each side
+    GetVwapToFillNotional {
+        symbol: Symbol,
+        notional: Notional,
+    },
+    GetQuantityWeightedAveragePrice(Symbol),
+    GetBestBidAsk(Symbol),
+    GetSpread(Symbol),
+    // cumulative quantity of each side within the basis points of the mid price
+    GetDepthWithinBps {
+        symbol: Symbol,
+        bps: Decimal,
+    },
+    // volume imbalance of the best levels of the book at each of the depths
+    GetImbalance {
+        symbol: Symbol,
+        depths: Vec<usize>,
+    },
+    // fill of a hypothetical market order against the current book
+    EstimateMarketOrder {
+        symbol: Symbol,
+        side: Side,
+        size: OrderSize,
+    },
+    // best levels of each side of the book consolidated from every venue
+    GetConsolidatedBook {
+        symbol: Symbol,
+        depth: usize,
+    },
+    GetConsolidatedBestBidOffer(Symbol),
+    GetConsolidatedDepthWithinBps {
+        symbol: Symbol,
+        bps: Decimal,
+    },
+    // buying on one venue and selling on another for a profit after fees
+    GetArbitrageOpportunities(Symbol),
+    // opportunities once a book of the symbol changes on any venue
+    WaitForArbitrageOpportunities(Symbol),
+    // round trips around the configured triangles for a profit after fees
+    GetTriangularArbitrage,
+    // round trips once a book of a symbol of any triangle changes
+    WaitForTriangularArbitrage,
+    // most recent candles of the trades of a symbol, ordered from the oldest
+    GetCandles {
+        symbol: Symbol,
+        interval: CandleInterval,
+        limit: usize,
+    },
+    // the next candle of the symbol and interval changed by a trade
+    WaitForCandle {
+        symbol: Symbol,
+        interval: CandleInterval,
+    },
+    // clients subscribe to the symbols they query and unsubscribe when they leave
+    SubscribeToSymbol(Symbol),
+    UnsubscribeFromSymbol(Symbol),
+    // event time and trace context of the market message that last changed the book of the symbol
+    GetLastBookUpdate(Symbol),
+}
+
+impl ApplicationQuery {
+    // name of the variant the metrics of the query are labelled with
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApplicationQuery::GetAverageValueOfSymbol(_) => "GetAverageValueOfSymbol",
+            ApplicationQuery::GetCurrentAveragePrice(_) => "GetCurrentAveragePrice",
+            ApplicationQuery::GetOrderBookSyncStatus(_) => "GetOrderBookSyncStatus",
+            ApplicationQuery::GetMarketConnectionState => "GetMarketConnectionState",
+            ApplicationQuery::GetActiveSymbols => "GetActiveSymbols",
+            ApplicationQuery::GetOrderBookLevels { .. } => "GetOrderBookLevels",
+            ApplicationQuery::GetMidPrice(_) => "GetMidPrice",
+            ApplicationQuery::GetMicroprice(_) => "GetMicroprice",
+            ApplicationQuery::GetVwapOfTopLevels { .. } => "GetVwapOfTopLevels",
+            ApplicationQuery::GetVwapToFillNotional { .. } => "GetVwapToFillNotional",
+            ApplicationQuery::GetQuantityWeightedAveragePrice(_) => {
+                "GetQuantityWeightedAveragePrice"
+            }
+            ApplicationQuery::GetBestBidAsk(_) => "GetBestBidAsk",
+            ApplicationQuery::GetSpread(_) => "GetSpread",
+            ApplicationQuery::GetDepthWithinBps { .. } => "GetDepthWithinBps",
+            ApplicationQuery::GetImbalance { .. } => "GetImbalance",
+            ApplicationQuery::EstimateMarketOrder { .. } => "EstimateMarketOrder",
+            ApplicationQuery::GetConsolidatedBook { .. } => "GetConsolidatedBook",
+            ApplicationQuery::GetConsolidatedBestBidOffer(_) => "GetConsolidatedBestBidOffer",
+            ApplicationQuery::GetConsolidatedDepthWithinBps { .. } => {
+                "GetConsolidatedDepthWithinBps"
+            }
+            ApplicationQuery::GetArbitrageOpportunities(_) => "GetArbitrageOpportunities",
+            ApplicationQuery::WaitForArbitrageOpportunities(_) => "WaitForArbitrageOpportunities",
+            ApplicationQuery::GetTriangularArbitrage => "GetTriangularArbitrage",
+            ApplicationQuery::WaitForTriangularArbitrage => "WaitForTriangularArbitrage",
+            ApplicationQuery::GetCandles { .. } => "GetCandles",
+            ApplicationQuery::WaitForCandle { .. } => "WaitForCandle",
+            ApplicationQuery::SubscribeToSymbol(_) => "SubscribeToSymbol",
+            ApplicationQuery::UnsubscribeFromSymbol(_) => "UnsubscribeFromSymbol",
+            ApplicationQuery::GetLastBookUpdate(_) => "GetLastBookUpdate",
+        }
+    }
 }
 
 // enum ApplicationResponses acts as a DTO and a sum return type
 pub enum ApplicationResponse {
     CurrentAveragePriceForSymbol {
         symbol: Symbol,
-        // String as placeholder for a more terse type dealing with ticker prices
-        price: String,
+        // None when the symbol has no book or the book is empty
+        price: Option<Price>,
+    },
+    OrderBookOutOfSync {
+        symbol: Symbol,
+    },
+    OrderBookSyncStatus {
+        symbol: Symbol,
+        state: OrderBookSyncState,
+        last_update_id: Option<u64>,
+        gaps_detected: u64,
+        resyncs: u64,
+        lagged_messages: u64,
+    },
+    SubscribedToSymbol {
+        symbol: Symbol,
+    },
+    UnsubscribedFromSymbol {
+        symbol: Symbol,
+    },
+    UnknownSymbol {
+        symbol: Symbol,
+    },
+    MarketConnectionState {
+        state: ConnectionState,
+    },
+    ActiveSymbols {
+        symbols: Vec<(Symbol, OrderBookSyncState)>,
+    },
+    // levels ordered from the best price of each side
+    OrderBookLevels {
+        symbol: Symbol,
+        last_update_id: u64,
+        bids: Vec<PriceLevel>,
+        asks: Vec<PriceLevel>,
+    },
+    MidPrice {
+        symbol: Symbol,
+        price: Option<Price>,
+    },
+    Microprice {
+        symbol: Symbol,
+        price: Option<Price>,
+    },
+    VwapOfTopLevels {
+        symbol: Symbol,
+        depth: usize,
+        bids: Option<Price>,
+        asks: Option<Price>,
+    },
+    // a side is None when it does not hold enough quantity to fill the notional
+    VwapToFillNotional {
+        symbol: Symbol,
+        notional: Notional,
+        bids: Option<Price>,
+        asks: Option<Price>,
+    },
+    QuantityWeightedAveragePrice {
+        symbol: Symbol,
+        price: Option<Price>,
+    },
+    BestBidAsk {
+        symbol: Symbol,
+        bid: Option<PriceLevel>,
+        ask: Option<PriceLevel>,
+    },
+    // None when a side of the book is empty
+    Spread {
+        symbol: Symbol,
+        absolute: Option<Price>,
+        relative_bps: Option<Decimal>,
+    },
+    DepthWithinBps {
+        symbol: Symbol,
+        bps: Decimal,
+        bids: Option<Quantity>,
+        asks: Option<Quantity>,
+    },
+    // imbalance at each of the queried depths, None when no level holds quantity
+    Imbalance {
+        symbol: Symbol,
+        imbalances: Vec<(usize, Option<Decimal>)>,
+    },
+    MarketOrderEstimate {
+        symbol: Symbol,
+        side: Side,
+        size: OrderSize,
+        estimate: FillEstimate,
+    },
+    // consolidated responses are for the normalised symbol and list the venue
+    // symbols whose synced books were consolidated
+    ConsolidatedBook {
+        symbol: Symbol,
+        venues: Vec<(Venue, Symbol)>,
+        book: ConsolidatedBook,
+    },
+    ConsolidatedBestBidOffer {
+        symbol: Symbol,
+        venues: Vec<(Venue, Symbol)>,
+        bid: Option<ConsolidatedLevel>,
+        offer: Option<ConsolidatedLevel>,
+    },
+    ConsolidatedDepthWithinBps {
+        symbol: Symbol,
+        venues: Vec<(Venue, Symbol)>,
+        bps: Decimal,
+        bids: Option<Quantity>,
+        asks: Option<Quantity>,
+    },
+    // detected_at is in milliseconds since the unix epoch
+    ArbitrageOpportunities {
+        symbol: Symbol,
+        detected_at: u64,
+        opportunities: Vec<ArbitrageOpportunity>,
+    },
+    // detected_at is in milliseconds since the unix epoch
+    TriangularArbitrage {
+        detected_at: u64,
+        round_trips: Vec<TriangleRoundTrip>,
+    },
+    Candles {
+        symbol: Symbol,
+        interval: CandleInterval,
+        candles: Vec<Candle>,
+    },
+    CandleUpdate {
+        symbol: Symbol,
+        interval: CandleInterval,
+        candle: Candle,
+    },
+    // event time is in milliseconds since the unix epoch, None until an update changed the book.
+    // Work done on the update, like sending it to clients, is traced in spans linked to the
+    // context of the span it was applied in, which is empty until then
+    LastBookUpdate {
+        symbol: Symbol,
+        event_time: Option<u64>,
+        trace_context: TraceContext,
     },
     InfrastructureConnected,
     InternalError,
 }
 
 impl Application {
+    pub fn new(market_stream: MarketStreamConnection) -> Self {
+        let (book_updates, _) = broadcast::channel::<Symbol>(16);
+        let (venue_book_updates, _) = broadcast::channel::<Symbol>(64);
+        let (candle_updates, _) = broadcast::channel::<CandleUpdate>(64);
+
+        Self {
+            market_stream: market_stream.receiver.clone(),
+            subscriptions: Arc::new(SymbolSubscriptions::new(market_stream)),
+            order_books: Arc::new(RwLock::new(BTreeMap::new())),
+            book_updates,
+            lagged_messages: Arc::new(AtomicU64::new(0)),
+            // a connection is handed over only once it is made
+            connection_state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            venue_books: Arc::new(RwLock::new(BTreeMap::new())),
+            venue_book_updates,
+            arbitrage_settings: Arc::new(ArbitrageSettings::default()),
+            triangles: Arc::new(vec![]),
+            candles: Arc::new(RwLock::new(BTreeMap::new())),
+            candle_updates,
+            metrics: NoMetrics::shared(),
+        }
+    }
+
+    // metrics the application reports its queries, lags and resyncs to
+    pub fn with_metrics(self, metrics: SharedMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    // Capacity of the channels telling clients about changed books and candles, clients
+    // falling further behind skip to the latest state
+    pub fn with_update_capacity(self, capacity: usize) -> Self {
+        Self {
+            book_updates: broadcast::channel::<Symbol>(capacity).0,
+            venue_book_updates: broadcast::channel::<Symbol>(capacity).0,
+            candle_updates: broadcast::channel::<CandleUpdate>(capacity).0,
+            ..self
+        }
+    }
+
     pub async fn handle_query(&self, query: ApplicationQuery) -> Result<ApplicationResponse> {
+        let name = query.name();
+        let started_at = Instant::now();
+
+        let res = self
+            .answer_query(query)
+            .instrument(debug_span!("query", query = name))
+            .await;
+        self.metrics.query_answered(name, started_at.elapsed());
+
+        res
+    }
+
+    async fn answer_query(&self, query: ApplicationQuery) -> Result<ApplicationResponse> {
         match query {
             ApplicationQuery::GetAverageValueOfSymbol(symbol) => {
-                // Get the asks and bids from a stream provided by the MarketStream adapter
-
-                /* Problem:
-                  Market api is recieving data on another thread due being a websocket.
-                  Application layer needs to retrieve the latest data from the market api
-                  and do transformations to get the average order book value.
-                */
-
-                //two Arc clones are needed to work around lifetime error
-                let ms = self.market_stream.clone();
-                // subscribe to market stream broadcast through receiver resubscriptians due to sender moving in another thread
-                let mut broadcast_receiver = ms.resubscribe();
-                let app_res: ApplicationResponse = loop {
-                    let message: Option<Arc<String>> = broadcast_receiver.recv().await.ok();
-                    match message {
-                        Some(string) => {
-                            let json_value: Value =
-                                serde_json::from_str(string.as_str()).expect("json string");
-
-                            /*
-                            This can be abstracted into the market stream implementation.
-                            Since streams can return any kind of multiple response types
-                            a adapter to handle each transformed value can be created that return
-                            explicit struct types that match the return types from an API.
-                            And then have those values be used in the application layer.
-                            */
-
-                            // guard to handle initial connection message
-                            if json_value["result"] == Value::Null {
-                                ApplicationResponse::InfrastructureConnected;
-                            };
-
-                            let stream_to_match = format! {"{}@depth", symbol.0.to_lowercase()};
-                            if json_value["stream"] == stream_to_match {
-                                // transforms asks and bids to remove qty since each value is a vec with a price quantity pair.
-                                let price_array_to_vec_f32 = |json_array: &Value| {
-                                    let cloned_value = json_array.clone();
-                                    let prices: Vec<f32> = cloned_value.as_array().expect("'a' to be an array").into_iter().map(|price_qty_pair| {
-                                        let price = price_qty_pair
-                                            .as_array()
-                                            .expect("pairs in 'a' to be an array")
-                                            .first()
-                                                .expect("some value")
-                                                .as_str()
-                                                .expect("to be a string type due to binance diff depth spec");
-
-                                        price.parse::<f32>().expect("price like numbers in given vec")
-                                    }).collect();
-
-                                    prices
-                                };
-
-                                /*
-                                Instead of json values being used a struct that replicates the API return calls can be used and
-                                branching code can be created for different market streams
-                                */
-                                let asks = {
-                                    let origin_asks = &json_value["data"]["a"];
-                                    price_array_to_vec_f32(origin_asks)
-                                };
-
-                                let bids = {
-                                    let origin_bids = &json_value["data"]["b"];
-                                    price_array_to_vec_f32(origin_bids)
-                                };
-
-                                // Run asks and bids through pure functions from the application layer
-                                let avg_price = core::average_price_of_order_book(asks, bids);
-
-                                break ApplicationResponse::CurrentAveragePriceForSymbol {
-                                    symbol,
-                                    price: avg_price.to_string(),
-                                };
-                            }
-                        }
-                        None => {
-                            break ApplicationResponse::CurrentAveragePriceForSymbol {
-                                symbol,
-                                price: "None".into(),
-                            }
-                        }
-                    };
+                self.average_value_of_symbol(symbol).await
+            }
+            ApplicationQuery::GetCurrentAveragePrice(symbol) => {
+                Ok(self.current_average_price(symbol).await)
+            }
+            ApplicationQuery::GetOrderBookSyncStatus(symbol) => {
+                self.order_book_sync_status(symbol).await
+            }
+            ApplicationQuery::GetMarketConnectionState => {
+                Ok(ApplicationResponse::MarketConnectionState {
+                    state: self.connection_state.read().await.clone(),
+                })
+            }
+            ApplicationQuery::GetActiveSymbols => Ok(ApplicationResponse::ActiveSymbols {
+                symbols: self
+                    .order_books
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(symbol, local)| (symbol.clone(), local.state))
+                    .collect(),
+            }),
+            ApplicationQuery::GetOrderBookLevels { symbol, depth } => {
+                Ok(self.order_book_levels(symbol, depth).await)
+            }
+            ApplicationQuery::GetMidPrice(symbol) => Ok(self.mid_price(symbol).await),
+            ApplicationQuery::GetMicroprice(symbol) => Ok(self.microprice(symbol).await),
+            ApplicationQuery::GetVwapOfTopLevels { symbol, depth } => {
+                Ok(self.vwap_of_top_levels(symbol, depth).await)
+            }
+            ApplicationQuery::GetVwapToFillNotional { symbol, notional } => {
+                Ok(self.vwap_to_fill_notional(symbol, notional).await)
+            }
+            ApplicationQuery::GetQuantityWeightedAveragePrice(symbol) => {
+                Ok(self.quantity_weighted_average_price(symbol).await)
+            }
+            ApplicationQuery::GetBestBidAsk(symbol) => Ok(self.best_bid_ask(symbol).await),
+            ApplicationQuery::GetSpread(symbol) => Ok(self.spread(symbol).await),
+            ApplicationQuery::GetDepthWithinBps { symbol, bps } => {
+                Ok(self.depth_within_bps(symbol, bps).await)
+            }
+            ApplicationQuery::GetImbalance { symbol, depths } => {
+                Ok(self.imbalance(symbol, depths).await)
+            }
+            ApplicationQuery::EstimateMarketOrder { symbol, side, size } => {
+                Ok(self.estimate_market_order(symbol, side, size).await)
+            }
+            ApplicationQuery::GetConsolidatedBook { symbol, depth } => {
+                Ok(self.consolidated_book(symbol, depth).await)
+            }
+            ApplicationQuery::GetConsolidatedBestBidOffer(symbol) => {
+                Ok(self.consolidated_best_bid_offer(symbol).await)
+            }
+            ApplicationQuery::GetConsolidatedDepthWithinBps { symbol, bps } => {
+                Ok(self.consolidated_depth_within_bps(symbol, bps).await)
+            }
+            ApplicationQuery::GetArbitrageOpportunities(symbol) => {
+                Ok(self.arbitrage_opportunities(symbol).await)
+            }
+            ApplicationQuery::WaitForArbitrageOpportunities(symbol) => {
+                Ok(self.next_arbitrage_opportunities(symbol).await)
+            }
+            ApplicationQuery::GetTriangularArbitrage => Ok(self.triangular_arbitrage().await),
+            ApplicationQuery::WaitForTriangularArbitrage => {
+                Ok(self.next_triangular_arbitrage().await)
+            }
+            ApplicationQuery::GetCandles {
+                symbol,
+                interval,
+                limit,
+            } => Ok(self.last_candles(symbol, interval, limit).await),
+            ApplicationQuery::WaitForCandle { symbol, interval } => {
+                Ok(self.next_candle(symbol, interval).await)
+            }
+            ApplicationQuery::SubscribeToSymbol(symbol) => self.subscribe_to_symbol(symbol).await,
+            ApplicationQuery::UnsubscribeFromSymbol(symbol) => {
+                self.unsubscribe_from_symbol(symbol).await
+            }
+            ApplicationQuery::GetLastBookUpdate(symbol) => {
+                let (event_time, trace_context) = match self.order_books.read().await.get(&symbol) {
+                    Some(local) => (local.last_event_time, local.last_update_context),
+                    None => (None, TraceContext::default()),
                 };
-                Ok(app_res)
+
+                Ok(ApplicationResponse::LastBookUpdate {
+                    symbol,
+                    event_time,
+                    trace_context,
+                })
             }
         }
     }
+
+    async fn average_value_of_symbol(&self, symbol: Symbol) -> Result<ApplicationResponse> {
+        // subscribe before checking the books so no update is missed in between
+        let mut book_updates = self.book_updates.subscribe();
+
+        if !self.order_books.read().await.contains_key(&symbol) {
+            return Ok(ApplicationResponse::CurrentAveragePriceForSymbol {
+                symbol,
+                price: None,
+            });
+        }
+
+        // wait for the book to change so each query answers with a new value
+        loop {
+            match book_updates.recv().await {
+                Ok(updated) if updated == symbol => break,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => {
+                    return Ok(ApplicationResponse::CurrentAveragePriceForSymbol {
+                        symbol,
+                        price: None,
+                    })
+                }
+            }
+        }
+
+        let books = self.order_books.read().await;
+        let res = match books.get(&symbol) {
+            Some(LocalOrderBook {
+                book: Some(book),
+                state: OrderBookSyncState::Synced,
+                ..
+            }) => ApplicationResponse::CurrentAveragePriceForSymbol {
+                symbol,
+                price: book_metrics::average_price(book),
+            },
+            Some(_) => ApplicationResponse::OrderBookOutOfSync { symbol },
+            None => ApplicationResponse::CurrentAveragePriceForSymbol {
+                symbol,
+                price: None,
+            },
+        };
+
+        Ok(res)
+    }
+
+    async fn order_book_sync_status(&self, symbol: Symbol) -> Result<ApplicationResponse> {
+        let books = self.order_books.read().await;
+
+        let res = match books.get(&symbol) {
+            Some(local) => ApplicationResponse::OrderBookSyncStatus {
+                state: local.state,
+                last_update_id: local.book.as_ref().map(|book| book.last_update_id()),
+                gaps_detected: local.gaps_detected,
+                resyncs: local.resyncs,
+                lagged_messages: self.lagged_messages.load(Ordering::Relaxed),
+                symbol,
+            },
+            None => ApplicationResponse::UnknownSymbol { symbol },
+        };
+
+        Ok(res)
+    }
 }
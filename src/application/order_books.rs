@@ -0,0 +1,841 @@
+use super::Application;
+use crate::{
+    core::{DepthUpdateOutcome, OrderBook},
+    ports::{ConnectionState, DepthSnapshotSource, MarketEvent, TraceContext},
+    typespec::{DepthSnapshot, DepthUpdate, Symbol},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    time::Instant,
+};
+use tracing::{debug, info_span, warn, Instrument, Span};
+
+// snapshots are heavy requests, a book out of sync fetches one at most this often at first
+const MIN_RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+// the interval doubles with every snapshot that does not bring the book back in sync
+const MAX_RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+// updates kept for a book while its snapshot is fetched, the oldest are dropped beyond it
+const MAX_BUFFERED_UPDATES: usize = 1000;
+
+// snapshot fetched in its own task for the book of a symbol
+type FetchedSnapshot = (Symbol, Result<DepthSnapshot>);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderBookSyncState {
+    Synced,
+    // book missed updates and can not be used until it is bootstrapped from a new snapshot
+    OutOfSync,
+}
+
+// Local order book of a symbol together with the state of its sync with the market stream
+pub(super) struct LocalOrderBook {
+    pub(super) book: Option<OrderBook>,
+    pub(super) state: OrderBookSyncState,
+    pub(super) gaps_detected: u64,
+    pub(super) resyncs: u64,
+    // event time of the last update applied to the book
+    pub(super) last_event_time: Option<u64>,
+    // trace context of the span the last update was applied in. Work done on the update links
+    // to it, so the span itself closes once the update is applied
+    pub(super) last_update_context: TraceContext,
+    // snapshots fetched since the book was last in sync and when the next one may be fetched
+    resync_attempts: u32,
+    next_resync: Option<Instant>,
+    // updates received while a snapshot is fetched, applied on top of it. None while
+    // no snapshot is fetched
+    buffered_updates: Option<VecDeque<DepthUpdate>>,
+}
+
+impl LocalOrderBook {
+    pub(super) fn new() -> Self {
+        Self {
+            book: None,
+            state: OrderBookSyncState::OutOfSync,
+            gaps_detected: 0,
+            resyncs: 0,
+            last_event_time: None,
+            last_update_context: TraceContext::default(),
+            resync_attempts: 0,
+            next_resync: None,
+            buffered_updates: None,
+        }
+    }
+
+    // Keeps an update for the snapshot being fetched, false when no snapshot is fetched.
+    // Dropping the oldest updates leaves a gap the snapshot is found out of sync with
+    // unless it is newer than them
+    fn buffer(&mut self, update: &DepthUpdate) -> bool {
+        let buffered_updates = match self.buffered_updates.as_mut() {
+            Some(buffered_updates) => buffered_updates,
+            None => return false,
+        };
+
+        if buffered_updates.len() == MAX_BUFFERED_UPDATES {
+            buffered_updates.pop_front();
+        }
+        buffered_updates.push_back(update.clone());
+        true
+    }
+
+    // Claims the next snapshot fetch of the book, false while the previous one is too recent
+    fn claim_resync(&mut self, now: Instant) -> bool {
+        if self
+            .next_resync
+            .is_some_and(|next_resync| now < next_resync)
+        {
+            return false;
+        }
+
+        self.next_resync = Some(now + resync_interval(self.resync_attempts));
+        self.resync_attempts = self.resync_attempts.saturating_add(1);
+        true
+    }
+
+    // Applies an update to a synced book. None is returned when the book is out of sync
+    fn apply(&mut self, update: &DepthUpdate, span: &Span) -> Option<DepthUpdateOutcome> {
+        let book = match (self.book.as_mut(), self.state) {
+            (Some(book), OrderBookSyncState::Synced) => book,
+            _ => return None,
+        };
+
+        match book.apply(update) {
+            Ok(outcome) => {
+                if outcome == DepthUpdateOutcome::Applied {
+                    self.last_event_time = Some(update.event_time);
+                    self.last_update_context = TraceContext::from(span);
+                }
+                Some(outcome)
+            }
+            Err(gap) => {
+                warn!(symbol = %update.symbol.0, %gap, "order book out of sync");
+                self.gaps_detected += 1;
+                self.state = OrderBookSyncState::OutOfSync;
+                None
+            }
+        }
+    }
+}
+
+impl Application {
+    /*
+    Keeps a local order book for each symbol in sync with the market stream.
+    Runs until the market stream is closed so it is meant to be spawned in its own task.
+
+    The symbols are the ones the market stream connection was made with and stay subscribed.
+    Symbols subscribed later by clients are bootstrapped with the first update they receive.
+
+    The receiver is subscribed before the snapshots are fetched so the diff depth
+    updates sent in the meantime are kept in the channel and applied on top of the snapshot.
+    A book is marked out of sync and bootstrapped again from a new snapshot when an
+    update id is skipped or when the receiver lags behind and messages are lost.
+    Books are also out of sync while the market stream is disconnected and bootstrapped
+    again once it is connected. Markets that send snapshots on the stream itself
+    bootstrap the book of the symbol with each of them.
+
+    Snapshots are fetched in tasks of their own and handed back through a channel, so a
+    slow market api only holds back the book being fetched while the others stay in sync.
+    */
+    pub async fn maintain_order_books<S: DepthSnapshotSource + Send + Sync + 'static>(
+        &self,
+        snapshot_source: S,
+        symbols: Vec<Symbol>,
+    ) -> Result<()> {
+        // each book fetches a single snapshot at a time, so the channel holds at most one
+        // snapshot of each book
+        let (fetched, mut fetched_receiver) = mpsc::unbounded_channel::<FetchedSnapshot>();
+        let fetcher = SnapshotFetcher {
+            source: Arc::new(snapshot_source),
+            fetched,
+        };
+        let mut receiver = self.market_stream.resubscribe();
+        self.pin_symbols(&symbols).await;
+
+        {
+            let mut books = self.order_books.write().await;
+            for symbol in symbols.iter() {
+                books
+                    .entry(symbol.clone())
+                    .or_insert_with(LocalOrderBook::new);
+            }
+        }
+
+        for symbol in symbols.iter() {
+            self.resync_order_book(&fetcher, symbol, None).await;
+        }
+
+        loop {
+            let received = tokio::select! {
+                received = receiver.recv() => received,
+                Some((symbol, snapshot)) = fetched_receiver.recv() => {
+                    self.snapshot_fetched(&symbol, snapshot).await;
+                    continue;
+                }
+            };
+            let message = match received {
+                Ok(message) => message,
+                // skipped messages can hold updates of any symbol so no book can be trusted
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "order book sync lagged behind the market stream");
+                    self.lagged_messages.fetch_add(skipped, Ordering::Relaxed);
+                    self.metrics.messages_lagged("order_book_sync", skipped);
+
+                    for symbol in self.mark_order_books_out_of_sync().await {
+                        self.resync_order_book(&fetcher, &symbol, None).await;
+                    }
+                    continue;
+                }
+                Err(RecvError::Closed) => return Err(anyhow!("market stream closed")),
+            };
+
+            let update = match &message.event {
+                MarketEvent::DepthUpdate(update) => update,
+                MarketEvent::BookSnapshot { symbol, snapshot } => {
+                    self.bootstrap_order_book(symbol, snapshot.clone(), vec![])
+                        .await;
+                    continue;
+                }
+                MarketEvent::ConnectionState(state) => {
+                    self.update_connection_state(&fetcher, state).await;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let span = info_span!(
+                parent: &message.span,
+                "apply_depth_update",
+                symbol = %update.symbol.0,
+                first_update_id = update.first_update_id,
+                final_update_id = update.final_update_id,
+                event_time = update.event_time,
+            );
+            let outcome = match self.order_books.write().await.get_mut(&update.symbol) {
+                Some(local) => span.in_scope(|| local.apply(update, &span)),
+                None => continue,
+            };
+
+            match outcome {
+                Some(DepthUpdateOutcome::Applied) => {
+                    let _ = self.book_updates.send(update.symbol.clone());
+                }
+                Some(DepthUpdateOutcome::Stale) => {}
+                // books out of sync retry a new snapshot with each update of their symbol
+                None => {
+                    let _ = self.book_updates.send(update.symbol.clone());
+                    self.resync_order_book(&fetcher, &update.symbol, Some(update))
+                        .instrument(span)
+                        .await;
+                }
+            }
+        }
+    }
+
+    /*
+    Starts fetching a new snapshot of the book of a symbol. The update that found the book
+    out of sync and the ones received until the snapshot arrives are applied on top of the
+    snapshot as they can be newer than the snapshot.
+
+    Snapshots of a book are fetched at a growing interval until it is back in sync, the
+    attempts in between are skipped so a book out of sync does not fetch one per update and
+    a lagging receiver does not fetch every book again each time it lags.
+    */
+    async fn resync_order_book<S: DepthSnapshotSource + Send + Sync + 'static>(
+        &self,
+        fetcher: &SnapshotFetcher<S>,
+        symbol: &Symbol,
+        pending_update: Option<&DepthUpdate>,
+    ) {
+        let mut books = self.order_books.write().await;
+        let local = match books.get_mut(symbol) {
+            Some(local) => local,
+            None => return,
+        };
+
+        if let Some(update) = pending_update {
+            if local.buffer(update) {
+                return;
+            }
+        }
+        if !local.claim_resync(Instant::now()) {
+            return;
+        }
+
+        local.buffered_updates = Some(pending_update.cloned().into_iter().collect());
+        fetcher.fetch(symbol.clone());
+    }
+
+    // Bootstraps the book of a symbol from the snapshot fetched for it along with the
+    // updates received meanwhile. When fetching failed the book stays out of sync until
+    // the next attempt
+    async fn snapshot_fetched(&self, symbol: &Symbol, snapshot: Result<DepthSnapshot>) {
+        let buffered_updates = match self.order_books.write().await.get_mut(symbol) {
+            Some(local) => local.buffered_updates.take().unwrap_or_default(),
+            None => return,
+        };
+
+        match snapshot {
+            Ok(snapshot) => {
+                self.bootstrap_order_book(symbol, snapshot, buffered_updates.into())
+                    .await
+            }
+            Err(e) => warn!(symbol = %symbol.0, error = %e, "failed to fetch depth snapshot"),
+        }
+    }
+
+    // Replaces the book of a tracked symbol with a snapshot. Snapshots sent on the
+    // market stream bootstrap books the same way as the fetched ones
+    async fn bootstrap_order_book(
+        &self,
+        symbol: &Symbol,
+        snapshot: DepthSnapshot,
+        pending_updates: Vec<DepthUpdate>,
+    ) {
+        let mut book = OrderBook::from_snapshot(snapshot);
+        let in_sync = pending_updates
+            .iter()
+            .all(|update| book.apply(update).is_ok());
+
+        if let Some(local) = self.order_books.write().await.get_mut(symbol) {
+            if in_sync && local.book.is_some() {
+                local.resyncs += 1;
+                self.metrics.book_resynced(symbol);
+            }
+
+            debug!(symbol = %symbol.0, in_sync, "order book bootstrapped from a snapshot");
+            if in_sync {
+                local.resync_attempts = 0;
+                local.next_resync = None;
+            }
+            local.book = Some(book);
+            local.state = if in_sync {
+                OrderBookSyncState::Synced
+            } else {
+                OrderBookSyncState::OutOfSync
+            };
+        }
+
+        let _ = self.book_updates.send(symbol.clone());
+    }
+
+    async fn update_connection_state<S: DepthSnapshotSource + Send + Sync + 'static>(
+        &self,
+        fetcher: &SnapshotFetcher<S>,
+        state: &ConnectionState,
+    ) {
+        *self.connection_state.write().await = state.clone();
+
+        let symbols = match state {
+            // updates sent while disconnected are lost
+            ConnectionState::Disconnected { .. } => self.mark_order_books_out_of_sync().await,
+            ConnectionState::Reconnecting { .. } => {
+                self.order_books.read().await.keys().cloned().collect()
+            }
+            ConnectionState::Connected => {
+                for symbol in self.mark_order_books_out_of_sync().await {
+                    self.resync_order_book(fetcher, &symbol, None).await;
+                }
+                return;
+            }
+        };
+
+        // waiting queries answer with the book being out of sync
+        for symbol in symbols {
+            let _ = self.book_updates.send(symbol);
+        }
+    }
+
+    // returns the symbols of all books that were marked
+    async fn mark_order_books_out_of_sync(&self) -> Vec<Symbol> {
+        let mut books = self.order_books.write().await;
+
+        for local in books.values_mut() {
+            local.state = OrderBookSyncState::OutOfSync;
+        }
+
+        books.keys().cloned().collect()
+    }
+}
+
+// Fetches snapshots in tasks of their own and sends them to the task maintaining the books
+struct SnapshotFetcher<S> {
+    source: Arc<S>,
+    fetched: mpsc::UnboundedSender<FetchedSnapshot>,
+}
+
+impl<S: DepthSnapshotSource + Send + Sync + 'static> SnapshotFetcher<S> {
+    fn fetch(&self, symbol: Symbol) {
+        let source = self.source.clone();
+        let fetched = self.fetched.clone();
+
+        tokio::spawn(
+            async move {
+                let snapshot = source.fetch_snapshot(&symbol).await;
+                let _ = fetched.send((symbol, snapshot));
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+// Interval before the next snapshot of a book after the given number of fetched ones
+fn resync_interval(attempts: u32) -> Duration {
+    MIN_RESYNC_INTERVAL
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(MAX_RESYNC_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::{ApplicationQuery, ApplicationResponse},
+        ports::{MarketMessage, MarketStreamConnection},
+        typespec::levels,
+    };
+    use std::sync::{atomic::AtomicU64, Arc, Mutex};
+    use tokio::sync::{broadcast, mpsc, Notify};
+    use tracing::{span, Subscriber};
+    use tracing_subscriber::{layer::Context, layer::SubscriberExt, registry::LookupSpan, Layer};
+
+    // local stand-in for a market api so tests do not need a network connection.
+    // every fetched snapshot is 100 update ids after the previous one
+    #[derive(Default)]
+    struct SequencedDepthSnapshot {
+        fetches: AtomicU64,
+    }
+
+    impl DepthSnapshotSource for SequencedDepthSnapshot {
+        async fn fetch_snapshot(&self, _symbol: &Symbol) -> Result<DepthSnapshot> {
+            let fetches = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+
+            Ok(DepthSnapshot {
+                last_update_id: fetches * 100,
+                bids: levels(&[("99.00", "1"), ("98.50", "1")]),
+                asks: levels(&[("101.00", "1"), ("102.25", "1")]),
+            })
+        }
+    }
+
+    // stand-in for a market api whose snapshots are always older than the stream
+    #[derive(Clone, Default)]
+    struct StaleDepthSnapshot {
+        fetches: Arc<AtomicU64>,
+    }
+
+    impl DepthSnapshotSource for StaleDepthSnapshot {
+        async fn fetch_snapshot(&self, _symbol: &Symbol) -> Result<DepthSnapshot> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+
+            Ok(DepthSnapshot {
+                last_update_id: 1,
+                bids: levels(&[("99.00", "1")]),
+                asks: levels(&[("101.00", "1")]),
+            })
+        }
+    }
+
+    // stand-in for a market api that answers with the snapshot of update id 100 once let
+    // through, and never for the symbol it hangs on
+    #[derive(Default)]
+    struct GatedDepthSnapshot {
+        gate: Arc<Notify>,
+        hanging: Option<Symbol>,
+    }
+
+    impl DepthSnapshotSource for GatedDepthSnapshot {
+        async fn fetch_snapshot(&self, symbol: &Symbol) -> Result<DepthSnapshot> {
+            if self.hanging.as_ref() == Some(symbol) {
+                std::future::pending::<()>().await;
+            }
+            self.gate.notified().await;
+
+            Ok(DepthSnapshot {
+                last_update_id: 100,
+                bids: levels(&[("99.00", "1"), ("98.50", "1")]),
+                asks: levels(&[("101.00", "1"), ("102.25", "1")]),
+            })
+        }
+    }
+
+    fn diff_depth_event(first_update_id: u64, final_update_id: u64) -> Arc<MarketMessage> {
+        Arc::new(
+            MarketEvent::DepthUpdate(DepthUpdate {
+                symbol: Symbol("BTCUSDC".into()),
+                event_time: 1,
+                first_update_id,
+                final_update_id,
+                bids: levels(&[("99.00", "0")]),
+                asks: levels(&[("103.00", "2")]),
+            })
+            .into(),
+        )
+    }
+
+    fn setup() -> (broadcast::Sender<Arc<MarketMessage>>, Application, Symbol) {
+        let (sender, app, symbol) = unsynced_setup();
+
+        let sync = app.clone();
+        let sync_symbols = vec![symbol.clone()];
+        tokio::spawn(async move {
+            sync.maintain_order_books(SequencedDepthSnapshot::default(), sync_symbols)
+                .await
+        });
+
+        (sender, app, symbol)
+    }
+
+    // application whose books are not maintained yet
+    fn unsynced_setup() -> (broadcast::Sender<Arc<MarketMessage>>, Application, Symbol) {
+        let (sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        let app = Application::new(MarketStreamConnection::new(Arc::new(receiver), commands));
+
+        (sender, app, Symbol("BTCUSDC".into()))
+    }
+
+    // records the names of the spans closed while its subscriber is the default one
+    #[derive(Clone, Default)]
+    struct ClosedSpans(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ClosedSpans {
+        fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+            if let Some(span) = ctx.span(&id) {
+                self.0.lock().unwrap().push(span.name().to_string());
+            }
+        }
+    }
+
+    // polls the sync status of a book until it has the given last update id
+    async fn wait_for_last_update_id(app: &Application, symbol: &Symbol, update_id: u64) {
+        loop {
+            let status = app
+                .handle_query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone()))
+                .await
+                .unwrap();
+
+            if let ApplicationResponse::OrderBookSyncStatus {
+                last_update_id: Some(id),
+                ..
+            } = status
+            {
+                if id == update_id {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_average_price_of_maintained_order_book() {
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        let query = tokio::spawn({
+            let app = app.clone();
+            let symbol = symbol.clone();
+            async move {
+                app.handle_query(ApplicationQuery::GetAverageValueOfSymbol(symbol))
+                    .await
+            }
+        });
+        // let the query subscribe to book updates before the diff is sent
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sender.send(diff_depth_event(95, 105)).unwrap();
+
+        let response = query.await.unwrap().unwrap();
+
+        // bid 99 removed and ask 103 added to the snapshot so the average of
+        // 98.50, 101.00, 102.25 and 103.00 is rounded to the tick size of 0.01
+        match response {
+            ApplicationResponse::CurrentAveragePriceForSymbol { price, .. } => {
+                assert_eq!(price.map(|p| p.to_string()), Some("101.19".into()))
+            }
+            _ => panic!("expected an average price response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequence_gap_resyncs_book_from_new_snapshot() {
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        sender.send(diff_depth_event(95, 105)).unwrap();
+        // update ids 106 to 109 are missing
+        sender.send(diff_depth_event(110, 115)).unwrap();
+        wait_for_last_update_id(&app, &symbol, 200).await;
+
+        let status = app
+            .handle_query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone()))
+            .await
+            .unwrap();
+
+        match status {
+            ApplicationResponse::OrderBookSyncStatus {
+                state,
+                gaps_detected,
+                resyncs,
+                lagged_messages,
+                ..
+            } => {
+                assert_eq!(state, OrderBookSyncState::Synced);
+                assert_eq!(gaps_detected, 1);
+                assert_eq!(resyncs, 1);
+                assert_eq!(lagged_messages, 0);
+            }
+            _ => panic!("expected a sync status response"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_book_out_of_sync_does_not_fetch_a_snapshot_per_update() {
+        let snapshot_source = StaleDepthSnapshot::default();
+        let fetches = snapshot_source.fetches.clone();
+        let (sender, app, symbol) = unsynced_setup();
+        let sync = app.clone();
+        let sync_symbols = vec![symbol.clone()];
+        tokio::spawn(async move {
+            sync.maintain_order_books(snapshot_source, sync_symbols)
+                .await
+        });
+        wait_for_last_update_id(&app, &symbol, 1).await;
+
+        // every update is past the snapshots so the book never gets back in sync
+        for update_id in 10..20 {
+            sender.send(diff_depth_event(update_id, update_id)).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the initial snapshot and a single one for the gap
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(MIN_RESYNC_INTERVAL).await;
+        sender.send(diff_depth_event(20, 20)).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        // the interval doubled with the failed attempt
+        tokio::time::sleep(MIN_RESYNC_INTERVAL).await;
+        sender.send(diff_depth_event(21, 21)).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_updates_received_while_fetching_applied_on_top_of_snapshot() {
+        let snapshot_source = GatedDepthSnapshot::default();
+        let gate = snapshot_source.gate.clone();
+        let (sender, app, symbol) = unsynced_setup();
+        let sync = app.clone();
+        let sync_symbols = vec![symbol.clone()];
+        tokio::spawn(async move {
+            sync.maintain_order_books(snapshot_source, sync_symbols)
+                .await
+        });
+
+        // let the sync task subscribe to the market stream and start fetching
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        sender.send(diff_depth_event(95, 105)).unwrap();
+        sender.send(diff_depth_event(106, 110)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        gate.notify_one();
+
+        wait_for_last_update_id(&app, &symbol, 110).await;
+    }
+
+    #[tokio::test]
+    async fn test_slow_snapshot_does_not_hold_back_other_books() {
+        let hanging = Symbol("ETHUSDC".into());
+        let snapshot_source = GatedDepthSnapshot {
+            hanging: Some(hanging.clone()),
+            ..Default::default()
+        };
+        snapshot_source.gate.notify_one();
+        let (sender, app, symbol) = unsynced_setup();
+        let sync = app.clone();
+        let sync_symbols = vec![hanging, symbol.clone()];
+        tokio::spawn(async move {
+            sync.maintain_order_books(snapshot_source, sync_symbols)
+                .await
+        });
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        sender.send(diff_depth_event(95, 105)).unwrap();
+
+        wait_for_last_update_id(&app, &symbol, 105).await;
+    }
+
+    #[test]
+    fn test_resync_interval_grows_up_to_limit() {
+        assert_eq!(resync_interval(0), MIN_RESYNC_INTERVAL);
+        assert_eq!(resync_interval(2), MIN_RESYNC_INTERVAL * 4);
+        assert_eq!(resync_interval(u32::MAX), MAX_RESYNC_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_on_market_stream_bootstraps_book() {
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        sender
+            .send(Arc::new(
+                MarketEvent::BookSnapshot {
+                    symbol: symbol.clone(),
+                    snapshot: DepthSnapshot {
+                        last_update_id: 7,
+                        bids: levels(&[("99.00", "1")]),
+                        asks: levels(&[("101.00", "1")]),
+                    },
+                }
+                .into(),
+            ))
+            .unwrap();
+        sender.send(diff_depth_event(8, 8)).unwrap();
+        wait_for_last_update_id(&app, &symbol, 8).await;
+
+        let status = app
+            .handle_query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone()))
+            .await
+            .unwrap();
+
+        match status {
+            ApplicationResponse::OrderBookSyncStatus { state, resyncs, .. } => {
+                assert_eq!(state, OrderBookSyncState::Synced);
+                assert_eq!(resyncs, 1);
+            }
+            _ => panic!("expected a sync status response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagged_receiver_resyncs_book_from_new_snapshot() {
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        // the test runtime is single threaded so the sync task can not receive
+        // any message until the test yields and the channel overflows
+        for update_id in 0..20 {
+            sender
+                .send(diff_depth_event(101 + update_id, 101 + update_id))
+                .unwrap();
+        }
+        wait_for_last_update_id(&app, &symbol, 200).await;
+
+        let status = app
+            .handle_query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone()))
+            .await
+            .unwrap();
+
+        match status {
+            ApplicationResponse::OrderBookSyncStatus {
+                state,
+                resyncs,
+                lagged_messages,
+                ..
+            } => {
+                assert_eq!(state, OrderBookSyncState::Synced);
+                assert_eq!(resyncs, 1);
+                assert_eq!(lagged_messages, 4);
+            }
+            _ => panic!("expected a sync status response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_out_of_sync_book_is_not_used_for_queries() {
+        let (_sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+        app.mark_order_books_out_of_sync().await;
+
+        let query = tokio::spawn({
+            let app = app.clone();
+            let symbol = symbol.clone();
+            async move {
+                app.handle_query(ApplicationQuery::GetAverageValueOfSymbol(symbol))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let _ = app.book_updates.send(symbol.clone());
+
+        let response = query.await.unwrap().unwrap();
+
+        assert!(matches!(
+            response,
+            ApplicationResponse::OrderBookOutOfSync { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reconnected_market_stream_resyncs_books() {
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        sender
+            .send(Arc::new(
+                MarketEvent::ConnectionState(ConnectionState::Disconnected {
+                    reason: "connection closed by binance".into(),
+                })
+                .into(),
+            ))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let status = app
+            .handle_query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            status,
+            ApplicationResponse::OrderBookSyncStatus {
+                state: OrderBookSyncState::OutOfSync,
+                ..
+            }
+        ));
+
+        sender
+            .send(Arc::new(
+                MarketEvent::ConnectionState(ConnectionState::Connected).into(),
+            ))
+            .unwrap();
+        wait_for_last_update_id(&app, &symbol, 200).await;
+
+        let state = app
+            .handle_query(ApplicationQuery::GetMarketConnectionState)
+            .await
+            .unwrap();
+        assert!(matches!(
+            state,
+            ApplicationResponse::MarketConnectionState {
+                state: ConnectionState::Connected
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_span_of_applied_update_is_not_kept_open() {
+        let closed = ClosedSpans::default();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(closed.clone()));
+        let (sender, app, symbol) = setup();
+        wait_for_last_update_id(&app, &symbol, 100).await;
+
+        sender.send(diff_depth_event(95, 105)).unwrap();
+        wait_for_last_update_id(&app, &symbol, 105).await;
+
+        // the book keeps the trace context of the update to link to, not its span
+        assert!(closed
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|name| name == "apply_depth_update"));
+    }
+}
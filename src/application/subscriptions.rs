@@ -0,0 +1,195 @@
+use super::{order_books::LocalOrderBook, Application, ApplicationResponse};
+use crate::{ports::MarketStreamConnection, typespec::Symbol};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+/*
+Reference counts of the clients interested in each symbol. The first client asking for a
+symbol subscribes it on the market stream connection and the last one leaving removes it.
+The lock is held while the connection is changed so concurrent clients can not subscribe
+or remove the same symbol twice.
+*/
+pub(super) struct SymbolSubscriptions {
+    connection: MarketStreamConnection,
+    counts: Mutex<BTreeMap<Symbol, usize>>,
+}
+
+impl SymbolSubscriptions {
+    pub(super) fn new(connection: MarketStreamConnection) -> Self {
+        Self {
+            connection,
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Application {
+    pub(super) async fn subscribe_to_symbol(&self, symbol: Symbol) -> Result<ApplicationResponse> {
+        let mut counts = self.subscriptions.counts.lock().await;
+        let count = counts.get(&symbol).copied().unwrap_or(0);
+
+        if count == 0 {
+            self.subscriptions
+                .connection
+                .add_symbols(vec![symbol.clone()])
+                .await?;
+
+            // book is bootstrapped from a snapshot once the first update of the symbol arrives
+            self.order_books
+                .write()
+                .await
+                .entry(symbol.clone())
+                .or_insert_with(LocalOrderBook::new);
+        }
+
+        counts.insert(symbol.clone(), count + 1);
+
+        Ok(ApplicationResponse::SubscribedToSymbol { symbol })
+    }
+
+    pub(super) async fn unsubscribe_from_symbol(
+        &self,
+        symbol: Symbol,
+    ) -> Result<ApplicationResponse> {
+        let mut counts = self.subscriptions.counts.lock().await;
+
+        match counts.get(&symbol).copied() {
+            Some(1) => {
+                self.subscriptions
+                    .connection
+                    .remove_symbols(vec![symbol.clone()])
+                    .await?;
+
+                counts.remove(&symbol);
+                self.order_books.write().await.remove(&symbol);
+            }
+            Some(count) => {
+                counts.insert(symbol.clone(), count - 1);
+            }
+            None => return Ok(ApplicationResponse::UnknownSymbol { symbol }),
+        }
+
+        Ok(ApplicationResponse::UnsubscribedFromSymbol { symbol })
+    }
+
+    // symbols subscribed when the connection was made are never removed
+    pub(super) async fn pin_symbols(&self, symbols: &[Symbol]) {
+        let mut counts = self.subscriptions.counts.lock().await;
+
+        for symbol in symbols {
+            *counts.entry(symbol.clone()).or_insert(0) += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::ApplicationQuery,
+        ports::{MarketMessage, SubscriptionCommand},
+    };
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+
+    #[derive(Debug, PartialEq)]
+    enum Recorded {
+        Subscribe(Vec<Symbol>),
+        Unsubscribe(Vec<Symbol>),
+    }
+
+    // stand-in for a market stream adapter that records every command it receives
+    fn setup() -> (Application, mpsc::UnboundedReceiver<Recorded>) {
+        let (_sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, mut command_receiver) = mpsc::unbounded_channel();
+        let (recorded, recorded_receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Some(command) = command_receiver.recv().await {
+                match command {
+                    SubscriptionCommand::Subscribe(symbols, respond) => {
+                        let _ = recorded.send(Recorded::Subscribe(symbols));
+                        let _ = respond.send(Ok(()));
+                    }
+                    SubscriptionCommand::Unsubscribe(symbols, respond) => {
+                        let _ = recorded.send(Recorded::Unsubscribe(symbols));
+                        let _ = respond.send(Ok(()));
+                    }
+                    SubscriptionCommand::Close(respond) => {
+                        let _ = respond.send(Ok(()));
+                    }
+                }
+            }
+        });
+
+        let connection = MarketStreamConnection::new(Arc::new(receiver), commands);
+
+        (Application::new(connection), recorded_receiver)
+    }
+
+    #[tokio::test]
+    async fn test_first_client_subscribes_and_last_client_unsubscribes() {
+        let (app, mut recorded) = setup();
+        let symbol = Symbol("ETHUSDC".into());
+
+        for _ in 0..2 {
+            app.handle_query(ApplicationQuery::SubscribeToSymbol(symbol.clone()))
+                .await
+                .unwrap();
+        }
+        for _ in 0..2 {
+            app.handle_query(ApplicationQuery::UnsubscribeFromSymbol(symbol.clone()))
+                .await
+                .unwrap();
+        }
+        drop(app);
+
+        let mut commands = vec![];
+        while let Some(command) = recorded.recv().await {
+            commands.push(command);
+        }
+
+        assert_eq!(
+            commands,
+            vec![
+                Recorded::Subscribe(vec![symbol.clone()]),
+                Recorded::Unsubscribe(vec![symbol.clone()]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pinned_symbol_is_never_unsubscribed() {
+        let (app, mut recorded) = setup();
+        let symbol = Symbol("BTCUSDC".into());
+        app.pin_symbols(std::slice::from_ref(&symbol)).await;
+
+        app.handle_query(ApplicationQuery::SubscribeToSymbol(symbol.clone()))
+            .await
+            .unwrap();
+        app.handle_query(ApplicationQuery::UnsubscribeFromSymbol(symbol.clone()))
+            .await
+            .unwrap();
+        drop(app);
+
+        assert_eq!(recorded.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribing_unknown_symbol() {
+        let (app, _recorded) = setup();
+
+        let response = app
+            .handle_query(ApplicationQuery::UnsubscribeFromSymbol(Symbol(
+                "ETHUSDC".into(),
+            )))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            ApplicationResponse::UnknownSymbol { .. }
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+use super::{order_books::LocalOrderBook, Application, ApplicationResponse, OrderBookSyncState};
+use crate::{
+    core::{self, TopOfBook, TriangleRoundTrip},
+    typespec::{unix_millis, Symbol, Triangle},
+};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+
+// decimal places of the rate and of the size of a round trip
+const ROUND_TRIP_DECIMAL_PLACES: u32 = 8;
+
+// decimal places of the profit of a round trip in basis points
+const PROFIT_DECIMAL_PLACES: u32 = 4;
+
+impl Application {
+    // Triangles of symbols of this market scanned for round trips
+    pub fn with_triangles(self, triangles: Vec<Triangle>) -> Self {
+        Self {
+            triangles: Arc::new(triangles),
+            ..self
+        }
+    }
+
+    /*
+    Round trips of the configured triangles with a profit above the arbitrage threshold after
+    the taker fee of every leg. Triangles with a book out of sync or not tracked are skipped.
+    */
+    pub(super) async fn triangular_arbitrage(&self) -> ApplicationResponse {
+        let tops: BTreeMap<Symbol, TopOfBook> = self
+            .order_books
+            .read()
+            .await
+            .iter()
+            .filter_map(|(symbol, local)| match local {
+                LocalOrderBook {
+                    book: Some(book),
+                    state: OrderBookSyncState::Synced,
+                    ..
+                } => Some((
+                    symbol.clone(),
+                    core::best_bid_ask(&book.bids(), &book.asks()),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let settings = &self.arbitrage_settings;
+        let round_trips = self
+            .triangles
+            .iter()
+            .flat_map(|triangle| {
+                core::triangle_round_trips(triangle, &tops, settings.default_taker_fee_bps)
+            })
+            .filter(|round_trip| round_trip.profit_bps > settings.threshold_bps)
+            .map(|round_trip| TriangleRoundTrip {
+                rate: round_trip.rate.round_dp(ROUND_TRIP_DECIMAL_PLACES),
+                profit_bps: round_trip
+                    .profit_bps
+                    .round_dp(PROFIT_DECIMAL_PLACES)
+                    .normalize(),
+                max_start_amount: round_trip
+                    .max_start_amount
+                    .round_dp(ROUND_TRIP_DECIMAL_PLACES),
+                ..round_trip
+            })
+            .collect();
+
+        ApplicationResponse::TriangularArbitrage {
+            detected_at: unix_millis(),
+            round_trips,
+        }
+    }
+
+    // Waits for a book of a symbol of any triangle to change before scanning the triangles
+    pub(super) async fn next_triangular_arbitrage(&self) -> ApplicationResponse {
+        let mut book_updates = self.book_updates.subscribe();
+
+        loop {
+            match book_updates.recv().await {
+                Ok(updated) => {
+                    let in_triangle = self
+                        .triangles
+                        .iter()
+                        .any(|triangle| triangle.symbols().contains(&updated));
+
+                    if in_triangle {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => return ApplicationResponse::InternalError,
+            }
+        }
+
+        self.triangular_arbitrage().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::{ApplicationQuery, ArbitrageSettings},
+        core::OrderBook,
+        ports::{MarketMessage, MarketStreamConnection},
+        typespec::{levels, DepthSnapshot, Side},
+    };
+    use tokio::sync::{broadcast, mpsc};
+
+    async fn insert_book(app: &Application, symbol: &str, bid: (&str, &str), ask: (&str, &str)) {
+        let mut local = LocalOrderBook::new();
+        local.book = Some(OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(&[bid]),
+            asks: levels(&[ask]),
+        }));
+        local.state = OrderBookSyncState::Synced;
+        app.order_books
+            .write()
+            .await
+            .insert(Symbol(symbol.into()), local);
+    }
+
+    #[tokio::test]
+    async fn test_round_trips_above_threshold_after_fees() {
+        let (_sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(16);
+        let (commands, _) = mpsc::unbounded_channel();
+        let app = Application::new(MarketStreamConnection::new(Arc::new(receiver), commands))
+            .with_arbitrage_settings(ArbitrageSettings {
+                threshold_bps: "20".parse().unwrap(),
+                ..Default::default()
+            })
+            .with_triangles(vec!["BTCUSDC,ETHBTC,ETHUSDC".parse().unwrap()]);
+        insert_book(&app, "BTCUSDC", ("30000", "1"), ("30001", "1")).await;
+        insert_book(&app, "ETHBTC", ("0.0665", "10"), ("0.0666", "10")).await;
+        insert_book(&app, "ETHUSDC", ("2010", "5"), ("2011", "10")).await;
+
+        let response = app
+            .handle_query(ApplicationQuery::GetTriangularArbitrage)
+            .await
+            .unwrap();
+
+        match response {
+            ApplicationResponse::TriangularArbitrage { round_trips, .. } => {
+                // the backward direction loses and is left out
+                assert_eq!(round_trips.len(), 1);
+                assert_eq!(round_trips[0].legs[2].side, Side::Sell);
+                assert_eq!(round_trips[0].profit_bps, "29.5757".parse().unwrap());
+            }
+            _ => panic!("expected a triangular arbitrage response"),
+        }
+    }
+}
@@ -0,0 +1,878 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    adapters::{DepthUpdateSpeed, ReplaySpeed},
+    core::{ArbitrageSettings, BASIS_POINTS},
+    lifecycle::DEFAULT_SHUTDOWN_TIMEOUT,
+    ports::{WebServerSettings, DEFAULT_EVENT_CAPACITY},
+    telemetry::{LogFormat, TelemetrySettings},
+    typespec::{CandleInterval, Symbol, Triangle, Venue},
+};
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+// read from the working directory when no file is given
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+// prefix of the environment variables, ORDERBOOK_<SECTION>_<KEY>
+const ENV_PREFIX: &str = "ORDERBOOK";
+
+const VENUES: [&str; 4] = ["binance", "coinbase", "kraken", "okx"];
+
+/*
+Settings of the service are layered, each layer overriding the keys it sets in the one before
+
+- the TOML file given with --config, or config.toml in the working directory when it exists
+- environment variables named ORDERBOOK_<SECTION>_<KEY>, like ORDERBOOK_SERVER_PORT
+- command line flags, either a dedicated one or --set <section>.<key>=<value>
+
+Every layer is merged into a single TOML table before it is read into the settings, so a key
+is parsed the same way no matter where it comes from. Settings are validated once at startup
+and all problems are reported together.
+*/
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Order book service of crypto markets")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Format of the results of a command
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub output: OutputFormat,
+    /// TOML file of settings, config.toml of the working directory when it exists
+    #[arg(long, env = "ORDERBOOK_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Host name or ip address the server listens on
+    #[arg(long, global = true)]
+    pub bind_address: Option<String>,
+    /// Port the server listens on
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Directory of the frontend assets
+    #[arg(long, global = true)]
+    pub static_dir: Option<PathBuf>,
+    /// Market the order books are kept for, binance, coinbase, kraken or okx
+    #[arg(long, global = true)]
+    pub venue: Option<String>,
+    /// Symbols subscribed for the whole run, BTCUSDC,ETHUSDC
+    #[arg(long, global = true)]
+    pub symbols: Option<String>,
+    /// Interval binance pushes depth updates in, 100ms or 1000ms
+    #[arg(long, global = true)]
+    pub depth_update_interval: Option<String>,
+    /// Any other setting, like --set channels.market_events=256
+    #[arg(long = "set", value_name = "SECTION.KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+}
+
+// every command connects through the same market stream adapters and application
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Keep the order books in sync and serve them to clients, the default command
+    Serve,
+    /// Record the market stream of the symbols into NDJSON files
+    Record {
+        /// Directory of the recording, recording.directory of the settings otherwise
+        #[arg(long)]
+        directory: Option<PathBuf>,
+        /// Seconds to record for, until interrupted otherwise
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Serve the order books of recordings instead of a live market
+    Replay {
+        /// Recording files or directories of recording files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// original, max or the factor the spacing of events is divided by
+        #[arg(long, default_value = "original")]
+        speed: ReplaySpeed,
+    },
+    /// Print the order book of a symbol once it is synced
+    Snapshot {
+        symbol: String,
+        /// Levels of each side of the book
+        #[arg(long, default_value_t = 10)]
+        depth: usize,
+    },
+    /// Compute metrics of recordings offline
+    Analyze {
+        /// Recording files or directories of recording files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Only analyze the symbol, every symbol of the recording otherwise
+        #[arg(long)]
+        symbol: Option<String>,
+        /// Interval of the candles of the trades
+        #[arg(long, default_value = "1m")]
+        interval: CandleInterval,
+        /// Basis points around the mid price the depth of the books is measured in
+        #[arg(long, default_value_t = Decimal::TEN)]
+        bps: Decimal,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    // aligned text for people reading a terminal
+    #[default]
+    Human,
+    // a single JSON document for scripts
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Str,
+    Int,
+    Bool,
+    // list of strings joined by the separator in environment variables and flags
+    List(char),
+}
+
+// every key that can be given in the environment or with --set
+const KEYS: [(&str, &str, Kind); 28] = [
+    ("server", "bind_address", Kind::Str),
+    ("server", "port", Kind::Int),
+    ("server", "static_dir", Kind::Str),
+    ("server", "shutdown_timeout_secs", Kind::Int),
+    ("market", "venue", Kind::Str),
+    ("market", "symbols", Kind::List(',')),
+    ("market", "depth_update_interval", Kind::Str),
+    ("market", "consolidate", Kind::List(',')),
+    ("market", "triangles", Kind::List(';')),
+    ("market", "candle_intervals", Kind::List(',')),
+    ("endpoints", "binance_stream", Kind::Str),
+    ("endpoints", "binance_rest", Kind::Str),
+    ("endpoints", "coinbase", Kind::Str),
+    ("endpoints", "kraken", Kind::Str),
+    ("endpoints", "okx", Kind::Str),
+    ("channels", "market_events", Kind::Int),
+    ("channels", "client_updates", Kind::Int),
+    ("arbitrage", "fee_bps", Kind::Str),
+    ("arbitrage", "threshold_bps", Kind::Str),
+    ("recording", "directory", Kind::Str),
+    ("recording", "compress", Kind::Bool),
+    ("features", "consolidation", Kind::Bool),
+    ("features", "triangular_arbitrage", Kind::Bool),
+    ("features", "candles", Kind::Bool),
+    ("features", "recording", Kind::Bool),
+    ("logging", "format", Kind::Str),
+    ("logging", "level", Kind::Str),
+    ("logging", "otlp_endpoint", Kind::Str),
+];
+
+// variables read before the settings existed, the prefixed names take precedence
+const LEGACY_ENV: [(&str, &str, &str); 7] = [
+    ("MARKET", "market", "venue"),
+    ("CONSOLIDATE_MARKETS", "market", "consolidate"),
+    ("TRIANGLES", "market", "triangles"),
+    ("CANDLE_INTERVALS", "market", "candle_intervals"),
+    ("RECORD_MARKET_STREAM", "recording", "directory"),
+    ("ARBITRAGE_FEE_BPS", "arbitrage", "fee_bps"),
+    ("ARBITRAGE_THRESHOLD_BPS", "arbitrage", "threshold_bps"),
+];
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub market: MarketSettings,
+    pub endpoints: EndpointSettings,
+    pub channels: ChannelSettings,
+    pub arbitrage: ArbitrageBpsSettings,
+    pub recording: RecordingSettings,
+    pub features: FeatureSettings,
+    pub logging: LoggingSettings,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub bind_address: String,
+    pub port: u16,
+    pub static_dir: PathBuf,
+    // clients are closed, markets unsubscribed and recordings flushed within it on SIGTERM
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        let WebServerSettings {
+            bind_address,
+            port,
+            static_dir,
+        } = WebServerSettings::default();
+
+        Self {
+            bind_address,
+            port,
+            static_dir,
+            shutdown_timeout_secs: DEFAULT_SHUTDOWN_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketSettings {
+    // main market, symbols are named the same on every market
+    pub venue: String,
+    // symbols that stay subscribed, clients can subscribe to more at runtime
+    pub symbols: Vec<String>,
+    pub depth_update_interval: String,
+    // other markets whose books are consolidated with the ones of the main market
+    pub consolidate: Vec<String>,
+    // triangles of the main market scanned for round trips, BTCUSDC,ETHBTC,ETHUSDC
+    pub triangles: Vec<String>,
+    // binance trades are aggregated into candles of these intervals, 1s, 1m, 5m, 1h
+    pub candle_intervals: Vec<String>,
+}
+
+impl Default for MarketSettings {
+    fn default() -> Self {
+        Self {
+            venue: "binance".into(),
+            symbols: vec!["BTCUSDC".into()],
+            depth_update_interval: "1000ms".into(),
+            consolidate: vec![],
+            triangles: vec![],
+            candle_intervals: vec![],
+        }
+    }
+}
+
+// adapters connect to the public endpoints of the markets unless one is given
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointSettings {
+    pub binance_stream: Option<String>,
+    pub binance_rest: Option<String>,
+    pub coinbase: Option<String>,
+    pub kraken: Option<String>,
+    pub okx: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelSettings {
+    // events buffered for each receiver of a market connection
+    pub market_events: usize,
+    // book and candle updates buffered for each client, the application picks its own when unset
+    pub client_updates: Option<usize>,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            market_events: DEFAULT_EVENT_CAPACITY,
+            client_updates: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArbitrageBpsSettings {
+    // taker fee of every venue
+    pub fee_bps: Decimal,
+    // edge left after fees an opportunity needs to be flagged
+    pub threshold_bps: Decimal,
+}
+
+impl Default for ArbitrageBpsSettings {
+    fn default() -> Self {
+        let defaults = ArbitrageSettings::default();
+
+        Self {
+            fee_bps: defaults.default_taker_fee_bps,
+            threshold_bps: defaults.threshold_bps,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingSettings {
+    // market events are recorded for offline replays when a directory is given
+    pub directory: Option<PathBuf>,
+    pub compress: bool,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            compress: true,
+        }
+    }
+}
+
+// a feature runs once it is configured, these switch it off without removing its settings
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureSettings {
+    pub consolidation: bool,
+    pub triangular_arbitrage: bool,
+    pub candles: bool,
+    pub recording: bool,
+}
+
+impl Default for FeatureSettings {
+    fn default() -> Self {
+        Self {
+            consolidation: true,
+            triangular_arbitrage: true,
+            candles: true,
+            recording: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    // pretty or json
+    pub format: String,
+    // error, warn, info, debug or trace, optionally per module like info,orderbook_trial_task=debug
+    pub level: String,
+    // spans are exported over OTLP/HTTP when given, like http://localhost:4318/v1/traces
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            format: "pretty".into(),
+            level: "info".into(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl Settings {
+    // settings of the process, read from the config file, the environment and the flags
+    pub fn load(cli: &Cli) -> Result<Self> {
+        Self::load_from(cli, |name| std::env::var(name).ok())
+    }
+
+    fn load_from(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut table = match &cli.config {
+            Some(path) => read_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                read_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => toml::Table::new(),
+        };
+
+        for (name, section, key) in LEGACY_ENV {
+            if let Some(value) = env(name) {
+                set(&mut table, section, key, &value).context(name)?;
+            }
+        }
+        for (section, key, _) in KEYS {
+            let name = format!("{}_{}_{}", ENV_PREFIX, section, key).to_uppercase();
+            if let Some(value) = env(&name) {
+                set(&mut table, section, key, &value).context(name)?;
+            }
+        }
+
+        for (flag, value) in cli.flags() {
+            let (section, key) = flag
+                .split_once('.')
+                .ok_or_else(|| anyhow!("--set {} is not of the form section.key=value", flag))?;
+            set(&mut table, section, key, &value).with_context(|| format!("--set {}", flag))?;
+        }
+
+        let settings: Settings = toml::Value::Table(table)
+            .try_into()
+            .context("invalid settings")?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    // every problem of the settings is reported at once so they can be fixed in one go
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        if self.server.bind_address.trim().is_empty() {
+            problems.push("server.bind_address is empty".to_string());
+        }
+        if self.server.shutdown_timeout_secs == 0 {
+            problems.push("server.shutdown_timeout_secs needs to be above 0".to_string());
+        }
+
+        if !VENUES.contains(&self.market.venue.as_str()) {
+            problems.push(format!(
+                "market.venue {} is not one of {}",
+                self.market.venue,
+                VENUES.join(", ")
+            ));
+        }
+        for venue in &self.market.consolidate {
+            if !VENUES.contains(&venue.as_str()) {
+                problems.push(format!(
+                    "market.consolidate {} is not one of {}",
+                    venue,
+                    VENUES.join(", ")
+                ));
+            } else if *venue == self.market.venue {
+                problems.push(format!(
+                    "market.consolidate {} is already the main market",
+                    venue
+                ));
+            }
+        }
+
+        if self.market.symbols.is_empty() {
+            problems.push("market.symbols needs at least one symbol".to_string());
+        }
+        for symbol in &self.market.symbols {
+            if symbol.is_empty() || !symbol.chars().all(|c| c.is_ascii_alphanumeric()) {
+                problems.push(format!("market.symbols {:?} is not a symbol", symbol));
+            }
+        }
+
+        if let Err(e) = self.depth_update_speed() {
+            problems.push(e.to_string());
+        }
+        for triangle in &self.market.triangles {
+            if let Err(e) = triangle.parse::<Triangle>() {
+                problems.push(format!("market.triangles: {}", e));
+            }
+        }
+        for interval in &self.market.candle_intervals {
+            if let Err(e) = interval.parse::<CandleInterval>() {
+                problems.push(format!("market.candle_intervals: {}", e));
+            }
+        }
+
+        let endpoints = [
+            ("binance_stream", &self.endpoints.binance_stream, "ws"),
+            ("binance_rest", &self.endpoints.binance_rest, "http"),
+            ("coinbase", &self.endpoints.coinbase, "ws"),
+            ("kraken", &self.endpoints.kraken, "ws"),
+            ("okx", &self.endpoints.okx, "ws"),
+        ];
+        for (name, endpoint, scheme) in endpoints {
+            if let Some(url) = endpoint {
+                let secure = format!("{}s://", scheme);
+                let plain = format!("{}://", scheme);
+                if !url.starts_with(&secure) && !url.starts_with(&plain) {
+                    problems.push(format!(
+                        "endpoints.{} {} does not start with {} or {}",
+                        name, url, secure, plain
+                    ));
+                }
+            }
+        }
+
+        if self.channels.market_events == 0 {
+            problems.push("channels.market_events needs to be above 0".to_string());
+        }
+        if self.channels.client_updates == Some(0) {
+            problems.push("channels.client_updates needs to be above 0".to_string());
+        }
+
+        if self.arbitrage.fee_bps.is_sign_negative() {
+            problems.push(format!(
+                "arbitrage.fee_bps {} is negative",
+                self.arbitrage.fee_bps
+            ));
+        }
+        // a fee of the whole amount leaves nothing to convert on any leg
+        if self.arbitrage.fee_bps >= BASIS_POINTS {
+            problems.push(format!(
+                "arbitrage.fee_bps {} needs to be below {}",
+                self.arbitrage.fee_bps, BASIS_POINTS
+            ));
+        }
+
+        if let Err(e) = self.telemetry_settings() {
+            problems.push(e.to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!("invalid settings\n  - {}", problems.join("\n  - "))),
+        }
+    }
+
+    pub fn web_server_settings(&self) -> WebServerSettings {
+        WebServerSettings {
+            bind_address: self.server.bind_address.clone(),
+            port: self.server.port,
+            static_dir: self.server.static_dir.clone(),
+        }
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.shutdown_timeout_secs)
+    }
+
+    pub fn venue(&self) -> Venue {
+        Venue(self.market.venue.clone())
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.market
+            .symbols
+            .iter()
+            .map(|symbol| Symbol(symbol.clone()))
+            .collect()
+    }
+
+    pub fn depth_update_speed(&self) -> Result<DepthUpdateSpeed> {
+        match self.market.depth_update_interval.as_str() {
+            "100ms" => Ok(DepthUpdateSpeed::Millis100),
+            "1000ms" => Ok(DepthUpdateSpeed::Millis1000),
+            other => Err(anyhow!(
+                "market.depth_update_interval {} is not 100ms or 1000ms",
+                other
+            )),
+        }
+    }
+
+    // markets consolidated with the main market, none when the feature is off
+    pub fn consolidated_venues(&self) -> Vec<Venue> {
+        match self.features.consolidation {
+            true => self.market.consolidate.iter().cloned().map(Venue).collect(),
+            false => vec![],
+        }
+    }
+
+    pub fn triangles(&self) -> Result<Vec<Triangle>> {
+        match self.features.triangular_arbitrage {
+            true => self.market.triangles.iter().map(|t| t.parse()).collect(),
+            false => Ok(vec![]),
+        }
+    }
+
+    pub fn candle_intervals(&self) -> Result<Vec<CandleInterval>> {
+        match self.features.candles {
+            true => self
+                .market
+                .candle_intervals
+                .iter()
+                .map(|i| i.parse())
+                .collect(),
+            false => Ok(vec![]),
+        }
+    }
+
+    pub fn recording_directory(&self) -> Option<&Path> {
+        self.recording
+            .directory
+            .as_deref()
+            .filter(|_| self.features.recording)
+    }
+
+    pub fn telemetry_settings(&self) -> Result<TelemetrySettings> {
+        let format: LogFormat = self
+            .logging
+            .format
+            .parse()
+            .map_err(|e| anyhow!("logging.format {}", e))?;
+        if let Err(e) = EnvFilter::try_new(&self.logging.level) {
+            return Err(anyhow!(
+                "logging.level {} is not valid: {}",
+                self.logging.level,
+                e
+            ));
+        }
+
+        if let Some(endpoint) = &self.logging.otlp_endpoint {
+            if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                return Err(anyhow!(
+                    "logging.otlp_endpoint {} does not start with https:// or http://",
+                    endpoint
+                ));
+            }
+        }
+
+        Ok(TelemetrySettings {
+            format,
+            level: self.logging.level.clone(),
+            otlp_endpoint: self.logging.otlp_endpoint.clone(),
+        })
+    }
+
+    pub fn arbitrage_settings(&self) -> ArbitrageSettings {
+        ArbitrageSettings {
+            default_taker_fee_bps: self.arbitrage.fee_bps,
+            threshold_bps: self.arbitrage.threshold_bps,
+            ..ArbitrageSettings::default()
+        }
+    }
+}
+
+impl Cli {
+    // dedicated flags are shorthands of --set and are applied before it
+    fn flags(&self) -> Vec<(String, String)> {
+        let dedicated = [
+            ("server.bind_address", self.bind_address.clone()),
+            ("server.port", self.port.map(|port| port.to_string())),
+            (
+                "server.static_dir",
+                self.static_dir
+                    .as_ref()
+                    .map(|dir| dir.to_string_lossy().into_owned()),
+            ),
+            ("market.venue", self.venue.clone()),
+            ("market.symbols", self.symbols.clone()),
+            (
+                "market.depth_update_interval",
+                self.depth_update_interval.clone(),
+            ),
+        ];
+
+        let mut flags: Vec<(String, String)> = dedicated
+            .into_iter()
+            .filter_map(|(flag, value)| value.map(|value| (flag.to_string(), value)))
+            .collect();
+        for flag in &self.overrides {
+            match flag.split_once('=') {
+                Some((key, value)) => flags.push((key.trim().into(), value.trim().into())),
+                // reported as a malformed flag when applied
+                None => flags.push((flag.clone(), String::new())),
+            }
+        }
+
+        flags
+    }
+}
+
+fn read_file(path: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("config file {} can not be read", path.display()))?;
+
+    content
+        .parse()
+        .with_context(|| format!("config file {} is not valid TOML", path.display()))
+}
+
+// places a value given as text into the table, typed by the kind of its key
+fn set(table: &mut toml::Table, section: &str, key: &str, value: &str) -> Result<()> {
+    let kind = KEYS
+        .iter()
+        .find(|(s, k, _)| *s == section && *k == key)
+        .map(|(_, _, kind)| *kind)
+        .ok_or_else(|| anyhow!("unknown setting {}.{}", section, key))?;
+
+    let value = match kind {
+        Kind::Str => toml::Value::String(value.into()),
+        Kind::Int => toml::Value::Integer(
+            value
+                .parse()
+                .map_err(|_| anyhow!("{} is not a whole number", value))?,
+        ),
+        Kind::Bool => toml::Value::Boolean(
+            value
+                .parse()
+                .map_err(|_| anyhow!("{} is not true or false", value))?,
+        ),
+        Kind::List(separator) => toml::Value::Array(
+            value
+                .split(separator)
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.into()))
+                .collect(),
+        ),
+    };
+
+    let section = table
+        .entry(section)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    match section {
+        toml::Value::Table(section) => {
+            section.insert(key.into(), value);
+            Ok(())
+        }
+        _ => Err(anyhow!("{} is not a table in the config file", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: BTreeMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn test_layers_override_each_other() {
+        let path = config_file(
+            "layers",
+            r#"
+            [server]
+            port = 8080
+            bind_address = "0.0.0.0"
+
+            [market]
+            symbols = ["ETHUSDC"]
+            depth_update_interval = "100ms"
+            "#,
+        );
+        let cli = Cli {
+            config: Some(path.clone()),
+            port: Some(9000),
+            overrides: vec!["channels.market_events=256".into()],
+            ..Default::default()
+        };
+
+        let settings = Settings::load_from(
+            &cli,
+            env(&[
+                ("ORDERBOOK_SERVER_PORT", "8081"),
+                ("ORDERBOOK_MARKET_SYMBOLS", "BTCUSDC, ETHUSDC"),
+                ("MARKET", "kraken"),
+                ("TRIANGLES", "BTCUSDC,ETHBTC,ETHUSDC;"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(settings.server.port, 9000);
+        assert_eq!(settings.server.bind_address, "0.0.0.0");
+        assert_eq!(
+            settings.symbols(),
+            vec![Symbol("BTCUSDC".into()), Symbol("ETHUSDC".into())]
+        );
+        assert_eq!(settings.venue(), Venue("kraken".into()));
+        assert_eq!(settings.triangles().unwrap().len(), 1);
+        assert_eq!(
+            settings.depth_update_speed().unwrap(),
+            DepthUpdateSpeed::Millis100
+        );
+        assert_eq!(settings.channels.market_events, 256);
+        assert_eq!(settings.endpoints, EndpointSettings::default());
+    }
+
+    #[test]
+    fn test_defaults_without_any_layer() {
+        let settings = Settings::load_from(&Cli::default(), env(&[])).unwrap();
+
+        assert_eq!(settings.web_server_settings(), WebServerSettings::default());
+        assert_eq!(settings.symbols(), vec![Symbol("BTCUSDC".into())]);
+        assert_eq!(settings.arbitrage_settings(), ArbitrageSettings::default());
+        assert!(settings.consolidated_venues().is_empty());
+        assert_eq!(settings.recording_directory(), None);
+        assert_eq!(
+            settings.telemetry_settings().unwrap(),
+            TelemetrySettings::default()
+        );
+    }
+
+    #[test]
+    fn test_disabled_features_are_not_run() {
+        let cli = Cli {
+            overrides: vec![
+                "features.candles=false".into(),
+                "features.recording=false".into(),
+            ],
+            ..Default::default()
+        };
+
+        let settings = Settings::load_from(
+            &cli,
+            env(&[
+                ("CANDLE_INTERVALS", "1m,5m"),
+                ("ORDERBOOK_RECORDING_DIRECTORY", "/tmp/recordings"),
+            ]),
+        )
+        .unwrap();
+
+        assert!(settings.candle_intervals().unwrap().is_empty());
+        assert_eq!(settings.recording_directory(), None);
+    }
+
+    #[test]
+    fn test_all_problems_are_reported() {
+        let cli = Cli {
+            overrides: vec![
+                "market.consolidate=okx,ftx".into(),
+                "logging.format=yaml".into(),
+            ],
+            ..Default::default()
+        };
+
+        let error = Settings::load_from(
+            &cli,
+            env(&[
+                ("ORDERBOOK_MARKET_DEPTH_UPDATE_INTERVAL", "250ms"),
+                ("ORDERBOOK_CHANNELS_MARKET_EVENTS", "0"),
+                ("ORDERBOOK_ENDPOINTS_BINANCE_REST", "wss://api.binance.com"),
+            ]),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("market.consolidate ftx is not one of"));
+        assert!(error.contains("market.depth_update_interval 250ms"));
+        assert!(error.contains("channels.market_events needs to be above 0"));
+        assert!(error.contains("endpoints.binance_rest"));
+        assert!(error.contains("logging.format yaml is not pretty or json"));
+    }
+
+    #[test]
+    fn test_fee_taking_the_whole_amount_is_rejected() {
+        let settings = |fee_bps: &str| {
+            let cli = Cli {
+                overrides: vec![format!("arbitrage.fee_bps={}", fee_bps)],
+                ..Default::default()
+            };
+            Settings::load_from(&cli, env(&[]))
+        };
+
+        assert!(settings("9999.9").is_ok());
+        assert!(settings("10000")
+            .unwrap_err()
+            .to_string()
+            .contains("arbitrage.fee_bps 10000 needs to be below 10000"));
+    }
+
+    #[test]
+    fn test_malformed_values_name_their_source() {
+        let error = |cli: Cli, vars: &[(&str, &str)]| {
+            format!("{:#}", Settings::load_from(&cli, env(vars)).unwrap_err())
+        };
+
+        assert!(error(Cli::default(), &[("ORDERBOOK_SERVER_PORT", "http")])
+            .contains("ORDERBOOK_SERVER_PORT: http is not a whole number"));
+
+        let unknown = Cli {
+            overrides: vec!["server.prot=80".into()],
+            ..Default::default()
+        };
+        assert!(error(unknown, &[]).contains("unknown setting server.prot"));
+
+        let path = config_file("unknown-field", "[server]\nprot = 80\n");
+        let file = Cli {
+            config: Some(path.clone()),
+            ..Default::default()
+        };
+        let message = error(file, &[]);
+        std::fs::remove_file(path).unwrap();
+        assert!(message.contains("unknown field `prot`"));
+    }
+}
@@ -0,0 +1,240 @@
+use super::{OrderBook, BASIS_POINTS};
+use crate::typespec::{Notional, Price, Quantity, Venue};
+use rust_decimal::Decimal;
+use std::{cmp::Reverse, collections::BTreeMap};
+
+// Fees and threshold an opportunity has to clear
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitrageSettings {
+    // taker fee of each venue in basis points of the notional traded
+    pub taker_fee_bps: BTreeMap<Venue, Decimal>,
+    // taker fee of venues without a fee of their own
+    pub default_taker_fee_bps: Decimal,
+    // edge left after fees, in basis points, an opportunity needs to be flagged
+    pub threshold_bps: Decimal,
+}
+
+impl Default for ArbitrageSettings {
+    // 10 bps is the base taker fee of most large venues
+    fn default() -> Self {
+        Self {
+            taker_fee_bps: BTreeMap::new(),
+            default_taker_fee_bps: Decimal::TEN,
+            threshold_bps: Decimal::ZERO,
+        }
+    }
+}
+
+impl ArbitrageSettings {
+    fn fee_rate(&self, venue: &Venue) -> Decimal {
+        self.taker_fee_bps
+            .get(venue)
+            .copied()
+            .unwrap_or(self.default_taker_fee_bps)
+            / BASIS_POINTS
+    }
+}
+
+// Buying on one venue and selling on another at the same time for a profit after fees
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitrageOpportunity {
+    pub buy_venue: Venue,
+    pub sell_venue: Venue,
+    // best ask of the buy venue
+    pub buy_price: Price,
+    // best bid of the sell venue
+    pub sell_price: Price,
+    // profit of the best prices after fees in basis points of the cost
+    pub edge_bps: Decimal,
+    // quantity that can be bought and sold while every level pair clears the threshold
+    pub quantity: Quantity,
+    // profit after fees of trading the whole quantity
+    pub profit: Notional,
+}
+
+/*
+  Flags every pair of venues where the best bid of one venue is above the best ask of
+  another one by more than the taker fees of both venues plus the threshold.
+
+  Edge = (bid * (1 - sell fee) - ask * (1 + buy fee)) / (ask * (1 + buy fee)) * 10000
+
+  The executable quantity walks the asks of the buy venue and the bids of the sell venue
+  from their best prices and matches them level by level for as long as the edge of the
+  matched levels stays above the threshold. Opportunities are ordered from the best edge.
+*/
+pub fn detect_arbitrage<'b>(
+    books: impl IntoIterator<Item = (&'b Venue, &'b OrderBook)>,
+    settings: &ArbitrageSettings,
+) -> Vec<ArbitrageOpportunity> {
+    let books: Vec<(&Venue, &OrderBook)> = books.into_iter().collect();
+    let mut opportunities = vec![];
+
+    for (buy_venue, buy_book) in books.iter() {
+        for (sell_venue, sell_book) in books.iter() {
+            if buy_venue == sell_venue {
+                continue;
+            }
+
+            let buy_fee = settings.fee_rate(buy_venue);
+            let sell_fee = settings.fee_rate(sell_venue);
+            let edge_bps = |ask: Price, bid: Price| {
+                let cost = ask.0 * (Decimal::ONE + buy_fee);
+                let proceeds = bid.0 * (Decimal::ONE - sell_fee);
+
+                (proceeds - cost) / cost * BASIS_POINTS
+            };
+
+            let asks = buy_book.asks();
+            let bids = sell_book.bids();
+            let (buy_price, sell_price) = match (asks.first(), bids.first()) {
+                (Some((ask, _)), Some((bid, _))) if ask.0 > Decimal::ZERO => (*ask, *bid),
+                _ => continue,
+            };
+
+            let top_edge_bps = edge_bps(buy_price, sell_price);
+            if top_edge_bps <= settings.threshold_bps {
+                continue;
+            }
+
+            let mut asks = asks.into_iter().peekable();
+            let mut bids = bids.into_iter().peekable();
+            let mut ask_remaining = Decimal::ZERO;
+            let mut bid_remaining = Decimal::ZERO;
+            let mut quantity = Decimal::ZERO;
+            let mut profit = Decimal::ZERO;
+
+            while let (Some((ask, ask_qty)), Some((bid, bid_qty))) = (asks.peek(), bids.peek()) {
+                if edge_bps(*ask, *bid) <= settings.threshold_bps {
+                    break;
+                }
+                if ask_remaining.is_zero() {
+                    ask_remaining = ask_qty.0;
+                }
+                if bid_remaining.is_zero() {
+                    bid_remaining = bid_qty.0;
+                }
+
+                let taken = ask_remaining.min(bid_remaining);
+                quantity += taken;
+                profit += taken * (bid.0 * (Decimal::ONE - sell_fee))
+                    - taken * (ask.0 * (Decimal::ONE + buy_fee));
+
+                ask_remaining -= taken;
+                bid_remaining -= taken;
+                if ask_remaining.is_zero() {
+                    asks.next();
+                }
+                if bid_remaining.is_zero() {
+                    bids.next();
+                }
+            }
+
+            opportunities.push(ArbitrageOpportunity {
+                buy_venue: (*buy_venue).clone(),
+                sell_venue: (*sell_venue).clone(),
+                buy_price,
+                sell_price,
+                edge_bps: top_edge_bps,
+                quantity: Quantity(quantity),
+                profit: Notional(profit),
+            });
+        }
+    }
+
+    opportunities.sort_by_key(|opportunity| Reverse(opportunity.edge_bps));
+
+    opportunities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::{levels, DepthSnapshot};
+
+    fn book(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> OrderBook {
+        OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(bids),
+            asks: levels(asks),
+        })
+    }
+
+    fn settings(threshold_bps: &str) -> ArbitrageSettings {
+        ArbitrageSettings {
+            threshold_bps: threshold_bps.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bid_above_ask_of_other_venue_is_flagged_with_executable_size() {
+        let binance = book(&[("99.00", "1")], &[("100.00", "1"), ("100.20", "2")]);
+        let kraken = book(
+            &[("101.00", "0.5"), ("100.90", "2"), ("100.00", "5")],
+            &[("101.50", "1")],
+        );
+        let (binance_venue, kraken_venue) = (Venue("binance".into()), Venue("kraken".into()));
+
+        let opportunities = detect_arbitrage(
+            vec![(&binance_venue, &binance), (&kraken_venue, &kraken)],
+            &settings("5"),
+        );
+
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.buy_venue, binance_venue);
+        assert_eq!(opportunity.sell_venue, kraken_venue);
+        // (101 * 0.999 - 100 * 1.001) / (100 * 1.001)
+        assert_eq!(opportunity.edge_bps.round_dp(2), "79.82".parse().unwrap());
+        // 0.5 at 101.00 and 0.5 at 100.90 against 100.00, 1.5 at 100.90 against 100.20
+        assert_eq!(opportunity.quantity, "2.5".parse().unwrap());
+        assert_eq!(opportunity.profit.0.normalize(), "1.4974".parse().unwrap());
+    }
+
+    #[test]
+    fn test_crossed_books_within_fees_and_threshold_are_not_flagged() {
+        let binance = book(&[("99.00", "1")], &[("100.00", "1")]);
+        let kraken = book(&[("100.15", "1")], &[("101.00", "1")]);
+        let coinbase = book(&[("100.30", "1")], &[("101.00", "1")]);
+        let venues = [
+            Venue("binance".into()),
+            Venue("kraken".into()),
+            Venue("coinbase".into()),
+        ];
+
+        let within_fees = detect_arbitrage(
+            vec![(&venues[0], &binance), (&venues[1], &kraken)],
+            &settings("0"),
+        );
+        // about 9.96 bps after fees
+        let below_threshold = detect_arbitrage(
+            vec![(&venues[0], &binance), (&venues[2], &coinbase)],
+            &settings("10"),
+        );
+
+        assert_eq!(within_fees, vec![]);
+        assert_eq!(below_threshold, vec![]);
+    }
+
+    #[test]
+    fn test_fee_of_venue_overrides_default() {
+        let binance = book(&[("99.00", "1")], &[("100.00", "1")]);
+        let kraken = book(&[("100.15", "1")], &[("101.00", "1")]);
+        let venues = [Venue("binance".into()), Venue("kraken".into())];
+        let settings = ArbitrageSettings {
+            taker_fee_bps: BTreeMap::from([
+                (venues[0].clone(), Decimal::ZERO),
+                (venues[1].clone(), Decimal::ZERO),
+            ]),
+            ..Default::default()
+        };
+
+        let opportunities = detect_arbitrage(
+            vec![(&venues[0], &binance), (&venues[1], &kraken)],
+            &settings,
+        );
+
+        assert_eq!(opportunities.len(), 1);
+        assert_eq!(opportunities[0].edge_bps, "15".parse().unwrap());
+    }
+}
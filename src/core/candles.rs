@@ -0,0 +1,227 @@
+use crate::typespec::{CandleInterval, Notional, Price, Quantity, Trade};
+use std::collections::BTreeMap;
+
+// Open, high, low, close and volume of the trades of a symbol within an interval
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candle {
+    // milliseconds since the unix epoch, the close time is the last millisecond of the candle
+    pub open_time: u64,
+    pub close_time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    // traded quantity of the base asset
+    pub volume: Quantity,
+    // traded notional of the quote asset
+    pub quote_volume: Notional,
+    pub vwap: Price,
+    pub trades: u64,
+    // the open and close are the prices of the trades with the lowest and highest id
+    pub first_trade_id: u64,
+    pub last_trade_id: u64,
+}
+
+impl Candle {
+    fn new(open_time: u64, interval: CandleInterval, trade: &Trade) -> Self {
+        Self {
+            open_time,
+            // the last candle before the end of time closes with it
+            close_time: open_time.saturating_add(interval.millis() - 1),
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.quantity,
+            quote_volume: Notional(trade.price.0 * trade.quantity.0),
+            vwap: trade.price,
+            trades: 1,
+            first_trade_id: trade.trade_id,
+            last_trade_id: trade.trade_id,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        if trade.trade_id < self.first_trade_id {
+            self.open = trade.price;
+            self.first_trade_id = trade.trade_id;
+        }
+        if trade.trade_id > self.last_trade_id {
+            self.close = trade.price;
+            self.last_trade_id = trade.trade_id;
+        }
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.volume = Quantity(self.volume.0 + trade.quantity.0);
+        self.quote_volume = Notional(self.quote_volume.0 + trade.price.0 * trade.quantity.0);
+        self.trades += 1;
+
+        if !self.volume.0.is_zero() {
+            self.vwap = Price(self.quote_volume.0 / self.volume.0);
+        }
+    }
+}
+
+/*
+  Aggregates the trades of a symbol into candles of an interval and keeps the given
+  number of most recent candles.
+
+  A trade goes into the candle its trade time falls into rather than the latest one, so
+  trades arriving late still update the candle they belong to as long as it is kept.
+  The open and close follow the trade ids, so a late trade only moves them when it was
+  traded before the first or after the last trade of the candle. Trades older than the
+  oldest kept candle are dropped. Intervals without trades have no candle.
+*/
+#[derive(Clone, Debug)]
+pub struct CandleAggregator {
+    interval: CandleInterval,
+    max_candles: usize,
+    // candles by open time
+    candles: BTreeMap<u64, Candle>,
+}
+
+impl CandleAggregator {
+    pub fn new(interval: CandleInterval, max_candles: usize) -> Self {
+        Self {
+            interval,
+            max_candles: max_candles.max(1),
+            candles: BTreeMap::new(),
+        }
+    }
+
+    // Returns the candle the trade was added to or None when the trade was dropped
+    pub fn add_trade(&mut self, trade: &Trade) -> Option<&Candle> {
+        let open_time = self.interval.open_time(trade.trade_time);
+
+        let is_full = self.candles.len() >= self.max_candles;
+        let is_older = self
+            .candles
+            .first_key_value()
+            .is_some_and(|(oldest, _)| open_time < *oldest);
+        if is_full && is_older {
+            return None;
+        }
+
+        match self.candles.get_mut(&open_time) {
+            Some(candle) => candle.add(trade),
+            None => {
+                self.candles
+                    .insert(open_time, Candle::new(open_time, self.interval, trade));
+
+                if self.candles.len() > self.max_candles {
+                    self.candles.pop_first();
+                }
+            }
+        }
+
+        self.candles.get(&open_time)
+    }
+
+    // the given number of most recent candles ordered from the oldest
+    pub fn last_candles(&self, count: usize) -> Vec<Candle> {
+        let skip = self.candles.len().saturating_sub(count);
+
+        self.candles.values().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::Symbol;
+
+    fn trade(trade_id: u64, trade_time: u64, price: &str, quantity: &str) -> Trade {
+        Trade {
+            symbol: Symbol("BTCUSDC".into()),
+            trade_id,
+            price: price.parse().unwrap(),
+            quantity: quantity.parse().unwrap(),
+            trade_time,
+            buyer_is_maker: false,
+        }
+    }
+
+    fn one_minute() -> CandleInterval {
+        "1m".parse().unwrap()
+    }
+
+    #[test]
+    fn test_trades_aggregated_into_candles_of_their_interval() {
+        let mut aggregator = CandleAggregator::new(one_minute(), 10);
+
+        aggregator.add_trade(&trade(1, 60_000, "100", "1"));
+        aggregator.add_trade(&trade(2, 90_000, "102", "3"));
+        aggregator.add_trade(&trade(3, 119_999, "99", "1"));
+        aggregator.add_trade(&trade(4, 120_000, "101", "2"));
+
+        let candles = aggregator.last_candles(10);
+        assert_eq!(candles.len(), 2);
+        let candle = &candles[0];
+        assert_eq!((candle.open_time, candle.close_time), (60_000, 119_999));
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (
+                "100".parse().unwrap(),
+                "102".parse().unwrap(),
+                "99".parse().unwrap(),
+                "99".parse().unwrap()
+            )
+        );
+        assert_eq!(candle.volume, "5".parse().unwrap());
+        // (100 + 306 + 99) / 5
+        assert_eq!(candle.vwap, "101".parse().unwrap());
+        assert_eq!(candle.trades, 3);
+        assert_eq!(candles[1].open, "101".parse().unwrap());
+    }
+
+    #[test]
+    fn test_late_trade_updates_the_candle_it_belongs_to() {
+        let mut aggregator = CandleAggregator::new(one_minute(), 10);
+        aggregator.add_trade(&trade(2, 70_000, "100", "1"));
+        aggregator.add_trade(&trade(4, 130_000, "105", "1"));
+
+        // traded before the first trade of the earlier candle but received last
+        let updated = aggregator.add_trade(&trade(1, 65_000, "98", "1")).cloned();
+
+        let candle = updated.unwrap();
+        assert_eq!(candle.open_time, 60_000);
+        assert_eq!(candle.open, "98".parse().unwrap());
+        assert_eq!(candle.close, "100".parse().unwrap());
+        assert_eq!(candle.low, "98".parse().unwrap());
+        assert_eq!(aggregator.last_candles(1)[0].trades, 1);
+    }
+
+    #[test]
+    fn test_candle_closing_after_the_last_time_closes_at_it() {
+        let interval = CandleInterval::from_millis(u64::MAX / 2 + 1).unwrap();
+        let mut aggregator = CandleAggregator::new(interval, 10);
+
+        let candle = aggregator
+            .add_trade(&trade(1, u64::MAX, "100", "1"))
+            .cloned();
+
+        let candle = candle.unwrap();
+        assert_eq!(candle.open_time, u64::MAX / 2 + 1);
+        assert_eq!(candle.close_time, u64::MAX);
+    }
+
+    #[test]
+    fn test_only_the_most_recent_candles_kept() {
+        let mut aggregator = CandleAggregator::new(one_minute(), 2);
+        aggregator.add_trade(&trade(1, 0, "100", "1"));
+        aggregator.add_trade(&trade(2, 60_000, "101", "1"));
+        aggregator.add_trade(&trade(3, 120_000, "102", "1"));
+
+        let dropped = aggregator.add_trade(&trade(4, 30_000, "99", "1")).is_none();
+
+        assert!(dropped);
+        assert_eq!(
+            aggregator
+                .last_candles(5)
+                .iter()
+                .map(|candle| candle.open_time)
+                .collect::<Vec<u64>>(),
+            vec![60_000, 120_000]
+        );
+    }
+}
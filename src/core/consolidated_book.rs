@@ -0,0 +1,165 @@
+use super::OrderBook;
+use crate::typespec::{Price, PriceLevel, Quantity, Venue};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// A price level of the consolidated book with the quantity each venue quotes at the price
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsolidatedLevel {
+    pub price: Price,
+    // total quantity of all venues at the price
+    pub quantity: Quantity,
+    // venues ordered from the largest quantity
+    pub venues: Vec<(Venue, Quantity)>,
+}
+
+/*
+  Book of an instrument merged from the books of several venues. Levels at the same
+  price are summed and keep the quantity of each venue so the liquidity of every
+  level can be attributed to where it is quoted.
+
+  Venues quote on their own tick sizes so levels are only merged on equal prices.
+  The book can be crossed when a venue bids above the ask of another one.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsolidatedBook {
+    // bids ordered from the best (highest) price
+    pub bids: Vec<ConsolidatedLevel>,
+    // asks ordered from the best (lowest) price
+    pub asks: Vec<ConsolidatedLevel>,
+}
+
+impl ConsolidatedBook {
+    pub fn from_books<'b>(books: impl IntoIterator<Item = (&'b Venue, &'b OrderBook)>) -> Self {
+        let mut bids: BTreeMap<Price, Vec<(Venue, Quantity)>> = BTreeMap::new();
+        let mut asks: BTreeMap<Price, Vec<(Venue, Quantity)>> = BTreeMap::new();
+
+        for (venue, book) in books {
+            for (price, quantity) in book.bids() {
+                bids.entry(price)
+                    .or_default()
+                    .push((venue.clone(), quantity));
+            }
+            for (price, quantity) in book.asks() {
+                asks.entry(price)
+                    .or_default()
+                    .push((venue.clone(), quantity));
+            }
+        }
+
+        Self {
+            bids: bids.into_iter().rev().map(consolidated_level).collect(),
+            asks: asks.into_iter().map(consolidated_level).collect(),
+        }
+    }
+
+    // best bid and best offer across all venues
+    pub fn best_bid_offer(&self) -> (Option<&ConsolidatedLevel>, Option<&ConsolidatedLevel>) {
+        (self.bids.first(), self.asks.first())
+    }
+
+    // the given number of best levels of each side
+    pub fn top_levels(&self, depth: usize) -> ConsolidatedBook {
+        ConsolidatedBook {
+            bids: self.bids.iter().take(depth).cloned().collect(),
+            asks: self.asks.iter().take(depth).cloned().collect(),
+        }
+    }
+
+    // levels without attribution so the metrics of a single book can run on them
+    pub fn bid_levels(&self) -> Vec<PriceLevel> {
+        price_levels(&self.bids)
+    }
+
+    pub fn ask_levels(&self) -> Vec<PriceLevel> {
+        price_levels(&self.asks)
+    }
+}
+
+fn consolidated_level((price, mut venues): (Price, Vec<(Venue, Quantity)>)) -> ConsolidatedLevel {
+    venues.sort_by(|(venue_a, qty_a), (venue_b, qty_b)| {
+        qty_b.cmp(qty_a).then_with(|| venue_a.cmp(venue_b))
+    });
+
+    ConsolidatedLevel {
+        price,
+        quantity: Quantity(venues.iter().map(|(_, qty)| qty.0).sum::<Decimal>()),
+        venues,
+    }
+}
+
+fn price_levels(levels: &[ConsolidatedLevel]) -> Vec<PriceLevel> {
+    levels
+        .iter()
+        .map(|level| (level.price, level.quantity))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::{levels, DepthSnapshot};
+
+    fn book(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> OrderBook {
+        OrderBook::from_snapshot(DepthSnapshot {
+            last_update_id: 1,
+            bids: levels(bids),
+            asks: levels(asks),
+        })
+    }
+
+    fn venue(name: &str) -> Venue {
+        Venue(name.into())
+    }
+
+    #[test]
+    fn test_levels_of_venues_merged_with_attribution() {
+        let binance = book(&[("99.00", "1"), ("98.00", "2")], &[("101.00", "1")]);
+        let kraken = book(&[("99.00", "3"), ("98.50", "1")], &[("100.50", "2")]);
+
+        let consolidated = ConsolidatedBook::from_books(vec![
+            (&venue("binance"), &binance),
+            (&venue("kraken"), &kraken),
+        ]);
+
+        assert_eq!(
+            consolidated.bid_levels(),
+            levels(&[("99", "4"), ("98.5", "1"), ("98", "2")])
+        );
+        assert_eq!(
+            consolidated.ask_levels(),
+            levels(&[("100.5", "2"), ("101", "1")])
+        );
+        // the venue quoting more at a price comes first
+        assert_eq!(
+            consolidated.bids[0].venues,
+            vec![
+                (venue("kraken"), "3".parse().unwrap()),
+                (venue("binance"), "1".parse().unwrap())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_best_bid_offer_taken_across_venues() {
+        let binance = book(&[("99.00", "1")], &[("101.00", "1")]);
+        let coinbase = book(&[("99.50", "2")], &[("101.50", "1")]);
+
+        let consolidated = ConsolidatedBook::from_books(vec![
+            (&venue("binance"), &binance),
+            (&venue("coinbase"), &coinbase),
+        ]);
+        let (bid, offer) = consolidated.best_bid_offer();
+
+        assert_eq!(bid.unwrap().venues[0].0, venue("coinbase"));
+        assert_eq!(offer.unwrap().venues[0].0, venue("binance"));
+        assert_eq!(consolidated.top_levels(1).bids.len(), 1);
+    }
+
+    #[test]
+    fn test_no_books_consolidate_into_empty_book() {
+        let consolidated = ConsolidatedBook::from_books(vec![]);
+
+        assert_eq!(consolidated.best_bid_offer(), (None, None));
+    }
+}
@@ -0,0 +1,161 @@
+use super::{mid_price, BASIS_POINTS};
+use crate::typespec::{Price, PriceLevel, Quantity};
+use rust_decimal::Decimal;
+
+/*
+  Spread, depth and imbalance of an order book. Levels of both sides are expected
+  to be ordered from the best price, as returned by OrderBook::bids and OrderBook::asks.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spread {
+    // best ask - best bid
+    pub absolute: Price,
+    // absolute spread relative to the mid price in basis points
+    pub relative_bps: Decimal,
+}
+
+// Best bid and best ask levels, None for an empty side
+pub fn best_bid_ask(
+    bids: &[PriceLevel],
+    asks: &[PriceLevel],
+) -> (Option<PriceLevel>, Option<PriceLevel>) {
+    (bids.first().copied(), asks.first().copied())
+}
+
+// None is returned when a side is empty
+pub fn spread(bids: &[PriceLevel], asks: &[PriceLevel]) -> Option<Spread> {
+    let (best_bid, _) = bids.first()?;
+    let (best_ask, _) = asks.first()?;
+    let mid = mid_price(bids, asks)?;
+
+    let absolute = best_ask.0 - best_bid.0;
+    let relative_bps = if mid.0.is_zero() {
+        Decimal::ZERO
+    } else {
+        absolute / mid.0 * BASIS_POINTS
+    };
+
+    Some(Spread {
+        absolute: Price(absolute),
+        relative_bps,
+    })
+}
+
+/*
+  Cumulative quantity of the levels of one side priced within the given
+  basis points of the mid price.
+
+  |level price - mid| <= mid * bps / 10000
+
+  None is returned when the distance does not fit a decimal.
+*/
+pub fn depth_within_bps(levels: &[PriceLevel], mid: Price, bps: Decimal) -> Option<Quantity> {
+    let max_distance = mid.0.checked_mul(bps)? / BASIS_POINTS;
+
+    let quantity = levels
+        .iter()
+        .take_while(|(price, _)| (price.0 - mid.0).abs() <= max_distance)
+        .fold(Decimal::ZERO, |acc, (_, qty)| acc + qty.0);
+
+    Some(Quantity(quantity))
+}
+
+/*
+  Volume imbalance of the given number of best levels of each side
+
+  Imbalance = (bid quantity - ask quantity) / (bid quantity + ask quantity)
+
+  Ranges from -1 when only asks hold quantity to 1 when only bids do.
+  None is returned when neither side holds any quantity.
+*/
+pub fn imbalance(bids: &[PriceLevel], asks: &[PriceLevel], depth: usize) -> Option<Decimal> {
+    let quantity = |levels: &[PriceLevel]| {
+        levels
+            .iter()
+            .take(depth)
+            .fold(Decimal::ZERO, |acc, (_, qty)| acc + qty.0)
+    };
+
+    let bid_qty = quantity(bids);
+    let ask_qty = quantity(asks);
+    let total_qty = bid_qty + ask_qty;
+
+    if total_qty.is_zero() {
+        return None;
+    }
+
+    Some((bid_qty - ask_qty) / total_qty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::levels;
+
+    fn decimal(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_best_bid_ask_of_book() {
+        let bids = levels(&[("99", "1"), ("98", "2")]);
+        let asks = levels(&[("101", "3")]);
+
+        assert_eq!(best_bid_ask(&bids, &asks), (Some(bids[0]), Some(asks[0])));
+        assert_eq!(best_bid_ask(&[], &asks), (None, Some(asks[0])));
+    }
+
+    #[test]
+    fn test_spread_absolute_and_relative() {
+        let bids = levels(&[("99", "1")]);
+        let asks = levels(&[("101", "1")]);
+
+        let spread = spread(&bids, &asks).unwrap();
+
+        assert_eq!(spread.absolute, "2".parse().unwrap());
+        // 2 / 100 = 2%
+        assert_eq!(spread.relative_bps, decimal("200"));
+    }
+
+    #[test]
+    fn test_spread_of_one_sided_book() {
+        assert_eq!(spread(&levels(&[("99", "1")]), &[]), None);
+    }
+
+    #[test]
+    fn test_depth_within_bps_of_mid() {
+        let bids = levels(&[("99.9", "1"), ("99.5", "2"), ("98", "100")]);
+        let asks = levels(&[("100.1", "3"), ("100.5", "4"), ("102", "100")]);
+        let mid: Price = "100".parse().unwrap();
+
+        // 50 bps of 100 is 0.5
+        assert_eq!(
+            depth_within_bps(&bids, mid, decimal("50")),
+            Some(Quantity(decimal("3")))
+        );
+        assert_eq!(
+            depth_within_bps(&asks, mid, decimal("50")),
+            Some(Quantity(decimal("7")))
+        );
+        assert_eq!(
+            depth_within_bps(&asks, mid, decimal("5")),
+            Some(Quantity(Decimal::ZERO))
+        );
+        assert_eq!(depth_within_bps(&asks, mid, Decimal::MAX), None);
+    }
+
+    #[test]
+    fn test_imbalance_at_several_depths() {
+        let bids = levels(&[("99", "3"), ("98", "1")]);
+        let asks = levels(&[("101", "1"), ("102", "7")]);
+
+        assert_eq!(imbalance(&bids, &asks, 1), Some(decimal("0.5")));
+        assert_eq!(
+            imbalance(&bids, &asks, 2),
+            Some(decimal("-0.3333333333333333333333333333"))
+        );
+        assert_eq!(imbalance(&[], &asks, 2), Some(decimal("-1")));
+        assert_eq!(imbalance(&[], &[], 2), None);
+    }
+}
@@ -0,0 +1,173 @@
+use super::{mid_price, BASIS_POINTS};
+use crate::typespec::{Notional, OrderSize, Price, PriceLevel, Quantity, Side};
+use rust_decimal::Decimal;
+
+// Outcome of a hypothetical market order filled against the current book
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FillEstimate {
+    pub filled_quantity: Quantity,
+    pub filled_notional: Notional,
+    // None when nothing could be filled
+    pub average_price: Option<Price>,
+    // price of the last level the order reached
+    pub worst_price: Option<Price>,
+    // average price against the mid price in basis points, positive when the fill is worse than mid
+    pub slippage_bps: Option<Decimal>,
+    // levels taken fully or partially by the order
+    pub levels_consumed: usize,
+    // false when the book ran out of levels before the whole size was filled
+    pub sufficient_depth: bool,
+}
+
+/*
+  Walks the levels a market order would take from the best price, the asks for
+  a buy and the bids for a sell, until the size is filled or the side runs out.
+  Levels of both sides are expected to be ordered from the best price.
+
+  Slippage = (average price - mid) / mid * 10000 for a buy
+           = (mid - average price) / mid * 10000 for a sell
+*/
+pub fn estimate_market_order(
+    bids: &[PriceLevel],
+    asks: &[PriceLevel],
+    side: Side,
+    size: OrderSize,
+) -> FillEstimate {
+    let levels = match side {
+        Side::Buy => asks,
+        Side::Sell => bids,
+    };
+
+    let mut remaining = match size {
+        OrderSize::Quantity(qty) => qty.0,
+        OrderSize::Notional(notional) => notional.0,
+    };
+    let mut filled_qty = Decimal::ZERO;
+    let mut filled_notional = Decimal::ZERO;
+    let mut worst_price = None;
+    let mut levels_consumed = 0;
+
+    for (price, qty) in levels {
+        if remaining <= Decimal::ZERO {
+            break;
+        }
+        if price.0 <= Decimal::ZERO || qty.0 <= Decimal::ZERO {
+            continue;
+        }
+
+        let (taken_qty, taken_notional) = match size {
+            OrderSize::Quantity(_) => {
+                let taken_qty = remaining.min(qty.0);
+                remaining -= taken_qty;
+                (taken_qty, taken_qty * price.0)
+            }
+            OrderSize::Notional(_) => {
+                let taken_notional = remaining.min(qty.0 * price.0);
+                remaining -= taken_notional;
+                (taken_notional / price.0, taken_notional)
+            }
+        };
+
+        filled_qty += taken_qty;
+        filled_notional += taken_notional;
+        worst_price = Some(*price);
+        levels_consumed += 1;
+    }
+
+    let average_price = if filled_qty.is_zero() {
+        None
+    } else {
+        Some(Price(filled_notional / filled_qty))
+    };
+
+    let slippage_bps = match (average_price, mid_price(bids, asks)) {
+        (Some(average), Some(mid)) if !mid.0.is_zero() => {
+            let difference = match side {
+                Side::Buy => average.0 - mid.0,
+                Side::Sell => mid.0 - average.0,
+            };
+            Some(difference / mid.0 * BASIS_POINTS)
+        }
+        _ => None,
+    };
+
+    FillEstimate {
+        filled_quantity: Quantity(filled_qty),
+        filled_notional: Notional(filled_notional),
+        average_price,
+        worst_price,
+        slippage_bps,
+        levels_consumed,
+        sufficient_depth: remaining <= Decimal::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::levels;
+
+    fn book() -> (Vec<PriceLevel>, Vec<PriceLevel>) {
+        (
+            levels(&[("99", "1"), ("98", "2")]),
+            levels(&[("101", "1"), ("102", "2"), ("105", "1")]),
+        )
+    }
+
+    #[test]
+    fn test_market_buy_by_quantity_walks_asks() {
+        let (bids, asks) = book();
+        let size = OrderSize::Quantity("2".parse().unwrap());
+
+        let estimate = estimate_market_order(&bids, &asks, Side::Buy, size);
+
+        // 1 at 101 and 1 at 102 against a mid price of 100
+        assert_eq!(estimate.average_price, Some("101.5".parse().unwrap()));
+        assert_eq!(estimate.worst_price, Some("102".parse().unwrap()));
+        assert_eq!(estimate.slippage_bps, Some("150".parse().unwrap()));
+        assert_eq!(estimate.levels_consumed, 2);
+        assert_eq!(estimate.filled_notional, Notional("203".parse().unwrap()));
+        assert!(estimate.sufficient_depth);
+    }
+
+    #[test]
+    fn test_market_sell_by_notional_walks_bids() {
+        let (bids, asks) = book();
+        let size = OrderSize::Notional("197".parse().unwrap());
+
+        let estimate = estimate_market_order(&bids, &asks, Side::Sell, size);
+
+        // 99 fills the first level and the other 98 sells 1 at 98
+        assert_eq!(estimate.filled_quantity, Quantity("2".parse().unwrap()));
+        assert_eq!(estimate.average_price, Some("98.5".parse().unwrap()));
+        assert_eq!(estimate.slippage_bps, Some("150".parse().unwrap()));
+        assert_eq!(estimate.levels_consumed, 2);
+        assert!(estimate.sufficient_depth);
+    }
+
+    #[test]
+    fn test_market_order_larger_than_book() {
+        let (bids, asks) = book();
+        let size = OrderSize::Quantity("10".parse().unwrap());
+
+        let estimate = estimate_market_order(&bids, &asks, Side::Buy, size);
+
+        assert_eq!(estimate.filled_quantity, Quantity("4".parse().unwrap()));
+        assert_eq!(estimate.worst_price, Some("105".parse().unwrap()));
+        assert_eq!(estimate.levels_consumed, 3);
+        assert!(!estimate.sufficient_depth);
+    }
+
+    #[test]
+    fn test_market_order_against_empty_side() {
+        let (bids, _) = book();
+        let size = OrderSize::Quantity("1".parse().unwrap());
+
+        let estimate = estimate_market_order(&bids, &[], Side::Buy, size);
+
+        assert_eq!(estimate.average_price, None);
+        assert_eq!(estimate.slippage_bps, None);
+        assert_eq!(estimate.levels_consumed, 0);
+        assert!(!estimate.sufficient_depth);
+    }
+}
@@ -1,70 +1,122 @@
 /// Core functions of the domain
+use crate::typespec::Price;
+use rust_decimal::Decimal;
 use std::ops::{Add, Div};
 
+mod arbitrage;
+mod candles;
+mod consolidated_book;
+mod depth_metrics;
+mod market_impact;
+mod order_book;
+mod price_metrics;
+mod triangular_arbitrage;
+
+pub use arbitrage::{detect_arbitrage, ArbitrageOpportunity, ArbitrageSettings};
+pub use candles::{Candle, CandleAggregator};
+pub use consolidated_book::{ConsolidatedBook, ConsolidatedLevel};
+pub use depth_metrics::{best_bid_ask, depth_within_bps, imbalance, spread};
+pub use market_impact::{estimate_market_order, FillEstimate};
+pub use order_book::{DepthUpdateOutcome, OrderBook};
+pub use price_metrics::{
+    microprice, mid_price, quantity_weighted_average_price, vwap_of_top_levels,
+    vwap_to_fill_notional,
+};
+pub use triangular_arbitrage::{triangle_round_trips, TopOfBook, TriangleRoundTrip};
+
+// basis points in a whole, a ratio times this is in basis points
+pub const BASIS_POINTS: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+
 /*
   Calculates the average order book price according to the spec given
   in the task prompt
 
   Average price of order book = (Sum of Asks + Sum of Bids ) / Number of Asks and Bids
+
+  None is returned for an empty book since there is no price to average
 */
-pub fn average_price_of_order_book(asks: Vec<f32>, bids: Vec<f32>) -> f32 {
-    let sum = |values: &Vec<f32>| values.into_iter().fold(0f32, |acc, v| acc.add(v));
+pub fn average_price_of_order_book(asks: Vec<Price>, bids: Vec<Price>) -> Option<Price> {
+    let sum = |values: &Vec<Price>| values.iter().fold(Decimal::ZERO, |acc, v| acc.add(v.0));
 
     let asks_sum = sum(&asks);
     let bids_sum = sum(&bids);
 
     let asks_len = asks.len();
-    let bids_len = asks.len();
+    let bids_len = bids.len();
 
     let sum_prices = asks_sum.add(bids_sum);
 
     let sum_len = {
         let total = asks_len.add(bids_len);
 
-        // coerce type from usize to f32
-        total as f32
+        // coerce type from usize to decimal
+        Decimal::from(total)
     };
 
-    sum_prices.div(sum_len)
+    if sum_len.is_zero() {
+        return None;
+    }
+
+    Some(Price(sum_prices.div(sum_len)))
 }
 
 #[cfg(test)]
 mod tests {
+    use rust_decimal::Decimal;
     use std::ops::{Add, Div};
 
     use super::average_price_of_order_book;
+    use crate::typespec::Price;
 
     #[test]
     fn test_average_price() {
         // setup
-        let asks: Vec<f32> = vec![1f32, 2f32, 3f32, 4f32];
-        let bids: Vec<f32> = vec![1f32, 2f32, 3f32, 4f32, 5f32];
+        let prices = |values: &[i64]| -> Vec<Price> {
+            values.iter().map(|v| Price(Decimal::from(*v))).collect()
+        };
+        let asks: Vec<Price> = prices(&[1, 2, 3, 4]);
+        let bids: Vec<Price> = prices(&[1, 2, 3, 4, 5]);
 
-        let sum = |values: &Vec<f32>| values.into_iter().fold(0f32, |acc, v| acc.add(v));
+        let sum = |values: &Vec<Price>| values.iter().fold(Decimal::ZERO, |acc, v| acc.add(v.0));
 
         let setup_avg_price = {
             let asks_sum = sum(&asks);
             let bids_sum = sum(&bids);
 
             let asks_len = asks.len();
-            let bids_len = asks.len();
+            let bids_len = bids.len();
 
             let sum_prices = asks_sum.add(bids_sum);
 
             let sum_len = {
                 let total = asks_len.add(bids_len);
 
-                // coerce type from usize to f32
-                total as f32
+                // coerce type from usize to decimal
+                Decimal::from(total)
             };
 
-            sum_prices.div(sum_len)
+            Price(sum_prices.div(sum_len))
         };
 
         let _test_fn = {
             let price = average_price_of_order_book(asks, bids);
 
-            assert_eq!(price, setup_avg_price)
+            assert_eq!(price, Some(setup_avg_price))
         };
     }
+
+    #[test]
+    fn test_average_price_is_exact() {
+        let asks = vec![Price("97000.01".parse().unwrap())];
+        let bids = vec![Price("96999.99".parse().unwrap())];
+
+        let price = average_price_of_order_book(asks, bids);
+
+        assert_eq!(price.map(|p| p.to_string()), Some("97000.00".into()));
+    }
+
+    #[test]
+    fn test_average_price_of_empty_book() {
+        assert_eq!(average_price_of_order_book(vec![], vec![]), None);
+    }
 }
@@ -0,0 +1,217 @@
+use crate::typespec::{DepthSnapshot, DepthUpdate, Price, PriceLevel, Quantity, TickSize};
+use std::{collections::BTreeMap, fmt};
+
+/*
+  Local order book of a single symbol. It is bootstrapped from a depth snapshot and kept
+  up to date by applying diff depth updates following the rules given by binance in
+  "How to manage a local order book correctly"
+
+  - updates where u <= last update id of the book are already part of the book and dropped
+  - the first update applied after a snapshot must have U <= last update id + 1 <= u
+  - every following update must have U == u of the previous update + 1
+  - a level is removed when its quantity is 0
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderBook {
+    last_update_id: u64,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DepthUpdateOutcome {
+    Applied,
+    // update only contains changes already in the book
+    Stale,
+}
+
+// Returned when an update does not follow the last applied update id. The book has
+// to be bootstrapped from a new snapshot
+#[derive(Debug, PartialEq)]
+pub struct SequenceGap {
+    pub expected_update_id: u64,
+    pub first_update_id: u64,
+}
+
+impl fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected update id {} but update starts at {}",
+            self.expected_update_id, self.first_update_id
+        )
+    }
+}
+
+impl std::error::Error for SequenceGap {}
+
+impl OrderBook {
+    pub fn from_snapshot(snapshot: DepthSnapshot) -> Self {
+        let mut book = Self {
+            last_update_id: snapshot.last_update_id,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        };
+
+        update_levels(&mut book.bids, &snapshot.bids);
+        update_levels(&mut book.asks, &snapshot.asks);
+
+        book
+    }
+
+    pub fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+
+    pub fn apply(&mut self, update: &DepthUpdate) -> Result<DepthUpdateOutcome, SequenceGap> {
+        if update.final_update_id <= self.last_update_id {
+            return Ok(DepthUpdateOutcome::Stale);
+        }
+
+        let expected_update_id = self.last_update_id + 1;
+        if update.first_update_id > expected_update_id {
+            return Err(SequenceGap {
+                expected_update_id,
+                first_update_id: update.first_update_id,
+            });
+        }
+
+        update_levels(&mut self.bids, &update.bids);
+        update_levels(&mut self.asks, &update.asks);
+        self.last_update_id = update.final_update_id;
+
+        Ok(DepthUpdateOutcome::Applied)
+    }
+
+    // bids ordered from the best (highest) price
+    pub fn bids(&self) -> Vec<PriceLevel> {
+        self.bids.iter().rev().map(|(p, q)| (*p, *q)).collect()
+    }
+
+    // asks ordered from the best (lowest) price
+    pub fn asks(&self) -> Vec<PriceLevel> {
+        self.asks.iter().map(|(p, q)| (*p, *q)).collect()
+    }
+
+    // tick size inferred from the prices of the levels in the book
+    pub fn tick_size(&self) -> TickSize {
+        TickSize::from_prices(self.bids.keys().chain(self.asks.keys()))
+    }
+}
+
+fn update_levels(side: &mut BTreeMap<Price, Quantity>, levels: &[PriceLevel]) {
+    for (price, quantity) in levels {
+        if quantity.0.is_zero() {
+            side.remove(price);
+        } else {
+            side.insert(*price, *quantity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::{levels, Symbol};
+
+    fn snapshot() -> DepthSnapshot {
+        DepthSnapshot {
+            last_update_id: 100,
+            bids: levels(&[("99.00", "1"), ("98.00", "2"), ("97.00", "3")]),
+            asks: levels(&[("101.00", "1"), ("103.00", "3"), ("102.00", "2")]),
+        }
+    }
+
+    fn update(first_update_id: u64, final_update_id: u64) -> DepthUpdate {
+        DepthUpdate {
+            symbol: Symbol("BTCUSDC".into()),
+            event_time: 1,
+            first_update_id,
+            final_update_id,
+            bids: levels(&[("99.00", "0.00"), ("96.00", "4")]),
+            asks: levels(&[("101.00", "5")]),
+        }
+    }
+
+    #[test]
+    fn test_book_from_snapshot_is_ordered_from_best_price() {
+        let book = OrderBook::from_snapshot(snapshot());
+
+        assert_eq!(book.last_update_id, 100);
+        assert_eq!(
+            book.bids(),
+            levels(&[("99", "1"), ("98", "2"), ("97", "3")])
+        );
+        assert_eq!(
+            book.asks(),
+            levels(&[("101", "1"), ("102", "2"), ("103", "3")])
+        );
+    }
+
+    #[test]
+    fn test_update_straddling_snapshot_is_applied() {
+        let mut book = OrderBook::from_snapshot(snapshot());
+
+        let outcome = book.apply(&update(95, 105));
+
+        assert_eq!(outcome, Ok(DepthUpdateOutcome::Applied));
+        assert_eq!(book.last_update_id, 105);
+        // level with zero quantity is removed
+        assert_eq!(
+            book.bids(),
+            levels(&[("98", "2"), ("97", "3"), ("96", "4")])
+        );
+        assert_eq!(
+            book.asks(),
+            levels(&[("101", "5"), ("102", "2"), ("103", "3")])
+        );
+    }
+
+    #[test]
+    fn test_update_already_in_snapshot_is_dropped() {
+        let mut book = OrderBook::from_snapshot(snapshot());
+
+        let outcome = book.apply(&update(90, 100));
+
+        assert_eq!(outcome, Ok(DepthUpdateOutcome::Stale));
+        assert_eq!(book, OrderBook::from_snapshot(snapshot()));
+    }
+
+    #[test]
+    fn test_consecutive_updates_are_applied() {
+        let mut book = OrderBook::from_snapshot(snapshot());
+
+        let _ = book.apply(&update(95, 105));
+        let outcome = book.apply(&update(106, 110));
+
+        assert_eq!(outcome, Ok(DepthUpdateOutcome::Applied));
+        assert_eq!(book.last_update_id, 110);
+    }
+
+    #[test]
+    fn test_update_after_missing_ids_is_a_gap() {
+        let mut book = OrderBook::from_snapshot(snapshot());
+
+        let outcome = book.apply(&update(102, 110));
+
+        assert_eq!(
+            outcome,
+            Err(SequenceGap {
+                expected_update_id: 101,
+                first_update_id: 102,
+            })
+        );
+        assert_eq!(book, OrderBook::from_snapshot(snapshot()));
+    }
+
+    #[test]
+    fn test_tick_size_inferred_from_book_levels() {
+        let mut book = OrderBook::from_snapshot(snapshot());
+        let _ = book.apply(&DepthUpdate {
+            asks: levels(&[("101.25000000", "1")]),
+            ..update(95, 105)
+        });
+
+        assert_eq!(book.tick_size(), "0.01".parse().unwrap());
+    }
+}
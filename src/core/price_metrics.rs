@@ -0,0 +1,173 @@
+use crate::typespec::{Notional, Price, PriceLevel};
+use rust_decimal::Decimal;
+
+/*
+  Price metrics of an order book that take the quantity of each level into
+  account. Levels of both sides are expected to be ordered from the best price,
+  as returned by OrderBook::bids and OrderBook::asks.
+
+  None is returned when a side needed by a metric is empty or has no quantity.
+*/
+
+// Mid price = (best bid + best ask) / 2
+pub fn mid_price(bids: &[PriceLevel], asks: &[PriceLevel]) -> Option<Price> {
+    let (best_bid, _) = bids.first()?;
+    let (best_ask, _) = asks.first()?;
+
+    Some(Price((best_bid.0 + best_ask.0) / Decimal::TWO))
+}
+
+/*
+  Microprice = (best bid * best ask quantity + best ask * best bid quantity)
+               / (best bid quantity + best ask quantity)
+
+  Leans towards the side with less quantity as that side is more likely to be
+  taken out first.
+*/
+pub fn microprice(bids: &[PriceLevel], asks: &[PriceLevel]) -> Option<Price> {
+    let (best_bid, bid_qty) = bids.first()?;
+    let (best_ask, ask_qty) = asks.first()?;
+
+    let total_qty = bid_qty.0 + ask_qty.0;
+    if total_qty.is_zero() {
+        return None;
+    }
+
+    Some(Price(
+        (best_bid.0 * ask_qty.0 + best_ask.0 * bid_qty.0) / total_qty,
+    ))
+}
+
+// Volume weighted average price of the best levels of one side of the book
+pub fn vwap_of_top_levels(levels: &[PriceLevel], depth: usize) -> Option<Price> {
+    let top_levels = &levels[..depth.min(levels.len())];
+
+    weighted_average(top_levels.iter())
+}
+
+/*
+  Volume weighted average price paid to fill the given notional by taking the
+  levels of one side of the book from the best price. None is returned when the
+  side does not hold enough quantity to fill the whole notional.
+*/
+pub fn vwap_to_fill_notional(levels: &[PriceLevel], notional: Notional) -> Option<Price> {
+    if notional.0 <= Decimal::ZERO {
+        return None;
+    }
+
+    let mut remaining = notional.0;
+    let mut filled_qty = Decimal::ZERO;
+
+    for (price, qty) in levels {
+        if price.0.is_zero() {
+            continue;
+        }
+
+        let taken = remaining.min(price.0 * qty.0);
+        filled_qty += taken / price.0;
+        remaining -= taken;
+
+        if remaining.is_zero() {
+            return Some(Price(notional.0 / filled_qty));
+        }
+    }
+
+    None
+}
+
+// Quantity weighted average price of every level of both sides of the book
+pub fn quantity_weighted_average_price(bids: &[PriceLevel], asks: &[PriceLevel]) -> Option<Price> {
+    weighted_average(bids.iter().chain(asks.iter()))
+}
+
+fn weighted_average<'l>(levels: impl Iterator<Item = &'l PriceLevel>) -> Option<Price> {
+    let (notional, qty) = levels.fold(
+        (Decimal::ZERO, Decimal::ZERO),
+        |(notional, qty), (price, level_qty)| (notional + price.0 * level_qty.0, qty + level_qty.0),
+    );
+
+    if qty.is_zero() {
+        return None;
+    }
+
+    Some(Price(notional / qty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typespec::levels;
+
+    fn price(value: &str) -> Option<Price> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn test_mid_price_between_best_levels() {
+        let bids = levels(&[("99.00", "1"), ("98.00", "5")]);
+        let asks = levels(&[("100.01", "2"), ("101.00", "5")]);
+
+        assert_eq!(mid_price(&bids, &asks), price("99.505"));
+        assert_eq!(mid_price(&bids, &[]), None);
+    }
+
+    #[test]
+    fn test_microprice_leans_towards_thinner_side() {
+        // a large bid makes the ask more likely to be taken next
+        let bids = levels(&[("99", "3")]);
+        let asks = levels(&[("100", "1")]);
+
+        assert_eq!(microprice(&bids, &asks), price("99.75"));
+        assert_eq!(microprice(&[], &asks), None);
+    }
+
+    #[test]
+    fn test_vwap_of_top_levels_weights_by_quantity() {
+        let asks = levels(&[("100", "1"), ("101", "3"), ("150", "100")]);
+
+        assert_eq!(vwap_of_top_levels(&asks, 2), price("100.75"));
+        // depth deeper than the book uses every level
+        assert_eq!(vwap_of_top_levels(&asks[..2], 10), price("100.75"));
+        assert_eq!(vwap_of_top_levels(&asks, 0), None);
+    }
+
+    #[test]
+    fn test_vwap_to_fill_notional_walks_levels() {
+        let asks = levels(&[("100", "1"), ("200", "1")]);
+
+        // 100 fills the first level, the other 100 buys half of the second
+        assert_eq!(
+            vwap_to_fill_notional(&asks, "200".parse().unwrap()),
+            Some(Price(
+                "200".parse::<Decimal>().unwrap() / "1.5".parse::<Decimal>().unwrap()
+            ))
+        );
+        assert_eq!(
+            vwap_to_fill_notional(&asks, "50".parse().unwrap()),
+            price("100")
+        );
+    }
+
+    #[test]
+    fn test_vwap_to_fill_notional_without_enough_depth() {
+        let asks = levels(&[("100", "1"), ("200", "1")]);
+
+        assert_eq!(
+            vwap_to_fill_notional(&asks, "300.01".parse().unwrap()),
+            None
+        );
+        assert_eq!(vwap_to_fill_notional(&asks, "0".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_quantity_weighted_average_of_whole_book() {
+        // the 50 unit wall outweighs the small levels
+        let bids = levels(&[("99", "0.0001"), ("90", "50")]);
+        let asks = levels(&[("101", "0.0001")]);
+
+        let average = quantity_weighted_average_price(&bids, &asks).unwrap();
+
+        assert_eq!(average.round_dp(2), "90.00".parse().unwrap());
+        assert_eq!(quantity_weighted_average_price(&[], &[]), None);
+    }
+}
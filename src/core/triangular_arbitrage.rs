@@ -0,0 +1,252 @@
+use super::BASIS_POINTS;
+use crate::typespec::{Price, PriceLevel, Side, Symbol, Triangle};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// best bid and best ask of a book
+pub type TopOfBook = (Option<PriceLevel>, Option<PriceLevel>);
+
+// Conversion of one asset of a triangle into the next one at the top of the book of a symbol
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriangleLeg {
+    pub symbol: Symbol,
+    // buys take the best ask to convert the quote asset into the base asset, sells the best bid
+    pub side: Side,
+    pub price: Price,
+}
+
+// Converting the start asset around a triangle and back into itself
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriangleRoundTrip {
+    pub start_asset: String,
+    pub legs: Vec<TriangleLeg>,
+    // amount of the start asset received for each unit of it after fees
+    pub rate: Decimal,
+    // profit of the round trip in basis points, negative for a loss
+    pub profit_bps: Decimal,
+    // largest amount of the start asset the best levels of every leg can take
+    pub max_start_amount: Decimal,
+}
+
+/*
+  Round trips around a triangle in both directions at the top of the book of its
+  symbols, taking the taker fee off the amount received on every leg.
+
+  A leg converting the quote asset of a symbol buys at the best ask, amount / ask,
+  and a leg converting the base asset sells at the best bid, amount * bid.
+
+  Rate = product of the conversions of the legs * (1 - fee) ^ 3
+  Profit = (rate - 1) * 10000
+
+  The size of a round trip is limited by the quantity of the best level each leg takes,
+  expressed in the start asset through the conversions of the legs before it. A direction
+  is left out when a book of its legs has no level on the side it takes or a leg leaves
+  nothing of the start asset to convert.
+*/
+pub fn triangle_round_trips(
+    triangle: &Triangle,
+    tops: &BTreeMap<Symbol, TopOfBook>,
+    fee_bps: Decimal,
+) -> Vec<TriangleRoundTrip> {
+    let [first, second, third] = triangle.symbols().clone();
+
+    // the first and third symbol both trade the start asset
+    [
+        [first.clone(), second.clone(), third.clone()],
+        [third, second, first],
+    ]
+    .into_iter()
+    .filter_map(|path| round_trip(triangle.start_asset(), &path, tops, fee_bps))
+    .collect()
+}
+
+fn round_trip(
+    start_asset: &str,
+    path: &[Symbol; 3],
+    tops: &BTreeMap<Symbol, TopOfBook>,
+    fee_bps: Decimal,
+) -> Option<TriangleRoundTrip> {
+    let fee_rate = Decimal::ONE - fee_bps / BASIS_POINTS;
+    let mut asset = start_asset;
+    // amount held after each leg for one unit of the start asset
+    let mut rate = Decimal::ONE;
+    let mut max_start_amount: Option<Decimal> = None;
+    let mut legs = vec![];
+
+    for symbol in path {
+        let (base, quote) = symbol.base_and_quote()?;
+        let (bid, ask) = tops.get(symbol)?;
+
+        // capacity is the amount of the asset held the best level can convert
+        let (side, price, capacity, next_rate, next_asset) = if asset == quote {
+            let (price, qty) = (*ask)?;
+            if price.0 <= Decimal::ZERO {
+                return None;
+            }
+            (Side::Buy, price, price.0 * qty.0, rate / price.0, base)
+        } else if asset == base {
+            let (price, qty) = (*bid)?;
+            (Side::Sell, price, qty.0, rate * price.0, quote)
+        } else {
+            return None;
+        };
+
+        // nothing is left to convert after a zero priced bid or a fee taking everything
+        if rate.is_zero() {
+            return None;
+        }
+        let start_capacity = capacity / rate;
+        max_start_amount = Some(match max_start_amount {
+            Some(max) => max.min(start_capacity),
+            None => start_capacity,
+        });
+
+        legs.push(TriangleLeg {
+            symbol: symbol.clone(),
+            side,
+            price,
+        });
+        rate = next_rate * fee_rate;
+        asset = next_asset;
+    }
+
+    if asset != start_asset {
+        return None;
+    }
+
+    Some(TriangleRoundTrip {
+        start_asset: start_asset.to_string(),
+        legs,
+        profit_bps: (rate - Decimal::ONE) * BASIS_POINTS,
+        rate,
+        max_start_amount: max_start_amount?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: &str, qty: &str) -> Option<PriceLevel> {
+        Some((price.parse().unwrap(), qty.parse().unwrap()))
+    }
+
+    fn tops() -> BTreeMap<Symbol, TopOfBook> {
+        BTreeMap::from([
+            (
+                Symbol("BTCUSDC".into()),
+                (level("30000", "1"), level("30001", "1")),
+            ),
+            (
+                Symbol("ETHBTC".into()),
+                (level("0.0665", "10"), level("0.0666", "10")),
+            ),
+            (
+                Symbol("ETHUSDC".into()),
+                (level("2010", "5"), level("2011", "10")),
+            ),
+        ])
+    }
+
+    fn triangle() -> Triangle {
+        "BTCUSDC,ETHBTC,ETHUSDC".parse().unwrap()
+    }
+
+    #[test]
+    fn test_round_trips_in_both_directions() {
+        let round_trips = triangle_round_trips(&triangle(), &tops(), Decimal::ZERO);
+
+        assert_eq!(round_trips.len(), 2);
+        let (forward, backward) = (&round_trips[0], &round_trips[1]);
+
+        // buy BTC with USDC, buy ETH with BTC, sell ETH for USDC
+        assert_eq!(
+            forward
+                .legs
+                .iter()
+                .map(|leg| leg.side)
+                .collect::<Vec<Side>>(),
+            vec![Side::Buy, Side::Buy, Side::Sell]
+        );
+        // 2010 / (30001 * 0.0666)
+        assert_eq!(forward.profit_bps.round_dp(4), "59.7247".parse().unwrap());
+        // 5 ETH sold at 2010 bought for 5 * 0.0666 * 30001 USDC
+        assert_eq!(
+            forward.max_start_amount.round_dp(6),
+            "9990.333".parse().unwrap()
+        );
+
+        // buy ETH with USDC, sell ETH for BTC, sell BTC for USDC, 0.0665 * 30000 / 2011
+        assert_eq!(backward.start_asset, "USDC");
+        assert_eq!(backward.profit_bps.round_dp(4), "-79.5624".parse().unwrap());
+    }
+
+    #[test]
+    fn test_round_trips_of_triangle_given_out_of_order() {
+        let triangle: Triangle = "ETHBTC,BTCUSDC,ETHUSDC".parse().unwrap();
+
+        let round_trips = triangle_round_trips(&triangle, &tops(), Decimal::ZERO);
+
+        // buy ETH with BTC, sell ETH for USDC, buy BTC with USDC and the way back
+        assert_eq!(round_trips.len(), 2);
+        assert_eq!(round_trips[0].start_asset, "BTC");
+        assert_eq!(
+            round_trips[0]
+                .legs
+                .iter()
+                .map(|leg| leg.side)
+                .collect::<Vec<Side>>(),
+            vec![Side::Buy, Side::Sell, Side::Buy]
+        );
+        // the same cycle as the forward round trip of the triangle in order
+        assert_eq!(
+            round_trips[0].profit_bps.round_dp(4),
+            "59.7247".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fees_taken_off_every_leg() {
+        let round_trips = triangle_round_trips(&triangle(), &tops(), Decimal::TEN);
+
+        // 2010 / (30001 * 0.0666) * 0.999 ^ 3
+        assert_eq!(
+            round_trips[0].profit_bps.round_dp(4),
+            "29.5757".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_direction_without_levels_left_out() {
+        let mut tops = tops();
+        tops.insert(Symbol("ETHBTC".into()), (None, level("0.0666", "10")));
+
+        let round_trips = triangle_round_trips(&triangle(), &tops, Decimal::ZERO);
+
+        // only the forward direction buys ETH with BTC at the ask
+        assert_eq!(round_trips.len(), 1);
+        assert_eq!(round_trips[0].legs[0].symbol, Symbol("BTCUSDC".into()));
+    }
+
+    #[test]
+    fn test_direction_through_zero_priced_level_left_out() {
+        let mut tops = tops();
+        tops.insert(
+            Symbol("ETHBTC".into()),
+            (level("0", "10"), level("0.0666", "10")),
+        );
+
+        let round_trips = triangle_round_trips(&triangle(), &tops, Decimal::ZERO);
+
+        // the backward direction sells ETH for nothing at the bid
+        assert_eq!(round_trips.len(), 1);
+        assert_eq!(round_trips[0].legs[0].symbol, Symbol("BTCUSDC".into()));
+    }
+
+    #[test]
+    fn test_fee_taking_everything_leaves_no_round_trip() {
+        let round_trips = triangle_round_trips(&triangle(), &tops(), BASIS_POINTS);
+
+        assert!(round_trips.is_empty());
+    }
+}
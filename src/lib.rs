@@ -4,12 +4,18 @@
   - adapters: contains all the implementations of ports
   - ports: contains interfaces to be used within the service
   - application: contains all application layer code
+  - config: settings of the service read at startup
   - core: contains all pure business logic of domain
+  - lifecycle: shutdown of the service on termination
+  - telemetry: logging and tracing of the service
   - typespec: globally available types
 */
 
 pub mod adapters;
 pub mod application;
+pub mod config;
 mod core;
+pub mod lifecycle;
 pub mod ports;
+pub mod telemetry;
 pub mod typespec;
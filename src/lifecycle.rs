@@ -0,0 +1,224 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+// time the service is given to shut down unless configured otherwise
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ShutdownStep = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/*
+Coordinator of the shutdown of the process. Tasks that need to wind down hold a shutdown
+signal and stop on their own once it fires, like the web server that stops accepting
+connections and sends a close frame to every client websocket. Steps registered with
+on_shutdown run after the service stopped, in the order they were registered, like
+unsubscribing from the markets and flushing the recordings.
+
+The whole shutdown runs within the timeout, the process reports an error when anything
+is still running once it elapsed.
+*/
+pub struct Lifecycle {
+    state: watch::Sender<bool>,
+    timeout: Duration,
+    steps: Vec<(String, ShutdownStep)>,
+}
+
+impl Lifecycle {
+    pub fn new(timeout: Duration) -> Self {
+        // receivers are only handed out with signals so the sender knows when they are all gone
+        let (state, _) = watch::channel(false);
+
+        Self {
+            state,
+            timeout,
+            steps: vec![],
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(Some(self.state.subscribe()))
+    }
+
+    pub fn on_shutdown(
+        &mut self,
+        name: impl Into<String>,
+        step: impl Future<Output = Result<()>> + Send + 'static,
+    ) {
+        self.steps.push((name.into(), Box::pin(step)));
+    }
+
+    pub fn shutdown(&self) {
+        self.state.send_replace(true);
+    }
+
+    // Resolves once the process is asked to terminate, by SIGTERM, ctrl-c or shutdown
+    pub async fn terminated(&self) {
+        let mut signal = self.signal();
+
+        tokio::select! {
+            _ = termination_signal() => {}
+            _ = signal.wait() => {}
+        }
+    }
+
+    /*
+    Runs the service until it ends on its own or the process is asked to terminate, then
+    shuts down. The service is awaited once more so it can wind down on the signal, the
+    steps run after it and every holder of a signal needs to have dropped it before the
+    timeout elapses. The error of the service is returned over the ones of the shutdown.
+    */
+    pub async fn run(self, service: impl Future<Output = Result<()>>) -> Result<()> {
+        tokio::pin!(service);
+
+        let ended = tokio::select! {
+            res = &mut service => Some(res),
+            _ = self.terminated() => None,
+        };
+        info!("shutting down");
+        self.shutdown();
+
+        let Lifecycle {
+            state,
+            timeout,
+            steps,
+        } = self;
+        let shutdown = async {
+            let res = match ended {
+                Some(res) => res,
+                None => service.await,
+            };
+
+            for (name, step) in steps {
+                if let Err(e) = step.await {
+                    error!(step = %name, error = format!("{:#}", e), "shutdown step failed");
+                }
+            }
+            state.closed().await;
+
+            res
+        };
+
+        match tokio::time::timeout(timeout, shutdown).await {
+            Ok(res) => res,
+            Err(_) => Err(anyhow!(
+                "shutdown did not finish within {} seconds",
+                timeout.as_secs_f64()
+            )),
+        }
+    }
+}
+
+/*
+Signal of a lifecycle for tasks to wind down. A task holding it keeps the shutdown
+waiting until it drops it, so tasks wait on a clone of the signal they were given.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSignal(Option<watch::Receiver<bool>>);
+
+impl ShutdownSignal {
+    // signal of a service that is never shut down, like in tests
+    pub fn never() -> Self {
+        Self(None)
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.as_ref().is_some_and(|state| *state.borrow())
+    }
+
+    // Resolves once the shutdown started, never when the lifecycle is gone without one
+    pub async fn wait(&mut self) {
+        if let Some(state) = self.0.as_mut() {
+            if state.wait_for(|triggered| *triggered).await.is_ok() {
+                return;
+            }
+        }
+
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(unix)]
+async fn termination_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    use tracing::warn;
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to listen for SIGTERM, only ctrl-c terminates");
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_shutdown_runs_steps_in_order_and_waits_for_holders_of_the_signal() {
+        let mut lifecycle = Lifecycle::new(Duration::from_secs(5));
+        let log = Arc::new(Mutex::new(vec![]));
+
+        for name in ["markets", "recordings"] {
+            let log = log.clone();
+            lifecycle.on_shutdown(name, async move {
+                log.lock().unwrap().push(name);
+                Ok(())
+            });
+        }
+
+        // stand-in for a client connection that takes a while to close
+        let mut holder = lifecycle.signal();
+        let holder_log = log.clone();
+        tokio::spawn(async move {
+            holder.wait().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            holder_log.lock().unwrap().push("client");
+        });
+
+        // the service ending on its own shuts down the rest as well
+        let service_log = log.clone();
+        let res = lifecycle
+            .run(async move {
+                service_log.lock().unwrap().push("service");
+                Ok(())
+            })
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["service", "markets", "recordings", "client"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_once_the_timeout_elapsed() {
+        let mut lifecycle = Lifecycle::new(Duration::from_millis(50));
+        lifecycle.on_shutdown("recordings", async { Err(anyhow!("disk full")) });
+
+        // never dropped while the lifecycle runs
+        let holder = lifecycle.signal();
+        let res = lifecycle.run(async { Ok(()) }).await;
+
+        assert!(holder.is_triggered());
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("shutdown did not finish within"));
+    }
+}
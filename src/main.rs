@@ -1,9 +1,34 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
 use orderbook_trial_task::{
-    adapters::{BinanceDiffDepthStream, ClientWebServer},
-    application::Application,
-    ports::{MarketStream, WebServer, WebServerSettings},
-    typespec::Symbol,
+    adapters::{
+        BinanceDepthSnapshot, BinanceDiffDepthStream, BinanceTradeStream, ClientWebServer,
+        CoinbaseMarketStream, KrakenMarketStream, MarketStreamRecorder, OkxMarketStream,
+        PrometheusMetrics, RecordedDepthSnapshot, RecorderSettings, ReplayMarketStream,
+        ReplaySpeed, StreamDepthSnapshot,
+    },
+    application::{Application, ApplicationQuery, ApplicationResponse, OrderBookSyncState},
+    config::{Cli, Command, OutputFormat, Settings},
+    lifecycle::{Lifecycle, ShutdownSignal},
+    ports::{
+        DepthSnapshotSource, MarketEvent, MarketMessage, MarketStream, MarketStreamConnection,
+        NoMetrics, SharedMetrics, WebServer,
+    },
+    telemetry::Telemetry,
+    typespec::{CandleInterval, PriceLevel, Symbol},
 };
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{error, info, warn};
+
+// books of every market are synced well within this after connecting
+const SNAPSHOT_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+// events buffered between a market stream and its recorder
+const RECORDING_CHANNEL_CAPACITY: usize = 1024;
+// most candles the application keeps of a symbol and interval
+const MAX_CANDLES: usize = 1000;
 
 #[tokio::main]
 async fn main() {
@@ -24,24 +49,886 @@ async fn main() {
     application layer is already called by web server adapter
     */
 
-    // Market connections only pushes out raw values to be handled by other services
-    // TODO need to find a way to change subscriptions.
-    // this is a temp measure due to binance_spot_market_connector lib constraints
-    let receiver = BinanceDiffDepthStream
-        .subscribe(vec![Symbol("BTCUSDC".into())])
+    // settings are layered from a config file, the environment and the command line
+    let cli = Cli::parse();
+    let settings = match Settings::load(&cli) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let telemetry = match settings
+        .telemetry_settings()
+        .and_then(|t| Telemetry::init(&t))
+    {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let output = cli.output;
+    let res = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(settings).await,
+        Command::Record {
+            directory,
+            duration,
+        } => record(settings, directory, duration.map(Duration::from_secs)).await,
+        Command::Replay { paths, speed } => replay(settings, paths, speed).await,
+        Command::Snapshot { symbol, depth } => {
+            snapshot(settings, output, Symbol::from_pair(&symbol), depth).await
+        }
+        Command::Analyze {
+            paths,
+            symbol,
+            interval,
+            bps,
+        } => {
+            let symbol = symbol.as_deref().map(Symbol::from_pair);
+            analyze(output, paths, symbol, interval, bps).await
+        }
+    };
+
+    if let Err(e) = &res {
+        error!(error = format!("{:#}", e), "stopped");
+    }
+    // spans still buffered are exported before the process exits
+    let _ = tokio::task::spawn_blocking(move || telemetry.shutdown()).await;
+
+    if res.is_err() {
+        std::process::exit(1);
+    }
+}
+
+async fn serve(settings: Settings) -> Result<()> {
+    // on SIGTERM clients are closed first, then the markets and the recording
+    let mut lifecycle = Lifecycle::new(settings.shutdown_timeout());
+    // reported by the markets, the application and the web server, served on /metrics
+    let metrics: SharedMetrics = Arc::new(PrometheusMetrics::new()?);
+
+    // Market connections push typed events to be handled by other services.
+    // Symbols given here stay subscribed, clients can subscribe to more at runtime
+    let symbols = settings.symbols();
+    let market = settings.market.venue.clone();
+
+    // triangles of the market scanned for round trips, validated with the settings
+    let triangles = settings.triangles()?;
+    // the symbols of the triangles are only tracked on the main market
+    let mut market_symbols = symbols.clone();
+    for symbol in triangles.iter().flat_map(|triangle| triangle.symbols()) {
+        if !market_symbols.contains(symbol) {
+            market_symbols.push(symbol.clone());
+        }
+    }
+
+    let connection = connect_market(&settings, &market, market_symbols.clone(), &metrics)
         .await
-        .expect("receiver to be made");
+        .with_context(|| format!("failed to connect to {}", market))?;
 
-    let app_layer = Application {
-        market_stream: receiver,
+    close_on_shutdown(&mut lifecycle, &market, connection.clone());
+
+    // events are recorded for offline replays when a directory is given
+    let recording = match settings.recording_directory() {
+        Some(directory) => Some(
+            record_market(
+                &settings,
+                &market,
+                &connection,
+                &market_symbols,
+                directory.to_path_buf(),
+                &metrics,
+            )
+            .await?,
+        ),
+        None => None,
     };
 
-    let web_server_settings = WebServerSettings {
-        port: "3000".into(),
+    let mut app_layer = Application::new(connection.clone())
+        .with_arbitrage_settings(settings.arbitrage_settings())
+        .with_triangles(triangles)
+        .with_metrics(metrics.clone());
+    if let Some(capacity) = settings.channels.client_updates {
+        app_layer = app_layer.with_update_capacity(capacity);
+    }
+    spawn_order_book_sync(
+        &settings,
+        &market,
+        app_layer.clone(),
+        connection,
+        market_symbols,
+    );
+
+    // books of other markets are consolidated with the ones of the main market,
+    // each market keeps its own connection and order books
+    let venues = settings.consolidated_venues();
+    if !venues.is_empty() {
+        app_layer.register_venue(settings.venue()).await;
+
+        for venue in venues {
+            let connection = connect_market(&settings, &venue.0, symbols.clone(), &metrics)
+                .await
+                .with_context(|| format!("failed to connect to {}", venue.0))?;
+            close_on_shutdown(&mut lifecycle, &venue.0, connection.clone());
+            let venue_app = app_layer.add_venue(venue.clone(), connection.clone()).await;
+            spawn_order_book_sync(&settings, &venue.0, venue_app, connection, symbols.clone());
+        }
+
+        // opportunities between the markets are logged as they open and close
+        for symbol in symbols.iter().cloned() {
+            let arbitrage = app_layer.clone();
+            tokio::spawn(async move {
+                if let Err(e) = arbitrage.log_arbitrage_opportunities(symbol).await {
+                    error!(error = %e, "arbitrage detection stopped");
+                }
+            });
+        }
+    }
+
+    // trades of binance are aggregated into candles of the intervals given
+    let intervals = settings.candle_intervals()?;
+    if !intervals.is_empty() {
+        let trades = match &settings.endpoints.binance_stream {
+            Some(url) => BinanceTradeStream::aggregated_with_base_url(url.as_str()),
+            None => BinanceTradeStream::aggregated(),
+        };
+        let trades = trades
+            .with_event_capacity(settings.channels.market_events)
+            .with_metrics(metrics.clone())
+            .subscribe(symbols.clone())
+            .await
+            .context("failed to connect to the binance trade stream")?;
+        close_on_shutdown(&mut lifecycle, "binance trade", trades.clone());
+
+        let candles = app_layer.clone();
+        let symbols = symbols.clone();
+        tokio::spawn(async move {
+            if let Err(e) = candles
+                .aggregate_candles(trades.receiver, symbols, intervals)
+                .await
+            {
+                error!(error = %e, "candle aggregation stopped");
+            }
+        });
+    }
+
+    // the recording is flushed once no market sends events anymore
+    if let Some(recording) = recording {
+        lifecycle.on_shutdown("the market stream recording", recording.stop());
+    }
+
+    let server = run_web_server(&settings, app_layer, lifecycle.signal(), metrics);
+    lifecycle.run(server).await
+}
+
+// Records the market stream of the symbols until the duration passed or the process is interrupted
+async fn record(
+    settings: Settings,
+    directory: Option<PathBuf>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let directory = directory
+        .or_else(|| settings.recording.directory.clone())
+        .ok_or_else(|| anyhow!("record needs --directory or recording.directory"))?;
+    let market = settings.market.venue.clone();
+    let symbols = settings.symbols();
+
+    // nothing serves the metrics of a recording
+    let metrics = NoMetrics::shared();
+    let connection = connect_market(&settings, &market, symbols.clone(), &metrics)
+        .await
+        .with_context(|| format!("failed to connect to {}", market))?;
+    let recording = record_market(
+        &settings,
+        &market,
+        &connection,
+        &symbols,
+        directory.clone(),
+        &metrics,
+    )
+    .await?;
+    info!(
+        symbols = %symbol_list(&symbols),
+        market,
+        directory = %directory.display(),
+        "recording"
+    );
+
+    let mut lifecycle = Lifecycle::new(settings.shutdown_timeout());
+    close_on_shutdown(&mut lifecycle, &market, connection);
+    lifecycle.on_shutdown("the market stream recording", recording.stop());
+
+    lifecycle
+        .run(async {
+            match duration {
+                Some(duration) => tokio::time::sleep(duration).await,
+                None => std::future::pending().await,
+            }
+            Ok(())
+        })
+        .await?;
+    info!("recording stopped");
+
+    Ok(())
+}
+
+// Serves the order books of recordings as if they came from a live market
+async fn replay(settings: Settings, paths: Vec<PathBuf>, speed: ReplaySpeed) -> Result<()> {
+    let replay = ReplayMarketStream::from_paths(&paths, speed)?;
+    let symbols = replay.recorded_symbols().await?;
+    if symbols.is_empty() {
+        return Err(anyhow!("no symbol has an event in the recordings"));
+    }
+
+    let connection = replay.subscribe(symbols.clone()).await?;
+    let mut lifecycle = Lifecycle::new(settings.shutdown_timeout());
+    close_on_shutdown(&mut lifecycle, "replay", connection.clone());
+    let metrics: SharedMetrics = Arc::new(PrometheusMetrics::new()?);
+    let app_layer = Application::new(connection).with_metrics(metrics.clone());
+    info!(symbols = %symbol_list(&symbols), "replaying");
+
+    let sync = app_layer.clone();
+    tokio::spawn(async move {
+        if let Err(e) = sync
+            .maintain_order_books(RecordedDepthSnapshot, symbols)
+            .await
+        {
+            error!(error = %e, "order book sync of the replay stopped");
+        }
+    });
+
+    let server = run_web_server(&settings, app_layer, lifecycle.signal(), metrics);
+    lifecycle.run(server).await
+}
+
+// Prints the book of a symbol once it is synced with the market
+async fn snapshot(
+    settings: Settings,
+    output: OutputFormat,
+    symbol: Symbol,
+    depth: usize,
+) -> Result<()> {
+    let market = settings.market.venue.clone();
+    let connection = connect_market(
+        &settings,
+        &market,
+        vec![symbol.clone()],
+        &NoMetrics::shared(),
+    )
+    .await
+    .with_context(|| format!("failed to connect to {}", market))?;
+    let app_layer = Application::new(connection.clone());
+    spawn_order_book_sync(
+        &settings,
+        &market,
+        app_layer.clone(),
+        connection,
+        vec![symbol.clone()],
+    );
+
+    let synced_levels = async {
+        loop {
+            let query = ApplicationQuery::GetOrderBookLevels {
+                symbol: symbol.clone(),
+                depth,
+            };
+            match app_layer.handle_query(query).await? {
+                ApplicationResponse::OrderBookLevels {
+                    last_update_id,
+                    bids,
+                    asks,
+                    ..
+                } => return Ok::<_, anyhow::Error>((last_update_id, bids, asks)),
+                _ => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    };
+    let (last_update_id, bids, asks) = tokio::time::timeout(SNAPSHOT_SYNC_TIMEOUT, synced_levels)
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "book of {} not synced within {} seconds",
+                symbol.0,
+                SNAPSHOT_SYNC_TIMEOUT.as_secs()
+            )
+        })??;
+    let metrics = book_metrics(&app_layer, &symbol, None).await?;
+
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "symbol": symbol.0,
+                "venue": market,
+                "last_update_id": last_update_id,
+                "bids": levels_json(&bids),
+                "asks": levels_json(&asks),
+                "metrics": metrics.to_json(),
+            })
+        ),
+        OutputFormat::Human => {
+            println!(
+                "{} on {}, last update id {}",
+                symbol.0, market, last_update_id
+            );
+            println!("{:>6} {:>20} {:>20}", "side", "price", "quantity");
+            for (price, quantity) in asks.iter().rev() {
+                println!("{:>6} {:>20} {:>20}", "ask", price, quantity);
+            }
+            for (price, quantity) in bids.iter() {
+                println!("{:>6} {:>20} {:>20}", "bid", price, quantity);
+            }
+            metrics.print();
+        }
+    }
+
+    Ok(())
+}
+
+/*
+Replays recordings as fast as possible through the same order book sync and candle
+aggregation as the live service and reports the books and candles once the replay ended.
+The replay channel holds every event of the recordings so neither consumer lags behind.
+*/
+async fn analyze(
+    output: OutputFormat,
+    paths: Vec<PathBuf>,
+    symbol: Option<Symbol>,
+    interval: CandleInterval,
+    bps: Decimal,
+) -> Result<()> {
+    let replay = ReplayMarketStream::from_paths(&paths, ReplaySpeed::AsFastAsPossible)?;
+    let events = replay.recorded_events().await?;
+    let (first, last) = match (events.first(), events.last()) {
+        (Some(first), Some(last)) => (first.received_at, last.received_at),
+        _ => return Err(anyhow!("no events in the recordings")),
     };
 
-    println!("starting server on localhost:{}", web_server_settings.port);
-    let _ = ClientWebServer::new(web_server_settings, app_layer.clone())
+    let mut counts: BTreeMap<Symbol, EventCounts> = BTreeMap::new();
+    let mut other_events = 0;
+    for recorded in events.iter() {
+        let (event_symbol, kind) = match &recorded.event {
+            MarketEvent::DepthUpdate(update) => (&update.symbol, "depth_updates"),
+            MarketEvent::BookSnapshot { symbol, .. } => (symbol, "snapshots"),
+            MarketEvent::Trade(trade) => (&trade.symbol, "trades"),
+            MarketEvent::BookTicker(ticker) => (&ticker.symbol, "book_tickers"),
+            _ => {
+                other_events += 1;
+                continue;
+            }
+        };
+        if symbol.as_ref().is_some_and(|symbol| symbol != event_symbol) {
+            continue;
+        }
+        *counts
+            .entry(event_symbol.clone())
+            .or_default()
+            .0
+            .entry(kind)
+            .or_default() += 1;
+    }
+    let symbols: Vec<Symbol> = counts.keys().cloned().collect();
+    if symbols.is_empty() {
+        return Err(anyhow!("no events of the symbol in the recordings"));
+    }
+
+    let connection = replay
+        .with_event_capacity(events.len() + 1)
+        .with_consumers(2)
+        .closing_at_end()
+        .subscribe(symbols.clone())
+        .await?;
+    let app_layer = Application::new(connection.clone());
+    for symbol in symbols.iter() {
+        app_layer
+            .handle_query(ApplicationQuery::SubscribeToSymbol(symbol.clone()))
+            .await?;
+    }
+
+    // both end once the replay closed the stream
+    let books = app_layer.clone();
+    let books = tokio::spawn(async move {
+        books
+            .maintain_order_books(RecordedDepthSnapshot, vec![])
+            .await
+    });
+    let candles = app_layer.clone();
+    let candle_symbols = symbols.clone();
+    let candles = tokio::spawn(async move {
+        candles
+            .aggregate_candles(connection.receiver, candle_symbols, vec![interval])
+            .await
+    });
+    let _ = books.await;
+    let _ = candles.await;
+
+    let mut reports = vec![];
+    for symbol in symbols.iter() {
+        let metrics = book_metrics(&app_layer, symbol, Some(bps)).await?;
+        let candles = match app_layer
+            .handle_query(ApplicationQuery::GetCandles {
+                symbol: symbol.clone(),
+                interval,
+                limit: MAX_CANDLES,
+            })
+            .await?
+        {
+            ApplicationResponse::Candles { candles, .. } => candles
+                .iter()
+                .map(|candle| {
+                    json!({
+                        "open_time": candle.open_time,
+                        "open": candle.open.to_string(),
+                        "high": candle.high.to_string(),
+                        "low": candle.low.to_string(),
+                        "close": candle.close.to_string(),
+                        "volume": candle.volume.to_string(),
+                        "vwap": candle.vwap.to_string(),
+                        "trades": candle.trades,
+                    })
+                })
+                .collect(),
+            _ => vec![],
+        };
+        reports.push((symbol, metrics, candles));
+    }
+
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "files": paths,
+                "events": events.len(),
+                "other_events": other_events,
+                "first_received_at": first,
+                "last_received_at": last,
+                "interval": interval.to_string(),
+                "symbols": reports.iter().map(|(symbol, metrics, candles)| json!({
+                    "symbol": symbol.0,
+                    "events": counts[*symbol].0,
+                    "metrics": metrics.to_json(),
+                    "candles": candles,
+                })).collect::<Vec<_>>(),
+            })
+        ),
+        OutputFormat::Human => {
+            println!(
+                "{} events over {:.1} seconds, {} without a symbol",
+                events.len(),
+                (last - first) as f64 / 1000.0,
+                other_events
+            );
+            for (symbol, metrics, candles) in reports.iter() {
+                println!();
+                println!("{}", symbol.0);
+                for (kind, count) in counts[*symbol].0.iter() {
+                    println!("  {:<16} {}", kind, count);
+                }
+                metrics.print();
+                println!("  {} candles of {}", candles.len(), interval);
+                println!(
+                    "  {:>14} {:>14} {:>14} {:>14} {:>14} {:>14} {:>8}",
+                    "open_time", "open", "high", "low", "close", "volume", "trades"
+                );
+                for candle in candles.iter() {
+                    println!(
+                        "  {:>14} {:>14} {:>14} {:>14} {:>14} {:>14} {:>8}",
+                        candle["open_time"].to_string(),
+                        text_of(&candle["open"]),
+                        text_of(&candle["high"]),
+                        text_of(&candle["low"]),
+                        text_of(&candle["close"]),
+                        text_of(&candle["volume"]),
+                        candle["trades"].to_string()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// number of events of each kind of a symbol
+#[derive(Default)]
+struct EventCounts(BTreeMap<&'static str, u64>);
+
+// Metrics of a book the snapshot and analyze commands report, None when the book has none
+struct BookMetrics {
+    state: OrderBookSyncState,
+    gaps_detected: u64,
+    resyncs: u64,
+    best_bid: Option<PriceLevel>,
+    best_ask: Option<PriceLevel>,
+    mid: Option<String>,
+    spread: Option<String>,
+    spread_bps: Option<String>,
+    average: Option<String>,
+    // basis points and the quantity of each side within them
+    depth: Option<(Decimal, Option<String>, Option<String>)>,
+}
+
+impl BookMetrics {
+    fn to_json(&self) -> Value {
+        let level = |level: &Option<PriceLevel>| {
+            level
+                .as_ref()
+                .map(|(price, quantity)| json!([price.to_string(), quantity.to_string()]))
+        };
+
+        let mut metrics = json!({
+            "state": sync_state_name(self.state),
+            "gaps_detected": self.gaps_detected,
+            "resyncs": self.resyncs,
+            "best_bid": level(&self.best_bid),
+            "best_ask": level(&self.best_ask),
+            "mid": self.mid,
+            "spread": self.spread,
+            "spread_bps": self.spread_bps,
+            "average": self.average,
+        });
+        if let Some((bps, bids, asks)) = &self.depth {
+            metrics["depth"] = json!({"bps": bps.to_string(), "bids": bids, "asks": asks});
+        }
+
+        metrics
+    }
+
+    fn print(&self) {
+        let level = |level: &Option<PriceLevel>| match level {
+            Some((price, quantity)) => format!("{} x {}", price, quantity),
+            None => "-".into(),
+        };
+
+        println!(
+            "  book {}, {} gaps, {} resyncs",
+            sync_state_name(self.state),
+            self.gaps_detected,
+            self.resyncs
+        );
+        println!("  best bid    {}", level(&self.best_bid));
+        println!("  best ask    {}", level(&self.best_ask));
+        println!("  mid         {}", text(&self.mid));
+        println!(
+            "  spread      {} ({} bps)",
+            text(&self.spread),
+            text(&self.spread_bps)
+        );
+        println!("  average     {}", text(&self.average));
+        if let Some((bps, bids, asks)) = &self.depth {
+            println!(
+                "  depth {} bps  bids {}, asks {}",
+                bps,
+                text(bids),
+                text(asks)
+            );
+        }
+    }
+}
+
+async fn book_metrics(
+    app_layer: &Application,
+    symbol: &Symbol,
+    depth_bps: Option<Decimal>,
+) -> Result<BookMetrics> {
+    let query = |query| app_layer.handle_query(query);
+    let mut metrics = BookMetrics {
+        state: OrderBookSyncState::OutOfSync,
+        gaps_detected: 0,
+        resyncs: 0,
+        best_bid: None,
+        best_ask: None,
+        mid: None,
+        spread: None,
+        spread_bps: None,
+        average: None,
+        depth: None,
+    };
+
+    if let ApplicationResponse::OrderBookSyncStatus {
+        state,
+        gaps_detected,
+        resyncs,
+        ..
+    } = query(ApplicationQuery::GetOrderBookSyncStatus(symbol.clone())).await?
+    {
+        metrics.state = state;
+        metrics.gaps_detected = gaps_detected;
+        metrics.resyncs = resyncs;
+    }
+    if let ApplicationResponse::BestBidAsk { bid, ask, .. } =
+        query(ApplicationQuery::GetBestBidAsk(symbol.clone())).await?
+    {
+        metrics.best_bid = bid;
+        metrics.best_ask = ask;
+    }
+    if let ApplicationResponse::MidPrice { price, .. } =
+        query(ApplicationQuery::GetMidPrice(symbol.clone())).await?
+    {
+        metrics.mid = price.map(|price| price.to_string());
+    }
+    if let ApplicationResponse::Spread {
+        absolute,
+        relative_bps,
+        ..
+    } = query(ApplicationQuery::GetSpread(symbol.clone())).await?
+    {
+        metrics.spread = absolute.map(|spread| spread.to_string());
+        metrics.spread_bps = relative_bps.map(|bps| bps.round_dp(4).to_string());
+    }
+    if let ApplicationResponse::CurrentAveragePriceForSymbol { price, .. } =
+        query(ApplicationQuery::GetCurrentAveragePrice(symbol.clone())).await?
+    {
+        metrics.average = price.map(|price| price.to_string());
+    }
+    if let Some(bps) = depth_bps {
+        if let ApplicationResponse::DepthWithinBps { bids, asks, .. } =
+            query(ApplicationQuery::GetDepthWithinBps {
+                symbol: symbol.clone(),
+                bps,
+            })
+            .await?
+        {
+            metrics.depth = Some((
+                bps,
+                bids.map(|quantity| quantity.to_string()),
+                asks.map(|quantity| quantity.to_string()),
+            ));
+        }
+    }
+
+    Ok(metrics)
+}
+
+fn sync_state_name(state: OrderBookSyncState) -> &'static str {
+    match state {
+        OrderBookSyncState::Synced => "synced",
+        OrderBookSyncState::OutOfSync => "out_of_sync",
+    }
+}
+
+fn levels_json(levels: &[PriceLevel]) -> Value {
+    levels
+        .iter()
+        .map(|(price, quantity)| json!([price.to_string(), quantity.to_string()]))
+        .collect()
+}
+
+fn text<T: Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".into(),
+    }
+}
+
+fn text_of(value: &Value) -> &str {
+    value.as_str().unwrap_or("-")
+}
+
+fn symbol_list(symbols: &[Symbol]) -> String {
+    symbols
+        .iter()
+        .map(|symbol| symbol.0.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+async fn run_web_server(
+    settings: &Settings,
+    app_layer: Application,
+    shutdown: ShutdownSignal,
+    metrics: SharedMetrics,
+) -> Result<()> {
+    let web_server_settings = settings.web_server_settings();
+
+    info!(
+        bind_address = %web_server_settings.bind_address,
+        port = web_server_settings.port,
+        "starting server"
+    );
+    ClientWebServer::new(web_server_settings, app_layer)
+        .with_shutdown(shutdown)
+        .with_metrics(metrics)
         .run_server()
-        .await;
+        .await
+}
+
+// Recorder of a market stream together with the task feeding it, stopping the task
+// closes the stream of the recorder so it finishes its last file
+// Unsubscribes the symbols of a market connection and closes it once the service shut down
+fn close_on_shutdown(lifecycle: &mut Lifecycle, name: &str, connection: MarketStreamConnection) {
+    lifecycle.on_shutdown(format!("the {} connection", name), async move {
+        connection.close().await
+    });
+}
+
+struct Recording {
+    forwarder: JoinHandle<()>,
+    recorder: JoinHandle<Result<()>>,
+}
+
+impl Recording {
+    async fn stop(self) -> Result<()> {
+        self.forwarder.abort();
+
+        self.recorder.await?
+    }
+}
+
+/*
+Records the events of a market stream into the directory. Binance books are bootstrapped
+from a REST snapshot that is not on the stream, so a snapshot of each symbol is recorded
+before the events for replays to start their books from. Events received while the
+snapshots are fetched are recorded after them and are stale or continue the snapshots.
+*/
+async fn record_market(
+    settings: &Settings,
+    market: &str,
+    connection: &MarketStreamConnection,
+    symbols: &[Symbol],
+    directory: PathBuf,
+    metrics: &SharedMetrics,
+) -> Result<Recording> {
+    let mut events = connection.receiver.resubscribe();
+    let (sender, receiver) = broadcast::channel::<Arc<MarketMessage>>(RECORDING_CHANNEL_CAPACITY);
+
+    let recorder = MarketStreamRecorder::new(RecorderSettings {
+        directory,
+        compress: settings.recording.compress,
+        max_file_bytes: Some(64 * 1024 * 1024),
+        max_file_age: Some(Duration::from_secs(60 * 60)),
+    })
+    .record(Arc::new(receiver));
+
+    let mut snapshots = vec![];
+    if market == "binance" {
+        let snapshot_source = binance_depth_snapshot(settings);
+        for symbol in symbols {
+            snapshots.push(MarketEvent::BookSnapshot {
+                symbol: symbol.clone(),
+                snapshot: snapshot_source.fetch_snapshot(symbol).await?,
+            });
+        }
+    }
+
+    let metrics = metrics.clone();
+    let forwarder = tokio::spawn(async move {
+        for snapshot in snapshots {
+            let _ = sender.send(Arc::new(snapshot.into()));
+        }
+
+        loop {
+            match events.recv().await {
+                Ok(message) => {
+                    let _ = sender.send(message);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "market stream recording lagged behind");
+                    metrics.messages_lagged("recording", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    Ok(Recording {
+        forwarder,
+        recorder,
+    })
+}
+
+// adapters connect to the endpoints of the settings, or the public ones of the market
+async fn connect_market(
+    settings: &Settings,
+    market: &str,
+    symbols: Vec<Symbol>,
+    metrics: &SharedMetrics,
+) -> Result<MarketStreamConnection> {
+    let endpoints = &settings.endpoints;
+    let capacity = settings.channels.market_events;
+
+    match market {
+        "binance" => {
+            let stream = match &endpoints.binance_stream {
+                Some(url) => BinanceDiffDepthStream::with_base_url(url.as_str()),
+                None => BinanceDiffDepthStream::new(),
+            };
+            stream
+                .with_update_speed(settings.depth_update_speed()?)
+                .with_event_capacity(capacity)
+                .with_metrics(metrics.clone())
+                .subscribe(symbols)
+                .await
+        }
+        "coinbase" => {
+            let stream = match &endpoints.coinbase {
+                Some(url) => CoinbaseMarketStream::with_url(url.as_str()),
+                None => CoinbaseMarketStream::new(),
+            };
+            stream
+                .with_event_capacity(capacity)
+                .with_metrics(metrics.clone())
+                .subscribe(symbols)
+                .await
+        }
+        "kraken" => {
+            let stream = match &endpoints.kraken {
+                Some(url) => KrakenMarketStream::with_url(url.as_str()),
+                None => KrakenMarketStream::new(),
+            };
+            stream
+                .with_event_capacity(capacity)
+                .with_metrics(metrics.clone())
+                .subscribe(symbols)
+                .await
+        }
+        "okx" => {
+            let stream = match &endpoints.okx {
+                Some(url) => OkxMarketStream::with_url(url.as_str()),
+                None => OkxMarketStream::new(),
+            };
+            stream
+                .with_event_capacity(capacity)
+                .with_metrics(metrics.clone())
+                .subscribe(symbols)
+                .await
+        }
+        other => Err(anyhow!("unknown market {}", other)),
+    }
+}
+
+fn binance_depth_snapshot(settings: &Settings) -> BinanceDepthSnapshot {
+    match &settings.endpoints.binance_rest {
+        Some(url) => BinanceDepthSnapshot::with_base_url(url.as_str()),
+        None => BinanceDepthSnapshot::new(),
+    }
+}
+
+// order books are kept in sync in another task so queries read from the latest book.
+// only binance has a REST api for snapshots, other markets send them on the stream
+fn spawn_order_book_sync(
+    settings: &Settings,
+    market: &str,
+    app_layer: Application,
+    connection: MarketStreamConnection,
+    symbols: Vec<Symbol>,
+) {
+    let market = market.to_string();
+    let snapshots = binance_depth_snapshot(settings);
+
+    tokio::spawn(async move {
+        let sync = match market.as_str() {
+            "binance" => app_layer.maintain_order_books(snapshots, symbols).await,
+            _ => {
+                app_layer
+                    .maintain_order_books(StreamDepthSnapshot::new(connection), symbols)
+                    .await
+            }
+        };
+        if let Err(e) = sync {
+            error!(market, error = %e, "order book sync stopped");
+        }
+    });
 }
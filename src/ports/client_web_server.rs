@@ -3,11 +3,37 @@
 // to pass an instantiated application struct that is holding state and is called within as middleware
 // to run the actual server
 use anyhow::Result;
+use std::path::PathBuf;
 
 use crate::typespec::ApplicationLayer;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct WebServerSettings {
-    pub port: String,
+    // host name or ip address the server listens on
+    pub bind_address: String,
+    pub port: u16,
+    // directory of the built frontend served next to the api
+    pub static_dir: PathBuf,
+}
+
+impl Default for WebServerSettings {
+    // the container image serves the frontend from /etc/www and has to be reachable from
+    // outside of the container
+    fn default() -> Self {
+        if cfg!(feature = "prod") {
+            Self {
+                bind_address: "0.0.0.0".into(),
+                port: 3000,
+                static_dir: "/etc/www/dist".into(),
+            }
+        } else {
+            Self {
+                bind_address: "localhost".into(),
+                port: 3000,
+                static_dir: "frontend/svelte-client/dist".into(),
+            }
+        }
+    }
 }
 
 pub trait WebServer {
@@ -0,0 +1,12 @@
+use crate::typespec::{DepthSnapshot, Symbol};
+use anyhow::Result;
+use std::future::Future;
+
+/// Trait is used for fetching the full depth of a symbol's book from a crypto market api
+pub trait DepthSnapshotSource {
+    // snapshots are used to bootstrap local order books that are then kept
+    // up to date by the diff depth updates of a market stream. The fetch is
+    // Send so it can be spawned off the task maintaining the books
+    fn fetch_snapshot(&self, symbol: &Symbol)
+        -> impl Future<Output = Result<DepthSnapshot>> + Send;
+}
@@ -1,10 +1,207 @@
-use crate::typespec::Symbol;
-use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::broadcast::{Receiver, Sender};
+use crate::typespec::{BookTicker, DepthSnapshot, DepthUpdate, Symbol, Trade};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{
+    broadcast::{Receiver, Sender},
+    mpsc, oneshot,
+};
+use tracing::{field, info_span, Span};
 
-pub type MarketStreamMessageBroadcastSender = Sender<Arc<String>>;
-pub type MarketStreamMessageBroadcastReceiver = Arc<Receiver<Arc<String>>>;
+// events a market stream buffers for each consumer unless configured otherwise, consumers
+// falling further behind lose events
+pub const DEFAULT_EVENT_CAPACITY: usize = 16;
+
+pub type MarketStreamMessageBroadcastSender = Sender<Arc<MarketMessage>>;
+pub type MarketStreamMessageBroadcastReceiver = Arc<Receiver<Arc<MarketMessage>>>;
+pub type MarketStreamCommandSender = mpsc::UnboundedSender<SubscriptionCommand>;
+pub type MarketStreamCommandReceiver = mpsc::UnboundedReceiver<SubscriptionCommand>;
+
+/*
+Events of a market stream. Adapters transform the messages of their market api into
+these once so every consumer of the broadcast channel works on typed data no matter
+which market the messages came from.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum MarketEvent {
+    DepthUpdate(DepthUpdate),
+    // full depth of a book, sent by markets that bootstrap books on the stream itself
+    BookSnapshot {
+        symbol: Symbol,
+        snapshot: DepthSnapshot,
+    },
+    Trade(Trade),
+    BookTicker(BookTicker),
+    // market api accepted a subscription request
+    SubscriptionAck {
+        id: u64,
+    },
+    // market api rejected a request
+    Error {
+        code: i64,
+        msg: String,
+    },
+    // market api checked that the connection is still alive
+    Heartbeat,
+    // adapter lost or restored its connection to the market api
+    ConnectionState(ConnectionState),
+}
+
+impl MarketEvent {
+    // symbol of the book or trade the event is about
+    pub fn symbol(&self) -> Option<&Symbol> {
+        match self {
+            MarketEvent::DepthUpdate(update) => Some(&update.symbol),
+            MarketEvent::BookSnapshot { symbol, .. } => Some(symbol),
+            MarketEvent::Trade(trade) => Some(&trade.symbol),
+            MarketEvent::BookTicker(ticker) => Some(&ticker.symbol),
+            _ => None,
+        }
+    }
+}
+
+/*
+Event of a market stream as it is broadcast to the consumers, together with the span of
+the market message it was parsed from. Consumers handle the event in spans of their own
+under it, so a message can be followed from the adapter through the application to the
+clients it is delivered to. The span stays open as long as the event is held.
+*/
+#[derive(Debug)]
+pub struct MarketMessage {
+    pub event: MarketEvent,
+    pub span: Span,
+}
+
+impl MarketMessage {
+    pub fn new(event: MarketEvent, span: Span) -> Self {
+        Self { event, span }
+    }
+
+    // Span of a message received from a market api, the message is parsed within it
+    pub fn received(venue: &str, stream: &str) -> Span {
+        info_span!("market_message", venue, stream, symbol = field::Empty)
+    }
+
+    // Event parsed from the message of the span, the symbol of the event is recorded on it
+    pub fn parsed(event: MarketEvent, span: &Span) -> Self {
+        if let Some(symbol) = event.symbol() {
+            span.record("symbol", symbol.0.as_str());
+        }
+
+        Self::new(event, span.clone())
+    }
+}
+
+/*
+Ids of a span that may have closed already, so work done later on what happened in it can
+be traced in spans linked to it. The default is the empty context of no span, links to it
+are left out. Telemetry converts it from and to the context of the tracing backend.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    sampled: bool,
+}
+
+impl TraceContext {
+    pub fn new(trace_id: u128, span_id: u64, sampled: bool) -> Self {
+        Self {
+            trace_id,
+            span_id,
+            sampled,
+        }
+    }
+
+    pub fn trace_id(&self) -> u128 {
+        self.trace_id
+    }
+
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+}
+
+// events not parsed from a message of the market api, like changes of the connection state
+impl From<MarketEvent> for MarketMessage {
+    fn from(event: MarketEvent) -> Self {
+        Self::new(event, Span::none())
+    }
+}
+
+// State of the connection between an adapter and its market api. Events sent while the
+// connection is down are lost so consumers need to rebuild their state once it is back
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,
+    Disconnected { reason: String },
+    // waiting the given delay before the next attempt to connect
+    Reconnecting { attempt: u32, delay: Duration },
+}
+
+// Requests to change the subscriptions of a live connection. The adapter answers
+// through the oneshot sender once the request is sent to the market api or rejected.
+// While the connection is down the symbols are taken up for the next connection and
+// the request is answered right away
+pub enum SubscriptionCommand {
+    Subscribe(Vec<Symbol>, oneshot::Sender<Result<()>>),
+    Unsubscribe(Vec<Symbol>, oneshot::Sender<Result<()>>),
+    // unsubscribes every symbol, closes the connection to the market api and stops the
+    // adapter so receivers see the stream closed
+    Close(oneshot::Sender<Result<()>>),
+}
+
+/*
+Handle of a live market stream connection. The receiver gets the events of every
+subscribed symbol and symbols can be added or removed without reconnecting.
+*/
+#[derive(Clone)]
+pub struct MarketStreamConnection {
+    pub receiver: MarketStreamMessageBroadcastReceiver,
+    commands: MarketStreamCommandSender,
+}
+
+impl MarketStreamConnection {
+    pub fn new(
+        receiver: MarketStreamMessageBroadcastReceiver,
+        commands: MarketStreamCommandSender,
+    ) -> Self {
+        Self { receiver, commands }
+    }
+
+    pub async fn add_symbols(&self, symbols: Vec<Symbol>) -> Result<()> {
+        let (respond, response) = oneshot::channel();
+        self.send_command(SubscriptionCommand::Subscribe(symbols, respond))?;
+
+        response.await?
+    }
+
+    pub async fn remove_symbols(&self, symbols: Vec<Symbol>) -> Result<()> {
+        let (respond, response) = oneshot::channel();
+        self.send_command(SubscriptionCommand::Unsubscribe(symbols, respond))?;
+
+        response.await?
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        let (respond, response) = oneshot::channel();
+        self.send_command(SubscriptionCommand::Close(respond))?;
+
+        response.await?
+    }
+
+    fn send_command(&self, command: SubscriptionCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("market stream connection closed"))
+    }
+}
 
 /// Trait is used for implementing connection to a specific stream of a crypto market  api
 pub trait MarketStream {
@@ -13,6 +210,5 @@ pub trait MarketStream {
     broadcast::Sender is used for all processes listen to the
     process to receive the same values at once
     */
-    async fn subscribe(&self, symbols: Vec<Symbol>)
-        -> Result<MarketStreamMessageBroadcastReceiver>;
+    async fn subscribe(&self, symbols: Vec<Symbol>) -> Result<MarketStreamConnection>;
 }
@@ -0,0 +1,53 @@
+use crate::typespec::Symbol;
+use anyhow::Result;
+use std::{sync::Arc, time::Duration};
+
+pub type SharedMetrics = Arc<dyn Metrics>;
+
+/// Trait is used for reporting what happens inside the service to a monitoring system
+pub trait Metrics: Send + Sync {
+    // a message of a market stream arrived, stream is the kind of stream like depth or trade
+    fn message_received(&self, venue: &str, stream: &str);
+    fn message_parse_failed(&self, venue: &str, stream: &str);
+    // a consumer of a market stream lost messages by falling behind
+    fn messages_lagged(&self, consumer: &str, skipped: u64);
+    // a lost connection to a market api is made again
+    fn reconnected(&self, venue: &str, stream: &str);
+    // an order book that missed updates is bootstrapped again from a new snapshot
+    fn book_resynced(&self, symbol: &Symbol);
+    // endpoint is the path of the websocket or event stream the client connected to
+    fn client_connected(&self, endpoint: &str);
+    fn client_disconnected(&self, endpoint: &str);
+    // query is the name of the ApplicationQuery variant
+    fn query_answered(&self, query: &str, elapsed: Duration);
+    // time from the event time of the market message that last changed a book until an
+    // update of the book is sent to a client
+    fn update_delivered(&self, channel: &str, latency: Duration);
+    // text exposition format of the metrics, served to the scraper of the monitoring system
+    fn render(&self) -> Result<String>;
+}
+
+// Discards every report, used by adapters and the application unless given metrics
+pub struct NoMetrics;
+
+impl NoMetrics {
+    pub fn shared() -> SharedMetrics {
+        Arc::new(NoMetrics)
+    }
+}
+
+impl Metrics for NoMetrics {
+    fn message_received(&self, _venue: &str, _stream: &str) {}
+    fn message_parse_failed(&self, _venue: &str, _stream: &str) {}
+    fn messages_lagged(&self, _consumer: &str, _skipped: u64) {}
+    fn reconnected(&self, _venue: &str, _stream: &str) {}
+    fn book_resynced(&self, _symbol: &Symbol) {}
+    fn client_connected(&self, _endpoint: &str) {}
+    fn client_disconnected(&self, _endpoint: &str) {}
+    fn query_answered(&self, _query: &str, _elapsed: Duration) {}
+    fn update_delivered(&self, _channel: &str, _latency: Duration) {}
+
+    fn render(&self) -> Result<String> {
+        Ok(String::new())
+    }
+}
@@ -1,8 +1,12 @@
 mod client_web_server;
+mod depth_snapshot;
 mod market_stream;
+mod metrics;
 
 pub use client_web_server::{WebServer, WebServerSettings};
+pub use depth_snapshot::DepthSnapshotSource;
 pub use market_stream::*;
+pub use metrics::{Metrics, NoMetrics, SharedMetrics};
 
 /*
 Ports are used as an internal API in the application layer to decouple implmentations from the
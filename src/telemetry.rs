@@ -0,0 +1,340 @@
+use std::{io::IsTerminal, str::FromStr};
+
+use crate::ports::TraceContext;
+use anyhow::{anyhow, Context, Result};
+use opentelemetry::trace::{
+    SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider,
+};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Layer, Registry};
+
+// name the spans of the service are exported under
+const SERVICE_NAME: &str = "orderbook";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    // a line of text for each event, for people reading a terminal
+    #[default]
+    Pretty,
+    // a JSON object for each event with the fields of the spans it happened in, for collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(anyhow!("{} is not pretty or json", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TelemetrySettings {
+    pub format: LogFormat,
+    // directives of what is logged, like info or info,orderbook_trial_task::adapters=debug
+    pub level: String,
+    // spans are exported to an OpenTelemetry collector over OTLP/HTTP when given
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            level: "info".into(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+/*
+Logging and tracing of the process. Events are written to stderr, leveled and with the
+fields of the spans they happened in, so a market message can be followed from the adapter
+that received it through the application until its update reached the clients. The same
+spans are exported to an OpenTelemetry collector when an endpoint is configured.
+
+Spans are exported in batches from a thread of their own, shutdown exports the ones still
+buffered and blocks until the collector answered.
+*/
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    // sets up the logging of the whole process, fails when it was set up before
+    pub fn init(settings: &TelemetrySettings) -> Result<Self> {
+        let (subscriber, telemetry) = Self::subscriber(settings, std::io::stderr)?;
+        tracing::subscriber::set_global_default(subscriber).context("logging is already set up")?;
+
+        Ok(telemetry)
+    }
+
+    fn subscriber<W>(
+        settings: &TelemetrySettings,
+        writer: W,
+    ) -> Result<(impl Subscriber + Send + Sync, Self)>
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let filter = EnvFilter::try_new(&settings.level)
+            .with_context(|| format!("logging.level {} is not valid", settings.level))?;
+
+        let events = match settings.format {
+            LogFormat::Pretty => tracing_subscriber::fmt::layer()
+                .with_ansi(std::io::stderr().is_terminal())
+                .with_writer(writer)
+                .boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(writer)
+                .boxed(),
+        };
+
+        let provider = match &settings.otlp_endpoint {
+            Some(endpoint) => Some(tracer_provider(endpoint)?),
+            None => None,
+        };
+        let spans = provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+        });
+
+        let subscriber = Registry::default().with(filter).with(events).with(spans);
+
+        Ok((subscriber, Self { provider }))
+    }
+
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            // logging is winding down as well so the failure goes to stderr directly
+            if let Err(e) = provider.shutdown() {
+                eprintln!("spans could not be exported: {}", e);
+            }
+        }
+    }
+}
+
+fn tracer_provider(endpoint: &str) -> Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(endpoint)
+        .build()
+        .with_context(|| format!("spans can not be exported to {}", endpoint))?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build())
+}
+
+// context of a span as it is exported, empty when spans are not exported
+impl From<&Span> for TraceContext {
+    fn from(span: &Span) -> Self {
+        let context = span.context();
+        let span_context = context.span().span_context().clone();
+
+        TraceContext::new(
+            u128::from_be_bytes(span_context.trace_id().to_bytes()),
+            u64::from_be_bytes(span_context.span_id().to_bytes()),
+            span_context.is_sampled(),
+        )
+    }
+}
+
+impl From<TraceContext> for SpanContext {
+    fn from(context: TraceContext) -> Self {
+        let flags = match context.is_sampled() {
+            true => TraceFlags::SAMPLED,
+            false => TraceFlags::default(),
+        };
+
+        SpanContext::new(
+            TraceId::from_bytes(context.trace_id().to_be_bytes()),
+            SpanId::from_bytes(context.span_id().to_be_bytes()),
+            flags,
+            false,
+            TraceState::default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ports::{MarketEvent, MarketMessage},
+        typespec::{DepthUpdate, Symbol},
+    };
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{mpsc, Arc, Mutex},
+        time::Duration,
+    };
+    use tracing::warn;
+
+    // writer of the events of a test subscriber, kept for the assertions
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn depth_update() -> MarketEvent {
+        MarketEvent::DepthUpdate(DepthUpdate {
+            symbol: Symbol("BTCUSDC".into()),
+            event_time: 1,
+            first_update_id: 1,
+            final_update_id: 2,
+            bids: vec![],
+            asks: vec![],
+        })
+    }
+
+    /*
+    Stand-in for an OpenTelemetry collector, answers a single OTLP/HTTP request and hands
+    its body over. Runs on a thread of its own since the exporter blocks on the request.
+    */
+    fn collector() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}")
+                .unwrap();
+            let _ = sender.send(String::from_utf8(body).unwrap());
+        });
+
+        (endpoint, receiver)
+    }
+
+    #[test]
+    fn test_json_events_carry_the_fields_of_their_spans() {
+        let buffer = Buffer::default();
+        let settings = TelemetrySettings {
+            format: LogFormat::Json,
+            ..Default::default()
+        };
+        let writer = buffer.clone();
+        let (subscriber, _telemetry) =
+            Telemetry::subscriber(&settings, move || writer.clone()).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = MarketMessage::received("binance", "depth");
+            let message = MarketMessage::parsed(depth_update(), &span);
+            message
+                .span
+                .in_scope(|| warn!(gap = 3, "order book out of sync"));
+            // below the level of the settings
+            tracing::debug!("left out");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[0]["fields"]["message"], "order book out of sync");
+        assert_eq!(lines[0]["fields"]["gap"], 3);
+        assert_eq!(lines[0]["span"]["name"], "market_message");
+        assert_eq!(lines[0]["span"]["venue"], "binance");
+        assert_eq!(lines[0]["span"]["symbol"], "BTCUSDC");
+        assert_eq!(lines[0]["spans"][0]["stream"], "depth");
+    }
+
+    #[test]
+    fn test_trace_context_of_exported_span_links_back_to_it() {
+        let (endpoint, _bodies) = collector();
+        let settings = TelemetrySettings {
+            otlp_endpoint: Some(endpoint),
+            ..Default::default()
+        };
+        let (subscriber, _telemetry) = Telemetry::subscriber(&settings, std::io::sink).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = MarketMessage::received("binance", "depth");
+
+            let context = TraceContext::from(&span);
+
+            assert_ne!(context, TraceContext::default());
+            assert_eq!(
+                SpanContext::from(context),
+                span.context().span().span_context().clone()
+            );
+        });
+    }
+
+    #[test]
+    fn test_trace_context_of_span_not_exported_is_empty() {
+        let span = MarketMessage::received("binance", "depth");
+
+        let context = TraceContext::from(&span);
+
+        assert_eq!(context, TraceContext::default());
+        assert!(!SpanContext::from(context).is_valid());
+    }
+
+    #[test]
+    fn test_spans_exported_to_the_collector() {
+        let (endpoint, bodies) = collector();
+        let settings = TelemetrySettings {
+            otlp_endpoint: Some(endpoint),
+            ..Default::default()
+        };
+        let (subscriber, telemetry) = Telemetry::subscriber(&settings, std::io::sink).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = MarketMessage::received("binance", "depth");
+            let message = MarketMessage::parsed(depth_update(), &span);
+            drop(span);
+            drop(message);
+        });
+        // the closed span is still buffered until the shutdown exports it
+        telemetry.shutdown();
+
+        let body = bodies.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(body.contains("\"market_message\""));
+        assert!(body.contains("BTCUSDC"));
+        assert!(body.contains(SERVICE_NAME));
+    }
+}
@@ -1,6 +1,537 @@
 use crate::application::Application;
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Symbol(pub String);
 
 pub type ApplicationLayer = Application;
+
+// Name of a market a symbol is traded on, like binance or coinbase
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Venue(pub String);
+
+// quote assets of the supported markets, longer names first so USDT is not split as USD
+const QUOTE_ASSETS: [&str; 12] = [
+    "FDUSD", "USDT", "USDC", "EUR", "USD", "GBP", "JPY", "TRY", "DAI", "BTC", "ETH", "BNB",
+];
+
+// stablecoins and the dollar are priced the same when books of markets are consolidated
+const USD_EQUIVALENT_QUOTES: [&str; 5] = ["FDUSD", "USDT", "USDC", "USD", "DAI"];
+
+/*
+  Symbols are named like binance names them, the base asset followed by the
+  quote asset in upper case. Markets that separate the assets, like BTC-USD or
+  BTC/USD, are converted from and to this naming by their adapters.
+*/
+impl Symbol {
+    pub fn from_pair(pair: &str) -> Self {
+        Symbol(
+            pair.chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_uppercase(),
+        )
+    }
+
+    // None when the symbol does not end with a known quote asset
+    pub fn base_and_quote(&self) -> Option<(&str, &str)> {
+        QUOTE_ASSETS.iter().find_map(|quote| {
+            let base = self.0.strip_suffix(quote)?;
+
+            if base.is_empty() {
+                None
+            } else {
+                Some((base, *quote))
+            }
+        })
+    }
+
+    pub fn to_pair(&self, separator: char) -> Option<String> {
+        let (base, quote) = self.base_and_quote()?;
+
+        Some(format!("{}{}{}", base, separator, quote))
+    }
+
+    // Instrument the symbol is consolidated under across markets, BTCUSDT and BTCUSDC
+    // are both BTCUSD. Symbols without a usd equivalent quote asset stay as they are
+    pub fn normalised(&self) -> Symbol {
+        match self.base_and_quote() {
+            Some((base, quote)) if USD_EQUIVALENT_QUOTES.contains(&quote) => {
+                Symbol(format!("{}USD", base))
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/*
+  Three symbols whose assets form a cycle, like BTCUSDC, ETHBTC and ETHUSDC trading
+  USDC, BTC and ETH against each other. Round trips start and end in the quote asset
+  of the first symbol. The other two symbols are kept in the order of the cycle, the
+  third symbol trades the start asset as well, whatever order they are given in.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Triangle {
+    symbols: [Symbol; 3],
+}
+
+impl Triangle {
+    pub fn new(mut symbols: [Symbol; 3]) -> Result<Self> {
+        let mut assets: Vec<&str> = vec![];
+        for symbol in symbols.iter() {
+            let (base, quote) = symbol
+                .base_and_quote()
+                .ok_or_else(|| anyhow!("{} does not end with a known quote asset", symbol.0))?;
+            assets.extend([base, quote]);
+        }
+
+        // every asset of a cycle of three is traded by exactly two of the symbols
+        let cycle = assets
+            .iter()
+            .all(|asset| assets.iter().filter(|other| *other == asset).count() == 2);
+        if !cycle
+            || symbols[0] == symbols[1]
+            || symbols[1] == symbols[2]
+            || symbols[0] == symbols[2]
+        {
+            return Err(anyhow!(
+                "{}, {} and {} do not trade three assets in a cycle",
+                symbols[0].0,
+                symbols[1].0,
+                symbols[2].0
+            ));
+        }
+
+        // the quote asset of the first symbol is traded by exactly one of the other two
+        let start_asset = assets[1];
+        if assets[2] == start_asset || assets[3] == start_asset {
+            symbols.swap(1, 2);
+        }
+
+        Ok(Self { symbols })
+    }
+
+    pub fn symbols(&self) -> &[Symbol; 3] {
+        &self.symbols
+    }
+
+    pub fn start_asset(&self) -> &str {
+        // checked to have a quote asset when the triangle was made
+        self.symbols[0]
+            .base_and_quote()
+            .map(|(_, quote)| quote)
+            .unwrap_or_default()
+    }
+}
+
+// triangles are written as their symbols separated by commas, BTCUSDC,ETHBTC,ETHUSDC
+impl FromStr for Triangle {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let symbols: Vec<Symbol> = value.split(',').map(Symbol::from_pair).collect();
+        let symbols: [Symbol; 3] = symbols
+            .try_into()
+            .map_err(|_| anyhow!("triangle {} does not have three symbols", value))?;
+
+        Triangle::new(symbols)
+    }
+}
+
+/*
+  Prices and quantities are kept as decimals rather than floats so the
+  values parsed from a market api and the values calculated from them
+  are exact to the last digit the market sends.
+*/
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Price(pub Decimal);
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Quantity(pub Decimal);
+
+// Value of an amount in the quote asset of a symbol, price times quantity
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Notional(pub Decimal);
+
+// Smallest price movement of a symbol. Prices are only quoted in multiples of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TickSize(pub Decimal);
+
+// A price level of a book as a (price, quantity) pair
+pub type PriceLevel = (Price, Quantity);
+
+impl Price {
+    pub fn is_on_tick(&self, tick_size: TickSize) -> bool {
+        (self.0 % tick_size.0).is_zero()
+    }
+
+    // rounds to the closest multiple of the tick size and keeps as many
+    // decimal places as the tick size so it formats like a quoted price
+    pub fn round_to_tick(&self, tick_size: TickSize) -> Price {
+        let ticks = (self.0 / tick_size.0).round();
+        let mut price = ticks * tick_size.0;
+        price.rescale(tick_size.decimal_places());
+
+        Price(price)
+    }
+
+    // rounds to a number of decimal places for values that can fall between
+    // ticks, like the mid price, and keeps trailing zeros up to that place
+    pub fn round_dp(&self, decimal_places: u32) -> Price {
+        let mut price = self.0.round_dp(decimal_places);
+        price.rescale(decimal_places);
+
+        Price(price)
+    }
+
+    // parses a price and rejects it when it is not a multiple of the tick size
+    pub fn parse_on_tick(value: &str, tick_size: TickSize) -> Result<Price> {
+        let price: Price = value.parse()?;
+
+        if !price.is_on_tick(tick_size) {
+            return Err(anyhow!(
+                "price {} is not a multiple of tick size {}",
+                value,
+                tick_size.0
+            ));
+        }
+
+        Ok(price)
+    }
+}
+
+impl TickSize {
+    pub fn decimal_places(&self) -> u32 {
+        self.0.normalize().scale()
+    }
+
+    /*
+      Infers the tick size from prices quoted by a market. Markets pad prices
+      with trailing zeros (binance sends 8 decimal places) so the tick size is
+      taken from the most decimal places any of the prices needs.
+    */
+    pub fn from_prices<'p>(prices: impl IntoIterator<Item = &'p Price>) -> TickSize {
+        let decimal_places = prices
+            .into_iter()
+            .map(|price| price.0.normalize().scale())
+            .max()
+            .unwrap_or(0);
+
+        TickSize(Decimal::new(1, decimal_places))
+    }
+}
+
+impl FromStr for Price {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(Price(Decimal::from_str_exact(value)?))
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(Quantity(Decimal::from_str_exact(value)?))
+    }
+}
+
+impl FromStr for Notional {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(Notional(Decimal::from_str_exact(value)?))
+    }
+}
+
+impl FromStr for TickSize {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let tick_size = Decimal::from_str_exact(value)?;
+
+        if tick_size <= Decimal::ZERO {
+            return Err(anyhow!("tick size {} must be positive", value));
+        }
+
+        Ok(TickSize(tick_size.normalize()))
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for Notional {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Full depth of a book at a point in time. Used to bootstrap a local order book
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub last_update_id: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// Changed levels of a book between the first and final update ids of a diff depth event
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthUpdate {
+    pub symbol: Symbol,
+    // milliseconds since the unix epoch
+    pub event_time: u64,
+    pub first_update_id: u64,
+    pub final_update_id: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// A single trade between a buyer and a seller
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub symbol: Symbol,
+    pub trade_id: u64,
+    pub price: Price,
+    pub quantity: Quantity,
+    // milliseconds since the unix epoch
+    pub trade_time: u64,
+    pub buyer_is_maker: bool,
+}
+
+/// Side of an order. Buy orders take the asks of a book and sell orders the bids
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Size of an order, either an amount of the base asset or a notional of the quote asset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderSize {
+    Quantity(Quantity),
+    Notional(Notional),
+}
+
+/// Best bid and ask of a book
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BookTicker {
+    pub symbol: Symbol,
+    pub update_id: u64,
+    pub best_bid: PriceLevel,
+    pub best_ask: PriceLevel,
+}
+
+/// Length of the candles trades are aggregated into, written as 1s, 1m, 5m or 1h
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CandleInterval {
+    millis: u64,
+}
+
+impl CandleInterval {
+    pub fn from_millis(millis: u64) -> Result<Self> {
+        if millis == 0 {
+            return Err(anyhow!("candle interval has to be longer than zero"));
+        }
+
+        Ok(Self { millis })
+    }
+
+    pub fn millis(&self) -> u64 {
+        self.millis
+    }
+
+    // start of the candle a time in milliseconds since the unix epoch falls into
+    pub fn open_time(&self, time: u64) -> u64 {
+        time - time % self.millis
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let unit_at = value
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("candle interval {} has no unit", value))?;
+        let (count, unit) = value.split_at(unit_at);
+        let count: u64 = count
+            .parse()
+            .map_err(|_| anyhow!("candle interval {} has no length", value))?;
+        let unit_millis = match unit {
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            _ => return Err(anyhow!("candle interval {} has an unknown unit", value)),
+        };
+
+        let millis = count
+            .checked_mul(unit_millis)
+            .ok_or_else(|| anyhow!("candle interval {} is too long", value))?;
+
+        Self::from_millis(millis)
+    }
+}
+
+// written with the largest unit the interval is a whole number of
+impl fmt::Display for CandleInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit_millis, unit) = [(86_400_000, "d"), (3_600_000, "h"), (60_000, "m")]
+            .into_iter()
+            .find(|(unit_millis, _)| self.millis.is_multiple_of(*unit_millis))
+            .unwrap_or((1_000, "s"));
+
+        if self.millis.is_multiple_of(unit_millis) {
+            write!(f, "{}{}", self.millis / unit_millis, unit)
+        } else {
+            write!(f, "{}ms", self.millis)
+        }
+    }
+}
+
+// milliseconds since the unix epoch now, the time the markets give their events in
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// Levels of a book written as price and quantity, like ("100.25", "1.5"), for tests
+#[cfg(test)]
+pub fn levels(levels: &[(&str, &str)]) -> Vec<PriceLevel> {
+    levels
+        .iter()
+        .map(|(p, q)| (p.parse().unwrap(), q.parse().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_parsed_exactly() {
+        let price: Price = "97123.45678901".parse().unwrap();
+
+        assert_eq!(price.to_string(), "97123.45678901");
+        assert!("97123.4.5".parse::<Price>().is_err());
+    }
+
+    #[test]
+    fn test_price_rounded_to_tick_size() {
+        let tick_size: TickSize = "0.01000000".parse().unwrap();
+        let price: Price = "97123.456".parse().unwrap();
+
+        assert_eq!(tick_size.decimal_places(), 2);
+        assert_eq!(price.round_to_tick(tick_size).to_string(), "97123.46");
+        assert_eq!(
+            Price("97123.4".parse().unwrap())
+                .round_to_tick(tick_size)
+                .to_string(),
+            "97123.40"
+        );
+    }
+
+    #[test]
+    fn test_price_parsed_on_tick_size() {
+        let tick_size: TickSize = "0.5".parse().unwrap();
+
+        assert!(Price::parse_on_tick("100.50", tick_size).is_ok());
+        assert!(Price::parse_on_tick("100.25", tick_size).is_err());
+        assert!("0".parse::<TickSize>().is_err());
+    }
+
+    #[test]
+    fn test_symbol_converted_from_and_to_pairs() {
+        let symbol = Symbol::from_pair("btc-usdt");
+
+        assert_eq!(symbol, Symbol("BTCUSDT".into()));
+        assert_eq!(symbol.to_pair('/').unwrap(), "BTC/USDT");
+        assert_eq!(
+            Symbol::from_pair("ETH/USD").to_pair('-').unwrap(),
+            "ETH-USD"
+        );
+        assert_eq!(Symbol("USDT".into()).to_pair('-'), None);
+    }
+
+    #[test]
+    fn test_triangle_parsed_from_symbols_forming_a_cycle() {
+        let triangle: Triangle = "BTCUSDC,ETHBTC,ETHUSDC".parse().unwrap();
+
+        assert_eq!(triangle.start_asset(), "USDC");
+        assert_eq!(triangle.symbols()[1], Symbol("ETHBTC".into()));
+        // the second symbol trading the start asset is moved to the end of the cycle
+        let reordered: Triangle = "ETHBTC,BTCUSDC,ETHUSDC".parse().unwrap();
+        assert_eq!(reordered.start_asset(), "BTC");
+        assert_eq!(reordered.symbols()[2], Symbol("BTCUSDC".into()));
+        assert!("BTCUSDC,ETHBTC,BNBUSDC".parse::<Triangle>().is_err());
+        assert!("BTCUSDC,ETHBTC".parse::<Triangle>().is_err());
+    }
+
+    #[test]
+    fn test_symbols_with_usd_equivalent_quotes_normalised_to_usd() {
+        assert_eq!(
+            Symbol("BTCUSDT".into()).normalised(),
+            Symbol("BTCUSD".into())
+        );
+        assert_eq!(
+            Symbol("BTCFDUSD".into()).normalised(),
+            Symbol("BTCUSD".into())
+        );
+        assert_eq!(
+            Symbol("ETHBTC".into()).normalised(),
+            Symbol("ETHBTC".into())
+        );
+    }
+
+    #[test]
+    fn test_candle_interval_parsed_and_written_with_largest_unit() {
+        let interval: CandleInterval = "5m".parse().unwrap();
+
+        assert_eq!(interval.millis(), 300_000);
+        assert_eq!(interval.open_time(1_700_000_123_456), 1_700_000_100_000);
+        assert_eq!("60s".parse::<CandleInterval>().unwrap().to_string(), "1m");
+        assert!("0s".parse::<CandleInterval>().is_err());
+        assert!("5".parse::<CandleInterval>().is_err());
+        assert!("5w".parse::<CandleInterval>().is_err());
+        assert!("300000000000000d".parse::<CandleInterval>().is_err());
+    }
+
+    #[test]
+    fn test_tick_size_inferred_from_padded_prices() {
+        let prices: Vec<Price> = vec!["97000.10000000", "97000.01000000", "97001.00000000"]
+            .into_iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            TickSize::from_prices(prices.iter()),
+            "0.01".parse().unwrap()
+        );
+    }
+}